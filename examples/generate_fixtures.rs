@@ -0,0 +1,226 @@
+//! Writes the MIDI fixtures used by `tests/parsing.rs` into `tests/fixtures`.
+//!
+//! Run with `cargo run --example generate_fixtures` after changing a fixture below, and commit
+//! the regenerated files together with the test changes.
+
+use midly::{
+    num::{u15, u24, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+use std::path::Path;
+
+const TICKS_PER_BEAT: u16 = 480;
+
+/// Builds a track from `(delta, kind)` events and terminates it.
+fn track(events: Vec<(u32, TrackEventKind<'static>)>) -> Track<'static> {
+    let mut track: Track = events
+        .into_iter()
+        .map(|(delta, kind)| TrackEvent {
+            delta: delta.into(),
+            kind,
+        })
+        .collect();
+    track.push(TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+fn midi(message: MidiMessage) -> TrackEventKind<'static> {
+    TrackEventKind::Midi {
+        channel: u4::new(0),
+        message,
+    }
+}
+
+fn note_on(key: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::NoteOn {
+        key: u7::new(key),
+        vel: u7::new(100),
+    })
+}
+
+fn note_off(key: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::NoteOff {
+        key: u7::new(key),
+        vel: u7::new(0),
+    })
+}
+
+/// A note-on with velocity zero, which files commonly use instead of a note-off.
+fn note_on_zero(key: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::NoteOn {
+        key: u7::new(key),
+        vel: u7::new(0),
+    })
+}
+
+fn program(program: u8) -> TrackEventKind<'static> {
+    midi(MidiMessage::ProgramChange {
+        program: u7::new(program),
+    })
+}
+
+fn tempo(microseconds_per_beat: u32) -> TrackEventKind<'static> {
+    TrackEventKind::Meta(MetaMessage::Tempo(u24::new(microseconds_per_beat)))
+}
+
+fn track_name(name: &'static str) -> TrackEventKind<'static> {
+    TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes()))
+}
+
+fn channel_prefix(channel: u8) -> TrackEventKind<'static> {
+    TrackEventKind::Meta(MetaMessage::MidiChannel(u4::new(channel)))
+}
+
+fn save(directory: &Path, name: &str, format: Format, tracks: Vec<Track<'static>>) {
+    let smf = Smf {
+        header: Header::new(format, Timing::Metrical(u15::new(TICKS_PER_BEAT))),
+        tracks,
+    };
+    let path = directory.join(name);
+    smf.save(&path).expect("could not write fixture");
+    println!("wrote {}", path.display());
+}
+
+fn main() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+    // One track: C4, E4 (released by a zero-velocity note-on), then a G4 + C5 chord.
+    save(
+        &directory,
+        "format0.mid",
+        Format::SingleTrack,
+        vec![track(vec![
+            (0, tempo(500_000)),
+            (0, note_on(60)),
+            (480, note_off(60)),
+            (0, note_on(64)),
+            (480, note_on_zero(64)),
+            (0, note_on(67)),
+            (0, note_on(72)),
+            (480, note_off(67)),
+            (0, note_off(72)),
+        ])],
+    );
+
+    // A conductor track with tempo and a 3/4 time signature, then melody and bass tracks.
+    save(
+        &directory,
+        "format1.mid",
+        Format::Parallel,
+        vec![
+            track(vec![
+                (0, tempo(600_000)),
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(3, 2, 24, 8)),
+                ),
+            ]),
+            track(vec![
+                (0, note_on(60)),
+                (240, note_off(60)),
+                (0, note_on(62)),
+                (240, note_off(62)),
+            ]),
+            track(vec![(0, note_on(48)), (480, note_off(48))]),
+        ],
+    );
+
+    // Piano for two notes, then a flute for two more.
+    save(
+        &directory,
+        "program_changes.mid",
+        Format::SingleTrack,
+        vec![track(vec![
+            (0, program(1)),
+            (0, note_on(60)),
+            (480, note_off(60)),
+            (0, note_on(62)),
+            (480, note_off(62)),
+            (0, program(74)),
+            (0, note_on(64)),
+            (480, note_off(64)),
+            (0, note_on(65)),
+            (480, note_off(65)),
+        ])],
+    );
+
+    // A named track with an instrument, and an unnamed track without one.
+    save(
+        &directory,
+        "track_names.mid",
+        Format::Parallel,
+        vec![
+            track(vec![
+                (0, track_name("Melody")),
+                (0, program(74)),
+                (0, note_on(72)),
+                (480, note_off(72)),
+            ]),
+            track(vec![(0, note_on(48)), (480, note_off(48))]),
+        ],
+    );
+
+    // Tracks declaring their channel with a channel prefix. The first also sets up another
+    // channel's instrument, which must not name it.
+    save(
+        &directory,
+        "channel_prefix.mid",
+        Format::Parallel,
+        vec![
+            track(vec![
+                (0, channel_prefix(3)),
+                (0, program(74)),
+                (
+                    0,
+                    TrackEventKind::Midi {
+                        channel: u4::new(3),
+                        message: MidiMessage::ProgramChange {
+                            program: u7::new(41),
+                        },
+                    },
+                ),
+                (0, note_on(60)),
+                (480, note_off(60)),
+            ]),
+            track(vec![
+                (0, channel_prefix(9)),
+                (0, note_on(36)),
+                (480, note_off(36)),
+            ]),
+        ],
+    );
+
+    // Three beats at 120, 240 and 60 BPM.
+    save(
+        &directory,
+        "tempo.mid",
+        Format::SingleTrack,
+        vec![track(vec![
+            (0, tempo(500_000)),
+            (0, note_on(60)),
+            (480, note_off(60)),
+            (0, tempo(250_000)),
+            (0, note_on(62)),
+            (480, note_off(62)),
+            (0, tempo(1_000_000)),
+            (0, note_on(64)),
+            (480, note_off(64)),
+        ])],
+    );
+
+    // Two beats without any tempo event, so the default of 120 BPM applies.
+    save(
+        &directory,
+        "no_tempo.mid",
+        Format::SingleTrack,
+        vec![track(vec![
+            (0, note_on(60)),
+            (480, note_off(60)),
+            (0, note_on(62)),
+            (480, note_off(62)),
+        ])],
+    );
+}
@@ -0,0 +1,5344 @@
+//! The Planck Scribe window.
+
+#[cfg(feature = "audio")]
+use crate::click_track;
+#[cfg(feature = "pdf")]
+use crate::key_chart;
+#[cfg(feature = "synth")]
+use crate::synth;
+use crate::{
+    batch, beat_grid, config, console, export, fit, history, instrument_family, keyboard_key_name,
+    layout, live_input, load_song, mapping, midi_output, minimap, notes, piano_roll,
+    planck_mapping, playback, practice, program_to_string, qmk,
+    settings::{
+        self, DuplicateNotes, FilteredNotes, KeyNames, MappingKind, MappingSettings, Settings,
+        TrackLayout, UnmappedNotes, VoiceOrder,
+    },
+    split_by_channel, text, timeline, translation, velocity, KeyLine, LoadMidiFileError,
+    MidiKeyTrack, NoteFilter, PlanckRows, SegmentKind, SongTiming, MIDI_C_KEY,
+};
+use eframe::{
+    egui::{self, RichText},
+    epaint::Color32,
+};
+use std::{collections::HashMap, fmt::Write, fs};
+
+// TODO: Add custom icon
+// https://github.com/rust-windowing/winit/blob/master/examples/window_icon.rs
+
+/// Opens the Planck Scribe window with `file`, or else the latest file if that is turned on,
+/// and runs until it is closed.
+pub fn run(file: Option<std::path::PathBuf>) -> Result<(), eframe::Error> {
+    console::init(); // Log to stderr (if you run with `RUST_LOG=debug`) and the in-app log.
+    let preferences = config::Preferences::load().unwrap_or_else(|err| {
+        log::warn!("Could not read the preferences: {err}");
+        config::Preferences::default()
+    });
+    let options = eframe::NativeOptions {
+        drag_and_drop_support: true,
+        initial_window_size: Some(
+            preferences
+                .window_size
+                .map_or(egui::vec2(320.0, 240.0), egui::Vec2::from),
+        ),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Planck Scribe",
+        options,
+        Box::new(|_cc| {
+            let mut app = MyApp::default();
+            app.apply_preferences(preferences);
+            match file {
+                Some(file) => app.open_midi_file(file.display().to_string()),
+                None => app.reopen_latest_file(),
+            }
+            Box::new(app)
+        }),
+    )
+}
+
+struct MyApp {
+    picked_midi_path: Option<String>,
+    midi_key_tracks: Vec<MidiKeyTrack>,
+    key_to_keyboard_mapping: HashMap<u8, String>,
+    song_timing: SongTiming,
+    playback: playback::Playback,
+    settings: Settings,
+    /// Message shown to the user after an action, e.g. an import error.
+    notice: Option<String>,
+    /// Index of the track that track-specific actions (like "Find note") apply to.
+    selected_track: usize,
+    find_note_text: String,
+    /// Track and note index of the last "Find note" result.
+    found_note: Option<(usize, usize)>,
+    scroll_to_found_note: bool,
+    /// The song that was loaded before the current one, restored by "Back".
+    previous_song: Option<LoadedSong>,
+    /// Files opened in this run, listed in the sidebar to switch between without reading them
+    /// again. Each keeps the edits made to it, e.g. transposes.
+    open_songs: Vec<LoadedSong>,
+    /// Whether the log panel is open.
+    show_console: bool,
+    /// Bumped whenever the loaded tracks change, so cached track lines are rebuilt.
+    tracks_revision: u64,
+    track_lines: Option<TrackLinesCache>,
+    /// Scroll offset picked on the mini-map, applied to the track view on the next frame.
+    minimap_jump: Option<f32>,
+    /// Track and line the key list last scrolled to while following playback.
+    followed_line: Option<(usize, usize)>,
+    /// The practice session in progress, if any.
+    practice: Option<practice::Practice>,
+    practice_history: practice::PracticeHistory,
+    /// How many recent sessions the practice trend shows.
+    practice_trend_len: usize,
+    /// Choices remembered for each MIDI file, like the channel order.
+    file_configs: config::FileConfigs,
+    live_input: Option<live_input::LiveInput>,
+    /// MIDI inputs found by the last refresh, and the one picked to connect to.
+    live_input_ports: Vec<String>,
+    live_input_port: usize,
+    /// Notes played on the MIDI input are being added to a track.
+    recording: Option<live_input::Recorder>,
+    /// The built-in synth or MIDI output that playback is heard through.
+    sound_output: Option<Box<dyn midi_output::Sound>>,
+    /// MIDI outputs found by the last refresh, and the one picked to connect to.
+    midi_output_ports: Vec<String>,
+    midi_output_port: usize,
+    /// Key clicked on the keyboard panel, whose notes are listed above it.
+    inspected_key: Option<String>,
+    /// Whether the keyboard panel shows the mapping editor for the inspected key.
+    editing_mapping: bool,
+    /// Note the mapping editor assigns to the inspected key.
+    note_to_assign: u8,
+    /// Playback position up to which notes were sent to the sound output, while playing.
+    sounded_until: Option<f64>,
+    /// Notes on notes of the current song, stored with its per-file settings.
+    annotations: Vec<config::Annotation>,
+    /// The annotation being written, shown in its own window.
+    editing_annotation: Option<config::Annotation>,
+    /// Edits that Ctrl+Z and Ctrl+Y undo and redo.
+    history: history::History<EditState>,
+    /// The state after the last recorded edit, to tell what the next one changed. `None` until
+    /// the first frame after a song is loaded.
+    edit_state: Option<EditState>,
+    /// Track transposes from before auto-transpose shifted the song just loaded, offered to be
+    /// restored.
+    auto_transposed: Option<Vec<i8>>,
+    /// First and last measure (counted from 1) shown, played and exported, or `None` for all.
+    measure_range: Option<(u32, u32)>,
+    /// Measures typed into the "Show measures" inputs, applied with its button.
+    measure_range_input: (u32, u32),
+    /// Index into [`notes::INTERVALS`] of the interval picked for transposing every track.
+    transpose_interval: usize,
+    transpose_down: bool,
+    /// Saved layout and mapping setups.
+    presets: config::Presets,
+    /// Index of the preset last applied or saved.
+    selected_preset: Option<usize>,
+    /// Name typed for saving or renaming a preset.
+    preset_name: String,
+    /// MIDI files opened lately, kept between sessions.
+    recent_files: config::RecentFiles,
+    /// Format picked for exporting all recent files.
+    batch_format: batch::BatchFormat,
+    /// The export of all recent files, while it runs and until its summary is dismissed.
+    batch_export: Option<batch::BatchExport>,
+    /// The session saved on the last exit, until it is restored or dismissed.
+    saved_session: Option<config::Session>,
+    /// Folder the last MIDI file was opened from, where the open dialog starts.
+    last_directory: Option<std::path::PathBuf>,
+    /// Size of the window in the last frame, remembered on exit.
+    window_size: Option<egui::Vec2>,
+}
+
+/// Width of the mini-map beside the track view.
+const MINIMAP_WIDTH: f32 = 48.0;
+
+/// Smallest height the keyboard panel can be dragged to.
+const KEYBOARD_PANEL_MIN_HEIGHT: f32 = 60.0;
+
+/// Height kept free for the track list however far the keyboard panel is dragged up.
+const TRACK_LIST_MIN_HEIGHT: f32 = 200.0;
+
+/// Heights the keyboard panel can take in a window `window_height` points tall.
+fn keyboard_panel_heights(window_height: f32) -> std::ops::RangeInclusive<f32> {
+    KEYBOARD_PANEL_MIN_HEIGHT
+        ..=(window_height - TRACK_LIST_MIN_HEIGHT).max(KEYBOARD_PANEL_MIN_HEIGHT)
+}
+
+/// Key lines of every track, kept between frames because building them formats every note.
+struct TrackLinesCache {
+    /// Tracks revision and the display settings the lines were built with.
+    key: (u64, String),
+    lines: Vec<Vec<KeyLine>>,
+    /// Width in monospace columns of the longest line of each track.
+    widest_line: Vec<usize>,
+    /// Number of notes caught by the filter in each track, for the count badge.
+    filtered: Vec<usize>,
+    /// Number of difficult jumps marked in each track.
+    jumps: Vec<usize>,
+    /// Condensed view of each track for the mini-map.
+    overviews: Vec<minimap::Overview>,
+}
+
+/// Everything needed to restore a loaded song, including its per-track transposes.
+#[derive(Clone)]
+struct LoadedSong {
+    path: Option<String>,
+    tracks: Vec<MidiKeyTrack>,
+    timing: SongTiming,
+}
+
+/// Formats a duration in milliseconds as `m:ss.s`.
+fn format_duration(ms: f64) -> String {
+    let tenths = (ms / 100.0).round() as u64;
+    format!("{}:{:02}.{}", tenths / 600, tenths / 10 % 60, tenths % 10)
+}
+
+/// Formats the time of `tick` into the song as `m:ss.s`, or "unknown" if ticks have no length.
+fn format_tick_time(timing: &SongTiming, tick: u64) -> String {
+    timing
+        .tick_to_time_ms(tick)
+        .map_or_else(|| "unknown".to_owned(), format_duration)
+}
+
+/// The MIDI keys of the notes played on each keyboard key, in the shown tracks and not hidden by
+/// `filter`. How many there are is how often the key is pressed.
+fn key_usage<'a>(tracks: &'a [MidiKeyTrack], filter: &NoteFilter) -> HashMap<&'a str, Vec<u8>> {
+    let mut usage: HashMap<&str, Vec<u8>> = HashMap::new();
+    for track in tracks.iter().filter(|track| !track.hidden) {
+        for pair in &track.midi_key_pairs {
+            if let (Some(label), false) = (pair.keyboard_key.as_deref(), filter.hides(pair)) {
+                usage.entry(label).or_default().push(pair.midi_key);
+            }
+        }
+    }
+    usage
+}
+
+/// What undo and redo bring back: the keyboard setup and the tracks.
+#[derive(Clone)]
+struct EditState {
+    base_key: String,
+    layout: PlanckRows,
+    mapping: MappingSettings,
+    tracks: Vec<MidiKeyTrack>,
+    /// `tracks_revision` when the snapshot was taken.
+    tracks_revision: u64,
+}
+
+impl EditState {
+    /// What kind of edit changed this state into `settings` and `tracks`, or `None` if
+    /// nothing undoable changed. Only the notes changing, e.g. while recording, isn't an edit.
+    fn edit_label(&self, settings: &Settings, tracks: &[MidiKeyTrack]) -> Option<&'static str> {
+        if self.layout != settings.layout {
+            Some("layout edit")
+        } else if self.base_key != settings.base_key {
+            Some("base key change")
+        } else if self.mapping != settings.mapping {
+            Some("mapping change")
+        } else if self.tracks.len() > tracks.len() {
+            Some("track removal")
+        } else if self.tracks.len() < tracks.len() {
+            Some("new track")
+        } else if self
+            .tracks
+            .iter()
+            .zip(tracks)
+            .any(|(before, after)| before.transpose != after.transpose)
+        {
+            Some("transposition")
+        } else if self.tracks.iter().zip(tracks).any(|(before, after)| {
+            before.name != after.name
+                || before.instrument != after.instrument
+                || before.percussion != after.percussion
+        }) {
+            Some("track change")
+        } else {
+            None
+        }
+    }
+}
+
+/// Keys to light on the keyboard panel while following playback.
+#[derive(PartialEq, Debug, Default)]
+struct KeyHighlights {
+    /// Keys of the notes sounding at the playback position.
+    sounding: Vec<String>,
+    /// Keys of the notes starting within the look-ahead, soonest first and each key once, with
+    /// how far into the look-ahead they start: 0 right away, 1 at its end.
+    upcoming: Vec<(String, f32)>,
+}
+
+/// The keys sounding at `position` and starting in the `look_ahead` ticks after it, in the shown
+/// tracks and not hidden by `filter`.
+fn key_highlights(
+    tracks: &[MidiKeyTrack],
+    filter: &NoteFilter,
+    position: f64,
+    look_ahead: f64,
+) -> KeyHighlights {
+    let mut highlights = KeyHighlights::default();
+    let mut upcoming: Vec<(u64, &str)> = Vec::new();
+    let pairs = tracks
+        .iter()
+        .filter(|track| !track.hidden)
+        .flat_map(|track| track.midi_key_pairs.iter())
+        .filter(|pair| !filter.hides(pair));
+    for pair in pairs {
+        let Some(label) = pair.keyboard_key.as_deref() else {
+            continue;
+        };
+        let start = pair.tick as f64;
+        let end = (pair.tick + pair.duration.unwrap_or(0)) as f64;
+        if start <= position && position < end {
+            if !highlights.sounding.iter().any(|key| key == label) {
+                highlights.sounding.push(label.to_owned());
+            }
+        } else if position < start && start <= position + look_ahead {
+            upcoming.push((pair.tick, label));
+        }
+    }
+    upcoming.sort();
+    for (tick, label) in upcoming {
+        if !highlights.upcoming.iter().any(|(key, _)| key == label) {
+            let soon = ((tick as f64 - position) / look_ahead.max(f64::EPSILON)) as f32;
+            highlights.upcoming.push((label.to_owned(), soon));
+        }
+    }
+    highlights
+}
+
+/// `lines` with a `| 3` line before the first note of each measure.
+fn with_bar_lines(lines: Vec<KeyLine>, track: &MidiKeyTrack, timing: &SongTiming) -> Vec<KeyLine> {
+    let mut with_bars = Vec::with_capacity(lines.len());
+    let mut measure = None;
+    for line in lines {
+        if let Some(&index) = line.pair_indices.first() {
+            let line_measure = timing.measure_at(track.midi_key_pairs[index].tick);
+            if measure != Some(line_measure) {
+                measure = Some(line_measure);
+                with_bars.push(KeyLine {
+                    segments: vec![(format!("| {}", line_measure + 1), SegmentKind::Dimmed)],
+                    pair_indices: Vec::new(),
+                });
+            }
+        }
+        with_bars.push(line);
+    }
+    with_bars
+}
+
+/// Index of the line of `lines` holding the latest onset at or before `position`.
+fn playing_line(lines: &[KeyLine], track: &MidiKeyTrack, position: u64) -> Option<usize> {
+    lines.iter().rposition(|line| {
+        line.pair_indices
+            .first()
+            .is_some_and(|&index| track.midi_key_pairs[index].tick <= position)
+    })
+}
+
+/// Every labelled key of `rows` once, in reading order.
+fn layout_keys(rows: &PlanckRows) -> Vec<&String> {
+    let mut keys: Vec<&String> = Vec::new();
+    for key in rows.iter().flatten() {
+        if !key.is_empty() && !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// The track after (or before) `selected` out of `count`, wrapping around at either end.
+fn cycle_track(selected: usize, count: usize, forward: bool) -> usize {
+    let selected = selected.min(count - 1);
+    if forward {
+        (selected + 1) % count
+    } else {
+        (selected + count - 1) % count
+    }
+}
+
+/// Extensions of the files [`load_song`] reads: MIDI files and MusicXML scores.
+const SONG_EXTENSIONS: [&str; 5] = ["mid", "midi", "musicxml", "mxl", "xml"];
+
+/// What a dropped file is taken for, going by its extension.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum DroppedFile {
+    /// A MIDI file or a MusicXML score.
+    Song,
+    Layout,
+    Unsupported,
+}
+
+fn dropped_file_kind(path: &std::path::Path) -> DroppedFile {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some(extension) if SONG_EXTENSIONS.contains(&extension) => DroppedFile::Song,
+        Some("json") => DroppedFile::Layout,
+        _ => DroppedFile::Unsupported,
+    }
+}
+
+/// Indices into `paths` of the layout and the MIDI files that dropping them loads: the first
+/// layout and every MIDI file.
+fn files_to_load(paths: &[std::path::PathBuf]) -> (Option<usize>, Vec<usize>) {
+    let layout = paths
+        .iter()
+        .position(|path| dropped_file_kind(path) == DroppedFile::Layout);
+    let midi = (0..paths.len())
+        .filter(|&index| dropped_file_kind(&paths[index]) == DroppedFile::Song)
+        .collect();
+    (layout, midi)
+}
+
+/// Paints a miniature of the keyboard layout with the `pressed` (row, column) cells lit.
+fn key_grid(
+    painter: &egui::Painter,
+    origin: egui::Pos2,
+    cell: f32,
+    rows: &PlanckRows,
+    pressed: &[(usize, usize)],
+) {
+    let visuals = painter.ctx().style().visuals.clone();
+    for (row_index, row) in rows.iter().enumerate() {
+        for column in 0..row.len() {
+            let rect = egui::Rect::from_min_size(
+                origin + egui::vec2(column as f32 * cell, row_index as f32 * cell),
+                egui::vec2(cell - 1.0, cell - 1.0),
+            );
+            let fill = if pressed.contains(&(row_index, column)) {
+                visuals.selection.stroke.color
+            } else {
+                visuals.faint_bg_color
+            };
+            painter.rect_filled(rect, 0.0, fill);
+        }
+    }
+}
+
+/// Menu button for overriding the instrument of `track` with any General MIDI instrument.
+fn instrument_menu(ui: &mut egui::Ui, track: &mut MidiKeyTrack) {
+    let hover = match track.instrument() {
+        Some(instrument) => match instrument_family(instrument) {
+            Some(family) => format!("Instrument: {} ({})", instrument, family),
+            None => format!("Instrument: {}", instrument),
+        },
+        None => "Instrument: unknown".to_owned(),
+    };
+    ui.menu_button("🎷", |ui| {
+        if track.instrument.is_some() {
+            let detected = track.detected_instrument().unwrap_or("none").to_owned();
+            if ui
+                .button(format!("Reset to detected ({})", detected))
+                .clicked()
+            {
+                track.instrument = None;
+                ui.close_menu();
+            }
+            ui.separator();
+        }
+        let mut programs: Vec<_> = program_to_string().into_iter().collect();
+        programs.sort();
+        egui::ScrollArea::vertical()
+            .max_height(240.0)
+            .show(ui, |ui| {
+                for (_, name) in programs {
+                    let selected = track.instrument() == Some(name.as_str());
+                    if ui.selectable_label(selected, &name).clicked() {
+                        track.instrument = Some(name);
+                        ui.close_menu();
+                    }
+                }
+            });
+    })
+    .response
+    .on_hover_text(hover);
+}
+
+/// Channels, instruments over time, note count, pitch range and how much of the track the layout
+/// can't play, shown when a track in the track list is expanded.
+///
+/// Returns whether the track was switched between percussion and pitched, so it needs mapping.
+fn track_details_ui(ui: &mut egui::Ui, track: &mut MidiKeyTrack, timing: &SongTiming) -> bool {
+    let percussion_changed = ui
+        .checkbox(&mut track.percussion, "Percussion")
+        .on_hover_text(
+            "Drum hits: shown by General MIDI drum name and left out of mapping and exports",
+        )
+        .changed();
+    let details = track.details();
+    egui::Grid::new(ui.id().with("details"))
+        .num_columns(2)
+        .show(ui, |ui| {
+            let channels: Vec<_> = details
+                .channels
+                .iter()
+                .map(|channel| (channel + 1).to_string())
+                .collect();
+            ui.label("Channels:");
+            ui.label(if channels.is_empty() {
+                "none".to_owned()
+            } else {
+                channels.join(", ")
+            });
+            ui.end_row();
+            ui.label("Notes:");
+            ui.label(details.notes.to_string());
+            ui.end_row();
+            ui.label("Range:");
+            ui.label(match details.range {
+                Some((low, high)) => format!(
+                    "{} to {} ({} semitones)",
+                    notes::note_name(low),
+                    notes::note_name(high),
+                    high - low
+                ),
+                None => "no notes".to_owned(),
+            });
+            ui.end_row();
+            ui.label("Unmappable:");
+            if track.percussion {
+                ui.label("not mapped, percussion");
+                ui.end_row();
+                return;
+            }
+            let unmappable = format!(
+                "{} ({:.0}%)",
+                details.unmappable,
+                details.unmappable_percent()
+            );
+            if details.unmappable > 0 {
+                ui.colored_label(ui.visuals().warn_fg_color, unmappable)
+                    .on_hover_text("Notes without a key of their own in the current layout");
+            } else {
+                ui.label(unmappable);
+            }
+            ui.end_row();
+        });
+    if track.program_changes.is_empty() {
+        ui.weak("No program changes.");
+    }
+    for change in &track.program_changes {
+        ui.label(format!(
+            "{}  {} (channel {})",
+            format_tick_time(timing, change.tick),
+            change.name,
+            change.channel + 1
+        ));
+    }
+    percussion_changed
+}
+
+/// Color picker for an optional color setting, where `None` follows the theme.
+fn color_setting_ui(ui: &mut egui::Ui, label: &str, color: &mut Option<[u8; 3]>, current: Color32) {
+    ui.label(format!("{}:", label));
+    let mut rgb = [current.r(), current.g(), current.b()];
+    if ui.color_edit_button_srgb(&mut rgb).changed() {
+        *color = Some(rgb);
+    }
+    if color.is_some()
+        && ui
+            .small_button("↺")
+            .on_hover_text("Use theme color")
+            .clicked()
+    {
+        *color = None;
+    }
+}
+
+/// Small pill showing how many notes a track has, and how many of them are visible.
+fn note_count_badge(ui: &mut egui::Ui, total: usize, filtered: usize) {
+    let text = if filtered > 0 {
+        format!("{}/{} notes", total - filtered, total)
+    } else {
+        format!("{} notes", total)
+    };
+    let response = egui::Frame::none()
+        .fill(ui.visuals().faint_bg_color)
+        .stroke(ui.visuals().widgets.noninteractive.bg_stroke)
+        .rounding(8.0)
+        .inner_margin(egui::vec2(6.0, 1.0))
+        .show(ui, |ui| ui.label(RichText::new(text).small()))
+        .response;
+    if filtered > 0 {
+        response.on_hover_text(format!("{} grace notes filtered out", filtered));
+    }
+}
+
+/// Where the practice session log is kept.
+fn practice_history_path() -> Option<std::path::PathBuf> {
+    config::config_dir().map(|dir| dir.join("practice-history.json"))
+}
+
+impl Default for MyApp {
+    fn default() -> Self {
+        let settings = Settings::default();
+        MyApp {
+            picked_midi_path: None,
+            midi_key_tracks: Vec::new(),
+            key_to_keyboard_mapping: planck_mapping(&settings),
+            song_timing: SongTiming::default(),
+            playback: playback::Playback::default(),
+            settings,
+            notice: None,
+            selected_track: 0,
+            find_note_text: String::new(),
+            found_note: None,
+            scroll_to_found_note: false,
+            previous_song: None,
+            open_songs: Vec::new(),
+            show_console: false,
+            tracks_revision: 0,
+            track_lines: None,
+            minimap_jump: None,
+            followed_line: None,
+            practice: None,
+            practice_history: practice_history_path()
+                .filter(|path| path.exists())
+                .and_then(|path| {
+                    practice::PracticeHistory::load(&path)
+                        .map_err(|err| log::warn!("Could not read the practice history: {err}"))
+                        .ok()
+                })
+                .unwrap_or_default(),
+            practice_trend_len: 10,
+            file_configs: config::FileConfigs::load()
+                .map_err(|err| log::warn!("Could not read the per-file settings: {err}"))
+                .unwrap_or_default(),
+            live_input: None,
+            live_input_ports: Vec::new(),
+            live_input_port: 0,
+            recording: None,
+            sound_output: None,
+            midi_output_ports: Vec::new(),
+            midi_output_port: 0,
+            sounded_until: None,
+            inspected_key: None,
+            editing_mapping: false,
+            note_to_assign: MIDI_C_KEY,
+            annotations: Vec::new(),
+            editing_annotation: None,
+            history: history::History::default(),
+            edit_state: None,
+            auto_transposed: None,
+            measure_range: None,
+            measure_range_input: (1, 1),
+            transpose_interval: 0,
+            transpose_down: false,
+            presets: config::Presets::load()
+                .map_err(|err| log::warn!("Could not read the presets: {err}"))
+                .unwrap_or_default(),
+            selected_preset: None,
+            preset_name: String::new(),
+            recent_files: config::RecentFiles::load()
+                .map_err(|err| log::warn!("Could not read the recent files: {err}"))
+                .unwrap_or_default(),
+            batch_format: batch::BatchFormat::Csv,
+            batch_export: None,
+            saved_session: config::Session::load()
+                .map_err(|err| log::warn!("Could not read the previous session: {err}"))
+                .ok()
+                .flatten(),
+            last_directory: None,
+            window_size: None,
+        }
+    }
+}
+
+impl MyApp {
+    /// Rebuilds the key mapping from the settings and re-maps every loaded note.
+    fn remap_tracks(&mut self) {
+        self.tracks_revision += 1;
+        self.key_to_keyboard_mapping = planck_mapping(&self.settings);
+        for track in self.midi_key_tracks.iter_mut() {
+            track.remap(
+                &self.key_to_keyboard_mapping,
+                self.settings.unmapped_notes,
+                self.settings.mapping.transpose,
+            );
+        }
+    }
+
+    fn export_settings(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Planck Scribe settings", &["json"])
+            .set_file_name("planck-scribe-settings.json")
+            .save_file()
+        {
+            if let Err(err) = self.settings.export(&path) {
+                log::error!("could not export settings: {}", err);
+                self.notice = Some(format!("Could not export settings: {}", err));
+            }
+        }
+    }
+
+    fn import_settings(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Planck Scribe settings", &["json"])
+            .pick_file()
+        {
+            match Settings::import(&path) {
+                Ok(settings) => {
+                    self.notice = (settings.version != settings::SETTINGS_VERSION).then(|| {
+                        format!(
+                            "Settings file is version {} but this app uses version {}; some options may not apply.",
+                            settings.version,
+                            settings::SETTINGS_VERSION
+                        )
+                    });
+                    self.settings = Settings {
+                        version: settings::SETTINGS_VERSION,
+                        ..settings
+                    };
+                    self.remap_tracks();
+                }
+                Err(err) => {
+                    log::error!("could not import settings: {}", err);
+                    self.notice = Some(format!("Could not import settings: {}", err))
+                }
+            }
+        }
+    }
+
+    /// Takes up the settings, folder and window size of the last run. Settings that can't be
+    /// mapped, such as a base key missing from the layout, are left at their defaults.
+    fn apply_preferences(&mut self, preferences: config::Preferences) {
+        match preferences.settings.validate() {
+            Ok(()) => {
+                self.settings = Settings {
+                    version: settings::SETTINGS_VERSION,
+                    ..preferences.settings
+                }
+            }
+            Err(err) => {
+                log::error!("could not use the saved settings: {}", err);
+                self.notice = Some(format!("Could not use the saved settings: {}", err));
+            }
+        }
+        self.last_directory = preferences.last_directory;
+        self.remap_tracks();
+    }
+
+    fn save_preferences(&self) {
+        let preferences = config::Preferences {
+            settings: self.settings.clone(),
+            last_directory: self.last_directory.clone(),
+            window_size: self.window_size.map(<[f32; 2]>::from),
+        };
+        if let Err(err) = preferences.save() {
+            log::warn!("could not save the preferences: {}", err);
+        }
+    }
+
+    /// Saves where the app is for the next start, or forgets it if sessions aren't kept.
+    fn save_session(&self) {
+        let result = if self.settings.save_session {
+            config::Session {
+                file: self.picked_midi_path.clone(),
+                transposes: self
+                    .midi_key_tracks
+                    .iter()
+                    .map(|track| track.transpose)
+                    .collect(),
+                selected_track: self.selected_track,
+                settings: self.settings.clone(),
+            }
+            .save()
+        } else {
+            config::Session::clear()
+        };
+        if let Err(err) = result {
+            log::warn!("could not save the session: {}", err);
+        }
+    }
+
+    /// Brings back the settings and file of a saved session. If the file was moved or deleted
+    /// since, that is reported and only the settings are restored. Settings that can't be
+    /// mapped are reported and the current ones kept.
+    fn restore_session(&mut self, session: config::Session) {
+        match session.settings.validate() {
+            Ok(()) => {
+                self.settings = Settings {
+                    version: settings::SETTINGS_VERSION,
+                    ..session.settings
+                };
+                self.remap_tracks();
+            }
+            Err(err) => {
+                log::error!("could not restore the session's settings: {}", err);
+                self.notice = Some(format!("Could not restore the session's settings: {}", err));
+            }
+        }
+        let Some(file) = session.file else {
+            return;
+        };
+        if !std::path::Path::new(&file).exists() {
+            log::warn!("the file of the previous session is gone: {}", file);
+            self.notice = Some(format!(
+                "{} was moved or deleted since the last session.",
+                file
+            ));
+            return;
+        }
+        self.open_midi_file(file);
+        if session.transposes.len() == self.midi_key_tracks.len() {
+            for (track, transpose) in self.midi_key_tracks.iter_mut().zip(session.transposes) {
+                track.transpose = transpose;
+            }
+            self.remap_tracks();
+        }
+        self.selected_track = session
+            .selected_track
+            .min(self.midi_key_tracks.len().saturating_sub(1));
+    }
+
+    /// Offers to pick up where the last session left off.
+    fn saved_session_window(&mut self, ctx: &egui::Context) {
+        let Some(session) = &self.saved_session else {
+            return;
+        };
+        let mut restore = None;
+        egui::Window::new("Restore previous session?")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                match &session.file {
+                    Some(file) => ui.label(format!("Reopen {} with the settings you had?", file)),
+                    None => ui.label("Bring back the settings you had?"),
+                };
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        restore = Some(true);
+                    }
+                    if ui.button("Start fresh").clicked() {
+                        restore = Some(false);
+                    }
+                });
+            });
+        match restore {
+            Some(true) => {
+                if let Some(session) = self.saved_session.take() {
+                    self.restore_session(session);
+                }
+            }
+            Some(false) => self.saved_session = None,
+            None => {}
+        }
+    }
+
+    /// Preview hovering files:
+    /// Lists the files being dragged over the window by type, marking the ones a drop loads.
+    fn preview_hovering_files(&mut self, ctx: &egui::Context) {
+        if !ctx.input(|i| i.raw.hovered_files.is_empty()) {
+            let text = ctx.input(|i| {
+                let paths: Vec<_> = i
+                    .raw
+                    .hovered_files
+                    .iter()
+                    .filter_map(|file| file.path.clone())
+                    .collect();
+                let (layout, midi) = files_to_load(&paths);
+                let mut text = "Dropping files:\n".to_owned();
+                for (kind, heading) in [
+                    (DroppedFile::Song, "Songs"),
+                    (DroppedFile::Layout, "Layouts"),
+                    (DroppedFile::Unsupported, "Not supported"),
+                ] {
+                    let mut files = paths
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, path)| dropped_file_kind(path) == kind)
+                        .peekable();
+                    if files.peek().is_none() {
+                        continue;
+                    }
+                    write!(text, "\n\n{}:", heading).ok();
+                    for (index, path) in files {
+                        let note = match kind {
+                            DroppedFile::Unsupported => "ignored",
+                            _ if layout == Some(index) || midi.contains(&index) => "will be loaded",
+                            _ => "ignored, only one is loaded",
+                        };
+                        write!(text, "\n  {} ({})", path.display(), note).ok();
+                    }
+                }
+                // Some platforms only tell the type of a dragged file, not its path.
+                for file in i
+                    .raw
+                    .hovered_files
+                    .iter()
+                    .filter(|file| file.path.is_none())
+                {
+                    if file.mime.is_empty() {
+                        text += "\n\n???";
+                    } else {
+                        write!(text, "\n\n{}", file.mime).ok();
+                    }
+                }
+                text
+            });
+
+            egui::CentralPanel::default()
+                .frame(
+                    egui::Frame::default()
+                        .inner_margin(16.0)
+                        .fill(Color32::from_black_alpha(192)),
+                )
+                .show(ctx, |ui| {
+                    ui.label(text);
+                });
+        }
+    }
+
+    fn collect_dropped_files(&mut self, ctx: &egui::Context) {
+        let paths: Vec<_> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        let (layout, midi) = files_to_load(&paths);
+        if let Some(index) = layout {
+            self.import_layout(&paths[index]);
+        }
+        for &index in &midi {
+            self.open_midi_file(paths[index].display().to_string());
+        }
+        // Of several files, the first is shown and the others wait in the sidebar.
+        if midi.len() > 1 {
+            let first = paths[midi[0]].display().to_string();
+            if let Some(index) = self
+                .open_songs
+                .iter()
+                .position(|song| song.path.as_deref() == Some(first.as_str()))
+            {
+                self.switch_to_open_song(index);
+            }
+        }
+    }
+
+    /// Makes the layout stored in `path` the active keyboard layout and re-maps the tracks.
+    fn import_layout(&mut self, path: &std::path::Path) {
+        match layout::Layout::import(path) {
+            Ok(layout) => self.use_layout(layout),
+            Err(err) => {
+                log::error!("could not import layout: {}", err);
+                self.notice = Some(format!("Could not import layout: {}", err))
+            }
+        }
+    }
+
+    /// Switches to `layout`, moving middle C to its first key if the base key isn't on it.
+    fn use_layout(&mut self, layout: layout::Layout) {
+        let keys = || layout.rows.iter().flatten();
+        if !keys().any(|key| *key == self.settings.base_key) {
+            let base_key = keys().find(|key| !key.is_empty()).cloned();
+            log::warn!(
+                "layout {:?} has no {} key, moving middle C to {:?}",
+                layout.name,
+                self.settings.base_key,
+                base_key
+            );
+            self.notice = Some(format!(
+                "Layout \"{}\" has no {} key, so middle C is now on {}.",
+                layout.name,
+                self.settings.base_key,
+                base_key.as_deref().unwrap_or_default()
+            ));
+            self.settings.base_key = base_key.unwrap_or_default();
+        }
+        self.settings.layout = layout.rows;
+        self.remap_tracks();
+    }
+
+    /// Picks one of the built-in keyboard layouts.
+    fn layout_menu(&mut self, ui: &mut egui::Ui) {
+        let layouts = layout::builtin_layouts();
+        let current = layouts
+            .iter()
+            .find(|layout| layout.rows == self.settings.layout)
+            .map_or("Custom", |layout| layout.name.as_str());
+        let mut picked = None;
+        egui::ComboBox::from_id_source("keyboard_layout")
+            .selected_text(current)
+            .show_ui(ui, |ui| {
+                for layout in &layouts {
+                    if ui
+                        .selectable_label(layout.name == current, &layout.name)
+                        .clicked()
+                    {
+                        picked = Some(layout.clone());
+                    }
+                }
+            })
+            .response
+            .on_hover_text("Keyboard layout; import a layout file for others");
+        if let Some(layout) = picked.filter(|layout| layout.name != current) {
+            self.use_layout(layout);
+        }
+    }
+
+    /// Picks the key of the layout that middle C is mapped to, re-mapping the loaded tracks.
+    fn base_key_menu(&mut self, ui: &mut egui::Ui) {
+        let mut picked = None;
+        egui::ComboBox::from_id_source("base_key")
+            .selected_text(format!("Middle C: {}", self.settings.base_key))
+            .show_ui(ui, |ui| {
+                for key in layout_keys(&self.settings.layout) {
+                    if ui
+                        .selectable_label(*key == self.settings.base_key, key)
+                        .clicked()
+                    {
+                        picked = Some(key.clone());
+                    }
+                }
+            })
+            .response
+            .on_hover_text("The key middle C (MIDI note 60) is played on");
+        if let Some(key) = picked.filter(|key| *key != self.settings.base_key) {
+            self.settings.base_key = key;
+            self.remap_tracks();
+        }
+    }
+
+    /// Switches to the saved preset at `index`, re-mapping the loaded tracks.
+    fn apply_preset(&mut self, index: usize) {
+        let Some(preset) = self.presets.presets.get(index) else {
+            return;
+        };
+        self.settings.base_key = preset.base_key.clone();
+        self.settings.layout = preset.layout.clone();
+        self.settings.mapping = preset.mapping.clone();
+        self.selected_preset = Some(index);
+        self.preset_name = preset.name.clone();
+        self.remap_tracks();
+    }
+
+    fn save_presets(&mut self) {
+        if let Err(err) = self.presets.save() {
+            log::error!("could not save presets: {}", err);
+            self.notice = Some(format!("Could not save presets: {}", err));
+        }
+    }
+
+    /// Picks, saves, renames and deletes layout and mapping presets.
+    fn presets_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Preset:");
+            let selected_name = self
+                .selected_preset
+                .and_then(|index| self.presets.presets.get(index))
+                .map_or("(unsaved)", |preset| preset.name.as_str());
+            let mut picked = None;
+            egui::ComboBox::from_id_source("preset")
+                .selected_text(selected_name)
+                .show_ui(ui, |ui| {
+                    for (index, preset) in self.presets.presets.iter().enumerate() {
+                        if ui
+                            .selectable_label(self.selected_preset == Some(index), &preset.name)
+                            .clicked()
+                        {
+                            picked = Some(index);
+                        }
+                    }
+                });
+            if let Some(index) = picked {
+                self.apply_preset(index);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.preset_name)
+                    .desired_width(160.0)
+                    .hint_text("Preset name"),
+            );
+            let name = self.preset_name.trim().to_owned();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save current"))
+                .on_hover_text("Saves the layout, middle C key and mapping under this name")
+                .clicked()
+            {
+                let index = self.presets.save_preset(config::Preset {
+                    name,
+                    base_key: self.settings.base_key.clone(),
+                    layout: self.settings.layout.clone(),
+                    mapping: self.settings.mapping.clone(),
+                });
+                self.selected_preset = Some(index);
+                self.save_presets();
+            }
+            if let Some(index) = self.selected_preset {
+                if ui.button("Rename").clicked() {
+                    if self.presets.rename(index, &self.preset_name) {
+                        self.save_presets();
+                    } else {
+                        self.notice = Some(format!(
+                            "Can't rename the preset to \"{}\": the name is empty or taken.",
+                            self.preset_name.trim()
+                        ));
+                    }
+                }
+                if ui.button("Delete").clicked() {
+                    self.presets.presets.remove(index);
+                    self.selected_preset = None;
+                    self.save_presets();
+                }
+            }
+        });
+    }
+
+    // C  -> key = 60 + 0
+    // C# -> key = 60 + 1
+    // D  -> key = 60 + 2
+    // D# -> key = 60 + 3
+    // E  -> key = 60 + 4
+    // F  -> key = 60 + 5
+    // F# -> key = 60 + 6
+    // G  -> key = 60 + 7
+    // G# -> key = 60 + 8
+    // A  -> key = 60 + 9
+    // A# -> key = 60 + 10
+    // B  -> key = 60 + 11
+
+    /// Loads a file picked by the user, remembering the current song so "Back" can restore it.
+    /// The current song stays as it is if the file can't be loaded.
+    fn open_midi_file(&mut self, path: String) {
+        match self.read_midi_file(&path) {
+            Ok((tracks, timing)) => {
+                self.remember_current_song();
+                self.picked_midi_path = Some(path.clone());
+                self.show_song(tracks, timing);
+                self.last_directory = std::path::Path::new(&path)
+                    .parent()
+                    .map(std::path::Path::to_path_buf);
+                self.recent_files.push(&path);
+                if let Err(err) = self.recent_files.save() {
+                    log::warn!("could not save the recent files: {}", err);
+                }
+                self.open_songs
+                    .retain(|song| song.path.as_deref() != Some(path.as_str()));
+                self.open_songs.push(self.current_song());
+            }
+            Err(err) => {
+                log::error!("could not load MIDI file: {}", err);
+                self.notice = Some(format!("Could not load MIDI file: {}", err));
+            }
+        }
+    }
+
+    /// Opens the most recent file if that is turned on. A file that is gone is taken off the
+    /// recent files, with a notice.
+    fn reopen_latest_file(&mut self) {
+        let Some(path) = self.recent_files.file_to_reopen().map(str::to_owned) else {
+            return;
+        };
+        if std::path::Path::new(&path).exists() {
+            self.open_midi_file(path);
+            return;
+        }
+        log::warn!("the most recent file is gone: {}", path);
+        self.notice = Some(format!(
+            "{} was moved or deleted, so it was taken off the recent files.",
+            path
+        ));
+        self.recent_files.remove(&path);
+        if let Err(err) = self.recent_files.save() {
+            log::warn!("could not save the recent files: {}", err);
+        }
+    }
+
+    /// Lists the recent files to reopen, and offers to export all of them at once.
+    fn recent_files_menu(&mut self, ui: &mut egui::Ui) {
+        if self.recent_files.paths.is_empty() {
+            ui.weak("No recent files");
+            return;
+        }
+        for path in self.recent_files.paths.clone() {
+            if ui.button(&path).clicked() {
+                self.open_midi_file(path);
+                ui.close_menu();
+            }
+        }
+        if ui
+            .checkbox(
+                &mut self.recent_files.reopen_latest,
+                "Open the latest file on start",
+            )
+            .changed()
+        {
+            if let Err(err) = self.recent_files.save() {
+                log::warn!("could not save the recent files: {}", err);
+            }
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Export all as");
+            egui::ComboBox::from_id_source("batch_format")
+                .selected_text(self.batch_format.name())
+                .show_ui(ui, |ui| {
+                    for format in batch::BatchFormat::ALL {
+                        ui.selectable_value(&mut self.batch_format, format, format.name());
+                    }
+                });
+        });
+        let running = self
+            .batch_export
+            .as_ref()
+            .is_some_and(|batch| !batch.is_finished());
+        if ui
+            .add_enabled(!running, egui::Button::new("Export all to folder…"))
+            .on_hover_text("Files that no longer exist are skipped")
+            .clicked()
+        {
+            let dialog = rfd::FileDialog::new();
+            let dialog = match &self.settings.export.directory {
+                Some(directory) => dialog.set_directory(directory),
+                None => dialog,
+            };
+            if let Some(directory) = dialog.pick_folder() {
+                self.batch_export = Some(batch::BatchExport::start(
+                    &self.recent_files,
+                    directory,
+                    self.batch_format,
+                    self.settings.clone(),
+                    {
+                        let ctx = ui.ctx().clone();
+                        move || ctx.request_repaint()
+                    },
+                ));
+            }
+            ui.close_menu();
+        }
+    }
+
+    /// Progress of the batch export, then a summary of what was exported.
+    fn batch_export_window(&mut self, ctx: &egui::Context) {
+        let Some(batch) = &mut self.batch_export else {
+            return;
+        };
+        batch.poll();
+        let mut open = true;
+        egui::Window::new(format!("Exporting recent files as {}", batch.format.name()))
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.add(
+                    egui::ProgressBar::new(batch.outcomes.len() as f32 / batch.total.max(1) as f32)
+                        .text(format!("{} / {}", batch.outcomes.len(), batch.total)),
+                );
+                if batch.is_finished() {
+                    let (exported, skipped, failed) = batch.summary();
+                    ui.label(format!(
+                        "{} exported, {} skipped (missing), {} failed",
+                        exported, skipped, failed
+                    ));
+                }
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for outcome in &batch.outcomes {
+                            match &outcome.result {
+                                Ok(target) => {
+                                    ui.label(format!("✔ {}", target.display()));
+                                }
+                                Err(err) => {
+                                    ui.label(
+                                        RichText::new(format!("✖ {}: {}", outcome.source, err))
+                                            .color(ui.visuals().warn_fg_color),
+                                    );
+                                }
+                            }
+                        }
+                    });
+            });
+        if !open {
+            self.batch_export = None;
+        }
+    }
+
+    fn remember_current_song(&mut self) {
+        // The recorded track goes with the song it was added to, and so do its edits.
+        self.recording = None;
+        self.forget_edits();
+        if !self.midi_key_tracks.is_empty() {
+            let song = self.current_song();
+            if let Some(open_song) = self
+                .open_songs
+                .iter_mut()
+                .find(|open_song| open_song.path.is_some() && open_song.path == song.path)
+            {
+                *open_song = song.clone();
+            }
+            self.previous_song = Some(song);
+        }
+    }
+
+    fn current_song(&self) -> LoadedSong {
+        LoadedSong {
+            path: self.picked_midi_path.clone(),
+            tracks: self.midi_key_tracks.clone(),
+            timing: self.song_timing.clone(),
+        }
+    }
+
+    /// Makes `song` the current one, as it was left.
+    fn restore_song(&mut self, song: LoadedSong) {
+        self.picked_midi_path = song.path;
+        self.midi_key_tracks = song.tracks;
+        self.song_timing = song.timing;
+        self.selected_track = 0;
+        self.found_note = None;
+        self.load_annotations();
+        self.measure_range = None;
+        self.remap_tracks();
+        self.playback.stop();
+        self.playback.loop_start = 0;
+        self.playback.loop_end = self.song_end_tick();
+        self.measure_range_input = (1, self.measure_count());
+    }
+
+    /// Switches to the open song at `index`, keeping the current one's edits for coming back.
+    fn switch_to_open_song(&mut self, index: usize) {
+        let song = self.open_songs[index].clone();
+        if song.path.is_some() && song.path == self.picked_midi_path {
+            return;
+        }
+        self.remember_current_song();
+        self.restore_song(song);
+    }
+
+    /// Lists the open songs, the current one highlighted, to switch to or close.
+    fn open_songs_ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Songs");
+        ui.separator();
+        let mut switch_to = None;
+        let mut close = None;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (index, song) in self.open_songs.iter().enumerate() {
+                let path = song.path.as_deref().unwrap_or_default();
+                let name = std::path::Path::new(path)
+                    .file_name()
+                    .map_or(path.into(), |name| name.to_string_lossy());
+                let current = song.path == self.picked_midi_path;
+                ui.horizontal(|ui| {
+                    if ui.small_button("✖").on_hover_text("Close").clicked() {
+                        close = Some(index);
+                    }
+                    if ui
+                        .selectable_label(current, name)
+                        .on_hover_text(path)
+                        .clicked()
+                    {
+                        switch_to = Some(index);
+                    }
+                });
+            }
+        });
+        if let Some(index) = switch_to {
+            self.switch_to_open_song(index);
+        }
+        if let Some(index) = close {
+            self.open_songs.remove(index);
+        }
+    }
+
+    fn edit_snapshot(&self) -> EditState {
+        EditState {
+            base_key: self.settings.base_key.clone(),
+            layout: self.settings.layout.clone(),
+            mapping: self.settings.mapping.clone(),
+            tracks: self.midi_key_tracks.clone(),
+            tracks_revision: self.tracks_revision,
+        }
+    }
+
+    /// Records an undo step if this frame changed the keyboard setup or the tracks since the
+    /// last one. Other changes to the tracks, like recorded notes, only move the baseline on.
+    fn record_edits(&mut self) {
+        let Some(before) = &self.edit_state else {
+            self.edit_state = Some(self.edit_snapshot());
+            return;
+        };
+        if let Some(label) = before.edit_label(&self.settings, &self.midi_key_tracks) {
+            let before = self.edit_state.replace(self.edit_snapshot());
+            self.history
+                .record(label, before.unwrap(), std::time::Instant::now());
+        } else if before.tracks_revision != self.tracks_revision {
+            self.edit_state = Some(self.edit_snapshot());
+        }
+    }
+
+    fn forget_edits(&mut self) {
+        self.history.clear();
+        self.edit_state = None;
+    }
+
+    fn undo(&mut self) {
+        if let Some(state) = self.history.undo(self.edit_snapshot()) {
+            self.restore_edit_state(state);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(state) = self.history.redo(self.edit_snapshot()) {
+            self.restore_edit_state(state);
+        }
+    }
+
+    fn restore_edit_state(&mut self, state: EditState) {
+        self.settings.base_key = state.base_key;
+        self.settings.layout = state.layout;
+        self.settings.mapping = state.mapping;
+        self.midi_key_tracks = state.tracks;
+        let track_count = self.midi_key_tracks.len();
+        self.selected_track = self.selected_track.min(track_count.saturating_sub(1));
+        if self
+            .recording
+            .as_ref()
+            .is_some_and(|recorder| recorder.track >= track_count)
+        {
+            self.recording = None;
+        }
+        self.found_note = None;
+        self.remap_tracks();
+        self.edit_state = Some(self.edit_snapshot());
+    }
+
+    fn undo_redo_ui(&mut self, ui: &mut egui::Ui) {
+        let undo = self.history.undo_label();
+        if ui
+            .add_enabled(undo.is_some(), egui::Button::new("↶"))
+            .on_hover_text(format!("Undo {} (Ctrl+Z)", undo.unwrap_or("")))
+            .clicked()
+        {
+            self.undo();
+        }
+        let redo = self.history.redo_label();
+        if ui
+            .add_enabled(redo.is_some(), egui::Button::new("↷"))
+            .on_hover_text(format!("Redo {} (Ctrl+Y)", redo.unwrap_or("")))
+            .clicked()
+        {
+            self.redo();
+        }
+    }
+
+    /// Swaps the current song with the previously loaded one.
+    fn go_back(&mut self) {
+        let Some(previous) = self.previous_song.take() else {
+            return;
+        };
+        self.remember_current_song();
+        self.restore_song(previous);
+    }
+
+    fn load_midi_file(&mut self, path: String) -> Result<(), LoadMidiFileError> {
+        let (tracks, timing) = self.read_midi_file(&path)?;
+        self.picked_midi_path = Some(path);
+        self.show_song(tracks, timing);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    fn load_midi_bytes(&mut self, file: &[u8]) -> Result<(), LoadMidiFileError> {
+        let (tracks, timing) = self.read_song(file, self.picked_midi_path.as_deref())?;
+        self.show_song(tracks, timing);
+        Ok(())
+    }
+
+    fn read_midi_file(
+        &self,
+        path: &str,
+    ) -> Result<(Vec<MidiKeyTrack>, SongTiming), LoadMidiFileError> {
+        let file = fs::read(path)?;
+        self.read_song(&file, Some(path))
+    }
+
+    /// Parses a song, split by channel in the order saved for `path` if that is turned on,
+    /// without touching the current one.
+    fn read_song(
+        &self,
+        file: &[u8],
+        path: Option<&str>,
+    ) -> Result<(Vec<MidiKeyTrack>, SongTiming), LoadMidiFileError> {
+        let (mut tracks, timing) = load_song(file, self.settings.duplicate_notes)?;
+        if self.settings.split_by_channel {
+            let order = path
+                .and_then(|path| self.file_configs.get(path))
+                .map(|config| config.channel_order.clone())
+                .unwrap_or_default();
+            tracks = split_by_channel(&tracks, &order);
+        }
+        Ok((tracks, timing))
+    }
+
+    /// Makes a freshly loaded song the current one, fitted to the keyboard if that is turned on.
+    fn show_song(&mut self, tracks: Vec<MidiKeyTrack>, timing: SongTiming) {
+        self.midi_key_tracks = tracks;
+        self.song_timing = timing;
+        self.forget_edits();
+        self.selected_track = 0;
+        self.found_note = None;
+        self.load_annotations();
+        self.measure_range = None;
+        self.auto_transposed = None;
+        if self.settings.auto_transpose {
+            self.auto_transpose();
+        }
+        self.remap_tracks();
+
+        self.playback.stop();
+        self.playback.loop_start = 0;
+        self.playback.loop_end = self.song_end_tick();
+        self.measure_range_input = (1, self.measure_count());
+    }
+
+    fn note_filter(&self) -> NoteFilter {
+        let filter = &self.settings.filter;
+        NoteFilter {
+            min_duration: filter.hide_grace_notes.then(|| {
+                let whole_note = self.song_timing.ticks_per_beat() * 4.0;
+                (whole_note / filter.grace_note_value.max(1) as f64).round() as u64
+            }),
+            dim: filter.filtered_notes == FilteredNotes::Dim,
+            ticks: self.measure_ticks(),
+            drop_unmapped: self.settings.unmapped_notes == UnmappedNotes::Drop,
+        }
+    }
+
+    /// Number of measures the song spans, following every time signature change.
+    fn measure_count(&self) -> u32 {
+        self.song_timing.measure_at(self.song_end_tick()) as u32 + 1
+    }
+
+    /// Tick range of the measures picked with "Show measures", if any.
+    fn measure_ticks(&self) -> Option<std::ops::Range<u64>> {
+        let (first, last) = self.measure_range?;
+        let timing = &self.song_timing;
+        Some(timing.measure_start(first as usize - 1)..timing.measure_start(last as usize))
+    }
+
+    /// The tracks to export: those picked for export, with only the notes of the shown
+    /// measures if a range is picked.
+    fn exported_tracks(&self) -> std::borrow::Cow<'_, [MidiKeyTrack]> {
+        let ticks = self.measure_ticks();
+        let drop_unmapped = self.settings.unmapped_notes == UnmappedNotes::Drop;
+        if ticks.is_none()
+            && !drop_unmapped
+            && self.midi_key_tracks.iter().all(MidiKeyTrack::is_exported)
+        {
+            return std::borrow::Cow::Borrowed(&self.midi_key_tracks);
+        }
+        let mut tracks: Vec<_> = self
+            .midi_key_tracks
+            .iter()
+            .filter(|track| track.is_exported())
+            .cloned()
+            .collect();
+        if let Some(ticks) = ticks {
+            for track in tracks.iter_mut() {
+                track
+                    .midi_key_pairs
+                    .retain(|pair| ticks.contains(&pair.tick));
+            }
+        }
+        if drop_unmapped {
+            for track in tracks.iter_mut() {
+                track.drop_unmapped();
+            }
+        }
+        std::borrow::Cow::Owned(tracks)
+    }
+
+    /// Checkboxes picking which tracks are shown.
+    fn shown_tracks_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.small_button("All").clicked() {
+                self.midi_key_tracks
+                    .iter_mut()
+                    .for_each(|track| track.hidden = false);
+            }
+            if ui.small_button("None").clicked() {
+                self.midi_key_tracks
+                    .iter_mut()
+                    .for_each(|track| track.hidden = true);
+            }
+        });
+        let timing = &self.song_timing;
+        let mut percussion_changed = false;
+        for (index, track) in self.midi_key_tracks.iter_mut().enumerate() {
+            let id = ui.make_persistent_id(("track_details", index));
+            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false)
+                .show_header(ui, |ui| {
+                    let mut shown = !track.hidden;
+                    if ui
+                        .checkbox(&mut shown, format!("{}. {}", index + 1, track.name))
+                        .on_hover_text("Hidden tracks are left out of every view and export")
+                        .changed()
+                    {
+                        track.hidden = !shown;
+                    }
+                })
+                .body(|ui| percussion_changed |= track_details_ui(ui, track, timing));
+        }
+        if percussion_changed {
+            self.remap_tracks();
+        }
+    }
+
+    /// Checkboxes picking which tracks are exported.
+    fn export_tracks_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.small_button("All").clicked() {
+                self.midi_key_tracks
+                    .iter_mut()
+                    .for_each(|track| track.skip_export = false);
+            }
+            if ui.small_button("None").clicked() {
+                self.midi_key_tracks
+                    .iter_mut()
+                    .for_each(|track| track.skip_export = true);
+            }
+        });
+        for (index, track) in self.midi_key_tracks.iter_mut().enumerate() {
+            let mut exported = !track.skip_export;
+            if ui
+                .checkbox(&mut exported, format!("{}. {}", index + 1, track.name))
+                .changed()
+            {
+                track.skip_export = !exported;
+            }
+        }
+    }
+
+    /// Length of the song up to the end of its last note, or `None` if it can't be determined.
+    fn song_duration_ms(&self) -> Option<f64> {
+        let end_tick = self
+            .midi_key_tracks
+            .iter()
+            .flat_map(|track| track.midi_key_pairs.iter())
+            .map(|pair| pair.tick + pair.duration.unwrap_or(0))
+            .max()?;
+        self.song_timing.tick_to_time_ms(end_tick)
+    }
+
+    /// Tick of the last note onset across all tracks.
+    fn song_end_tick(&self) -> u64 {
+        self.midi_key_tracks
+            .iter()
+            .filter_map(|track| track.midi_key_pairs.last())
+            .map(|pair| pair.tick)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// `(notes reached, notes shown)`: how many of the shown notes start at or before the
+    /// playback position, matching the onset that is highlighted.
+    fn note_count(&self) -> (usize, usize) {
+        let filter = self.note_filter();
+        let position = self.playback.position as u64;
+        let shown = || {
+            self.midi_key_tracks
+                .iter()
+                .flat_map(|track| track.midi_key_pairs.iter())
+                .filter(|pair| !filter.hides(pair))
+        };
+        let reached = if self.playback.is_started() {
+            shown().filter(|pair| pair.tick <= position).count()
+        } else {
+            0
+        };
+        (reached, shown().count())
+    }
+
+    /// Moves the playback position to the next (or previous) onset of a shown note.
+    fn step(&mut self, forward: bool) {
+        let filter = self.note_filter();
+        let position = self.playback.position as u64;
+        let onsets = self
+            .midi_key_tracks
+            .iter()
+            .flat_map(|track| track.midi_key_pairs.iter())
+            .filter(|pair| !filter.hides(pair))
+            .map(|pair| pair.tick);
+        let target = if forward {
+            // From a fresh stop, the first step lands on the first onset itself.
+            let started = self.playback.is_started();
+            onsets
+                .filter(|&tick| tick > position || (!started && tick == position))
+                .min()
+        } else {
+            onsets.filter(|&tick| tick < position).max()
+        };
+        if let Some(tick) = target {
+            self.playback.seek(tick as f64);
+        }
+    }
+
+    /// The look-ahead of "Follow playback" in ticks from the playback position, at the current
+    /// playback tempo.
+    fn look_ahead_ticks(&self) -> f64 {
+        let settings = &self.settings.playback;
+        let tempo = self.playback.current_tempo_percent(settings) as f64 / 100.0;
+        let look_ahead_ms = settings.look_ahead_secs as f64 * 1000.0 * tempo;
+        let timing = &self.song_timing;
+        let position = self.playback.position;
+        timing
+            .time_ms_at(position)
+            .and_then(|ms| timing.time_ms_to_tick(ms + look_ahead_ms))
+            .map_or_else(|| timing.ms_to_ticks(look_ahead_ms), |end| end - position)
+    }
+
+    /// Keyboard keys of the most recent note onset at or before the playback position.
+    fn now_playing_keys(&self) -> Vec<String> {
+        let position = self.playback.position as u64;
+        let current_tick = self
+            .midi_key_tracks
+            .iter()
+            .filter_map(|track| {
+                let index = track
+                    .midi_key_pairs
+                    .partition_point(|pair| pair.tick <= position);
+                index.checked_sub(1).map(|i| track.midi_key_pairs[i].tick)
+            })
+            .max();
+        let Some(current_tick) = current_tick else {
+            return Vec::new();
+        };
+        self.midi_key_tracks
+            .iter()
+            .filter(|track| !track.hidden)
+            .flat_map(|track| track.midi_key_pairs.iter())
+            .filter(|pair| pair.tick == current_tick)
+            .map(|pair| pair.keyboard_key.clone().unwrap_or("NONE".to_owned()))
+            .collect()
+    }
+
+    /// The keyboard layout filling the panel, each key with its note, lit while it plays or is
+    /// held on the MIDI input. Keys can be shaded by how often they are pressed, and clicking
+    /// one lists the notes of the song played on it.
+    fn keyboard_panel_ui(&mut self, ui: &mut egui::Ui) {
+        let filter = self.note_filter();
+        let played = key_usage(&self.midi_key_tracks, &filter);
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.settings.keyboard_panel.heatmap, "Heatmap")
+                .on_hover_text("Shade each key by how often the shown tracks press it");
+            ui.checkbox(&mut self.editing_mapping, "Edit mapping")
+                .on_hover_text("Assign notes to keys by hand, overriding the mapping");
+            match self.inspected_key.as_deref() {
+                Some(label) => {
+                    let mut counts: Vec<(u8, usize)> = Vec::new();
+                    for &key in played.get(label).into_iter().flatten() {
+                        match counts.iter_mut().find(|(played, _)| *played == key) {
+                            Some((_, count)) => *count += 1,
+                            None => counts.push((key, 1)),
+                        }
+                    }
+                    counts.sort();
+                    let notes: Vec<_> = counts
+                        .iter()
+                        .map(|(key, count)| format!("{} ×{}", notes::note_name(*key), count))
+                        .collect();
+                    let mut mapped: Vec<u8> = self
+                        .key_to_keyboard_mapping
+                        .iter()
+                        .filter(|(_, key)| key.as_str() == label)
+                        .map(|(&note, _)| note)
+                        .collect();
+                    mapped.sort();
+                    let mapped: Vec<_> = mapped.into_iter().map(notes::note_name).collect();
+                    if mapped.is_empty() {
+                        ui.label(format!("{}: no note maps here", label));
+                    } else {
+                        ui.label(format!("{} plays {}", label, mapped.join(", ")));
+                    }
+                    if notes.is_empty() {
+                        ui.weak("not pressed in this song");
+                    } else {
+                        ui.weak(format!("pressed for {}", notes.join(", ")));
+                    }
+                }
+                None => {
+                    ui.weak("Click a key to see the notes played on it.");
+                }
+            }
+        });
+        let played = if self.editing_mapping {
+            // The editor can change which keys the notes are played on.
+            drop(played);
+            self.mapping_editor_ui(ui);
+            key_usage(&self.midi_key_tracks, &filter)
+        } else {
+            played
+        };
+        let rows = &self.settings.layout;
+        let mut lit = if self.playback.is_started() {
+            self.now_playing_keys()
+        } else {
+            Vec::new()
+        };
+        let highlights = if self.playback.is_started() && self.settings.playback.follow {
+            key_highlights(
+                &self.midi_key_tracks,
+                &filter,
+                self.playback.position,
+                self.look_ahead_ticks(),
+            )
+        } else {
+            KeyHighlights::default()
+        };
+        lit.extend(highlights.sounding);
+        if let Some(live_input) = &self.live_input {
+            lit.extend(
+                live_input
+                    .held()
+                    .filter_map(|key| self.key_to_keyboard_mapping.get(&key).cloned()),
+            );
+        }
+        let notes: HashMap<&str, u8> = self
+            .key_to_keyboard_mapping
+            .iter()
+            .map(|(key, label)| (label.as_str(), *key))
+            .collect();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(1) as f32;
+        let available = ui.available_rect_before_wrap();
+        let cell = (available.width() / columns)
+            .min(available.height() / rows.len().max(1) as f32)
+            .max(8.0);
+        let (rect, response) = ui.allocate_exact_size(
+            egui::vec2(columns * cell, rows.len() as f32 * cell),
+            egui::Sense::click(),
+        );
+        let painter = ui.painter_at(rect);
+        let visuals = ui.visuals();
+        let font = egui::FontId::proportional((cell * 0.28).clamp(6.0, 20.0));
+        let most_presses = played.values().map(Vec::len).max().unwrap_or(0).max(1);
+        let mut clicked = None;
+        for (row_index, row) in rows.iter().enumerate() {
+            for (column, label) in row.iter().enumerate() {
+                let key_rect = egui::Rect::from_min_size(
+                    rect.min + egui::vec2(column as f32 * cell, row_index as f32 * cell),
+                    egui::vec2(cell, cell),
+                )
+                .shrink(1.0);
+                let presses = played.get(label.as_str()).map_or(0, Vec::len);
+                let (fill, text_color) = if lit.contains(label) {
+                    (visuals.selection.bg_fill, visuals.selection.stroke.color)
+                } else {
+                    (visuals.faint_bg_color, visuals.text_color())
+                };
+                painter.rect_filled(key_rect, 3.0, fill);
+                if self.settings.keyboard_panel.heatmap && presses > 0 && !lit.contains(label) {
+                    let heat = presses as f32 / most_presses as f32;
+                    painter.rect_filled(
+                        key_rect,
+                        3.0,
+                        visuals.warn_fg_color.gamma_multiply(0.15 + 0.6 * heat),
+                    );
+                }
+                if let Some((_, soon)) = highlights
+                    .upcoming
+                    .iter()
+                    .find(|(key, _)| key == label)
+                    .filter(|_| !lit.contains(label))
+                {
+                    // Keys coming up sooner are drawn stronger.
+                    let strength = 0.15 + 0.6 * (1.0 - soon.clamp(0.0, 1.0));
+                    painter.rect_filled(
+                        key_rect,
+                        3.0,
+                        visuals.selection.bg_fill.gamma_multiply(strength),
+                    );
+                    painter.rect_stroke(key_rect, 3.0, visuals.selection.stroke);
+                }
+                if self.settings.mapping.custom.keys.contains_key(label) {
+                    painter.rect_stroke(
+                        key_rect.shrink(2.0),
+                        3.0,
+                        egui::Stroke::new(1.0, visuals.warn_fg_color),
+                    );
+                }
+                if self.inspected_key.as_ref() == Some(label) {
+                    painter.rect_stroke(key_rect, 3.0, visuals.selection.stroke);
+                }
+                if response.clicked()
+                    && !label.is_empty()
+                    && response
+                        .interact_pointer_pos()
+                        .is_some_and(|pointer| key_rect.contains(pointer))
+                {
+                    clicked = Some(label.clone());
+                }
+                let mut text = match notes.get(label.as_str()) {
+                    Some(&key) => format!("{}\n{}", label, notes::note_name(key)),
+                    None => label.clone(),
+                };
+                if self.settings.keyboard_panel.heatmap && presses > 0 {
+                    text += &format!("\n×{}", presses);
+                }
+                painter.text(
+                    key_rect.center(),
+                    egui::Align2::CENTER_CENTER,
+                    text,
+                    font.clone(),
+                    text_color,
+                );
+            }
+        }
+        if let Some(label) = clicked {
+            self.inspected_key = (self.inspected_key.as_ref() != Some(&label)).then_some(label);
+        }
+    }
+
+    /// Assigns notes to the inspected key by hand, and saves and loads the assignments as
+    /// key map files. Keys assigned by hand are outlined on the keyboard.
+    fn mapping_editor_ui(&mut self, ui: &mut egui::Ui) {
+        let custom = self.settings.mapping.custom.clone();
+        let edited = &mut self.settings.mapping.custom;
+        let (mut save, mut load) = (false, false);
+        ui.horizontal(|ui| {
+            match self.inspected_key.as_deref() {
+                Some(key) => {
+                    ui.label(format!("{}:", key));
+                    match edited.keys.get_mut(key) {
+                        Some(notes) if notes.is_empty() => {
+                            ui.weak("silent");
+                        }
+                        Some(notes) => {
+                            let mut removed = None;
+                            for &note in notes.iter() {
+                                if ui
+                                    .small_button(format!("{} ✖", notes::note_name(note)))
+                                    .on_hover_text("Take this note off the key")
+                                    .clicked()
+                                {
+                                    removed = Some(note);
+                                }
+                            }
+                            notes.retain(|note| Some(*note) != removed);
+                        }
+                        None => {
+                            ui.weak("as mapped");
+                        }
+                    }
+                    ui.add(
+                        egui::DragValue::new(&mut self.note_to_assign)
+                            .clamp_range(0..=127)
+                            .custom_formatter(|note, _| notes::note_name(note as u8)),
+                    );
+                    if ui.button("Assign").clicked() {
+                        edited.assign(key, self.note_to_assign);
+                    }
+                    if ui
+                        .button("Silence")
+                        .on_hover_text("Play no note on this key")
+                        .clicked()
+                    {
+                        edited.keys.insert(key.to_owned(), Vec::new());
+                    }
+                    if ui
+                        .add_enabled(edited.keys.contains_key(key), egui::Button::new("Reset"))
+                        .on_hover_text("Give the key back its mapped note")
+                        .clicked()
+                    {
+                        edited.keys.remove(key);
+                    }
+                }
+                None => {
+                    ui.weak("Click a key to assign notes to it.");
+                }
+            }
+            ui.separator();
+            if ui
+                .add_enabled(!edited.keys.is_empty(), egui::Button::new("Reset all"))
+                .clicked()
+            {
+                edited.keys.clear();
+            }
+            save = ui.button("Save key map…").clicked();
+            load = ui.button("Load key map…").clicked();
+        });
+        if save {
+            self.export_key_map();
+        }
+        if load {
+            self.import_key_map();
+        }
+        if self.settings.mapping.custom != custom {
+            self.remap_tracks();
+        }
+    }
+
+    fn export_key_map(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Planck Scribe key map", &["json"])
+            .set_file_name("key-map.json")
+            .save_file()
+        {
+            if let Err(err) = self.settings.mapping.custom.export(&path) {
+                log::error!("could not save key map: {}", err);
+                self.notice = Some(format!("Could not save key map: {}", err));
+            }
+        }
+    }
+
+    fn import_key_map(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Planck Scribe key map", &["json"])
+            .pick_file()
+        {
+            match mapping::CustomMapping::import(&path) {
+                Ok(custom) => self.settings.mapping.custom = custom,
+                Err(err) => {
+                    log::error!("could not load key map: {}", err);
+                    self.notice = Some(format!("Could not load key map: {}", err));
+                }
+            }
+        }
+    }
+
+    /// Horizontal "now playing" bar that can be clicked or dragged to seek.
+    fn progress_bar_ui(&mut self, ui: &mut egui::Ui, song_end: u64) {
+        let size = egui::vec2(ui.available_width(), 12.0);
+        let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click_and_drag());
+        let song_end = song_end.max(1) as f32;
+        let x_for_tick = |tick: f32| rect.left() + rect.width() * (tick / song_end).clamp(0.0, 1.0);
+
+        let visuals = ui.visuals();
+        let painter = ui.painter();
+        painter.rect_filled(rect, 4.0, visuals.extreme_bg_color);
+        if self.playback.looping {
+            let loop_rect = egui::Rect::from_x_y_ranges(
+                x_for_tick(self.playback.loop_start as f32)
+                    ..=x_for_tick(self.playback.loop_end as f32),
+                rect.y_range(),
+            );
+            painter.rect_filled(
+                loop_rect,
+                0.0,
+                visuals.selection.bg_fill.gamma_multiply(0.4),
+            );
+        }
+        let played_rect = egui::Rect::from_x_y_ranges(
+            rect.left()..=x_for_tick(self.playback.position as f32),
+            rect.y_range(),
+        );
+        painter.rect_filled(played_rect, 4.0, visuals.selection.bg_fill);
+
+        if let Some(pointer) = response.interact_pointer_pos() {
+            let fraction = ((pointer.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+            self.playback.seek((fraction * song_end) as f64);
+        }
+    }
+
+    /// Picks a range of measures to focus on; the rest of the song is hidden, skipped by
+    /// playback and left out of exports until "Show all" is pressed.
+    fn measure_range_ui(&mut self, ui: &mut egui::Ui) {
+        let measures = self.measure_count();
+        ui.horizontal(|ui| {
+            ui.label("Show measures");
+            let (first, last) = &mut self.measure_range_input;
+            ui.add(egui::DragValue::new(first).clamp_range(1..=measures));
+            ui.label("to");
+            ui.add(egui::DragValue::new(last).clamp_range(1..=measures));
+            ui.weak(format!("of {}", measures));
+            if ui.button("Apply").clicked() {
+                self.set_measure_range(self.measure_range_input);
+            }
+            if self.measure_range.is_some() && ui.button("Show all").clicked() {
+                self.measure_range = None;
+                self.playback.looping = false;
+                self.playback.loop_start = 0;
+                self.playback.loop_end = self.song_end_tick();
+            }
+        });
+    }
+
+    /// Shows only measures `first` to `last` and loops playback over them.
+    fn set_measure_range(&mut self, (first, last): (u32, u32)) {
+        let measures = self.measure_count();
+        if first < 1 || last > measures || first > last {
+            self.notice = Some(format!(
+                "Pick measures between 1 and {}, with the first no later than the last.",
+                measures
+            ));
+            return;
+        }
+        self.measure_range = Some((first, last));
+        self.found_note = None;
+        if let Some(ticks) = self.measure_ticks() {
+            self.playback.looping = true;
+            self.playback.loop_start = ticks.start;
+            self.playback.loop_end = ticks.end;
+            self.playback.seek(ticks.start as f64);
+        }
+    }
+
+    fn find_note_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Find note:");
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.find_note_text)
+                    .hint_text("e.g. Bb3")
+                    .desired_width(60.0),
+            );
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Find").clicked() || submitted {
+                self.find_note();
+            }
+            egui::ComboBox::from_id_source("middle_c")
+                .selected_text(match self.settings.middle_c {
+                    notes::MiddleC::C4 => "Middle C = C4",
+                    notes::MiddleC::C3 => "Middle C = C3",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut self.settings.middle_c,
+                        notes::MiddleC::C4,
+                        "Middle C = C4",
+                    );
+                    ui.selectable_value(
+                        &mut self.settings.middle_c,
+                        notes::MiddleC::C3,
+                        "Middle C = C3",
+                    );
+                })
+                .response
+                .on_hover_text("How octave numbers in typed note names are read");
+        });
+    }
+
+    fn reduce_track_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let reduction = &mut self.settings.reduction;
+            ui.label("Reduce selected track to");
+            ui.add(egui::DragValue::new(&mut reduction.voices).clamp_range(1..=8));
+            ui.label("voices, keeping the");
+            ui.selectable_value(&mut reduction.keep, VoiceOrder::Highest, "highest");
+            ui.selectable_value(&mut reduction.keep, VoiceOrder::Lowest, "lowest");
+            if ui
+                .button("Add reduced track")
+                .on_hover_text("Adds a new track; the selected track is kept as it is")
+                .clicked()
+            {
+                if let Some(track) = self.midi_key_tracks.get(self.selected_track) {
+                    let reduced = track.reduced(&self.settings.reduction);
+                    self.midi_key_tracks
+                        .insert(self.selected_track + 1, reduced);
+                    self.tracks_revision += 1;
+                    self.found_note = None;
+                }
+            }
+        });
+    }
+
+    /// Auto-fit: octave shifts that bring as many notes as possible onto the keyboard.
+    fn fit_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Auto-fit:");
+            if ui
+                .button("Fit all tracks")
+                .on_hover_text("Shift every track by the same number of octaves")
+                .clicked()
+            {
+                let tracks: Vec<_> = self.midi_key_tracks.iter().collect();
+                let shift = fit::best_octave_shift(
+                    &tracks,
+                    &self.key_to_keyboard_mapping,
+                    self.settings.mapping.transpose,
+                );
+                self.transpose_all(shift);
+            }
+            if ui
+                .button("Fit each track")
+                .on_hover_text("Shift each track by its own number of octaves, e.g. bass and melody separately")
+                .clicked()
+            {
+                for track in self.midi_key_tracks.iter_mut() {
+                    let shift = fit::best_octave_shift(
+                        &[track],
+                        &self.key_to_keyboard_mapping,
+                        self.settings.mapping.transpose,
+                    );
+                    track.transpose = track.transpose.saturating_add(shift).clamp(-48, 48);
+                }
+                self.remap_tracks();
+            }
+            ui.label(format!(
+                "{:.0}% playable",
+                fit::playable_percent(&self.midi_key_tracks)
+            ));
+            ui.checkbox(&mut self.settings.auto_transpose, "Fit all tracks on load")
+                .on_hover_text("Shift newly loaded files into range automatically");
+        });
+    }
+
+    /// Adds `semitones` to the transpose of every track, within the range the per-track
+    /// transpose allows.
+    fn transpose_all(&mut self, semitones: i8) {
+        for track in self.midi_key_tracks.iter_mut() {
+            track.transpose = track.transpose.saturating_add(semitones).clamp(-48, 48);
+        }
+        self.remap_tracks();
+    }
+
+    fn transpose_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Transpose song:");
+            let transpose = ui
+                .add(
+                    egui::Slider::new(
+                        &mut self.settings.mapping.transpose,
+                        settings::TRANSPOSE_RANGE,
+                    )
+                    .suffix(" semitones"),
+                )
+                .on_hover_text(
+                    "Shifts every note before it is mapped, on top of each track's own transpose",
+                );
+            if transpose.changed() {
+                self.remap_tracks();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Transpose all tracks:");
+            ui.selectable_value(&mut self.transpose_down, false, "up");
+            ui.selectable_value(&mut self.transpose_down, true, "down");
+            ui.label("by");
+            let (name, semitones) = notes::INTERVALS[self.transpose_interval];
+            egui::ComboBox::from_id_source("transpose_interval")
+                .selected_text(name)
+                .show_ui(ui, |ui| {
+                    for (index, (name, semitones)) in notes::INTERVALS.iter().enumerate() {
+                        ui.selectable_value(&mut self.transpose_interval, index, *name)
+                            .on_hover_text(format!("{} semitones", semitones));
+                    }
+                });
+            if ui
+                .button("Apply")
+                .on_hover_text("Adds to each track's own transpose")
+                .clicked()
+            {
+                self.transpose_all(if self.transpose_down {
+                    -semitones
+                } else {
+                    semitones
+                });
+            }
+        });
+    }
+
+    /// The channel tracks split by channel, in their display order. Drag a row's handle to
+    /// move its track; the new order is remembered for the file.
+    fn channel_order_ui(&mut self, ui: &mut egui::Ui) {
+        let pointer = ui.input(|i| i.pointer.interact_pos());
+        // Index of the dragged row, and whether it was just dropped.
+        let mut drag = None;
+        let mut rows = Vec::with_capacity(self.midi_key_tracks.len());
+        for (i, track) in self.midi_key_tracks.iter().enumerate() {
+            let row = ui.horizontal(|ui| {
+                let handle = ui
+                    .add(egui::Label::new("☰").sense(egui::Sense::drag()))
+                    .on_hover_cursor(egui::CursorIcon::Grab);
+                match track.midi_key_pairs.first() {
+                    Some(pair) => ui.label(format!("Channel {}: {}", pair.channel + 1, track.name)),
+                    None => ui.label(&track.name),
+                };
+                handle
+            });
+            if row.inner.dragged() || row.inner.drag_released() {
+                drag = Some((i, row.inner.drag_released()));
+            }
+            rows.push(row.response.rect);
+        }
+        let (Some((from, dropped)), Some(pointer), Some(last)) = (drag, pointer, rows.last())
+        else {
+            return;
+        };
+        // Position among the rows the track would be inserted at.
+        let to = rows.iter().filter(|row| row.center().y < pointer.y).count();
+        if !dropped {
+            let y = rows.get(to).map_or(last.bottom(), |row| row.top());
+            ui.painter()
+                .hline(last.x_range(), y, ui.visuals().selection.stroke);
+            return;
+        }
+        let to = if to > from { to - 1 } else { to };
+        if to == from {
+            return;
+        }
+        let track = self.midi_key_tracks.remove(from);
+        self.midi_key_tracks.insert(to, track);
+        self.selected_track = to;
+        self.found_note = None;
+        self.tracks_revision += 1;
+        self.save_channel_order();
+    }
+
+    /// Remembers the current order of the channel tracks for the open file.
+    fn save_channel_order(&mut self) {
+        let Some(path) = self.picked_midi_path.clone() else {
+            return;
+        };
+        let mut order = Vec::new();
+        for track in &self.midi_key_tracks {
+            if let Some(pair) = track.midi_key_pairs.first() {
+                if !order.contains(&pair.channel) {
+                    order.push(pair.channel);
+                }
+            }
+        }
+        self.file_configs.entry(&path).channel_order = order;
+        self.save_file_configs();
+    }
+
+    fn save_file_configs(&mut self) {
+        if let Err(err) = self.file_configs.save() {
+            log::error!("Could not save the per-file settings: {err}");
+            self.notice = Some(format!("Could not save the per-file settings: {err}"));
+        }
+    }
+
+    fn load_annotations(&mut self) {
+        self.editing_annotation = None;
+        self.annotations = self
+            .picked_midi_path
+            .as_deref()
+            .and_then(|path| self.file_configs.get(path))
+            .map(|config| config.annotations.clone())
+            .unwrap_or_default();
+    }
+
+    /// Replaces the annotation of the same note, or removes it if `annotation` has no text.
+    fn set_annotation(&mut self, annotation: config::Annotation) {
+        self.annotations.retain(|existing| {
+            (existing.track, existing.note) != (annotation.track, annotation.note)
+        });
+        if !annotation.text.trim().is_empty() {
+            self.annotations.push(annotation);
+        }
+        if let Some(path) = self.picked_midi_path.clone() {
+            self.file_configs.entry(&path).annotations = self.annotations.clone();
+            self.save_file_configs();
+        }
+    }
+
+    /// Window for writing the annotation picked by clicking a note.
+    fn annotation_window(&mut self, ctx: &egui::Context) {
+        let Some(annotation) = &mut self.editing_annotation else {
+            return;
+        };
+        let mut open = true;
+        let mut done = None;
+        egui::Window::new("Annotation")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let note = self
+                    .midi_key_tracks
+                    .get(annotation.track)
+                    .and_then(|track| track.midi_key_pairs.get(annotation.note));
+                if let Some(pair) = note {
+                    ui.label(format!(
+                        "Track {}, note {} ({})",
+                        annotation.track + 1,
+                        annotation.note + 1,
+                        notes::note_name(pair.midi_key)
+                    ));
+                }
+                ui.add(
+                    egui::TextEdit::multiline(&mut annotation.text)
+                        .hint_text("e.g. watch this jump")
+                        .desired_rows(3),
+                );
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        done = Some(annotation.clone());
+                    }
+                    if ui.button("Remove").clicked() {
+                        annotation.text.clear();
+                        done = Some(annotation.clone());
+                    }
+                });
+            });
+        if let Some(annotation) = done {
+            self.set_annotation(annotation);
+            self.editing_annotation = None;
+        } else if !open {
+            self.editing_annotation = None;
+        }
+    }
+
+    /// Takes in notes from the live MIDI input; in practice mode they count as key presses.
+    fn poll_live_input(&mut self) {
+        let Some(live_input) = &mut self.live_input else {
+            return;
+        };
+        let events = live_input.poll();
+        for event in events.iter().filter(|event| event.pressed) {
+            if let (Some(practice), Some(label)) = (
+                &mut self.practice,
+                self.key_to_keyboard_mapping.get(&event.key),
+            ) {
+                practice.press(label);
+            }
+        }
+        let Some(recorder) = &mut self.recording else {
+            return;
+        };
+        let Some(track) = self.midi_key_tracks.get_mut(recorder.track) else {
+            self.recording = None;
+            return;
+        };
+        if events.is_empty() {
+            return;
+        }
+        for event in &events {
+            recorder.record(event, track, &self.song_timing);
+        }
+        track.remap(
+            &self.key_to_keyboard_mapping,
+            self.settings.unmapped_notes,
+            self.settings.mapping.transpose,
+        );
+        self.tracks_revision += 1;
+    }
+
+    /// Adds an empty track and starts writing the notes played on the MIDI input into it.
+    fn start_recording(&mut self) {
+        let mut track = MidiKeyTrack::new();
+        track.name = format!("Recording {}", self.midi_key_tracks.len() + 1);
+        self.midi_key_tracks.push(track);
+        self.selected_track = self.midi_key_tracks.len() - 1;
+        self.recording = Some(live_input::Recorder::new(self.selected_track));
+        self.tracks_revision += 1;
+    }
+
+    fn live_input_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(live_input) = &self.live_input {
+                ui.label(format!("Connected to {}", live_input.port_name));
+                if ui.button("Disconnect").clicked() {
+                    self.live_input = None;
+                    self.recording = None;
+                }
+                if self.recording.is_some() {
+                    if ui.button("⏹ Stop recording").clicked() {
+                        self.recording = None;
+                    }
+                } else if ui
+                    .button("⏺ Record")
+                    .on_hover_text(
+                        "Add the notes you play to a new track, timed at the song's tempo",
+                    )
+                    .clicked()
+                {
+                    self.start_recording();
+                }
+                return;
+            }
+            if ui.button("⟳ Find inputs").clicked() {
+                match live_input::ports() {
+                    Ok(ports) => self.live_input_ports = ports,
+                    Err(err) => {
+                        log::error!("could not list MIDI inputs: {err}");
+                        self.notice = Some(format!("Could not list MIDI inputs: {err}"));
+                    }
+                }
+                self.live_input_port = 0;
+            }
+            if self.live_input_ports.is_empty() {
+                ui.weak("No MIDI inputs found.");
+                return;
+            }
+            egui::ComboBox::from_id_source("live_input_port")
+                .selected_text(&self.live_input_ports[self.live_input_port])
+                .show_ui(ui, |ui| {
+                    for (i, name) in self.live_input_ports.iter().enumerate() {
+                        ui.selectable_value(&mut self.live_input_port, i, name);
+                    }
+                });
+            if ui.button("Connect").clicked() {
+                match live_input::LiveInput::connect(self.live_input_port, ui.ctx().clone()) {
+                    Ok(live_input) => self.live_input = Some(live_input),
+                    Err(err) => {
+                        log::error!("could not connect to MIDI input: {err}");
+                        self.notice = Some(format!("Could not connect to MIDI input: {err}"));
+                    }
+                }
+            }
+        });
+        let Some(live_input) = &self.live_input else {
+            return;
+        };
+        let rows = &self.settings.layout;
+        let pressed: Vec<_> = live_input
+            .held()
+            .filter_map(|key| self.key_to_keyboard_mapping.get(&key))
+            .filter_map(|label| layout::key_position(rows, label))
+            .collect();
+        let cell = 16.0;
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(columns as f32 * cell, rows.len() as f32 * cell),
+            egui::Sense::hover(),
+        );
+        key_grid(ui.painter(), rect.min, cell, rows, &pressed);
+        match live_input.latency() {
+            Some(latency) => ui.label(format!(
+                "Input latency: {:.1} ms",
+                latency.as_secs_f64() * 1000.0
+            )),
+            None => ui.weak("Play a note to measure the input latency."),
+        }
+        .on_hover_text("Time from a note arriving to the frame that shows it");
+    }
+
+    /// Number keys pick a track, Tab and Shift+Tab move to the next and previous one. Left alone
+    /// while typing into a text field or practicing, where these keys are typed.
+    fn switch_track_keys(&mut self, ctx: &egui::Context) {
+        let count = self.midi_key_tracks.len();
+        if count == 0 || ctx.wants_keyboard_input() || self.practice.is_some() {
+            return;
+        }
+        const NUMBER_KEYS: [egui::Key; 9] = [
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+            egui::Key::Num8,
+            egui::Key::Num9,
+        ];
+        ctx.input_mut(|i| {
+            for (index, key) in NUMBER_KEYS.into_iter().enumerate().take(count) {
+                if i.consume_key(egui::Modifiers::NONE, key) {
+                    self.selected_track = index;
+                }
+            }
+            if i.consume_key(egui::Modifiers::NONE, egui::Key::Tab) {
+                self.selected_track = cycle_track(self.selected_track, count, true);
+            }
+            if i.consume_key(egui::Modifiers::SHIFT, egui::Key::Tab) {
+                self.selected_track = cycle_track(self.selected_track, count, false);
+            }
+        });
+    }
+
+    /// Practice mode: type the selected track's keys in order and get scored on accuracy and
+    /// speed. Finished sessions are added to the practice history.
+    fn practice_ui(&mut self, ui: &mut egui::Ui) {
+        // Typing into a text box (e.g. "Find note") isn't playing.
+        let typing = ui.ctx().wants_keyboard_input();
+        if let Some(practice) = self.practice.as_mut().filter(|_| !typing) {
+            ui.input(|i| {
+                for event in &i.events {
+                    if let Some(label) = practice::typed_key(event) {
+                        practice.press(&label);
+                    }
+                }
+            });
+        }
+        ui.horizontal(|ui| match &self.practice {
+            Some(practice) => {
+                let (done, total) = practice.progress();
+                ui.strong(format!("Track {}", practice.track + 1));
+                if practice.is_finished() {
+                    ui.label(format!("Done! {total} notes"));
+                } else {
+                    ui.label(format!("Press: {}", practice.expected().join(" + ")));
+                    ui.weak(format!("{done}/{total}"));
+                }
+                ui.label(format!("Accuracy: {:.0}%", practice.accuracy() * 100.0));
+                if ui.button("Stop").clicked() {
+                    self.finish_practice();
+                }
+            }
+            None => {
+                if ui
+                    .add_enabled(
+                        !self.midi_key_tracks.is_empty(),
+                        egui::Button::new("Practice selected track"),
+                    )
+                    .clicked()
+                {
+                    if let Some(track) = self.midi_key_tracks.get(self.selected_track) {
+                        self.practice = Some(practice::Practice::start(self.selected_track, track));
+                    }
+                }
+            }
+        });
+        if self
+            .practice
+            .as_ref()
+            .is_some_and(|practice| practice.is_finished())
+        {
+            self.finish_practice();
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Last");
+            ui.add(egui::DragValue::new(&mut self.practice_trend_len).clamp_range(2..=50));
+            ui.label("sessions");
+            if ui
+                .button("Keep only these")
+                .on_hover_text("Delete older sessions from the history")
+                .clicked()
+            {
+                self.practice_history.prune(self.practice_trend_len);
+                self.save_practice_history();
+            }
+            if ui.button("Clear history").clicked() {
+                self.practice_history.sessions.clear();
+                self.save_practice_history();
+            }
+        });
+        let sessions = &self.practice_history.sessions;
+        let recent = &sessions[sessions.len().saturating_sub(self.practice_trend_len)..];
+        if recent.is_empty() {
+            ui.weak("No practice sessions yet.");
+            return;
+        }
+        // One bar per session, as tall as its accuracy.
+        let bar_width = 16.0;
+        let (rect, _) = ui.allocate_exact_size(
+            egui::vec2(recent.len() as f32 * (bar_width + 4.0), 60.0),
+            egui::Sense::hover(),
+        );
+        let painter = ui.painter_at(rect);
+        for (i, session) in recent.iter().enumerate() {
+            let left = rect.left() + i as f32 * (bar_width + 4.0);
+            let bar = egui::Rect::from_min_max(
+                egui::pos2(left, rect.bottom() - rect.height() * session.accuracy),
+                egui::pos2(left + bar_width, rect.bottom()),
+            );
+            painter.rect_filled(bar, 2.0, ui.visuals().selection.bg_fill);
+        }
+        if let Some(last) = recent.last() {
+            ui.label(format!(
+                "Last session: {} — {:.0}% accuracy, {:.0} notes/min",
+                last.song,
+                last.accuracy * 100.0,
+                last.notes_per_minute
+            ));
+        }
+    }
+
+    /// Ends the practice session, logging it if any notes were played.
+    fn finish_practice(&mut self) {
+        let Some(practice) = self.practice.take() else {
+            return;
+        };
+        let record = practice.record(&self.song_name());
+        if record.notes > 0 {
+            self.practice_history.push(record);
+            self.save_practice_history();
+        }
+    }
+
+    fn save_practice_history(&mut self) {
+        let Some(path) = practice_history_path() else {
+            return;
+        };
+        if let Err(err) = self.practice_history.save(&path) {
+            log::error!("Could not save the practice history: {err}");
+            self.notice = Some(format!("Could not save the practice history: {err}"));
+        }
+    }
+
+    /// Shifts every track by the best auto-fit octave shift, if that isn't zero, and says so.
+    /// The shift is an undo step of its own.
+    fn auto_transpose(&mut self) {
+        let tracks: Vec<_> = self.midi_key_tracks.iter().collect();
+        let shift = fit::best_octave_shift(
+            &tracks,
+            &self.key_to_keyboard_mapping,
+            self.settings.mapping.transpose,
+        );
+        if shift == 0 {
+            return;
+        }
+        let before = self.edit_snapshot();
+        let previous = self
+            .midi_key_tracks
+            .iter()
+            .map(|track| track.transpose)
+            .collect();
+        for track in self.midi_key_tracks.iter_mut() {
+            track.transpose = track.transpose.saturating_add(shift).clamp(-48, 48);
+        }
+        self.history
+            .record("transposition", before, std::time::Instant::now());
+        self.edit_state = Some(self.edit_snapshot());
+        self.auto_transposed = Some(previous);
+        self.notice = Some(format!(
+            "Auto-transposed by {:+} semitones ({:+} octaves) to fit the keyboard.",
+            shift,
+            shift / 12
+        ));
+    }
+
+    fn undo_auto_transpose(&mut self) {
+        let Some(previous) = self.auto_transposed.take() else {
+            return;
+        };
+        for (track, transpose) in self.midi_key_tracks.iter_mut().zip(previous) {
+            track.transpose = transpose;
+        }
+        self.notice = None;
+        self.remap_tracks();
+    }
+
+    /// Scrolls the selected track to the first visible occurrence of the note typed in the
+    /// "Find note" box.
+    fn find_note(&mut self) {
+        self.found_note = None;
+        let Some(key) = notes::parse_note_name(&self.find_note_text, self.settings.middle_c) else {
+            self.notice = Some(format!(
+                "\"{}\" is not a note name (try e.g. C4, F#3 or Bb2)",
+                self.find_note_text.trim()
+            ));
+            return;
+        };
+        let filter = self.note_filter();
+        let Some(track) = self.midi_key_tracks.get(self.selected_track) else {
+            return;
+        };
+        match track
+            .midi_key_pairs
+            .iter()
+            .position(|pair| pair.midi_key == key && !filter.hides(pair))
+        {
+            Some(index) => {
+                self.found_note = Some((self.selected_track, index));
+                self.scroll_to_found_note = true;
+                self.notice = None;
+            }
+            None => {
+                self.notice = Some(format!(
+                    "{} does not occur in {}",
+                    self.find_note_text.trim(),
+                    track.name
+                ))
+            }
+        }
+    }
+
+    /// Widest MIDI key, note name and key label among the notes the table shows, in columns.
+    fn table_column_widths(&self, filter: &NoteFilter) -> [usize; 3] {
+        let mut widths = [0; 3];
+        for pair in self
+            .midi_key_tracks
+            .iter()
+            .flat_map(|track| track.midi_key_pairs.iter())
+            .filter(|pair| !filter.hides(pair))
+        {
+            let cells = [
+                pair.midi_key.to_string(),
+                notes::note_name(pair.midi_key),
+                keyboard_key_name(pair, &self.settings),
+            ];
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(text::display_width(&cell));
+            }
+        }
+        widths
+    }
+
+    /// Shows every track as an aligned table with one row per note, combining the picked
+    /// fields, e.g. `60  C4  ESC  0:02.1`.
+    fn table_ui(&self, ui: &mut egui::Ui) {
+        let filter = self.note_filter();
+        let settings = &self.settings;
+        let fields = &settings.display.table_fields;
+        let timing = &self.song_timing;
+        let (mapped_color, unmapped_color) = settings.colors.resolve(ui.visuals());
+        let out_of_scale_color = settings.colors.resolve_out_of_scale(ui.visuals());
+        let display = &settings.display;
+        let velocities = velocity::VelocityScale::new(&self.midi_key_tracks, &settings.velocity);
+        // Without alignment, every column is as wide as its cell.
+        let [key_width, name_width, label_width] = if fields.align {
+            self.table_column_widths(&filter)
+        } else {
+            [0; 3]
+        };
+        egui::ScrollArea::new([true, true])
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.horizontal_top(|ui| {
+                    for (i, track) in self.midi_key_tracks.iter().enumerate() {
+                        if track.hidden {
+                            continue;
+                        }
+                        ui.vertical(|ui| {
+                            ui.strong(&track.name);
+                            egui::Grid::new(("note_table", i))
+                                .striped(true)
+                                .spacing(egui::vec2(12.0, 2.0))
+                                .show(ui, |ui| {
+                                    for pair in &track.midi_key_pairs {
+                                        if filter.hides(pair) {
+                                            continue;
+                                        }
+                                        let key_color = match pair.keyboard_key {
+                                            _ if filter.excludes(pair) => {
+                                                ui.visuals().weak_text_color()
+                                            }
+                                            None => unmapped_color,
+                                            Some(_)
+                                                if display.highlight_scale
+                                                    && !display.scale.contains(pair.midi_key) =>
+                                            {
+                                                out_of_scale_color
+                                            }
+                                            Some(_) => mapped_color,
+                                        };
+                                        let text_color = if filter.excludes(pair) {
+                                            ui.visuals().weak_text_color()
+                                        } else {
+                                            ui.visuals().text_color()
+                                        };
+                                        let cell = |ui: &mut egui::Ui, text: String, color| {
+                                            ui.label(RichText::new(text).monospace().color(color));
+                                        };
+                                        if fields.midi_key {
+                                            let midi_key = pair.midi_key.to_string();
+                                            cell(
+                                                ui,
+                                                text::pad_start(&midi_key, key_width),
+                                                text_color,
+                                            );
+                                        }
+                                        if fields.note_name {
+                                            let name = notes::note_name(pair.midi_key);
+                                            cell(ui, text::pad_end(&name, name_width), text_color);
+                                        }
+                                        if fields.keyboard_key {
+                                            let label = keyboard_key_name(pair, settings);
+                                            let mut label = text::pad_end(&label, label_width);
+                                            let mut key_color = key_color;
+                                            if pair
+                                                .keyboard_key
+                                                .as_deref()
+                                                .is_some_and(|key| display.is_modifier_key(key))
+                                            {
+                                                label += " ⚠";
+                                                key_color = ui.visuals().warn_fg_color;
+                                            }
+                                            cell(ui, label, key_color);
+                                        }
+                                        if fields.time {
+                                            let time = timing
+                                                .tick_to_time_ms(pair.tick)
+                                                .map_or("?".to_owned(), format_duration);
+                                            cell(ui, time, text_color);
+                                        }
+                                        if fields.velocity {
+                                            let velocity = velocities.apply(pair.velocity);
+                                            cell(ui, velocity.to_string(), text_color);
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                        });
+                        ui.separator();
+                    }
+                });
+            });
+    }
+
+    /// The cached key lines of every track, rebuilt if the tracks or display settings changed.
+    fn take_track_lines(&mut self, filter: &NoteFilter) -> TrackLinesCache {
+        let settings = &self.settings;
+        let display_key = serde_json::to_string(&(
+            &settings.display,
+            &settings.filter,
+            settings.reference_pitch,
+            self.measure_range,
+        ))
+        .unwrap_or_default();
+        let key = (self.tracks_revision, display_key);
+        match self.track_lines.take() {
+            Some(cache) if cache.key == key => cache,
+            _ => {
+                let timing = &self.song_timing;
+                let lines: Vec<_> = self
+                    .midi_key_tracks
+                    .iter()
+                    .map(|track| {
+                        let lines = track.get_midi_keys_lines(settings, filter);
+                        if settings.display.bar_lines {
+                            with_bar_lines(lines, track, timing)
+                        } else {
+                            lines
+                        }
+                    })
+                    .collect();
+                let widest_line = lines
+                    .iter()
+                    .map(|lines| {
+                        lines
+                            .iter()
+                            .map(|line| {
+                                line.segments
+                                    .iter()
+                                    .map(|(segment, _)| text::display_width(segment))
+                                    .sum::<usize>()
+                            })
+                            .max()
+                            .unwrap_or(0)
+                    })
+                    .collect();
+                let filtered = self
+                    .midi_key_tracks
+                    .iter()
+                    .map(|track| {
+                        track
+                            .midi_key_pairs
+                            .iter()
+                            .filter(|pair| filter.excludes(pair))
+                            .count()
+                    })
+                    .collect();
+                let jumps = lines
+                    .iter()
+                    .map(|lines| {
+                        lines
+                            .iter()
+                            .flat_map(|line| line.segments.iter())
+                            .filter(|(_, kind)| *kind == SegmentKind::Jump)
+                            .count()
+                    })
+                    .collect();
+                let overviews = self
+                    .midi_key_tracks
+                    .iter()
+                    .zip(&lines)
+                    .map(|(track, lines)| minimap::Overview::new(track, lines))
+                    .collect();
+                TrackLinesCache {
+                    key,
+                    lines,
+                    widest_line,
+                    filtered,
+                    jumps,
+                    overviews,
+                }
+            }
+        }
+    }
+
+    /// Shows one column of key lines per track.
+    ///
+    /// Only the columns and lines inside the visible part of the scroll area are laid out, so
+    /// songs with hundreds of tracks or thousands of notes stay responsive.
+    fn tracks_ui(&mut self, ui: &mut egui::Ui) {
+        let filter = self.note_filter();
+        let cache = self.take_track_lines(&filter);
+        let found_note = self.found_note;
+        let scroll_to_found_note = std::mem::take(&mut self.scroll_to_found_note);
+        let following = self.settings.playback.follow && self.playback.is_started();
+        let position = self.playback.position as u64;
+        // The key list follows the selected track, or the next shown one if it is hidden.
+        let followed_track = self
+            .midi_key_tracks
+            .iter()
+            .enumerate()
+            .filter(|(_, track)| !track.hidden)
+            .find(|(i, _)| *i >= self.selected_track)
+            .or_else(|| {
+                self.midi_key_tracks
+                    .iter()
+                    .enumerate()
+                    .find(|(_, track)| !track.hidden)
+            })
+            .map(|(i, _)| i);
+        let mut followed_line = self.followed_line;
+        let (mapped_color, unmapped_color) = self.settings.colors.resolve(ui.visuals());
+        let out_of_scale_color = self.settings.colors.resolve_out_of_scale(ui.visuals());
+        let velocities =
+            velocity::VelocityScale::new(&self.midi_key_tracks, &self.settings.velocity);
+        let mut removed_track = None;
+        let mut transposed = false;
+
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let char_width = ui.fonts(|fonts| fonts.glyph_width(&font_id, '0'));
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let header_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y * 2.0;
+        let layout_rows = &self.settings.layout;
+        let grid_columns = layout_rows.iter().map(Vec::len).max().unwrap_or(0);
+        let grid_cell = ((row_height - 2.0) / layout_rows.len().max(1) as f32)
+            .floor()
+            .max(2.0);
+        let grid_width = if self.settings.display.show_key_grid {
+            grid_columns as f32 * grid_cell + 6.0
+        } else {
+            0.0
+        };
+        // Hidden tracks get no width.
+        let column_lefts: Vec<f32> = std::iter::once(0.0)
+            .chain(cache.widest_line.iter().zip(&self.midi_key_tracks).scan(
+                0.0,
+                |left, (chars, track)| {
+                    if !track.hidden {
+                        *left += (*chars as f32 * char_width + grid_width + 16.0).max(220.0);
+                    }
+                    Some(*left)
+                },
+            ))
+            .collect();
+        let longest_track = cache.lines.iter().map(Vec::len).max().unwrap_or(0);
+        let mut clicked_note = None;
+
+        // The mini-map of the selected track is only worth its space once tracks need scrolling.
+        let available = ui.available_rect_before_wrap();
+        let show_minimap = header_height + longest_track as f32 * row_height > available.height();
+        let mut scroll_area = egui::ScrollArea::new([true, true]).auto_shrink([false, false]);
+        if show_minimap {
+            scroll_area = scroll_area
+                .max_width(available.width() - MINIMAP_WIDTH - ui.spacing().item_spacing.x);
+        }
+        if let Some(offset) = self.minimap_jump.take() {
+            scroll_area = scroll_area.vertical_scroll_offset(offset);
+        }
+        let output = scroll_area.show_viewport(ui, |ui, viewport| {
+            let size = egui::vec2(
+                *column_lefts.last().unwrap_or(&0.0),
+                header_height + longest_track as f32 * row_height,
+            );
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+            // Track and line under the pointer, if it is over a note line.
+            let line_at = |pos: egui::Pos2| {
+                let offset = pos - rect.min;
+                let track = column_lefts.iter().rposition(|left| *left <= offset.x)?;
+                let line = ((offset.y - header_height) / row_height).floor();
+                let line = cache
+                    .lines
+                    .get(track)?
+                    .get(usize::try_from(line as i64).ok()?)?;
+                Some((track, line))
+            };
+            let annotations = &self.annotations;
+            let annotation_of = |track: usize, line: &KeyLine| {
+                annotations.iter().find(|annotation| {
+                    annotation.track == track && line.pair_indices.contains(&annotation.note)
+                })
+            };
+            if let Some((track, line)) = response.hover_pos().and_then(line_at) {
+                if let Some(annotation) = annotation_of(track, line) {
+                    response.clone().on_hover_text(&annotation.text);
+                }
+            }
+            if response.clicked() {
+                if let Some((track, line)) = response.interact_pointer_pos().and_then(line_at) {
+                    clicked_note = annotation_of(track, line).cloned().or_else(|| {
+                        let note = *line.pair_indices.first()?;
+                        Some(config::Annotation {
+                            track,
+                            note,
+                            text: String::new(),
+                        })
+                    });
+                }
+            }
+            let first_line = ((viewport.top() - header_height) / row_height).max(0.0) as usize;
+            let last_line = ((viewport.bottom() - header_height) / row_height).max(0.0) as usize;
+
+            let settings = &self.settings;
+            let mapping = &self.key_to_keyboard_mapping;
+            for (i, track) in self.midi_key_tracks.iter_mut().enumerate() {
+                let (left, right) = (column_lefts[i], column_lefts[i + 1]);
+                if track.hidden || right < viewport.left() || left > viewport.right() {
+                    continue;
+                }
+                let column_rect = egui::Rect::from_min_max(
+                    rect.min + egui::vec2(left, 0.0),
+                    egui::pos2(rect.left() + right, rect.bottom()),
+                );
+                if i == self.selected_track {
+                    ui.painter().rect_filled(
+                        column_rect,
+                        0.0,
+                        ui.visuals().selection.bg_fill.gamma_multiply(0.15),
+                    );
+                }
+                let header_rect = egui::Rect::from_min_size(
+                    column_rect.min,
+                    egui::vec2(column_rect.width(), header_height),
+                );
+                let mut header_ui = ui.child_ui_with_id_source(
+                    header_rect,
+                    egui::Layout::left_to_right(egui::Align::Center),
+                    ("track_header", i),
+                );
+                header_ui.set_clip_rect(header_rect.intersect(ui.clip_rect()));
+                let name = format!("{}:", track.name);
+                if header_ui
+                    .selectable_label(i == self.selected_track, name)
+                    .clicked()
+                {
+                    self.selected_track = i;
+                }
+                let transpose = header_ui.add(
+                    egui::DragValue::new(&mut track.transpose)
+                        .clamp_range(-48..=48)
+                        .prefix("transpose: ")
+                        .suffix(" st"),
+                );
+                if transpose.changed() {
+                    track.remap(mapping, settings.unmapped_notes, settings.mapping.transpose);
+                    transposed = true;
+                }
+                if header_ui
+                    .small_button("👁")
+                    .on_hover_text("Hide this track (show it again under Tracks)")
+                    .clicked()
+                {
+                    track.hidden = true;
+                }
+                instrument_menu(&mut header_ui, track);
+                if track.percussion {
+                    header_ui
+                        .label(RichText::new("🥁 percussion").small().weak())
+                        .on_hover_text(
+                            "Drum hits, shown by drum and left out of mapping and exports",
+                        );
+                } else if let Some(family) = track.instrument().and_then(instrument_family) {
+                    header_ui
+                        .label(RichText::new(family).small().weak())
+                        .on_hover_text("General MIDI instrument family");
+                }
+                note_count_badge(
+                    &mut header_ui,
+                    track.midi_key_pairs.len(),
+                    cache.filtered[i],
+                );
+                if settings.display.highlight_jumps {
+                    header_ui
+                        .label(
+                            RichText::new(format!("↯ {}", cache.jumps[i]))
+                                .small()
+                                .color(header_ui.visuals().warn_fg_color),
+                        )
+                        .on_hover_text(format!(
+                            "{} difficult jumps of {} or more keys",
+                            cache.jumps[i], settings.display.jump_distance
+                        ));
+                }
+                if let Some(reduction) = &track.reduction {
+                    header_ui
+                        .label(RichText::new("reduction").italics().weak())
+                        .on_hover_text(format!("Keeps the {}", reduction));
+                    if header_ui
+                        .small_button("✖")
+                        .on_hover_text("Remove this reduction")
+                        .clicked()
+                    {
+                        removed_track = Some(i);
+                    }
+                }
+
+                let line_rect = |line_index: usize| {
+                    egui::Rect::from_min_size(
+                        header_rect.left_bottom() + egui::vec2(0.0, line_index as f32 * row_height),
+                        egui::vec2(column_rect.width(), row_height),
+                    )
+                };
+                let lines = &cache.lines[i];
+                if let Some((_, pair_index)) =
+                    found_note.filter(|(track_index, _)| *track_index == i && scroll_to_found_note)
+                {
+                    if let Some(line_index) = lines
+                        .iter()
+                        .position(|line| line.pair_indices.contains(&pair_index))
+                    {
+                        ui.scroll_to_rect(line_rect(line_index), Some(egui::Align::Center));
+                    }
+                }
+                let playing = playing_line(lines, track, position).filter(|_| following);
+                if let Some(line_index) = playing.filter(|_| Some(i) == followed_track) {
+                    // Only scroll when the line changes, so the list can still be scrolled by
+                    // hand while paused on a note.
+                    if followed_line != Some((i, line_index)) {
+                        followed_line = Some((i, line_index));
+                        ui.scroll_to_rect(line_rect(line_index), Some(egui::Align::Center));
+                    }
+                }
+                for (line_index, line) in lines
+                    .iter()
+                    .enumerate()
+                    .take(last_line + 1)
+                    .skip(first_line)
+                {
+                    let found = matches!(
+                        found_note,
+                        Some((track_index, pair_index))
+                            if track_index == i && line.pair_indices.contains(&pair_index)
+                    );
+                    let background = if found {
+                        ui.visuals().selection.bg_fill
+                    } else if playing == Some(line_index) {
+                        ui.visuals().selection.bg_fill.gamma_multiply(0.4)
+                    } else {
+                        Color32::TRANSPARENT
+                    };
+                    let mut job = egui::text::LayoutJob::default();
+                    // Note segments come in the order of the line's notes.
+                    let mut notes = line
+                        .pair_indices
+                        .iter()
+                        .map(|&index| &track.midi_key_pairs[index]);
+                    for (text, kind) in &line.segments {
+                        let color = match kind {
+                            SegmentKind::Plain => ui.visuals().text_color(),
+                            SegmentKind::Mapped => mapped_color,
+                            SegmentKind::Unmapped => unmapped_color,
+                            SegmentKind::OutOfScale => out_of_scale_color,
+                            SegmentKind::Jump | SegmentKind::ModifierKey => {
+                                ui.visuals().warn_fg_color
+                            }
+                            SegmentKind::Dimmed => ui.visuals().weak_text_color(),
+                        };
+                        let note = kind.is_note().then(|| notes.next()).flatten();
+                        let color = match note {
+                            Some(pair) if settings.velocity.fade => {
+                                color.gamma_multiply(velocity::fade_opacity(
+                                    velocities.apply(pair.velocity),
+                                    settings.velocity.fade_curve,
+                                ))
+                            }
+                            _ => color,
+                        };
+                        job.append(
+                            text,
+                            0.0,
+                            egui::TextFormat {
+                                font_id: font_id.clone(),
+                                color,
+                                background,
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    if annotation_of(i, line).is_some() {
+                        job.append(
+                            " 🗒",
+                            0.0,
+                            egui::TextFormat {
+                                font_id: font_id.clone(),
+                                color: ui.visuals().warn_fg_color,
+                                ..Default::default()
+                            },
+                        );
+                    }
+                    let line_rect = line_rect(line_index);
+                    if grid_width > 0.0 && !line.pair_indices.is_empty() {
+                        let pressed: Vec<_> = line
+                            .pair_indices
+                            .iter()
+                            .filter_map(|&index| {
+                                track.midi_key_pairs[index].keyboard_key.as_deref()
+                            })
+                            .filter_map(|label| layout::key_position(layout_rows, label))
+                            .collect();
+                        key_grid(
+                            ui.painter(),
+                            line_rect.min + egui::vec2(0.0, 1.0),
+                            grid_cell,
+                            layout_rows,
+                            &pressed,
+                        );
+                    }
+                    let galley = ui.fonts(|fonts| fonts.layout_job(job));
+                    ui.painter()
+                        .galley(line_rect.min + egui::vec2(grid_width, 0.0), galley);
+                }
+            }
+        });
+
+        if let Some(overview) = cache
+            .overviews
+            .get(self.selected_track)
+            .filter(|_| show_minimap)
+        {
+            let view = output.inner_rect;
+            let rect = egui::Rect::from_min_max(
+                egui::pos2(available.right() - MINIMAP_WIDTH, view.top()),
+                egui::pos2(available.right(), view.bottom()),
+            );
+            let first_line = (output.state.offset.y - header_height) / row_height;
+            let visible = first_line..first_line + view.height() / row_height;
+            if let Some(line) = minimap::minimap_ui(ui, rect, overview, longest_track, visible) {
+                let offset = header_height + line * row_height - view.height() / 2.0;
+                self.minimap_jump = Some(offset.max(0.0));
+                ui.ctx().request_repaint();
+            }
+        }
+        self.followed_line = followed_line.filter(|_| following);
+        if clicked_note.is_some() {
+            self.editing_annotation = clicked_note;
+        }
+        if !transposed && removed_track.is_none() {
+            self.track_lines = Some(cache);
+        } else {
+            self.tracks_revision += 1;
+        }
+        if let Some(index) = removed_track {
+            self.midi_key_tracks.remove(index);
+            match &mut self.recording {
+                Some(recorder) if recorder.track == index => self.recording = None,
+                Some(recorder) if recorder.track > index => recorder.track -= 1,
+                _ => {}
+            }
+            if self.selected_track >= index && self.selected_track > 0 {
+                self.selected_track -= 1;
+            }
+            self.found_note = None;
+        }
+    }
+
+    fn transport_ui(&mut self, ui: &mut egui::Ui) {
+        let song_end = self.song_end_tick();
+        let before = self.playback.position;
+        let wrapped_at = self
+            .playback
+            .update(&self.settings.playback, &self.song_timing, song_end);
+        self.sound_playback(before, wrapped_at);
+        if self.playback.playing {
+            ui.ctx().request_repaint();
+        }
+
+        ui.horizontal(|ui| {
+            if self.playback.playing {
+                if ui.button("⏸ Pause").clicked() {
+                    self.playback.pause();
+                }
+            } else if ui.button("▶ Play").clicked() {
+                self.playback.play();
+            }
+            if ui.button("⏹ Stop").clicked() {
+                self.playback.stop();
+            }
+            if ui.button("⏮").on_hover_text("Previous note").clicked() {
+                self.step(false);
+            }
+            if ui.button("⏭").on_hover_text("Next note").clicked() {
+                self.step(true);
+            }
+            ui.monospace(format!(
+                "{} / {}",
+                format_tick_time(&self.song_timing, self.playback.position as u64),
+                format_tick_time(&self.song_timing, song_end)
+            ));
+            if self.settings.playback.show_note_count {
+                let (reached, total) = self.note_count();
+                ui.monospace(format!("note {} / {}", reached, total));
+            }
+            ui.checkbox(&mut self.settings.playback.show_note_count, "Note count");
+        });
+        self.progress_bar_ui(ui, song_end);
+        ui.horizontal(|ui| {
+            ui.label("Tempo:");
+            ui.add(
+                egui::Slider::new(
+                    &mut self.settings.playback.tempo_percent,
+                    settings::TEMPO_PERCENT_RANGE,
+                )
+                .suffix("%"),
+            );
+            ui.checkbox(&mut self.playback.loop_song, "Loop song")
+                .on_hover_text("Start over from the beginning at the end of the song");
+        });
+        ui.horizontal(|ui| {
+            let playback = &mut self.settings.playback;
+            ui.checkbox(&mut playback.follow, "Follow playback")
+                .on_hover_text(
+                    "Light the sounding and upcoming keys on the keyboard panel and scroll the key list along",
+                );
+            ui.add_enabled(
+                playback.follow,
+                egui::DragValue::new(&mut playback.look_ahead_secs)
+                    .speed(0.05)
+                    .clamp_range(0.0..=10.0)
+                    .prefix("look ahead ")
+                    .suffix(" s"),
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.playback.looping, "Loop from");
+            let timing = &self.song_timing;
+            let secs = |tick: u64| timing.tick_to_time_ms(tick).unwrap_or(0.0) / 1000.0;
+            let tick =
+                |secs: f64| timing.time_ms_to_tick(secs * 1000.0).unwrap_or(0.0).round() as u64;
+            let mut loop_start_secs = secs(self.playback.loop_start);
+            let mut loop_end_secs = secs(self.playback.loop_end);
+            if ui
+                .add(
+                    egui::DragValue::new(&mut loop_start_secs)
+                        .speed(0.1)
+                        .clamp_range(0.0..=loop_end_secs)
+                        .suffix(" s"),
+                )
+                .changed()
+            {
+                self.playback.loop_start = tick(loop_start_secs);
+            }
+            ui.label("to");
+            if ui
+                .add(
+                    egui::DragValue::new(&mut loop_end_secs)
+                        .speed(0.1)
+                        .clamp_range(loop_start_secs..=f64::MAX)
+                        .suffix(" s"),
+                )
+                .changed()
+            {
+                self.playback.loop_end = tick(loop_end_secs);
+            }
+        });
+        ui.add_enabled_ui(self.playback.looping, |ui| {
+            ui.horizontal(|ui| {
+                let ramp = &mut self.settings.playback.ramp;
+                ui.checkbox(&mut ramp.enabled, "Speed up each loop by");
+                ui.add(
+                    egui::DragValue::new(&mut ramp.step_percent)
+                        .speed(0.5)
+                        .clamp_range(0.0..=100.0)
+                        .suffix("%"),
+                );
+                ui.label("up to");
+                ui.add(
+                    egui::DragValue::new(&mut ramp.target_percent)
+                        .speed(1.0)
+                        .clamp_range(25.0..=200.0)
+                        .suffix("%"),
+                );
+            });
+            if self.settings.playback.ramp.enabled {
+                ui.label(format!(
+                    "Current loop tempo: {:.0}%",
+                    self.playback.current_tempo_percent(&self.settings.playback)
+                ));
+            }
+        });
+        let now_playing = self.now_playing_keys();
+        if !now_playing.is_empty() {
+            ui.label(RichText::new(format!("Now: {}", now_playing.join(" "))).monospace());
+        }
+        ui.collapsing("Sound", |ui| self.sound_output_ui(ui));
+    }
+
+    /// Sends the notes playback moved past this frame, from `before`, to the sound output if
+    /// one is connected. If playback went back to the start of the loop or song, or stopped at
+    /// the end, the notes up to `wrapped_at` are sent first. Pausing, seeking and looping silence
+    /// the notes still sounding.
+    fn sound_playback(&mut self, before: f64, wrapped_at: Option<f64>) {
+        let Some(output) = &self.sound_output else {
+            return;
+        };
+        output.tune(self.settings.reference_pitch);
+        let tracks = &self.midi_key_tracks;
+        if let Some(end) = wrapped_at.filter(|_| self.sounded_until == Some(before)) {
+            output.send(midi_output::messages_between(
+                tracks,
+                &self.song_timing,
+                before,
+                end,
+                self.settings.mapping.transpose,
+            ));
+        }
+        let position = self.playback.position;
+        if !self.playback.playing {
+            if self.sounded_until.take().is_some() {
+                output.all_notes_off();
+            }
+            return;
+        }
+        let mut from = before;
+        let jumped = self.sounded_until != Some(before) || wrapped_at.is_some();
+        if jumped {
+            if self.sounded_until.is_some() {
+                output.all_notes_off();
+            }
+            if wrapped_at.is_some() {
+                from = position;
+            }
+            // The instruments may have changed since the place playback left.
+            output.send(midi_output::programs_at(tracks, from));
+        }
+        output.send(midi_output::messages_between(
+            tracks,
+            &self.song_timing,
+            from,
+            position,
+            self.settings.mapping.transpose,
+        ));
+        self.sounded_until = Some(position);
+    }
+
+    fn sound_output_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if let Some(output) = &self.sound_output {
+                ui.label(format!("Playing through {}", output.name()));
+                if ui.button("Disconnect").clicked() {
+                    self.sound_output = None;
+                    self.sounded_until = None;
+                }
+                return;
+            }
+            #[cfg(feature = "synth")]
+            if ui
+                .button("Built-in synth")
+                .on_hover_text("Play on the system's audio output")
+                .clicked()
+            {
+                match synth::Synth::start(self.settings.reference_pitch) {
+                    Ok(synth) => {
+                        self.sound_output = Some(Box::new(synth));
+                        return;
+                    }
+                    Err(err) => {
+                        log::error!("could not start the built-in synth: {err}");
+                        self.notice = Some(format!("Could not start the built-in synth: {err}"));
+                    }
+                }
+            }
+            if ui.button("⟳ Find outputs").clicked() {
+                match midi_output::ports() {
+                    Ok(ports) => self.midi_output_ports = ports,
+                    Err(err) => {
+                        log::error!("could not list MIDI outputs: {err}");
+                        self.notice = Some(format!("Could not list MIDI outputs: {err}"));
+                    }
+                }
+                self.midi_output_port = 0;
+            }
+            if self.midi_output_ports.is_empty() {
+                ui.weak("No MIDI outputs found.");
+                return;
+            }
+            egui::ComboBox::from_id_source("midi_output_port")
+                .selected_text(&self.midi_output_ports[self.midi_output_port])
+                .show_ui(ui, |ui| {
+                    for (i, name) in self.midi_output_ports.iter().enumerate() {
+                        ui.selectable_value(&mut self.midi_output_port, i, name);
+                    }
+                });
+            if ui.button("Connect").clicked() {
+                match midi_output::MidiOut::connect(self.midi_output_port) {
+                    Ok(output) => self.sound_output = Some(Box::new(output)),
+                    Err(err) => {
+                        log::error!("could not connect to MIDI output: {err}");
+                        self.notice = Some(format!("Could not connect to MIDI output: {err}"));
+                    }
+                }
+            }
+        });
+        ui.label(
+            RichText::new(
+                "Playback is heard through the built-in synth, or sent to a MIDI output: the system synth (e.g. Microsoft GS Wavetable Synth) or one like FluidSynth.",
+            )
+            .small()
+            .weak(),
+        );
+    }
+
+    /// File name of the loaded song without its extension.
+    fn song_name(&self) -> String {
+        self.picked_midi_path
+            .as_deref()
+            .and_then(|path| std::path::Path::new(path).file_stem())
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("song")
+            .to_owned()
+    }
+
+    /// Save dialog for an export, opened in the export folder and named from the template.
+    /// `suffix` tells apart exports that share an extension.
+    fn export_dialog(&self, filter_name: &str, extension: &str, suffix: &str) -> rfd::FileDialog {
+        let export = &self.settings.export;
+        let mut stem = export::expand_file_name(
+            &export.file_name_template,
+            &self.song_name(),
+            &self.settings.base_key,
+            &export::today(),
+        );
+        if stem.trim().is_empty() {
+            stem = self.song_name();
+        }
+        let dialog = rfd::FileDialog::new()
+            .add_filter(filter_name, &[extension])
+            .set_file_name(format!("{}{}.{}", stem, suffix, extension));
+        match &export.directory {
+            Some(directory) => dialog.set_directory(directory),
+            None => dialog,
+        }
+    }
+
+    fn export_options_ui(&mut self, ui: &mut egui::Ui) {
+        let export = &mut self.settings.export;
+        ui.horizontal(|ui| {
+            ui.label("Folder:");
+            match &export.directory {
+                Some(directory) => ui.monospace(directory.display().to_string()),
+                None => ui.weak("system default"),
+            };
+            if ui.button("Choose…").clicked() {
+                if let Some(directory) = rfd::FileDialog::new().pick_folder() {
+                    export.directory = Some(directory);
+                }
+            }
+            if export.directory.is_some() && ui.small_button("✖").clicked() {
+                export.directory = None;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("File name:");
+            ui.add(
+                egui::TextEdit::singleline(&mut export.file_name_template)
+                    .desired_width(160.0)
+                    .hint_text("{name}"),
+            );
+            ui.weak("{name}, {basekey} and {date} are filled in");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Batch export threads:");
+            ui.add(egui::DragValue::new(&mut export.batch_threads).clamp_range(0..=64))
+                .on_hover_text("Files exported at the same time; 0 uses one per CPU core");
+        });
+        ui.checkbox(&mut export.mark_rests, "Mark rests in tables")
+            .on_hover_text("CSV and Markdown tables get a rest:480t row (ticks of silence) wherever a track falls silent");
+        ui.checkbox(&mut export.group_bars, "Group key sequences by measure")
+            .on_hover_text("Write | A S D | F G H | lines instead of one line per chord");
+        #[cfg(feature = "audio")]
+        ui.checkbox(&mut export.accent_chords, "Accent chords in click tracks");
+    }
+
+    fn export_ahk_script(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self
+            .export_dialog("AutoHotkey script", "ahk", "")
+            .save_file()
+        {
+            let script = export::ahk_script(&song_name, &self.exported_tracks(), &self.song_timing);
+            if let Err(err) = fs::write(path, script) {
+                log::error!("could not export AutoHotkey script: {}", err);
+                self.notice = Some(format!("Could not export AutoHotkey script: {}", err));
+            }
+        }
+    }
+
+    fn export_row_breakdown(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self.export_dialog("Text", "txt", "-rows").save_file() {
+            let text =
+                export::row_breakdown(&song_name, &self.exported_tracks(), &self.settings.layout);
+            if let Err(err) = fs::write(path, text) {
+                log::error!("could not export row breakdown: {}", err);
+                self.notice = Some(format!("Could not export row breakdown: {}", err));
+            }
+        }
+    }
+
+    fn export_key_sequence(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self.export_dialog("Text", "txt", "-keys").save_file() {
+            let bars = Some(&self.song_timing).filter(|_| self.settings.export.group_bars);
+            let text = export::key_sequence(&song_name, &self.exported_tracks(), bars);
+            if let Err(err) = fs::write(path, text) {
+                log::error!("could not export key sequence: {}", err);
+                self.notice = Some(format!("Could not export key sequence: {}", err));
+            }
+        }
+    }
+
+    #[cfg(feature = "pdf")]
+    fn export_key_chart(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self.export_dialog("PDF", "pdf", "-chart").save_file() {
+            let lines = key_chart::chart_lines(&self.exported_tracks(), &self.song_timing);
+            if let Err(err) = key_chart::write_pdf(&path, &song_name, &lines) {
+                log::error!("could not export key chart: {}", err);
+                self.notice = Some(format!("Could not export key chart: {}", err));
+            }
+        }
+    }
+
+    fn export_qmk_keymap(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self
+            .export_dialog("QMK keymap", "c", "")
+            .set_file_name("keymap.c")
+            .save_file()
+        {
+            let keymap = qmk::keymap_c(
+                &song_name,
+                &self.exported_tracks(),
+                &self.song_timing,
+                &self.settings.layout,
+                &self.settings.base_key,
+            );
+            if let Err(err) = fs::write(path, keymap) {
+                log::error!("could not export QMK keymap: {}", err);
+                self.notice = Some(format!("Could not export QMK keymap: {}", err));
+            }
+        }
+    }
+
+    fn export_keyboard_legend(&mut self) {
+        if let Some(path) = self.export_dialog("Text", "txt", "-legend").save_file() {
+            let legend =
+                export::keyboard_legend(&self.settings.layout, &self.key_to_keyboard_mapping);
+            if let Err(err) = fs::write(path, legend) {
+                log::error!("could not export keyboard legend: {}", err);
+                self.notice = Some(format!("Could not export keyboard legend: {}", err));
+            }
+        }
+    }
+
+    fn export_csv(&mut self) {
+        if let Some(path) = self.export_dialog("CSV", "csv", "").save_file() {
+            let csv = export::csv(&self.exported_tracks(), self.settings.export.mark_rests);
+            if let Err(err) = fs::write(path, csv) {
+                log::error!("could not export CSV: {}", err);
+                self.notice = Some(format!("Could not export CSV: {}", err));
+            }
+        }
+    }
+
+    fn copy_markdown_table(&mut self, ctx: &egui::Context) {
+        let tracks = self.exported_tracks();
+        let table = export::markdown_table(&tracks, self.settings.export.mark_rests);
+        ctx.output_mut(|output| output.copied_text = table);
+        let notes: usize = tracks.iter().map(|track| track.midi_key_pairs.len()).sum();
+        self.notice = Some(format!("Copied {} notes as a Markdown table.", notes));
+    }
+
+    #[cfg(feature = "audio")]
+    fn export_click_track(&mut self) {
+        if let Some(path) = self.export_dialog("WAV", "wav", "-clicks").save_file() {
+            let clicks = click_track::clicks(
+                &self.exported_tracks(),
+                &self.song_timing,
+                &self.note_filter(),
+            );
+            if let Err(err) =
+                click_track::write_wav(&path, &clicks, self.settings.export.accent_chords)
+            {
+                log::error!("could not export click track: {}", err);
+                self.notice = Some(format!("Could not export click track: {}", err));
+            }
+        }
+    }
+
+    /// Saves the exported tracks (and measures, if a range is picked) as a new MIDI file.
+    fn export_midi(&mut self) {
+        if let Some(path) = self.export_dialog("MIDI", "mid", "-extract").save_file() {
+            let start = self.measure_ticks().map_or(0, |ticks| ticks.start);
+            let result = export::midi_file(&self.exported_tracks(), &self.song_timing, start)
+                .and_then(|bytes| fs::write(path, bytes));
+            if let Err(err) = result {
+                log::error!("could not export MIDI file: {}", err);
+                self.notice = Some(format!("Could not export MIDI file: {}", err));
+            }
+        }
+    }
+
+    fn export_chord_chart(&mut self) {
+        let song_name = self.song_name();
+        if let Some(path) = self.export_dialog("Text", "txt", "-chords").save_file() {
+            let text = export::chord_chart(&song_name, &self.exported_tracks(), &self.song_timing);
+            if let Err(err) = fs::write(path, text) {
+                log::error!("could not export chord chart: {}", err);
+                self.notice = Some(format!("Could not export chord chart: {}", err));
+            }
+        }
+    }
+
+    fn export_translation(&mut self) {
+        if let Some(path) = self
+            .export_dialog("Planck Scribe translation", "json", "")
+            .save_file()
+        {
+            // Annotations refer to tracks by index, so renumber them past the skipped tracks.
+            let mut exported_index = Vec::new();
+            let mut tracks = Vec::new();
+            for track in &self.midi_key_tracks {
+                exported_index.push(track.is_exported().then_some(tracks.len()));
+                if track.is_exported() {
+                    tracks.push(track.clone());
+                }
+            }
+            let annotations = self
+                .annotations
+                .iter()
+                .filter_map(|annotation| {
+                    let track = (*exported_index.get(annotation.track)?)?;
+                    Some(config::Annotation {
+                        track,
+                        ..annotation.clone()
+                    })
+                })
+                .collect();
+            let translation = translation::Translation {
+                version: translation::TRANSLATION_VERSION,
+                source: self.picked_midi_path.clone(),
+                base_key: self.settings.base_key.clone(),
+                layout: self.settings.layout.clone(),
+                mapping: self.settings.mapping.clone(),
+                tracks,
+                annotations,
+            };
+            if let Err(err) = translation.export(&path) {
+                log::error!("could not export translation: {}", err);
+                self.notice = Some(format!("Could not export translation: {}", err));
+            }
+        }
+    }
+
+    fn import_translation(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Planck Scribe translation", &["json"])
+            .pick_file()
+        {
+            match translation::Translation::import(&path) {
+                Ok(translation) => {
+                    self.remember_current_song();
+                    self.picked_midi_path = translation.source;
+                    self.settings.base_key = translation.base_key;
+                    self.settings.layout = translation.layout;
+                    self.settings.mapping = translation.mapping;
+                    self.midi_key_tracks = translation.tracks;
+                    self.selected_track = 0;
+                    self.found_note = None;
+                    self.load_annotations();
+                    if !translation.annotations.is_empty() {
+                        self.annotations = translation.annotations;
+                        if let Some(path) = self.picked_midi_path.clone() {
+                            self.file_configs.entry(&path).annotations = self.annotations.clone();
+                            self.save_file_configs();
+                        }
+                    }
+                    self.remap_tracks();
+                    self.playback.stop();
+                    self.playback.loop_start = 0;
+                    self.playback.loop_end = self.song_end_tick();
+                }
+                Err(err) => {
+                    log::error!("could not import translation: {}", err);
+                    self.notice = Some(format!("Could not import translation: {}", err))
+                }
+            }
+        }
+    }
+}
+
+impl eframe::App for MyApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.save_preferences();
+        self.save_session();
+        // Stops the MIDI input and the sound output before the process exits.
+        self.live_input = None;
+        self.sound_output = None;
+    }
+
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        self.window_size = Some(frame.info().window_info.size);
+        self.poll_live_input();
+        if self.show_console {
+            egui::TopBottomPanel::bottom("console")
+                .resizable(true)
+                .default_height(160.0)
+                .show(ctx, console::console_ui);
+        }
+        if self.settings.keyboard_panel.show {
+            let panel = egui::TopBottomPanel::bottom("keyboard")
+                .resizable(true)
+                .default_height(self.settings.keyboard_panel.height)
+                .height_range(keyboard_panel_heights(ctx.screen_rect().height()))
+                .show(ctx, |ui| self.keyboard_panel_ui(ui));
+            self.settings.keyboard_panel.height = panel.response.rect.height();
+        }
+        if self.open_songs.len() > 1 {
+            egui::SidePanel::left("open_songs")
+                .resizable(true)
+                .default_width(180.0)
+                .show(ctx, |ui| self.open_songs_ui(ui));
+        }
+        egui::CentralPanel::default()
+            .frame(egui::Frame::default().inner_margin(16.0))
+            .show(ctx, |ui| {
+                ui.style_mut().spacing.button_padding = egui::vec2(16.0, 8.0);
+                ui.vertical_centered(|ui| {
+                    ui.label(RichText::new("Planck Scribe 🎹").heading().size(32.0));
+                    ui.add_space(16.0);
+                    ui.label("Drag-and-drop MIDI or MusicXML files (or a layout .json) onto the window!");
+
+                    if ui.button("Open MIDI file…").clicked() {
+                        let mut dialog =
+                            rfd::FileDialog::new().add_filter("MIDI and MusicXML", &SONG_EXTENSIONS);
+                        if let Some(directory) = &self.last_directory {
+                            dialog = dialog.set_directory(directory);
+                        }
+                        if let Some(path) = dialog.pick_file() {
+                            self.open_midi_file(path.display().to_string());
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        ui.menu_button("🕘 Recent", |ui| self.recent_files_menu(ui));
+                        if self.previous_song.is_some()
+                            && ui
+                                .button("⬅ Back")
+                                .on_hover_text("Reopen the previous file (Alt+Left)")
+                                .clicked()
+                        {
+                            self.go_back();
+                        }
+                        self.undo_redo_ui(ui);
+                        if ui.button("Import translation…").clicked() {
+                            self.import_translation();
+                        }
+                        ui.toggle_value(&mut self.show_console, "🗒 Log")
+                            .on_hover_text("Show recent log messages");
+                        self.layout_menu(ui);
+                        self.base_key_menu(ui);
+                        if ui.button("Import layout…").clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Keyboard layout", &["json"])
+                                .pick_file()
+                            {
+                                self.import_layout(&path);
+                            }
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        if ui.button("Export settings…").clicked() {
+                            self.export_settings();
+                        }
+                        if ui.button("Import settings…").clicked() {
+                            self.import_settings();
+                        }
+                        ui.checkbox(&mut self.settings.save_session, "Remember session")
+                            .on_hover_text("Offer to reopen the current file with these settings on the next start");
+                    });
+                    ui.collapsing("Layout presets", |ui| self.presets_ui(ui));
+
+                    if let Some(notice) = self.notice.clone() {
+                        ui.add_space(8.0);
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label(RichText::new(notice).color(Color32::YELLOW));
+                            if self.auto_transposed.is_some() && ui.small_button("Undo").clicked() {
+                                self.undo_auto_transpose();
+                            }
+                            if ui.small_button("✖").clicked() {
+                                self.notice = None;
+                                self.auto_transposed = None;
+                            }
+                        });
+                    }
+
+                    if let Some(picked_midi_path) = &self.picked_midi_path {
+                        ui.add_space(16.0);
+                        ui.horizontal_wrapped(|ui| {
+                            ui.label("Picked file:");
+                            ui.monospace(picked_midi_path);
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Duration:");
+                            match self.song_duration_ms() {
+                                Some(ms) => {
+                                    let seconds = (ms / 1000.0).round() as u64;
+                                    ui.monospace(format!("{:02}:{:02}", seconds / 60, seconds % 60))
+                                }
+                                None => ui.monospace("unknown"),
+                            };
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Tempo:");
+                            let timing = &self.song_timing;
+                            let mut tempo = format!("{:.0} BPM", timing.bpm());
+                            if timing.has_default_tempo() {
+                                tempo += " (default)";
+                            } else if timing.tempo_changes.len() > 1 {
+                                tempo += &format!(" ({} changes)", timing.tempo_changes.len() - 1);
+                            }
+                            ui.monospace(tempo);
+                        });
+                        let sysex_events: usize = self
+                            .midi_key_tracks
+                            .iter()
+                            .map(|track| track.sysex_events)
+                            .sum();
+                        if sysex_events > 0 {
+                            ui.horizontal(|ui| {
+                                ui.label("SysEx events:");
+                                ui.monospace(sysex_events.to_string());
+                            })
+                            .response
+                            .on_hover_text("Device-specific messages; they don't affect the notes");
+                        }
+                    }
+
+                    if !self.midi_key_tracks.is_empty() {
+                        ui.add_space(16.0);
+                        ui.horizontal(|ui| {
+                            if ui.button("Export translation…").clicked() {
+                                self.export_translation();
+                            }
+                            if ui
+                                .button("Export key sequence…")
+                                .on_hover_text("The notes and keys of each track as plain text")
+                                .clicked()
+                            {
+                                self.export_key_sequence();
+                            }
+                            #[cfg(feature = "pdf")]
+                            if ui
+                                .button("Export key chart (PDF)…")
+                                .on_hover_text("A printable chart of the keys, measure by measure")
+                                .clicked()
+                            {
+                                self.export_key_chart();
+                            }
+                            if ui.button("Export row breakdown…").clicked() {
+                                self.export_row_breakdown();
+                            }
+                            if ui
+                                .button("Export keyboard legend…")
+                                .on_hover_text("The layout with the notes mapped to each key")
+                                .clicked()
+                            {
+                                self.export_keyboard_legend();
+                            }
+                            if ui.button("Export CSV…").clicked() {
+                                self.export_csv();
+                            }
+                            if ui.button("Copy as Markdown table").clicked() {
+                                self.copy_markdown_table(ui.ctx());
+                            }
+                            if ui
+                                .button("Export MIDI…")
+                                .on_hover_text("The tracks and measures picked for export as a new MIDI file")
+                                .clicked()
+                            {
+                                self.export_midi();
+                            }
+                            if ui
+                                .button("Export chord chart…")
+                                .on_hover_text("Best-effort chord names per measure")
+                                .clicked()
+                            {
+                                self.export_chord_chart();
+                            }
+                            #[cfg(feature = "audio")]
+                            if ui
+                                .button("Export click track…")
+                                .on_hover_text("A WAV file with a click on every note onset")
+                                .clicked()
+                            {
+                                self.export_click_track();
+                            }
+                            if ui.button("Export AHK script…").clicked() {
+                                self.export_ahk_script();
+                            }
+                            if ui
+                                .button("Export QMK keymap…")
+                                .on_hover_text("A keymap.c with the layout and a layer that types the song, to flash onto the keyboard")
+                                .clicked()
+                            {
+                                self.export_qmk_keymap();
+                            }
+                        });
+                        ui.label(
+                            RichText::new(
+                                "AHK export is a novelty: it sends real keystrokes to the focused window.",
+                            )
+                            .small()
+                            .weak(),
+                        );
+                        ui.collapsing("Tracks to export", |ui| self.export_tracks_ui(ui));
+                        ui.collapsing("Export options", |ui| self.export_options_ui(ui));
+                        ui.add_space(16.0);
+                        self.transport_ui(ui);
+                        ui.add_space(16.0);
+                        ui.collapsing("Display", |ui| {
+                            let display = &mut self.settings.display;
+                            ui.horizontal(|ui| {
+                                ui.label("Layout:");
+                                ui.selectable_value(&mut display.layout, TrackLayout::List, "List");
+                                ui.selectable_value(
+                                    &mut display.layout,
+                                    TrackLayout::Timeline,
+                                    "Timeline",
+                                );
+                                ui.selectable_value(&mut display.layout, TrackLayout::Table, "Table");
+                                ui.selectable_value(&mut display.layout, TrackLayout::Beats, "Beats")
+                                    .on_hover_text("One column per beat, from the tempo and time signature");
+                                ui.selectable_value(
+                                    &mut display.layout,
+                                    TrackLayout::PianoRoll,
+                                    "Piano roll",
+                                )
+                                .on_hover_text("Pitch upwards, time to the right; Ctrl+scroll zooms");
+                                ui.separator();
+                                ui.label("Keys as:");
+                                ui.selectable_value(
+                                    &mut display.key_names,
+                                    KeyNames::Labels,
+                                    "Labels",
+                                );
+                                ui.selectable_value(
+                                    &mut display.key_names,
+                                    KeyNames::Coordinates,
+                                    "(row,col)",
+                                );
+                                if matches!(
+                                    display.layout,
+                                    TrackLayout::Timeline | TrackLayout::PianoRoll
+                                ) {
+                                    ui.add(
+                                        egui::Slider::new(&mut display.timeline_zoom, 5.0..=400.0)
+                                            .logarithmic(true)
+                                            .text("px per beat"),
+                                    );
+                                }
+                                if display.layout == TrackLayout::PianoRoll {
+                                    ui.add(
+                                        egui::Slider::new(
+                                            &mut display.piano_roll_row_height,
+                                            4.0..=32.0,
+                                        )
+                                        .text("px per semitone"),
+                                    );
+                                }
+                            });
+                            if display.layout == TrackLayout::Table {
+                                let fields = &mut display.table_fields;
+                                ui.horizontal(|ui| {
+                                    ui.label("Table columns:");
+                                    ui.checkbox(&mut fields.midi_key, "MIDI key");
+                                    ui.checkbox(&mut fields.note_name, "Note");
+                                    ui.checkbox(&mut fields.keyboard_key, "Key");
+                                    ui.checkbox(&mut fields.time, "Time");
+                                    ui.checkbox(&mut fields.velocity, "Velocity");
+                                    ui.checkbox(&mut fields.align, "Align columns")
+                                        .on_hover_text("Pad columns to the same width in every track");
+                                });
+                            }
+                            ui.checkbox(&mut self.settings.keyboard_panel.show, "Show keyboard panel")
+                                .on_hover_text("The whole keyboard below the tracks; drag its edge to resize it");
+                            ui.checkbox(&mut display.show_key_grid, "Show key position grid")
+                                .on_hover_text("A small picture of the keyboard next to each line");
+                            ui.checkbox(&mut display.show_ticks, "Show ticks");
+                            ui.indent("ticks_options", |ui| {
+                                ui.checkbox(&mut display.show_delta, "Show raw delta time");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut display.show_frequency, "Show frequency with A4 =");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.settings.reference_pitch)
+                                        .speed(0.1)
+                                        .clamp_range(settings::REFERENCE_PITCH_RANGE)
+                                        .suffix(" Hz"),
+                                );
+                            });
+                            ui.checkbox(&mut display.show_cents, "Show pitch bend in cents")
+                                .on_hover_text("Approximate deviation from equal temperament of bent notes, assuming a bend range of ±2 semitones");
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut display.highlight_scale, "Highlight notes outside")
+                                    .on_hover_text("Color accidentals: mapped notes that are not in the scale");
+                                let scale = &mut display.scale;
+                                egui::ComboBox::from_id_source("scale_tonic")
+                                    .selected_text(notes::pitch_class_name(scale.tonic))
+                                    .width(48.0)
+                                    .show_ui(ui, |ui| {
+                                        for pitch_class in 0..12 {
+                                            ui.selectable_value(
+                                                &mut scale.tonic,
+                                                pitch_class,
+                                                notes::pitch_class_name(pitch_class),
+                                            );
+                                        }
+                                    });
+                                ui.selectable_value(&mut scale.mode, notes::ScaleMode::Major, "major");
+                                ui.selectable_value(&mut scale.mode, notes::ScaleMode::Minor, "minor");
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Warn about notes on:");
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut display.modifier_keys)
+                                        .desired_width(160.0)
+                                        .hint_text("SHF CTRL ALT"),
+                                )
+                                .on_hover_text("Modifier key labels, separated by spaces. Notes mapped to them are marked ⚠, as they are likely mapping mistakes.");
+                            });
+                            ui.checkbox(&mut display.bracket_chords, "Show chord keys together")
+                                .on_hover_text("Follow each chord with all of its keys, like [A S D]");
+                            ui.checkbox(&mut display.show_chord_names, "Show chord names")
+                                .on_hover_text("Best-guess name after each chord; ? marks a doubtful guess");
+                            ui.checkbox(&mut display.bar_lines, "Separate measures")
+                                .on_hover_text("Start each measure of the list with its number, following the time signatures");
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut display.highlight_jumps, "Mark jumps of at least")
+                                    .on_hover_text("Notes whose key is far from the keys of the previous notes, counted in rows plus columns");
+                                ui.add(
+                                    egui::DragValue::new(&mut display.jump_distance)
+                                        .clamp_range(1..=30)
+                                        .suffix(" keys"),
+                                );
+                            });
+                            let filter = &mut self.settings.filter;
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut filter.hide_grace_notes, "Filter out notes shorter than");
+                                egui::ComboBox::from_id_source("grace_note_value")
+                                    .selected_text(format!("1/{} note", filter.grace_note_value))
+                                    .show_ui(ui, |ui| {
+                                        for value in [16, 32, 64] {
+                                            ui.selectable_value(
+                                                &mut filter.grace_note_value,
+                                                value,
+                                                format!("1/{} note", value),
+                                            );
+                                        }
+                                    });
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Filtered notes:");
+                                ui.selectable_value(
+                                    &mut filter.filtered_notes,
+                                    FilteredNotes::Hide,
+                                    "Hide",
+                                );
+                                ui.selectable_value(
+                                    &mut filter.filtered_notes,
+                                    FilteredNotes::Dim,
+                                    "Dim",
+                                );
+                            });
+                            let (mapped_color, unmapped_color) =
+                                self.settings.colors.resolve(ui.visuals());
+                            let out_of_scale_color =
+                                self.settings.colors.resolve_out_of_scale(ui.visuals());
+                            let colors = &mut self.settings.colors;
+                            ui.horizontal(|ui| {
+                                color_setting_ui(ui, "Mapped notes", &mut colors.mapped, mapped_color);
+                                color_setting_ui(
+                                    ui,
+                                    "Unmapped notes",
+                                    &mut colors.unmapped,
+                                    unmapped_color,
+                                );
+                                color_setting_ui(
+                                    ui,
+                                    "Out of scale",
+                                    &mut colors.out_of_scale,
+                                    out_of_scale_color,
+                                );
+                            });
+                            let velocity = &mut self.settings.velocity;
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut velocity.normalize, "Normalize velocities to")
+                                    .on_hover_text(
+                                        "Evens out uneven recordings in the timeline shading and velocity column. The notes are not changed.",
+                                    );
+                                ui.add(egui::DragValue::new(&mut velocity.min).clamp_range(1..=127));
+                                ui.label("–");
+                                ui.add(egui::DragValue::new(&mut velocity.max).clamp_range(1..=127));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.checkbox(&mut velocity.fade, "Fade quieter notes")
+                                    .on_hover_text("Softer notes are drawn fainter in the list, louder ones stay bold. Nothing is hidden.");
+                                ui.selectable_value(&mut velocity.fade_curve, settings::FadeCurve::Linear, "Linear");
+                                ui.selectable_value(
+                                    &mut velocity.fade_curve,
+                                    settings::FadeCurve::Exponential,
+                                    "Exponential",
+                                )
+                                .on_hover_text("Only the loudest notes stay bold");
+                            });
+                            let mapping = self.settings.mapping.clone();
+                            let edited = &mut self.settings.mapping;
+                            ui.horizontal(|ui| {
+                                ui.label("Mapping:");
+                                ui.selectable_value(
+                                    &mut edited.kind,
+                                    MappingKind::Chromatic,
+                                    "Chromatic",
+                                );
+                                ui.selectable_value(
+                                    &mut edited.kind,
+                                    MappingKind::Isomorphic,
+                                    "Isomorphic",
+                                )
+                                .on_hover_text("Pick the intervals between columns and rows");
+                                ui.selectable_value(
+                                    &mut edited.kind,
+                                    MappingKind::WickiHayden,
+                                    "Wicki–Hayden",
+                                )
+                                .on_hover_text("Whole tones to the right, a fifth up, a fourth up and left");
+                                ui.selectable_value(
+                                    &mut edited.kind,
+                                    MappingKind::HarmonicTable,
+                                    "Harmonic Table",
+                                )
+                                .on_hover_text("Semitones to the right, a major third up, a minor third up and left");
+                                ui.selectable_value(&mut edited.kind, MappingKind::Janko, "Jankó")
+                                    .on_hover_text("Whole tones to the right, each row a semitone above the one below");
+                                if edited.kind == MappingKind::Isomorphic {
+                                    ui.add(
+                                        egui::DragValue::new(&mut edited.column_interval)
+                                            .clamp_range(-12..=12)
+                                            .prefix("column: ")
+                                            .suffix(" st"),
+                                    );
+                                    ui.add(
+                                        egui::DragValue::new(&mut edited.row_interval)
+                                            .clamp_range(-24..=24)
+                                            .prefix("row: ")
+                                            .suffix(" st"),
+                                    );
+                                }
+                            });
+                            if self.settings.mapping != mapping {
+                                self.remap_tracks();
+                            }
+                            let unmapped_notes = self.settings.unmapped_notes;
+                            ui.horizontal(|ui| {
+                                ui.label("Notes out of range:");
+                                let unmapped = &mut self.settings.unmapped_notes;
+                                ui.selectable_value(unmapped, UnmappedNotes::Mark, "Mark")
+                                    .on_hover_text("Keep them, shown as NONE");
+                                ui.selectable_value(unmapped, UnmappedNotes::Nearest, "Nearest key")
+                                    .on_hover_text("Lossy: play them on the nearest mapped note (~)");
+                                ui.selectable_value(unmapped, UnmappedNotes::FoldOctaves, "Fold octaves")
+                                    .on_hover_text("Lossy: play them an octave or more up or down, where mapped (~)");
+                                ui.selectable_value(unmapped, UnmappedNotes::Drop, "Drop")
+                                    .on_hover_text("Leave them out of the tracks and exports");
+                            });
+                            if self.settings.unmapped_notes != unmapped_notes {
+                                self.remap_tracks();
+                            }
+                            let duplicate_notes = self.settings.duplicate_notes;
+                            ui.horizontal(|ui| {
+                                ui.label("Same-tick duplicate notes:");
+                                ui.selectable_value(
+                                    &mut self.settings.duplicate_notes,
+                                    DuplicateNotes::KeepAll,
+                                    "Keep all",
+                                );
+                                ui.selectable_value(
+                                    &mut self.settings.duplicate_notes,
+                                    DuplicateNotes::KeepFirst,
+                                    "Keep first",
+                                );
+                            });
+                            let split_by_channel = self.settings.split_by_channel;
+                            ui.checkbox(
+                                &mut self.settings.split_by_channel,
+                                "Split tracks by channel",
+                            )
+                            .on_hover_text("Show one track per MIDI channel, e.g. for format 0 files");
+                            if self.settings.duplicate_notes != duplicate_notes
+                                || self.settings.split_by_channel != split_by_channel
+                            {
+                                if let Some(path) = self.picked_midi_path.clone() {
+                                    let _ = self.load_midi_file(path);
+                                }
+                            }
+                        });
+                        self.find_note_ui(ui);
+                        self.measure_range_ui(ui);
+                        self.reduce_track_ui(ui);
+                        self.fit_ui(ui);
+                        self.transpose_ui(ui);
+                        if self.settings.split_by_channel && !self.midi_key_tracks.is_empty() {
+                            ui.collapsing("Channel order", |ui| self.channel_order_ui(ui));
+                        }
+                        ui.collapsing("Practice", |ui| self.practice_ui(ui));
+                        ui.collapsing("Live MIDI input", |ui| self.live_input_ui(ui));
+                        let shown = self
+                            .midi_key_tracks
+                            .iter()
+                            .filter(|track| !track.hidden)
+                            .count();
+                        let title = if shown < self.midi_key_tracks.len() {
+                            format!("Tracks ({} of {} shown)", shown, self.midi_key_tracks.len())
+                        } else {
+                            format!("Tracks ({})", self.midi_key_tracks.len())
+                        };
+                        egui::CollapsingHeader::new(title)
+                            .id_source("shown_tracks")
+                            .show(ui, |ui| self.shown_tracks_ui(ui));
+                        match self.settings.display.layout {
+                            TrackLayout::List => self.tracks_ui(ui),
+                            TrackLayout::Table => self.table_ui(ui),
+                            TrackLayout::Beats => beat_grid::beat_grid_ui(
+                                ui,
+                                &self.midi_key_tracks,
+                                &self.note_filter(),
+                                &self.song_timing,
+                                self.playback.position,
+                            ),
+                            TrackLayout::Timeline => timeline::timeline_ui(
+                                ui,
+                                &self.midi_key_tracks,
+                                &self.note_filter(),
+                                &self.song_timing,
+                                &velocity::VelocityScale::new(
+                                    &self.midi_key_tracks,
+                                    &self.settings.velocity,
+                                ),
+                                self.settings.display.timeline_zoom,
+                                self.playback.position,
+                            ),
+                            TrackLayout::PianoRoll => {
+                                let filter = self.note_filter();
+                                let velocities = velocity::VelocityScale::new(
+                                    &self.midi_key_tracks,
+                                    &self.settings.velocity,
+                                );
+                                let song_transpose = self.settings.mapping.transpose;
+                                let display = &mut self.settings.display;
+                                piano_roll::piano_roll_ui(
+                                    ui,
+                                    &self.midi_key_tracks,
+                                    &filter,
+                                    &self.song_timing,
+                                    &velocities,
+                                    &mut display.timeline_zoom,
+                                    &mut display.piano_roll_row_height,
+                                    self.playback.position,
+                                    song_transpose,
+                                )
+                            }
+                        }
+                    }
+                });
+            });
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::ALT, egui::Key::ArrowLeft)) {
+            self.go_back();
+        }
+        // Text boxes have their own undo.
+        if !ctx.wants_keyboard_input() {
+            let redo_shift = egui::Modifiers::COMMAND | egui::Modifiers::SHIFT;
+            if ctx.input_mut(|i| {
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y)
+                    || i.consume_key(redo_shift, egui::Key::Z)
+            }) {
+                self.redo();
+            } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z)) {
+                self.undo();
+            }
+        }
+        self.record_edits();
+        self.switch_track_keys(ctx);
+
+        self.saved_session_window(ctx);
+        self.annotation_window(ctx);
+        self.batch_export_window(ctx);
+        self.preview_hovering_files(ctx);
+        self.collect_dropped_files(ctx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chromatic_planck_mapping, default_planck_rows,
+        settings::ReductionSettings,
+        tests::{note_off, note_on},
+        MidiKeyPair, PERCUSSION_CHANNEL,
+    };
+    use midly::{Format, Header, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+    /// A single track where C4 is struck twice on tick 0, alongside E4.
+    fn same_tick_duplicates() -> Vec<u8> {
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![vec![
+                note_on(0, 60),
+                note_on(0, 60),
+                note_on(0, 64),
+                note_off(480, 60),
+                note_off(0, 60),
+                note_off(0, 64),
+                note_on(0, 60),
+                note_off(480, 60),
+            ]],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn set_tempo(delta: u32, tempo: u32) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo.into())),
+        }
+    }
+
+    fn loaded_keys(app: &MyApp) -> Vec<(u64, u8)> {
+        app.midi_key_tracks[0]
+            .midi_key_pairs
+            .iter()
+            .map(|pair| (pair.tick, pair.midi_key))
+            .collect()
+    }
+
+    #[test]
+    fn same_tick_duplicates_are_kept_by_default() {
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&same_tick_duplicates()).unwrap();
+        assert_eq!(
+            loaded_keys(&app),
+            vec![(0, 60), (0, 60), (0, 64), (480, 60)]
+        );
+    }
+
+    #[test]
+    fn same_tick_duplicates_can_keep_first_only() {
+        let mut app = MyApp::default();
+        app.settings.duplicate_notes = DuplicateNotes::KeepFirst;
+        app.load_midi_bytes(&same_tick_duplicates()).unwrap();
+        assert_eq!(loaded_keys(&app), vec![(0, 60), (0, 64), (480, 60)]);
+        assert_eq!(app.midi_key_tracks[0].midi_key_pairs[0].duration, Some(480));
+    }
+
+    #[test]
+    fn auto_transpose_shifts_low_songs_into_range_and_undoes() {
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![vec![note_on(0, 24), note_off(480, 24)]],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let mut app = MyApp::default();
+        app.settings.auto_transpose = true;
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.midi_key_tracks[0].transpose, 24);
+        assert!(app.midi_key_tracks[0].midi_key_pairs[0]
+            .keyboard_key
+            .is_some());
+        app.undo_auto_transpose();
+        assert_eq!(app.midi_key_tracks[0].transpose, 0);
+        assert!(app.midi_key_tracks[0].midi_key_pairs[0]
+            .keyboard_key
+            .is_none());
+
+        // The shift is also an undo step, and loading again starts from the file's transposes.
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.history.undo_label(), Some("transposition"));
+        app.undo();
+        assert_eq!(app.midi_key_tracks[0].transpose, 0);
+        app.redo();
+        assert_eq!(app.midi_key_tracks[0].transpose, 24);
+    }
+
+    #[test]
+    fn note_count_follows_steps_and_resets_on_stop() {
+        let track = [60, 62, 64]
+            .into_iter()
+            .flat_map(|key| [note_on(0, key), note_off(480, key)])
+            .collect();
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![track],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.note_count(), (0, 3));
+
+        app.step(true);
+        assert_eq!(app.note_count(), (1, 3));
+        app.step(true);
+        app.step(true);
+        assert_eq!(app.note_count(), (3, 3));
+        app.step(true);
+        assert_eq!(app.note_count(), (3, 3));
+        app.step(false);
+        assert_eq!(app.note_count(), (2, 3));
+
+        app.playback.stop();
+        assert_eq!(app.note_count(), (0, 3));
+    }
+
+    #[test]
+    fn table_columns_fit_the_widest_shown_note() {
+        let mut app = MyApp::default();
+        let mut track = MidiKeyTrack::new();
+        for (tick, key, duration, label) in [
+            (0, 9, 480, Some("A")),
+            (480, 61, 480, Some("SHFDOWN")),
+            // A grace note.
+            (960, 100, 10, None),
+        ] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(duration),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: label.map(str::to_owned),
+                substituted: false,
+            });
+        }
+        app.midi_key_tracks.push(track);
+        let mut filter = app.note_filter();
+        assert_eq!(app.table_column_widths(&filter), [3, 3, 7]);
+        // Hidden notes don't widen the columns.
+        filter.min_duration = Some(100);
+        assert_eq!(app.table_column_widths(&filter), [2, 3, 7]);
+    }
+
+    #[test]
+    fn drops_load_the_first_layout_and_every_midi_file() {
+        let paths: Vec<std::path::PathBuf> = ["notes.txt", "a.MID", "b.midi", "planck.json"]
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        let kinds: Vec<_> = paths.iter().map(|path| dropped_file_kind(path)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                DroppedFile::Unsupported,
+                DroppedFile::Song,
+                DroppedFile::Song,
+                DroppedFile::Layout
+            ]
+        );
+        assert_eq!(files_to_load(&paths), (Some(3), vec![1, 2]));
+        assert_eq!(files_to_load(&paths[..1]), (None, vec![]));
+    }
+
+    #[test]
+    fn transposing_all_tracks_stays_in_range() {
+        let mut app = MyApp::default();
+        for transpose in [0, 40] {
+            let mut track = MidiKeyTrack::new();
+            track.transpose = transpose;
+            app.midi_key_tracks.push(track);
+        }
+        let fifth = notes::INTERVALS
+            .iter()
+            .find(|(name, _)| *name == "Perfect fifth")
+            .unwrap()
+            .1;
+        app.transpose_all(fifth);
+        let transposes: Vec<_> = app.midi_key_tracks.iter().map(|t| t.transpose).collect();
+        assert_eq!(transposes, vec![7, 47]);
+        app.transpose_all(12);
+        app.transpose_all(-3);
+        let transposes: Vec<_> = app.midi_key_tracks.iter().map(|t| t.transpose).collect();
+        assert_eq!(transposes, vec![16, 45]);
+    }
+
+    /// Keeps what playback sends, to check it.
+    #[derive(Default, Clone)]
+    struct RecordedSound(std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>);
+
+    impl midi_output::Sound for RecordedSound {
+        fn name(&self) -> String {
+            "recording".to_owned()
+        }
+
+        fn send(&self, messages: Vec<Vec<u8>>) {
+            self.0.borrow_mut().extend(messages);
+        }
+
+        fn all_notes_off(&self) {
+            self.0.borrow_mut().push(vec![0xB0, 123, 0]);
+        }
+    }
+
+    #[test]
+    fn notes_before_a_loop_wrap_are_sounded() {
+        let mut app = MyApp::default();
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![vec![
+                note_on(0, 60),
+                note_off(480, 60),
+                note_on(420, 62),
+                note_off(60, 62),
+            ]],
+        };
+        let mut bytes = Vec::new();
+        smf.write(&mut bytes).unwrap();
+        app.load_midi_bytes(&bytes).unwrap();
+        let recorded = RecordedSound::default();
+        app.sound_output = Some(Box::new(recorded.clone()));
+        app.sounded_until = Some(800.0);
+        // Playback went past the note at 900 and back to the start of the loop in one frame.
+        app.playback.play();
+        app.sound_playback(800.0, Some(960.0));
+        // The note is silenced with the rest as playback starts over.
+        assert_eq!(
+            *recorded.0.borrow(),
+            [vec![0x90, 62, 100], vec![0xB0, 123, 0]]
+        );
+        assert_eq!(app.sounded_until, Some(0.0));
+    }
+
+    #[test]
+    fn transposed_songs_play_the_notes_their_keys_show() {
+        let mut app = MyApp::default();
+        let mut track = MidiKeyTrack::new();
+        for midi_key in [60, 62] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick: 0,
+                duration: Some(480),
+                midi_key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        track.transpose = 12;
+        app.midi_key_tracks.push(track);
+        app.settings.mapping.transpose = -10;
+        app.remap_tracks();
+        // 60 + 12 - 10 is the D above middle C, two keys after ESC.
+        let keys: Vec<_> = app.midi_key_tracks[0]
+            .midi_key_pairs
+            .iter()
+            .map(|pair| pair.keyboard_key.as_deref())
+            .collect();
+        assert_eq!(keys, [Some("S"), Some("F")]);
+        assert_eq!(app.key_to_keyboard_mapping[&62], "S");
+
+        // Playback sounds the notes of the keys shown, not the ones in the file.
+        let messages = midi_output::messages_between(
+            &app.midi_key_tracks,
+            &app.song_timing,
+            0.0,
+            1.0,
+            app.settings.mapping.transpose,
+        );
+        assert_eq!(messages, [vec![0x90, 62, 100], vec![0x90, 64, 100]]);
+        let track = &app.midi_key_tracks[0];
+        let sounding: Vec<_> = track
+            .midi_key_pairs
+            .iter()
+            .map(|pair| track.sounding_key(pair.midi_key, app.settings.mapping.transpose))
+            .collect();
+        assert_eq!(sounding, [Some(62), Some(64)]);
+    }
+
+    #[test]
+    fn skipped_tracks_are_left_out_of_exports() {
+        let mut app = MyApp::default();
+        for (channel, name) in ["Melody", "Bass", "Drums"].into_iter().enumerate() {
+            let mut track = MidiKeyTrack::new();
+            track.name = name.to_owned();
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick: 0,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: channel as u8,
+                keyboard_key: Some("ESC".to_owned()),
+                substituted: false,
+            });
+            app.midi_key_tracks.push(track);
+        }
+        assert!(matches!(
+            app.exported_tracks(),
+            std::borrow::Cow::Borrowed(_)
+        ));
+        app.midi_key_tracks[1].skip_export = true;
+        let names: Vec<_> = app
+            .exported_tracks()
+            .iter()
+            .map(|track| track.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Melody", "Drums"]);
+
+        app.midi_key_tracks[1].skip_export = false;
+        app.midi_key_tracks[2].hidden = true;
+        let names: Vec<_> = app
+            .exported_tracks()
+            .iter()
+            .map(|track| track.name.clone())
+            .collect();
+        assert_eq!(names, vec!["Melody", "Bass"]);
+        let csv = export::csv(&app.exported_tracks(), false);
+        assert_eq!(csv.lines().count(), 3);
+        assert!(!csv.contains("Drums"));
+        // Hidden tracks aren't played either.
+        let messages =
+            midi_output::messages_between(&app.midi_key_tracks, &app.song_timing, 0.0, 960.0, 0);
+        assert!(messages.iter().all(|message| message[0] & 0x0F != 2));
+        assert_eq!(messages.len(), 4);
+    }
+
+    #[test]
+    fn measure_range_hides_other_measures() {
+        // One quarter note per beat in 4/4: measures start every 1920 ticks.
+        let track = (0..12)
+            .flat_map(|i| [note_on(0, 60 + i), note_off(480, 60 + i)])
+            .collect();
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![track],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.measure_count(), 3);
+        assert_eq!(app.measure_range_input, (1, 3));
+
+        app.set_measure_range((4, 4));
+        assert_eq!(app.measure_range, None);
+        assert!(app.notice.is_some());
+
+        app.set_measure_range((2, 2));
+        let filter = app.note_filter();
+        let shown: Vec<_> = app.midi_key_tracks[0]
+            .midi_key_pairs
+            .iter()
+            .filter(|pair| !filter.hides(pair))
+            .map(|pair| pair.midi_key)
+            .collect();
+        assert_eq!(shown, vec![64, 65, 66, 67]);
+        assert_eq!(app.exported_tracks()[0].midi_key_pairs.len(), 4);
+        assert_eq!(
+            (app.playback.loop_start, app.playback.loop_end),
+            (1920, 3840)
+        );
+    }
+
+    #[test]
+    fn duration_follows_tempo_changes() {
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![vec![
+                set_tempo(0, 500_000),
+                note_on(0, 60),
+                note_off(480, 60),
+                // Twice as fast for the second beat.
+                set_tempo(0, 250_000),
+                note_on(0, 62),
+                note_off(480, 62),
+            ]],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+
+        let mut app = MyApp::default();
+        assert_eq!(app.song_duration_ms(), None);
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.song_duration_ms(), Some(750.0));
+    }
+
+    #[test]
+    fn reduction_keeps_highest_notes_of_each_chord() {
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&same_tick_duplicates()).unwrap();
+        let reduced = app.midi_key_tracks[0].reduced(&ReductionSettings {
+            voices: 1,
+            keep: VoiceOrder::Highest,
+        });
+        let keys: Vec<_> = reduced
+            .midi_key_pairs
+            .iter()
+            .map(|pair| (pair.tick, pair.midi_key))
+            .collect();
+        assert_eq!(keys, vec![(0, 64), (480, 60)]);
+        assert!(reduced.reduction.is_some());
+        assert_eq!(app.midi_key_tracks[0].midi_key_pairs.len(), 4);
+    }
+
+    #[test]
+    fn keyboard_panel_leaves_room_for_the_tracks() {
+        assert_eq!(keyboard_panel_heights(800.0), 60.0..=600.0);
+        // In a tiny window the panel keeps its smallest height.
+        assert_eq!(keyboard_panel_heights(100.0), 60.0..=60.0);
+    }
+
+    #[test]
+    fn track_switching_wraps_around() {
+        assert_eq!(cycle_track(0, 3, true), 1);
+        assert_eq!(cycle_track(2, 3, true), 0);
+        assert_eq!(cycle_track(0, 3, false), 2);
+        assert_eq!(cycle_track(0, 1, true), 0);
+        // A selection left over from a song with more tracks is brought back in range.
+        assert_eq!(cycle_track(7, 3, false), 1);
+    }
+
+    #[test]
+    fn restoring_a_session_with_a_missing_file_keeps_its_settings() {
+        let mut app = MyApp::default();
+        let session = config::Session {
+            file: Some("/no/such/song.mid".to_owned()),
+            transposes: vec![12],
+            selected_track: 3,
+            settings: Settings {
+                base_key: "TAB".to_owned(),
+                ..Settings::default()
+            },
+        };
+        app.restore_session(session);
+        assert_eq!(app.settings.base_key, "TAB");
+        assert!(app.midi_key_tracks.is_empty());
+        assert_eq!(app.selected_track, 0);
+        assert!(app.notice.unwrap().contains("moved or deleted"));
+
+        let mut app = MyApp::default();
+        let session = config::Session {
+            settings: Settings {
+                base_key: "NOT A KEY".to_owned(),
+                ..Settings::default()
+            },
+            ..config::Session::default()
+        };
+        app.restore_session(session);
+        assert_eq!(app.settings.base_key, Settings::default().base_key);
+        assert!(app.notice.unwrap().contains("not in the layout"));
+    }
+
+    #[test]
+    fn builtin_layouts_are_mapped_key_by_key() {
+        let mut app = MyApp::default();
+        let preonic = layout::builtin_layouts()
+            .into_iter()
+            .find(|layout| layout.name == "Preonic")
+            .unwrap();
+        app.use_layout(preonic);
+        // ESC is on the third row of the Preonic, so the number row sits two octaves below.
+        assert_eq!(app.key_to_keyboard_mapping[&60], "ESC");
+        assert_eq!(app.key_to_keyboard_mapping[&36], "`");
+
+        let ansi = layout::builtin_layouts()
+            .into_iter()
+            .find(|layout| layout.name == "ANSI")
+            .unwrap();
+        app.use_layout(ansi);
+        // The ANSI layout has no ESC, so middle C moves to its first key.
+        assert_eq!(app.settings.base_key, "`");
+        assert_eq!(app.key_to_keyboard_mapping[&60], "`");
+        assert_eq!(app.key_to_keyboard_mapping[&74], "TAB");
+    }
+
+    #[test]
+    fn preferences_are_applied_on_start() {
+        let mut app = MyApp::default();
+        let mut preferences = config::Preferences::default();
+        preferences.settings.version = 0;
+        preferences.settings.base_key = "TAB".to_owned();
+        preferences.last_directory = Some("songs".into());
+        app.apply_preferences(preferences);
+        assert_eq!(app.settings.version, settings::SETTINGS_VERSION);
+        assert_eq!(app.key_to_keyboard_mapping[&60], "TAB");
+        assert_eq!(app.last_directory, Some("songs".into()));
+
+        let mut app = MyApp::default();
+        let mut preferences = config::Preferences::default();
+        preferences.settings.base_key = "NOT A KEY".to_owned();
+        app.apply_preferences(preferences);
+        assert_eq!(app.settings.base_key, Settings::default().base_key);
+        assert!(app.notice.unwrap().contains("not in the layout"));
+    }
+
+    #[test]
+    fn base_key_choices_skip_blank_and_repeated_keys() {
+        let rows = vec![
+            vec!["ESC".to_owned(), "".to_owned(), "Q".to_owned()],
+            vec!["SPACE".to_owned(), "SPACE".to_owned()],
+        ];
+        assert_eq!(layout_keys(&rows), ["ESC", "Q", "SPACE"]);
+    }
+
+    #[test]
+    fn unmapped_notes_follow_the_chosen_strategy() {
+        // The default layout covers MIDI keys 48 to 95, except 84 on the blank cell.
+        let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+        let mut track = MidiKeyTrack::new();
+        for (tick, key) in [(0, 60), (480, 100), (960, 84)] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 480,
+                tick,
+                duration: Some(480),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        let keys = |track: &MidiKeyTrack| -> Vec<Option<String>> {
+            track
+                .midi_key_pairs
+                .iter()
+                .map(|pair| pair.keyboard_key.clone())
+                .collect()
+        };
+        track.remap(&mapping, UnmappedNotes::Mark, 0);
+        assert_eq!(keys(&track), [Some("ESC".to_owned()), None, None]);
+        track.remap(&mapping, UnmappedNotes::Nearest, 0);
+        assert_eq!(
+            keys(&track),
+            [
+                Some("ESC".to_owned()),
+                Some("->".to_owned()),
+                Some("ETR".to_owned())
+            ]
+        );
+        track.remap(&mapping, UnmappedNotes::FoldOctaves, 0);
+        assert_eq!(
+            keys(&track),
+            [
+                Some("ESC".to_owned()),
+                Some("OS".to_owned()),
+                Some("SHF".to_owned())
+            ]
+        );
+        assert!(track.midi_key_pairs[1].substituted);
+
+        let mut app = MyApp::default();
+        app.settings.unmapped_notes = UnmappedNotes::Drop;
+        app.midi_key_tracks = vec![track];
+        app.remap_tracks();
+        let exported = app.exported_tracks();
+        assert_eq!(exported[0].midi_key_pairs.len(), 1);
+        let lines = app.midi_key_tracks[0].get_midi_keys_lines(&app.settings, &app.note_filter());
+        assert_eq!(lines.len(), 1);
+
+        // Older settings files had a flag for substituting the nearest note.
+        let settings: Settings = serde_json::from_str(r#"{"substitute_unmapped": true}"#).unwrap();
+        assert_eq!(settings.unmapped_notes, UnmappedNotes::Nearest);
+    }
+
+    #[test]
+    fn key_usage_counts_presses_of_shown_notes() {
+        let track = |keys: &[(u8, &str)]| {
+            let mut track = MidiKeyTrack::new();
+            for (tick, &(key, label)) in keys.iter().enumerate() {
+                track.midi_key_pairs.push(MidiKeyPair {
+                    delta: 0,
+                    tick: tick as u64 * 480,
+                    duration: Some(480),
+                    midi_key: key,
+                    velocity: 100,
+                    channel: 0,
+                    keyboard_key: Some(label.to_owned()),
+                    substituted: false,
+                });
+            }
+            track
+        };
+        let mut hidden = track(&[(60, "ESC")]);
+        hidden.hidden = true;
+        let tracks = [
+            track(&[(60, "ESC"), (62, "S"), (60, "ESC"), (72, "ESC")]),
+            hidden,
+        ];
+        let filter = NoteFilter {
+            ticks: Some(0..1440),
+            ..NoteFilter::default()
+        };
+        let usage = key_usage(&tracks, &filter);
+        assert_eq!(usage["ESC"], [60, 60]);
+        assert_eq!(usage["S"], [62]);
+        assert_eq!(usage.len(), 2);
+    }
+
+    #[test]
+    fn following_lights_sounding_and_upcoming_keys() {
+        let mut track = MidiKeyTrack::new();
+        for (tick, label) in [
+            (0, "A"),
+            (0, "S"),
+            (480, "D"),
+            (960, "F"),
+            (960, "D"),
+            (1440, "G"),
+        ] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: Some(label.to_owned()),
+                substituted: false,
+            });
+        }
+        let filter = NoteFilter::default();
+        let highlights = key_highlights(std::slice::from_ref(&track), &filter, 240.0, 960.0);
+        assert_eq!(highlights.sounding, ["A", "S"]);
+        assert_eq!(
+            highlights.upcoming,
+            [("D".to_owned(), 0.25), ("F".to_owned(), 0.75)]
+        );
+
+        let lines = track.get_midi_keys_lines(&Settings::default(), &filter);
+        let line = playing_line(&lines, &track, 1000).unwrap();
+        assert_eq!(lines[line].pair_indices, [3, 4]);
+        assert_eq!(
+            playing_line(&lines, &track, 0).map(|line| lines[line].pair_indices.clone()),
+            Some(vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn percussion_tracks_are_detected_and_left_unmapped() {
+        let drums: Vec<_> = [60, 62, 64]
+            .into_iter()
+            .flat_map(|key| {
+                [
+                    TrackEvent {
+                        delta: 0.into(),
+                        kind: TrackEventKind::Midi {
+                            channel: PERCUSSION_CHANNEL.into(),
+                            message: MidiMessage::NoteOn {
+                                key: key.into(),
+                                vel: 100.into(),
+                            },
+                        },
+                    },
+                    TrackEvent {
+                        delta: 240.into(),
+                        kind: TrackEventKind::Midi {
+                            channel: PERCUSSION_CHANNEL.into(),
+                            message: MidiMessage::NoteOff {
+                                key: key.into(),
+                                vel: 0.into(),
+                            },
+                        },
+                    },
+                ]
+            })
+            .collect();
+        let melody = [60, 62]
+            .into_iter()
+            .flat_map(|key| [note_on(0, key), note_off(480, key)])
+            .collect();
+        let smf = Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(480.into())),
+            tracks: vec![melody, drums],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&bytes).unwrap();
+        let percussion: Vec<bool> = app
+            .midi_key_tracks
+            .iter()
+            .map(|track| track.percussion)
+            .collect();
+        assert_eq!(percussion, [false, true]);
+        let drums = &app.midi_key_tracks[1];
+        assert!(drums
+            .midi_key_pairs
+            .iter()
+            .all(|pair| pair.keyboard_key.is_none()));
+        assert_eq!(app.exported_tracks().len(), 1);
+        let lines = drums.get_midi_keys_lines(&app.settings, &app.note_filter());
+        assert!(lines[1].segments[0].0.contains("Mute Hi Conga"));
+
+        // Unmarking it maps the notes like any other track.
+        app.midi_key_tracks[1].percussion = false;
+        app.remap_tracks();
+        assert!(app.midi_key_tracks[1]
+            .midi_key_pairs
+            .iter()
+            .any(|pair| pair.keyboard_key.is_some()));
+        assert_eq!(notes::drum_name(34), None);
+        assert_eq!(notes::drum_name(81), Some("Open Triangle"));
+        assert_eq!(notes::drum_name(82), None);
+    }
+
+    #[test]
+    fn edits_are_undone_and_redone() {
+        let track = || {
+            [60, 62]
+                .into_iter()
+                .flat_map(|key| [note_on(0, key), note_off(480, key)])
+                .collect()
+        };
+        let smf = Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(480.into())),
+            tracks: vec![track(), track()],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let mut app = MyApp::default();
+        app.load_midi_bytes(&bytes).unwrap();
+        app.record_edits();
+        let mapped_key = app.midi_key_tracks[0].midi_key_pairs[0]
+            .keyboard_key
+            .clone();
+
+        app.midi_key_tracks[0].transpose = 12;
+        app.remap_tracks();
+        app.record_edits();
+        app.midi_key_tracks.remove(1);
+        app.remap_tracks();
+        app.record_edits();
+        // Only the notes changing isn't an edit.
+        app.midi_key_tracks[0].midi_key_pairs.pop();
+        app.remap_tracks();
+        app.record_edits();
+        assert_eq!(app.history.undo_label(), Some("track removal"));
+
+        app.undo();
+        assert_eq!(app.midi_key_tracks.len(), 2);
+        assert_eq!(app.midi_key_tracks[0].transpose, 12);
+        app.undo();
+        assert_eq!(app.midi_key_tracks[0].transpose, 0);
+        assert_eq!(
+            app.midi_key_tracks[0].midi_key_pairs[0].keyboard_key,
+            mapped_key
+        );
+        assert_eq!(app.history.undo_label(), None);
+
+        app.redo();
+        app.redo();
+        assert_eq!(app.midi_key_tracks.len(), 1);
+        assert_eq!(app.midi_key_tracks[0].transpose, 12);
+        assert_eq!(app.history.redo_label(), None);
+
+        // Loading another song starts over.
+        app.load_midi_bytes(&bytes).unwrap();
+        assert_eq!(app.history.undo_label(), None);
+    }
+
+    #[test]
+    fn open_songs_keep_their_edits() {
+        let song = |keys: &[u8]| {
+            let smf = Smf {
+                header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+                tracks: vec![keys
+                    .iter()
+                    .flat_map(|&key| [note_on(0, key), note_off(480, key)])
+                    .collect()],
+            };
+            let mut bytes = Vec::new();
+            smf.write_std(&mut bytes).unwrap();
+            bytes
+        };
+        let mut app = MyApp::default();
+        for (path, keys) in [("a.mid", [60]), ("b.mid", [62])] {
+            app.remember_current_song();
+            app.picked_midi_path = Some(path.to_owned());
+            app.load_midi_bytes(&song(&keys)).unwrap();
+            app.open_songs.push(app.current_song());
+        }
+        app.midi_key_tracks[0].transpose = 12;
+
+        app.switch_to_open_song(0);
+        assert_eq!(app.picked_midi_path.as_deref(), Some("a.mid"));
+        assert_eq!(app.midi_key_tracks[0].midi_key_pairs[0].midi_key, 60);
+        assert_eq!(app.open_songs[1].tracks[0].transpose, 12);
+
+        app.switch_to_open_song(1);
+        assert_eq!(app.midi_key_tracks[0].midi_key_pairs[0].midi_key, 62);
+        assert_eq!(app.midi_key_tracks[0].transpose, 12);
+        assert_eq!(
+            app.previous_song
+                .as_ref()
+                .and_then(|song| song.path.as_deref()),
+            Some("a.mid")
+        );
+
+        // "Back" swaps with the song loaded before, keeping the edits of both.
+        app.go_back();
+        assert_eq!(app.picked_midi_path.as_deref(), Some("a.mid"));
+        assert_eq!(app.midi_key_tracks[0].midi_key_pairs[0].midi_key, 60);
+        app.go_back();
+        assert_eq!(app.picked_midi_path.as_deref(), Some("b.mid"));
+        assert_eq!(app.midi_key_tracks[0].transpose, 12);
+
+        // A file that can't be opened leaves the current and the previous song alone.
+        let missing = std::env::temp_dir().join("planck-scribe-missing.mid");
+        app.open_midi_file(missing.to_string_lossy().into_owned());
+        assert!(app.notice.is_some());
+        assert_eq!(app.picked_midi_path.as_deref(), Some("b.mid"));
+        assert_eq!(app.midi_key_tracks[0].transpose, 12);
+        assert_eq!(
+            app.previous_song
+                .as_ref()
+                .and_then(|song| song.path.as_deref()),
+            Some("a.mid")
+        );
+    }
+
+    #[test]
+    fn measures_follow_time_signature_changes() {
+        // 4/4, then 3/4 from the third measure, then 6/8 in the middle of the fourth.
+        let timing = SongTiming {
+            time_signature_changes: vec![(0, (4, 4)), (3840, (3, 4)), (5520, (6, 8))],
+            ..SongTiming::default()
+        };
+        let measures: Vec<usize> = [0, 1919, 1920, 3840, 5279, 5280, 5519, 5520, 6959]
+            .into_iter()
+            .map(|tick| timing.measure_at(tick))
+            .collect();
+        assert_eq!(measures, [0, 0, 1, 2, 2, 3, 3, 4, 4]);
+        assert_eq!(timing.measure_at(6960), 5);
+        let starts: Vec<u64> = (0..6)
+            .map(|measure| timing.measure_start(measure))
+            .collect();
+        assert_eq!(starts, [0, 1920, 3840, 5280, 5520, 6960]);
+
+        let mut track = MidiKeyTrack::new();
+        for tick in [0, 960, 3840] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: Some("ESC".to_owned()),
+                substituted: false,
+            });
+        }
+        let filter = NoteFilter::default();
+        let lines = with_bar_lines(
+            track.get_midi_keys_lines(&Settings::default(), &filter),
+            &track,
+            &timing,
+        );
+        let text: Vec<String> = lines
+            .iter()
+            .map(|line| line.segments[0].0.trim_end().to_owned())
+            .collect();
+        assert_eq!(text[0], "| 1");
+        assert_eq!(text[3], "| 3");
+        assert_eq!(lines.len(), 5);
+
+        // The song ends in the 3/4 measure, which "Show measures" picks by its own length.
+        let mut app = MyApp {
+            midi_key_tracks: vec![track],
+            song_timing: timing,
+            ..MyApp::default()
+        };
+        assert_eq!(app.measure_count(), 3);
+        app.set_measure_range((3, 3));
+        assert_eq!(app.measure_ticks(), Some(3840..5280));
+    }
+}
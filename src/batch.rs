@@ -0,0 +1,298 @@
+use crate::{
+    config::RecentFiles,
+    export, load_song, planck_mapping,
+    settings::{Settings, UnmappedNotes},
+    split_by_channel, LoadMidiFileError, MidiKeyTrack, SongTiming,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+use thiserror::*;
+
+/// Formats that a batch of files can be exported to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BatchFormat {
+    Csv,
+    RowBreakdown,
+    ChordChart,
+    MarkdownTable,
+    KeySequence,
+}
+
+impl BatchFormat {
+    pub const ALL: [BatchFormat; 5] = [
+        BatchFormat::Csv,
+        BatchFormat::RowBreakdown,
+        BatchFormat::ChordChart,
+        BatchFormat::MarkdownTable,
+        BatchFormat::KeySequence,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BatchFormat::Csv => "CSV",
+            BatchFormat::RowBreakdown => "Row breakdown",
+            BatchFormat::ChordChart => "Chord chart",
+            BatchFormat::MarkdownTable => "Markdown table",
+            BatchFormat::KeySequence => "Key sequence",
+        }
+    }
+
+    /// End of the file name, matching what the single-file export of the format suggests.
+    fn file_name_ending(self) -> &'static str {
+        match self {
+            BatchFormat::Csv => ".csv",
+            BatchFormat::RowBreakdown => "-rows.txt",
+            BatchFormat::ChordChart => "-chords.txt",
+            BatchFormat::MarkdownTable => ".md",
+            BatchFormat::KeySequence => "-keys.txt",
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("the file no longer exists")]
+    Missing,
+    #[error("could not load: {0}")]
+    Load(#[from] LoadMidiFileError),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// What became of one file of a batch.
+pub struct Outcome {
+    pub source: String,
+    /// The written file.
+    pub result: Result<PathBuf, BatchError>,
+}
+
+/// Loads the MIDI file at `path` as opening it would and maps it with `settings`.
+pub fn load_mapped(
+    path: &Path,
+    settings: &Settings,
+) -> Result<(Vec<MidiKeyTrack>, SongTiming), BatchError> {
+    if !path.exists() {
+        return Err(BatchError::Missing);
+    }
+    let (mut tracks, timing) = load_song(&fs::read(path)?, settings.duplicate_notes)?;
+    if settings.split_by_channel {
+        tracks = split_by_channel(&tracks, &[]);
+    }
+    // Drum tracks have no keys to export.
+    tracks.retain(|track| !track.percussion);
+    let mapping = planck_mapping(settings);
+    for track in tracks.iter_mut() {
+        track.remap(
+            &mapping,
+            settings.unmapped_notes,
+            settings.mapping.transpose,
+        );
+        if settings.unmapped_notes == UnmappedNotes::Drop {
+            track.drop_unmapped();
+        }
+    }
+    Ok((tracks, timing))
+}
+
+/// Loads the MIDI file at `source` as opening it would, maps it with `settings` and writes it
+/// into `directory` as `format`, named by the export file name template.
+pub fn export_file(
+    source: &str,
+    directory: &Path,
+    format: BatchFormat,
+    settings: &Settings,
+) -> Result<PathBuf, BatchError> {
+    let path = Path::new(source);
+    let (tracks, timing) = load_mapped(path, settings)?;
+
+    let song_name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("song");
+    let text = match format {
+        BatchFormat::Csv => export::csv(&tracks, settings.export.mark_rests),
+        BatchFormat::RowBreakdown => export::row_breakdown(song_name, &tracks, &settings.layout),
+        BatchFormat::ChordChart => export::chord_chart(song_name, &tracks, &timing),
+        BatchFormat::MarkdownTable => export::markdown_table(&tracks, settings.export.mark_rests),
+        BatchFormat::KeySequence => {
+            let bars = Some(&timing).filter(|_| settings.export.group_bars);
+            export::key_sequence(song_name, &tracks, bars)
+        }
+    };
+    let mut stem = export::expand_file_name(
+        &settings.export.file_name_template,
+        song_name,
+        &settings.base_key,
+        &export::today(),
+    );
+    if stem.trim().is_empty() {
+        stem = song_name.to_owned();
+    }
+    let target = directory.join(format!("{}{}", stem, format.file_name_ending()));
+    fs::write(&target, text)?;
+    Ok(target)
+}
+
+/// Number of threads a batch runs on: `setting`, or one per CPU core if it is 0, but never more
+/// than there are files.
+fn thread_count(setting: usize, files: usize) -> usize {
+    let threads = match setting {
+        0 => thread::available_parallelism().map_or(1, |cores| cores.get()),
+        threads => threads,
+    };
+    threads.min(files).max(1)
+}
+
+/// Exports every recent file on background threads, one outcome per file as it finishes.
+///
+/// Files are independent, so each thread takes the next file not yet started until none are
+/// left. A file that fails doesn't stop the others. `finished` is called on the thread that
+/// exported each file, e.g. to repaint the window.
+pub struct BatchExport {
+    pub format: BatchFormat,
+    /// Number of files in the batch.
+    pub total: usize,
+    /// Outcomes so far, in the order the files finished.
+    pub outcomes: Vec<Outcome>,
+    receiver: mpsc::Receiver<Outcome>,
+}
+
+impl BatchExport {
+    pub fn start(
+        recent: &RecentFiles,
+        directory: PathBuf,
+        format: BatchFormat,
+        settings: Settings,
+        finished: impl Fn() + Clone + Send + 'static,
+    ) -> BatchExport {
+        let sources = Arc::new(recent.paths.clone());
+        let total = sources.len();
+        let (sender, receiver) = mpsc::channel();
+        let next = Arc::new(AtomicUsize::new(0));
+        let settings = Arc::new(settings);
+        for _ in 0..thread_count(settings.export.batch_threads, total) {
+            let (sources, next, sender) = (sources.clone(), next.clone(), sender.clone());
+            let (directory, settings, finished) =
+                (directory.clone(), settings.clone(), finished.clone());
+            thread::spawn(move || {
+                while let Some(source) = sources.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let result = export_file(source, &directory, format, &settings);
+                    let outcome = Outcome {
+                        source: source.clone(),
+                        result,
+                    };
+                    if sender.send(outcome).is_err() {
+                        // The batch was dismissed.
+                        break;
+                    }
+                    finished();
+                }
+            });
+        }
+        BatchExport {
+            format,
+            total,
+            outcomes: Vec::new(),
+            receiver,
+        }
+    }
+
+    /// Takes in the outcomes that arrived since the last frame.
+    pub fn poll(&mut self) {
+        self.outcomes.extend(self.receiver.try_iter());
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.outcomes.len() >= self.total
+    }
+
+    /// `(exported, skipped because missing, failed)`
+    pub fn summary(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for outcome in &self.outcomes {
+            match outcome.result {
+                Ok(_) => counts.0 += 1,
+                Err(BatchError::Missing) => counts.1 += 1,
+                Err(_) => counts.2 += 1,
+            }
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_existing_files_and_skips_missing_ones() {
+        let directory =
+            std::env::temp_dir().join(format!("planck-scribe-batch-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("format0.mid");
+        fs::write(&source, include_bytes!("../tests/fixtures/format0.mid")).unwrap();
+        let settings = Settings::default();
+
+        let written = export_file(
+            &source.display().to_string(),
+            &directory,
+            BatchFormat::Csv,
+            &settings,
+        )
+        .unwrap();
+        assert_eq!(written, directory.join("format0.csv"));
+        let csv = fs::read_to_string(&written).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+
+        let missing = directory.join("gone.mid").display().to_string();
+        assert!(matches!(
+            export_file(&missing, &directory, BatchFormat::Csv, &settings),
+            Err(BatchError::Missing)
+        ));
+        fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn batches_run_on_several_threads() {
+        assert_eq!(thread_count(4, 10), 4);
+        assert_eq!(thread_count(4, 2), 2);
+        assert_eq!(thread_count(3, 0), 1);
+        assert!(thread_count(0, 100) >= 1);
+
+        let directory = std::env::temp_dir().join(format!(
+            "planck-scribe-batch-threads-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+        let mut recent = RecentFiles::default();
+        for name in ["a.mid", "b.mid", "c.mid"] {
+            let source = directory.join(name);
+            fs::write(&source, include_bytes!("../tests/fixtures/format0.mid")).unwrap();
+            recent.push(&source.display().to_string());
+        }
+        recent.push(&directory.join("gone.mid").display().to_string());
+        let mut settings = Settings::default();
+        settings.export.batch_threads = 2;
+
+        let mut batch = BatchExport::start(
+            &recent,
+            directory.clone(),
+            BatchFormat::Csv,
+            settings,
+            || {},
+        );
+        while !batch.is_finished() {
+            thread::sleep(std::time::Duration::from_millis(5));
+            batch.poll();
+        }
+        assert_eq!(batch.summary(), (3, 1, 0));
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{MidiKeyTrack, NoteFilter, SongTiming};
+use eframe::egui::{self, Align2, FontId, Rect, Sense};
+
+const BEAT_WIDTH: f32 = 64.0;
+const ROW_HEIGHT: f32 = 16.0;
+/// Room above each track's notes for its name and the measure numbers.
+const TRACK_HEADER_HEIGHT: f32 = 18.0;
+
+/// Ticks in one beat of the time signature, e.g. an eighth note in 6/8.
+fn ticks_per_signature_beat(timing: &SongTiming) -> f64 {
+    (timing.ticks_per_measure() / timing.time_signature.0.max(1) as f64).max(1.0)
+}
+
+/// Indices of the shown notes of `track` in each beat, from the first beat of the song to the
+/// last one with a note. Beats without notes are empty.
+fn beats(track: &MidiKeyTrack, filter: &NoteFilter, timing: &SongTiming) -> Vec<Vec<usize>> {
+    let beat_ticks = ticks_per_signature_beat(timing);
+    let mut beats: Vec<Vec<usize>> = Vec::new();
+    for (index, pair) in track.midi_key_pairs.iter().enumerate() {
+        if filter.hides(pair) {
+            continue;
+        }
+        let beat = (pair.tick as f64 / beat_ticks) as usize;
+        if beats.len() <= beat {
+            beats.resize_with(beat + 1, Vec::new);
+        }
+        beats[beat].push(index);
+    }
+    beats
+}
+
+/// Draws each track as a row of beat columns, with the keys of the notes starting in a beat
+/// stacked in its column. Measures are separated by a line and numbered.
+pub fn beat_grid_ui(
+    ui: &mut egui::Ui,
+    tracks: &[MidiKeyTrack],
+    filter: &NoteFilter,
+    timing: &SongTiming,
+    playhead: f64,
+) {
+    let tracks: Vec<&MidiKeyTrack> = tracks.iter().filter(|track| !track.hidden).collect();
+    let track_beats: Vec<_> = tracks
+        .iter()
+        .map(|track| beats(track, filter, timing))
+        .collect();
+    let beat_count = track_beats.iter().map(Vec::len).max().unwrap_or(0);
+    // Each track is as tall as its fullest beat.
+    let track_heights: Vec<f32> = track_beats
+        .iter()
+        .map(|beats| {
+            let stack = beats.iter().map(Vec::len).max().unwrap_or(0).max(1);
+            TRACK_HEADER_HEIGHT + stack as f32 * ROW_HEIGHT + 4.0
+        })
+        .collect();
+    let beats_per_measure = timing.time_signature.0.max(1) as usize;
+    let beat_ticks = ticks_per_signature_beat(timing);
+
+    egui::ScrollArea::both()
+        .auto_shrink([false, false])
+        .show_viewport(ui, |ui, viewport| {
+            let size = egui::vec2(
+                beat_count as f32 * BEAT_WIDTH,
+                track_heights.iter().sum::<f32>(),
+            );
+            let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+            let painter = ui.painter_at(rect);
+            let visuals = ui.visuals();
+            let font = FontId::monospace(11.0);
+            let first_beat = (viewport.left() / BEAT_WIDTH).max(0.0) as usize;
+            let last_beat = ((viewport.right() / BEAT_WIDTH) as usize + 1).min(beat_count);
+
+            let playhead_beat = (playhead / beat_ticks) as usize;
+            if playhead_beat < beat_count {
+                painter.rect_filled(
+                    Rect::from_min_size(
+                        rect.min + egui::vec2(playhead_beat as f32 * BEAT_WIDTH, 0.0),
+                        egui::vec2(BEAT_WIDTH, rect.height()),
+                    ),
+                    0.0,
+                    visuals.selection.bg_fill.gamma_multiply(0.3),
+                );
+            }
+
+            let mut top = rect.top();
+            for ((track, beats), height) in tracks.iter().zip(&track_beats).zip(&track_heights) {
+                painter.hline(
+                    rect.x_range(),
+                    top,
+                    visuals.widgets.noninteractive.bg_stroke,
+                );
+                for beat in first_beat..last_beat {
+                    let left = rect.left() + beat as f32 * BEAT_WIDTH;
+                    if beat % beats_per_measure == 0 {
+                        painter.vline(
+                            left,
+                            top..=top + height,
+                            visuals.widgets.noninteractive.fg_stroke,
+                        );
+                        painter.text(
+                            egui::pos2(left + 3.0, top + 2.0),
+                            Align2::LEFT_TOP,
+                            (beat / beats_per_measure + 1).to_string(),
+                            FontId::proportional(10.0),
+                            visuals.weak_text_color(),
+                        );
+                    }
+                    let Some(notes) = beats.get(beat) else {
+                        continue;
+                    };
+                    for (row, &index) in notes.iter().enumerate() {
+                        let pair = &track.midi_key_pairs[index];
+                        let (label, color) = match &pair.keyboard_key {
+                            Some(key) => (key.as_str(), visuals.strong_text_color()),
+                            None => ("NONE", visuals.error_fg_color),
+                        };
+                        let color = if filter.excludes(pair) {
+                            color.gamma_multiply(0.4)
+                        } else {
+                            color
+                        };
+                        painter.text(
+                            egui::pos2(
+                                left + 6.0,
+                                top + TRACK_HEADER_HEIGHT + row as f32 * ROW_HEIGHT,
+                            ),
+                            Align2::LEFT_TOP,
+                            label,
+                            font.clone(),
+                            color,
+                        );
+                    }
+                }
+                painter.text(
+                    egui::pos2(rect.left() + viewport.left() + 24.0, top + 2.0),
+                    Align2::LEFT_TOP,
+                    &track.name,
+                    FontId::proportional(10.0),
+                    visuals.weak_text_color(),
+                );
+                top += height;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    #[test]
+    fn notes_are_grouped_by_beat() {
+        let mut track = MidiKeyTrack::new();
+        for tick in [0, 0, 470, 1440, 1500] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(240),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        let filter = NoteFilter::default();
+        let timing = SongTiming::default();
+        assert_eq!(
+            beats(&track, &filter, &timing),
+            vec![vec![0, 1, 2], vec![], vec![], vec![3, 4]]
+        );
+        // In 6/8 a beat is an eighth note.
+        let timing = SongTiming {
+            time_signature: (6, 8),
+            ..SongTiming::default()
+        };
+        assert_eq!(beats(&track, &filter, &timing)[1], vec![2]);
+    }
+}
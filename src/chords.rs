@@ -0,0 +1,83 @@
+use crate::notes;
+
+/// Chord shapes that can be recognised, as intervals above the root and the suffix they are
+/// written with.
+const CHORD_SHAPES: [(&[u8], &str); 5] = [
+    (&[0, 4, 7], "maj"),
+    (&[0, 3, 7], "m"),
+    (&[0, 4, 7, 10], "7"),
+    (&[0, 4, 7, 11], "maj7"),
+    (&[0, 3, 7, 10], "m7"),
+];
+
+/// A chord name guessed from a set of notes.
+#[derive(PartialEq, Debug)]
+pub struct ChordGuess {
+    pub name: String,
+    /// `false` when the notes only partly match the chord, e.g. with extra or missing notes.
+    pub certain: bool,
+}
+
+/// Names the chord formed by `keys`, looking only at their pitch classes.
+///
+/// Each pitch class present is tried as the root of every known chord shape. A shape matching
+/// the notes exactly is a certain guess; otherwise the shape sharing the most notes with the
+/// fewest left over is an uncertain one. The lowest note wins ties, as it is usually the root.
+pub fn name_chord(keys: &[u8]) -> Option<ChordGuess> {
+    let bass = *keys.iter().min()?;
+    let pitch_classes = keys.iter().fold(0u16, |set, key| set | 1 << (key % 12));
+    let mut roots: Vec<u8> = (0..12).filter(|pc| pitch_classes & 1 << pc != 0).collect();
+    roots.sort_by_key(|pc| (*pc + 12 - bass % 12) % 12 != 0);
+
+    let mut best: Option<(i32, String, bool)> = None;
+    for root in roots {
+        for (intervals, suffix) in CHORD_SHAPES {
+            let shape = intervals
+                .iter()
+                .fold(0u16, |set, interval| set | 1 << ((root + interval) % 12));
+            let matched = (shape & pitch_classes).count_ones() as i32;
+            let missing = (shape & !pitch_classes).count_ones() as i32;
+            let extra = (pitch_classes & !shape).count_ones() as i32;
+            // Needs at least the root and one other chord tone to say anything.
+            if matched < 2 {
+                continue;
+            }
+            let score = matched * 2 - missing - extra * 2;
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, ..)| score > *best_score)
+            {
+                let name = format!("{}{}", notes::pitch_class_name(root), suffix);
+                best = Some((score, name, missing == 0 && extra == 0));
+            }
+        }
+    }
+    best.map(|(_, name, certain)| ChordGuess { name, certain })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guess(keys: &[u8]) -> Option<(String, bool)> {
+        name_chord(keys).map(|guess| (guess.name, guess.certain))
+    }
+
+    #[test]
+    fn names_triads_and_sevenths() {
+        assert_eq!(guess(&[60, 64, 67]), Some(("Cmaj".to_owned(), true)));
+        assert_eq!(guess(&[57, 60, 64]), Some(("Am".to_owned(), true)));
+        assert_eq!(guess(&[55, 59, 62, 65]), Some(("G7".to_owned(), true)));
+        assert_eq!(guess(&[57, 60, 64, 67]), Some(("Am7".to_owned(), true)));
+        // Inversions are named after the root, not the bass.
+        assert_eq!(guess(&[52, 55, 60]), Some(("Cmaj".to_owned(), true)));
+    }
+
+    #[test]
+    fn partial_matches_are_uncertain() {
+        assert_eq!(guess(&[60, 64]), Some(("Cmaj".to_owned(), false)));
+        assert_eq!(guess(&[60, 62, 64, 67]), Some(("Cmaj".to_owned(), false)));
+        assert_eq!(guess(&[60]), None);
+        assert_eq!(guess(&[]), None);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    batch::{self, BatchError},
+    export, layout,
+    settings::{Settings, SettingsError},
+};
+use clap::{Parser, Subcommand};
+use std::{fs, path::PathBuf};
+use thiserror::*;
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("could not read settings: {0}")]
+    Settings(#[from] SettingsError),
+    #[error("{file}: {source}")]
+    Convert { file: String, source: BatchError },
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The command line: a song to open in the window, or a command to run without it.
+#[derive(Parser, Debug)]
+#[command(
+    name = "planck-scribe",
+    version,
+    about = "Translates MIDI files and MusicXML scores into key sequences for a Planck keyboard"
+)]
+pub struct Args {
+    /// Run a command without opening the window.
+    #[arg(long)]
+    pub cli: bool,
+    /// A MIDI file or MusicXML score to open in the window.
+    #[arg(conflicts_with = "cli")]
+    pub file: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, PartialEq, Debug)]
+pub enum Command {
+    /// Writes the mapped key sequence of each file as text, next to the file as
+    /// <name>-keys.txt.
+    Convert(ConvertArgs),
+}
+
+/// What `planck-scribe convert` was asked to do.
+#[derive(clap::Args, PartialEq, Debug, Default)]
+pub struct ConvertArgs {
+    /// MIDI files and MusicXML scores to convert.
+    #[arg(required = true, value_name = "FILE")]
+    pub inputs: Vec<PathBuf>,
+    /// Key that middle C is played on [default: ESC].
+    #[arg(long, value_name = "KEY")]
+    pub base_key: Option<String>,
+    /// Settings exported from the app, for the layout and mapping.
+    #[arg(long, value_name = "FILE")]
+    pub settings: Option<PathBuf>,
+    /// Where to write the text; only with a single file.
+    #[arg(long, short, value_name = "FILE")]
+    pub out: Option<PathBuf>,
+    /// Group the keys into measures, like | A S D | F G H |.
+    #[arg(long)]
+    pub bars: bool,
+}
+
+/// Converts each input file as the app's key sequence export would, returning the written
+/// files. Stops at the first file that can't be converted.
+pub fn convert(args: &ConvertArgs) -> Result<Vec<PathBuf>, CliError> {
+    if args.out.is_some() && args.inputs.len() > 1 {
+        return Err(CliError::Usage(
+            "--out only works with a single file".to_owned(),
+        ));
+    }
+    let mut settings = match &args.settings {
+        Some(path) => Settings::import(path)?,
+        None => Settings::default(),
+    };
+    if let Some(base_key) = &args.base_key {
+        if layout::key_position(&settings.layout, base_key).is_none() {
+            return Err(SettingsError::MissingBaseKey(base_key.clone()).into());
+        }
+        settings.base_key = base_key.clone();
+    }
+    settings.export.group_bars |= args.bars;
+    let mut written = Vec::new();
+    for input in &args.inputs {
+        let (tracks, timing) =
+            batch::load_mapped(input, &settings).map_err(|source| CliError::Convert {
+                file: input.display().to_string(),
+                source,
+            })?;
+        let song_name = input
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("song");
+        let target = match &args.out {
+            Some(out) => out.clone(),
+            None => input.with_file_name(format!("{}-keys.txt", song_name)),
+        };
+        let bars = Some(&timing).filter(|_| settings.export.group_bars);
+        fs::write(&target, export::key_sequence(song_name, &tracks, bars))?;
+        written.push(target);
+    }
+    Ok(written)
+}
+
+/// Runs `command` and returns the process exit code: 0 on success, 1 if a conversion failed
+/// and 2 for a wrong command line, as clap uses for its own errors.
+pub fn main(command: &Command) -> i32 {
+    let Command::Convert(args) = command;
+    match convert(args) {
+        Ok(written) => {
+            for path in written {
+                println!("wrote {}", path.display());
+            }
+            0
+        }
+        Err(err @ CliError::Usage(_)) => {
+            eprintln!("error: {}", err);
+            2
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("planck-scribe").chain(line.split_whitespace()))
+    }
+
+    #[test]
+    fn convert_arguments_are_parsed() {
+        let args = parse("--cli convert song.mid --base-key TAB --out song.txt").unwrap();
+        assert!(args.cli);
+        assert_eq!(
+            args.command,
+            Some(Command::Convert(ConvertArgs {
+                inputs: vec!["song.mid".into()],
+                base_key: Some("TAB".to_owned()),
+                settings: None,
+                out: Some("song.txt".into()),
+                bars: false,
+            }))
+        );
+        assert!(matches!(
+            parse("convert song.mid --bars").unwrap().command,
+            Some(Command::Convert(ConvertArgs { bars: true, .. }))
+        ));
+        for wrong in [
+            "convert",
+            "convert song.mid --base-key",
+            "convert song.mid --loud",
+            "--cli song.mid",
+        ] {
+            assert!(parse(wrong).is_err(), "{wrong}");
+        }
+    }
+
+    #[test]
+    fn a_lone_file_is_opened_in_the_window() {
+        let args = parse("song.mid").unwrap();
+        assert_eq!(args.file, Some("song.mid".into()));
+        assert_eq!(args.command, None);
+        assert!(!args.cli);
+        let args = parse("").unwrap();
+        assert_eq!((args.file, args.command), (None, None));
+    }
+
+    #[test]
+    fn converts_files_to_key_sequences() {
+        let directory =
+            std::env::temp_dir().join(format!("planck-scribe-cli-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("format0.mid");
+        fs::write(&source, include_bytes!("../tests/fixtures/format0.mid")).unwrap();
+
+        let mut convert_args = ConvertArgs {
+            inputs: vec![source.clone()],
+            ..ConvertArgs::default()
+        };
+        let written = convert(&convert_args).unwrap();
+        assert_eq!(written, vec![directory.join("format0-keys.txt")]);
+        let text = fs::read_to_string(&written[0]).unwrap();
+        assert!(text.starts_with("Key sequence for format0\n"));
+        assert!(text.contains("  C4     ESC\n"), "{text}");
+
+        convert_args.inputs.push(source.clone());
+        convert_args.out = Some(directory.join("both.txt"));
+        assert!(matches!(convert(&convert_args), Err(CliError::Usage(_))));
+
+        convert_args.inputs.pop();
+        convert_args.base_key = Some("NOPE".to_owned());
+        assert!(matches!(
+            convert(&convert_args),
+            Err(CliError::Settings(SettingsError::MissingBaseKey(_)))
+        ));
+        fs::remove_dir_all(&directory).ok();
+    }
+}
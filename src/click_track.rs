@@ -0,0 +1,111 @@
+use crate::{MidiKeyTrack, NoteFilter, SongTiming};
+use std::{collections::BTreeMap, f32::consts::TAU, path::Path};
+
+const SAMPLE_RATE: u32 = 44_100;
+/// Length of one click; short enough not to blur fast passages.
+const CLICK_MS: f32 = 20.0;
+
+/// A click at a note onset.
+#[derive(PartialEq, Debug)]
+pub struct Click {
+    pub time_ms: f64,
+    /// Several notes start together here, so it is clicked louder and higher when accenting.
+    pub chord: bool,
+}
+
+/// One click per distinct note onset across `tracks`, in time order. Notes the filter hides
+/// don't get a click.
+pub fn clicks(tracks: &[MidiKeyTrack], timing: &SongTiming, filter: &NoteFilter) -> Vec<Click> {
+    let mut notes_at: BTreeMap<u64, usize> = BTreeMap::new();
+    for pair in tracks.iter().flat_map(|track| track.midi_key_pairs.iter()) {
+        if !filter.hides(pair) {
+            *notes_at.entry(pair.tick).or_default() += 1;
+        }
+    }
+    notes_at
+        .into_iter()
+        .filter_map(|(tick, notes)| {
+            Some(Click {
+                time_ms: timing.tick_to_time_ms(tick)?,
+                chord: notes > 1,
+            })
+        })
+        .collect()
+}
+
+/// Writes `clicks` as a mono 16-bit WAV file. With `accent_chords`, chord onsets get a louder,
+/// higher click.
+pub fn write_wav(path: &Path, clicks: &[Click], accent_chords: bool) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let click_samples = (SAMPLE_RATE as f32 * CLICK_MS / 1000.0) as usize;
+    let end_ms = clicks.last().map_or(0.0, |click| click.time_ms) + CLICK_MS as f64;
+    let mut samples = vec![0.0f32; (end_ms * SAMPLE_RATE as f64 / 1000.0).ceil() as usize];
+    for click in clicks {
+        let (frequency, volume) = if accent_chords && click.chord {
+            (1500.0, 0.9)
+        } else {
+            (1000.0, 0.5)
+        };
+        let start = (click.time_ms * SAMPLE_RATE as f64 / 1000.0) as usize;
+        for (i, sample) in samples
+            .iter_mut()
+            .skip(start)
+            .take(click_samples)
+            .enumerate()
+        {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let decay = 1.0 - i as f32 / click_samples as f32;
+            *sample += (TAU * frequency * t).sin() * volume * decay * decay;
+        }
+    }
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    #[test]
+    fn clicks_once_per_onset() {
+        let mut track = MidiKeyTrack::new();
+        for (tick, key) in [(0, 60), (0, 64), (480, 62)] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        let filter = NoteFilter::default();
+        // 480 ticks per beat at 120 BPM.
+        let clicks = clicks(&[track], &SongTiming::default(), &filter);
+        assert_eq!(
+            clicks,
+            vec![
+                Click {
+                    time_ms: 0.0,
+                    chord: true,
+                },
+                Click {
+                    time_ms: 500.0,
+                    chord: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::{
+    layout,
+    settings::{MappingSettings, Settings},
+    PlanckRows,
+};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::PathBuf};
+use thiserror::*;
+
+/// Folder for files the app keeps between sessions, created if it doesn't exist yet.
+///
+/// Returns `None` if the platform has no such folder (e.g. no home directory) or it can't be
+/// created, and always in unit tests, so they neither read nor write the user's files.
+pub fn config_dir() -> Option<PathBuf> {
+    if cfg!(test) {
+        return None;
+    }
+    let dirs = ProjectDirs::from("", "", "Planck Scribe")?;
+    let dir = dirs.config_dir();
+    std::fs::create_dir_all(dir).ok()?;
+    Some(dir.to_owned())
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid config file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("no config folder on this system")]
+    NoConfigDir,
+}
+
+/// Choices remembered for a single MIDI file.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct FileConfig {
+    /// Channels in the order their tracks are shown when splitting by channel.
+    pub channel_order: Vec<u8>,
+    pub annotations: Vec<Annotation>,
+}
+
+/// Free text attached to a note, e.g. a practice reminder.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Annotation {
+    pub track: usize,
+    /// Index of the note within the track.
+    pub note: usize,
+    pub text: String,
+}
+
+/// Per-file choices, keyed by the path of the MIDI file.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FileConfigs {
+    pub files: HashMap<String, FileConfig>,
+}
+
+impl FileConfigs {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("files.json"))
+    }
+
+    /// Reads the stored configs; having none stored yet is not an error.
+    pub fn load() -> Result<FileConfigs, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            _ => Ok(FileConfigs::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path().ok_or(ConfigError::NoConfigDir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, file: &str) -> Option<&FileConfig> {
+        self.files.get(file)
+    }
+
+    pub fn entry(&mut self, file: &str) -> &mut FileConfig {
+        self.files.entry(file.to_owned()).or_default()
+    }
+}
+
+/// Most files kept in the recent files list.
+pub const RECENT_CAPACITY: usize = 10;
+
+/// MIDI files opened lately, most recent first.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RecentFiles {
+    pub paths: Vec<String>,
+    /// Open the most recent file when the app starts.
+    #[serde(default)]
+    pub reopen_latest: bool,
+}
+
+impl RecentFiles {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("recent.json"))
+    }
+
+    /// Reads the stored list; having none stored yet is not an error.
+    pub fn load() -> Result<RecentFiles, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            _ => Ok(RecentFiles::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path().ok_or(ConfigError::NoConfigDir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Moves `file` to the top of the list, dropping the oldest files beyond
+    /// [`RECENT_CAPACITY`].
+    pub fn push(&mut self, file: &str) {
+        self.paths.retain(|path| path != file);
+        self.paths.insert(0, file.to_owned());
+        self.paths.truncate(RECENT_CAPACITY);
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.paths.retain(|path| path != file);
+    }
+
+    /// The file to open on start, if that is turned on and there is one.
+    pub fn file_to_reopen(&self) -> Option<&str> {
+        self.paths
+            .first()
+            .filter(|_| self.reopen_latest)
+            .map(String::as_str)
+    }
+}
+
+/// A named keyboard setup: layout, middle C key and mapping, to switch between songs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub base_key: String,
+    pub layout: PlanckRows,
+    pub mapping: MappingSettings,
+}
+
+/// Saved presets, in the order they were created.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Presets {
+    pub presets: Vec<Preset>,
+}
+
+impl Presets {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("presets.json"))
+    }
+
+    /// Reads the stored presets; having none stored yet is not an error.
+    pub fn load() -> Result<Presets, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Presets::from_json(&fs::read_to_string(path)?),
+            _ => Ok(Presets::default()),
+        }
+    }
+
+    /// Reads presets, leaving out those whose base key is not on their layout.
+    pub fn from_json(json: &str) -> Result<Presets, ConfigError> {
+        let mut presets: Presets = serde_json::from_str(json)?;
+        presets.presets.retain(|preset| {
+            let valid = layout::key_position(&preset.layout, &preset.base_key).is_some();
+            if !valid {
+                log::warn!(
+                    "leaving out preset {:?}: base key {} is not in its layout",
+                    preset.name,
+                    preset.base_key
+                );
+            }
+            valid
+        });
+        Ok(presets)
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path().ok_or(ConfigError::NoConfigDir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Adds `preset`, replacing any preset with the same name. Returns its index.
+    pub fn save_preset(&mut self, preset: Preset) -> usize {
+        match self
+            .presets
+            .iter()
+            .position(|saved| saved.name == preset.name)
+        {
+            Some(index) => {
+                self.presets[index] = preset;
+                index
+            }
+            None => {
+                self.presets.push(preset);
+                self.presets.len() - 1
+            }
+        }
+    }
+
+    /// Renames the preset at `index`, unless another preset already has the name.
+    pub fn rename(&mut self, index: usize, name: &str) -> bool {
+        let taken = self
+            .presets
+            .iter()
+            .enumerate()
+            .any(|(other, preset)| other != index && preset.name == name);
+        match self.presets.get_mut(index) {
+            Some(preset) if !taken && !name.trim().is_empty() => {
+                preset.name = name.trim().to_owned();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Settings and window state from the last run, applied on every start.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Preferences {
+    pub settings: Settings,
+    /// Folder the last MIDI file was opened from.
+    pub last_directory: Option<PathBuf>,
+    /// Inner size of the window, in points.
+    pub window_size: Option<[f32; 2]>,
+}
+
+impl Preferences {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("preferences.json"))
+    }
+
+    /// Reads the stored preferences; having none stored yet is not an error.
+    pub fn load() -> Result<Preferences, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            _ => Ok(Preferences::default()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path().ok_or(ConfigError::NoConfigDir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Where the app was when it was closed, offered to be restored on the next start.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Session {
+    /// Path of the MIDI file that was open.
+    pub file: Option<String>,
+    /// Transpose of each track of `file`, in track order.
+    pub transposes: Vec<i8>,
+    pub selected_track: usize,
+    pub settings: Settings,
+}
+
+impl Session {
+    fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("session.json"))
+    }
+
+    /// Reads the saved session, or `None` if there is none.
+    pub fn load() -> Result<Option<Session>, ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => {
+                Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path().ok_or(ConfigError::NoConfigDir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Forgets the saved session, if any.
+    pub fn clear() -> Result<(), ConfigError> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(fs::remove_file(path)?),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_files_are_deduplicated_and_capped() {
+        let mut recent = RecentFiles::default();
+        for i in 0..RECENT_CAPACITY + 2 {
+            recent.push(&format!("song{i}.mid"));
+        }
+        recent.push("song5.mid");
+        assert_eq!(recent.paths.len(), RECENT_CAPACITY);
+        assert_eq!(recent.paths[0], "song5.mid");
+        assert_eq!(recent.paths[1], format!("song{}.mid", RECENT_CAPACITY + 1));
+        assert_eq!(
+            recent
+                .paths
+                .iter()
+                .filter(|path| *path == "song5.mid")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn latest_file_is_reopened_only_when_asked() {
+        let mut recent = RecentFiles::default();
+        recent.push("old.mid");
+        recent.push("new.mid");
+        assert_eq!(recent.file_to_reopen(), None);
+        recent.reopen_latest = true;
+        assert_eq!(recent.file_to_reopen(), Some("new.mid"));
+        recent.remove("new.mid");
+        assert_eq!(recent.file_to_reopen(), Some("old.mid"));
+    }
+
+    #[test]
+    fn presets_are_saved_by_name() {
+        let preset = |name: &str, base_key: &str| Preset {
+            name: name.to_owned(),
+            base_key: base_key.to_owned(),
+            layout: crate::default_planck_rows(),
+            mapping: MappingSettings::default(),
+        };
+        let mut presets = Presets::default();
+        assert_eq!(presets.save_preset(preset("Planck chromatic C", "ESC")), 0);
+        assert_eq!(presets.save_preset(preset("Planck chromatic G", "R")), 1);
+        assert_eq!(presets.save_preset(preset("Planck chromatic C", "TAB")), 0);
+        assert_eq!(presets.presets.len(), 2);
+        assert_eq!(presets.presets[0].base_key, "TAB");
+
+        assert!(!presets.rename(1, "Planck chromatic C"));
+        assert!(!presets.rename(1, "  "));
+        assert!(presets.rename(1, "Preonic G"));
+        assert_eq!(presets.presets[1].name, "Preonic G");
+
+        presets.save_preset(preset("Broken", "NOT A KEY"));
+        let loaded = Presets::from_json(&serde_json::to_string(&presets).unwrap()).unwrap();
+        let names: Vec<_> = loaded.presets.iter().map(|preset| &preset.name).collect();
+        assert_eq!(names, ["Planck chromatic C", "Preonic G"]);
+    }
+}
@@ -0,0 +1,139 @@
+use eframe::egui;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+};
+
+/// How many log messages the console keeps before dropping the oldest.
+const CAPACITY: usize = 500;
+
+struct LogLine {
+    level: Level,
+    target: String,
+    message: String,
+}
+
+fn buffer() -> &'static Mutex<VecDeque<LogLine>> {
+    static BUFFER: OnceLock<Mutex<VecDeque<LogLine>>> = OnceLock::new();
+    BUFFER.get_or_init(|| Mutex::new(VecDeque::with_capacity(CAPACITY)))
+}
+
+/// Forwards to `env_logger` as before and additionally keeps recent messages from this app for
+/// the in-app console.
+struct ConsoleLogger {
+    stderr: env_logger::Logger,
+}
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || captures(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            self.stderr.log(record);
+        }
+        if captures(record.metadata()) {
+            let mut buffer = buffer().lock().unwrap_or_else(|err| err.into_inner());
+            if buffer.len() == CAPACITY {
+                buffer.pop_front();
+            }
+            buffer.push_back(LogLine {
+                level: record.level(),
+                target: record.target().to_owned(),
+                message: record.args().to_string(),
+            });
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+    }
+}
+
+/// The console shows info and above from this crate, whatever `RUST_LOG` says; the
+/// dependencies are too chatty to be useful there.
+fn captures(metadata: &Metadata) -> bool {
+    metadata.level() <= Level::Info && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+}
+
+/// Installs the logger. Stderr output is still controlled by `RUST_LOG`.
+pub fn init() {
+    let stderr = env_logger::Builder::from_default_env().build();
+    let max_level = stderr.filter().max(LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(ConsoleLogger { stderr })).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Shows the buffered log messages, newest at the bottom.
+pub fn console_ui(ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.strong("Log");
+        if ui.small_button("Clear").clicked() {
+            buffer()
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clear();
+        }
+    });
+    egui::ScrollArea::vertical()
+        .auto_shrink([false, false])
+        .stick_to_bottom(true)
+        .show(ui, |ui| {
+            let buffer = buffer().lock().unwrap_or_else(|err| err.into_inner());
+            if buffer.is_empty() {
+                ui.weak("Nothing logged yet.");
+            }
+            for line in buffer.iter() {
+                let color = match line.level {
+                    Level::Error => ui.visuals().error_fg_color,
+                    Level::Warn => ui.visuals().warn_fg_color,
+                    _ => ui.visuals().text_color(),
+                };
+                ui.label(
+                    egui::RichText::new(format!(
+                        "{:<5} {}: {}",
+                        line.level, line.target, line.message
+                    ))
+                    .monospace()
+                    .color(color),
+                );
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_messages_from_this_app() {
+        let logger = ConsoleLogger {
+            stderr: env_logger::Builder::new().build(),
+        };
+        let log = |level, target, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{}", message))
+                    .build(),
+            )
+        };
+        buffer().lock().unwrap().clear();
+        log(Level::Info, "planck_scribe::config", "first");
+        log(Level::Debug, "planck_scribe::config", "too detailed");
+        log(Level::Warn, "winit", "from a dependency");
+        for index in 0..CAPACITY {
+            log(Level::Warn, "planck_scribe", &index.to_string());
+        }
+
+        let buffer = buffer().lock().unwrap();
+        assert_eq!(buffer.len(), CAPACITY);
+        assert_eq!(buffer[0].message, "0");
+        assert_eq!(buffer[CAPACITY - 1].message, (CAPACITY - 1).to_string());
+        assert!(buffer.iter().all(|line| line.target == "planck_scribe"));
+    }
+}
@@ -0,0 +1,944 @@
+use crate::{chords, layout, notes, text, MidiKeyTrack, PlanckRows, SongTiming};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    time::SystemTime,
+};
+
+/// Fills in the tokens of an export file name template. Characters that can't appear in file
+/// names, such as the slashes in arrow key labels, become `_`.
+pub fn expand_file_name(template: &str, song_name: &str, base_key: &str, date: &str) -> String {
+    template
+        .replace("{name}", song_name)
+        .replace("{basekey}", base_key)
+        .replace("{date}", date)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Today's date (UTC) as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+    // Civil-from-days conversion from Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts a Planck keyboard label into the AutoHotkey `Send` syntax for that key.
+///
+/// Returns `None` for keys that have no sensible AutoHotkey equivalent (e.g. `ORYX`,
+/// the layer keys or blank cells).
+pub fn ahk_key_name(label: &str) -> Option<String> {
+    let name = match label {
+        "TAB" => "{Tab}",
+        "BCK" => "{Backspace}",
+        "ESC" => "{Esc}",
+        "SHF" => "{Shift}",
+        "ETR" => "{Enter}",
+        "CTRL" => "{Ctrl}",
+        "ALT" => "{Alt}",
+        "OS" => "{LWin}",
+        "SPACE" => "{Space}",
+        "<-" => "{Left}",
+        "\\/" => "{Down}",
+        "/\\" => "{Up}",
+        "->" => "{Right}",
+        _ => {
+            let mut chars = label.chars();
+            return match (chars.next(), chars.next()) {
+                // These characters are modifiers or syntax in AutoHotkey, so they need braces.
+                (Some(c), None) if "+^!#{},;`%".contains(c) => Some(format!("{{{}}}", c)),
+                (Some(c), None) => Some(c.to_lowercase().to_string()),
+                _ => None,
+            };
+        }
+    };
+    Some(name.to_owned())
+}
+
+/// Builds an AutoHotkey (v1) script that "plays" the song by sending the mapped keystrokes.
+///
+/// Notes from every track are merged and sorted by their start tick. Notes starting on the
+/// same tick are sent together, and the gap until the next group becomes a `Sleep`, following
+/// the song's tempo changes.
+pub fn ahk_script(song_name: &str, tracks: &[MidiKeyTrack], timing: &SongTiming) -> String {
+    let mut pairs: Vec<_> = tracks
+        .iter()
+        .flat_map(|track| track.midi_key_pairs.iter())
+        .collect();
+    pairs.sort_by_key(|pair| pair.tick);
+
+    let mut script = String::new();
+    writeln!(script, "; Generated by Planck Scribe from {}", song_name).ok();
+    writeln!(
+        script,
+        "; WARNING: This is a novelty/experiment. It sends real keystrokes to whatever window"
+    )
+    .ok();
+    writeln!(
+        script,
+        "; has focus, so make sure the right application is active before starting."
+    )
+    .ok();
+    writeln!(script, ";").ok();
+    writeln!(script, "; Press F8 to start playing, F9 to stop.").ok();
+    writeln!(script, "#NoEnv").ok();
+    writeln!(script, "SendMode Input").ok();
+    writeln!(script).ok();
+    writeln!(script, "F9::ExitApp").ok();
+    writeln!(script).ok();
+    writeln!(script, "F8::").ok();
+
+    // Rounding the time of each group rather than each gap keeps the sleeps from drifting.
+    let time_ms = |tick: u64| {
+        timing
+            .tick_to_time_ms(tick)
+            .unwrap_or_else(|| timing.ticks_to_ms(tick))
+            .round() as u64
+    };
+    let mut index = 0;
+    while index < pairs.len() {
+        let tick = pairs[index].tick;
+        let mut keys = String::new();
+        while index < pairs.len() && pairs[index].tick == tick {
+            let pair = pairs[index];
+            match pair.keyboard_key.as_deref().and_then(ahk_key_name) {
+                Some(key) => keys += &key,
+                None => {
+                    writeln!(
+                        script,
+                        "; note {} has no key ({})",
+                        pair.midi_key,
+                        pair.keyboard_key.as_deref().unwrap_or("NONE")
+                    )
+                    .ok();
+                }
+            }
+            index += 1;
+        }
+        if !keys.is_empty() {
+            writeln!(script, "Send, {}", keys).ok();
+        }
+        if let Some(next) = pairs.get(index) {
+            let sleep_ms = time_ms(next.tick) - time_ms(tick);
+            if sleep_ms > 0 {
+                writeln!(script, "Sleep, {}", sleep_ms).ok();
+            }
+        }
+    }
+    writeln!(script, "return").ok();
+    script
+}
+
+/// Summarises which keys the song uses on each physical row of the layout, with how often each
+/// key is pressed and which notes land on it, for practising hand positions row by row.
+pub fn row_breakdown(song_name: &str, tracks: &[MidiKeyTrack], rows: &PlanckRows) -> String {
+    // (row, column) -> (label, press count, MIDI keys played on it)
+    let mut used_keys: BTreeMap<(usize, usize), (&str, usize, Vec<u8>)> = BTreeMap::new();
+    let mut unmapped = 0;
+    for pair in tracks.iter().flat_map(|track| track.midi_key_pairs.iter()) {
+        let position = pair
+            .keyboard_key
+            .as_deref()
+            .and_then(|label| layout::key_position(rows, label));
+        match (pair.keyboard_key.as_deref(), position) {
+            (Some(label), Some(position)) => {
+                let (_, count, midi_keys) =
+                    used_keys.entry(position).or_insert((label, 0, Vec::new()));
+                *count += 1;
+                if !midi_keys.contains(&pair.midi_key) {
+                    midi_keys.push(pair.midi_key);
+                }
+            }
+            _ => unmapped += 1,
+        }
+    }
+
+    let mut text = String::new();
+    writeln!(text, "Row practice breakdown for {}", song_name).ok();
+    for (row_index, row) in rows.iter().enumerate() {
+        let row_keys: Vec<_> = used_keys
+            .iter()
+            .filter(|((row, _), _)| *row == row_index)
+            .map(|(_, used)| used)
+            .collect();
+        let presses: usize = row_keys.iter().map(|(_, count, _)| count).sum();
+        writeln!(text).ok();
+        writeln!(
+            text,
+            "Row {} ({} of {} keys used, {} presses)",
+            row_index + 1,
+            row_keys.len(),
+            row.len(),
+            presses
+        )
+        .ok();
+        for (label, count, midi_keys) in row_keys {
+            let mut midi_keys = midi_keys.clone();
+            midi_keys.sort();
+            let note_names: Vec<_> = midi_keys.into_iter().map(notes::note_name).collect();
+            writeln!(
+                text,
+                "  {} x{:<5} {}",
+                text::pad_end(label, 6),
+                count,
+                note_names.join(" ")
+            )
+            .ok();
+        }
+    }
+    if unmapped > 0 {
+        writeln!(text).ok();
+        writeln!(text, "Unmapped notes: {}", unmapped).ok();
+    }
+    text
+}
+
+/// Draws the layout as a grid with the note(s) `mapping` puts on each key under its label, as a
+/// reference card to keep beside the keyboard. Keys without a note show `-`.
+pub fn keyboard_legend(rows: &PlanckRows, mapping: &HashMap<u8, String>) -> String {
+    let mut notes_on: HashMap<&str, Vec<u8>> = HashMap::new();
+    for (&midi_key, label) in mapping {
+        notes_on.entry(label).or_default().push(midi_key);
+    }
+    // (label, note names) for every cell, rows padded to the widest one.
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    let cells: Vec<Vec<(&str, String)>> = rows
+        .iter()
+        .map(|row| {
+            (0..columns)
+                .map(|column| {
+                    let label = row.get(column).map_or("", |label| label.as_str());
+                    let names = match notes_on.get_mut(label) {
+                        _ if label.is_empty() => String::new(),
+                        Some(midi_keys) => {
+                            midi_keys.sort();
+                            let names: Vec<_> =
+                                midi_keys.iter().map(|&key| notes::note_name(key)).collect();
+                            names.join("/")
+                        }
+                        None => "-".to_owned(),
+                    };
+                    (label, names)
+                })
+                .collect()
+        })
+        .collect();
+    let width = cells
+        .iter()
+        .flatten()
+        .map(|(label, names)| text::display_width(label).max(text::display_width(names)))
+        .max()
+        .unwrap_or(0);
+
+    let border = format!(
+        "+{}\n",
+        format!("{}+", "-".repeat(width + 2)).repeat(columns)
+    );
+    let mut legend = border.clone();
+    for row in &cells {
+        let (mut labels, mut names) = (String::from("|"), String::from("|"));
+        for (label, note_names) in row {
+            write!(labels, " {} |", text::pad_end(label, width)).ok();
+            write!(names, " {} |", text::pad_end(note_names, width)).ok();
+        }
+        writeln!(legend, "{}\n{}", labels, names).ok();
+        legend += &border;
+    }
+    legend
+}
+
+/// Writes a simple chord chart with one guessed chord per measure, four measures to a line.
+///
+/// Every note sounding during a measure counts towards its chord, with measures following every
+/// time signature change. Guesses that only partly match a chord are marked with `?`, and
+/// measures without a recognisable chord show `?` alone.
+pub fn chord_chart(song_name: &str, tracks: &[MidiKeyTrack], timing: &SongTiming) -> String {
+    let pairs: Vec<_> = tracks
+        .iter()
+        .flat_map(|track| track.midi_key_pairs.iter())
+        .collect();
+    let measures = pairs
+        .iter()
+        .map(|pair| pair.tick + pair.duration.unwrap_or(0))
+        .max()
+        .map_or(0, |end| timing.measure_at(end) + 1);
+
+    let mut signatures = format!("{}/{}", timing.time_signature.0, timing.time_signature.1);
+    let mut signature = timing.time_signature;
+    for &(tick, change) in &timing.time_signature_changes {
+        if change != signature {
+            let measure = timing.measure_at(tick) + 1;
+            write!(
+                signatures,
+                ", {}/{} from measure {}",
+                change.0, change.1, measure
+            )
+            .ok();
+            signature = change;
+        }
+    }
+
+    let mut text = String::new();
+    writeln!(text, "Chord chart for {}", song_name).ok();
+    writeln!(
+        text,
+        "Time signature {}. Chords are best-effort guesses; ? marks uncertain ones.",
+        signatures
+    )
+    .ok();
+    writeln!(text).ok();
+    let mut line = String::new();
+    for measure in 0..measures {
+        let start = timing.measure_start(measure);
+        let end = timing.measure_start(measure + 1);
+        let keys: Vec<u8> = pairs
+            .iter()
+            .filter(|pair| {
+                let note_end = pair.tick + pair.duration.unwrap_or(0);
+                pair.tick < end && (note_end > start || pair.tick >= start)
+            })
+            .map(|pair| pair.midi_key)
+            .collect();
+        let chord = if keys.is_empty() {
+            "-".to_owned()
+        } else {
+            match chords::name_chord(&keys) {
+                Some(guess) if guess.certain => guess.name,
+                Some(guess) => format!("{}?", guess.name),
+                None => "?".to_owned(),
+            }
+        };
+        write!(line, "| {:<7}", chord).ok();
+        if measure % 4 == 3 || measure + 1 == measures {
+            writeln!(text, "{}|", line).ok();
+            line.clear();
+        }
+    }
+    text
+}
+
+/// Token standing for `ticks` of silence in the key column of the table exports.
+pub fn rest_token(ticks: u64) -> String {
+    format!("rest:{}t", ticks)
+}
+
+/// One `[track, instrument, note, name, key]` row per note, in track order, shared by the table
+/// exports.
+///
+/// With `mark_rests`, a row whose key is a [`rest_token`] is put wherever a track falls silent
+/// between two notes, with empty note and name cells. Silence before a track's first note isn't
+/// marked.
+fn note_rows(tracks: &[MidiKeyTrack], mark_rests: bool) -> Vec<[String; 5]> {
+    let mut rows = Vec::new();
+    for track in tracks {
+        let instrument = track.instrument().unwrap_or_default();
+        // Tick the notes started so far have all ended by.
+        let mut silent_from: Option<u64> = None;
+        for pair in &track.midi_key_pairs {
+            match silent_from {
+                Some(end) if mark_rests && pair.tick > end => rows.push([
+                    track.name.clone(),
+                    instrument.to_owned(),
+                    String::new(),
+                    String::new(),
+                    rest_token(pair.tick - end),
+                ]),
+                _ => {}
+            }
+            let end = pair.tick + pair.duration.unwrap_or(0);
+            silent_from = Some(silent_from.map_or(end, |silent_from| silent_from.max(end)));
+            rows.push([
+                track.name.clone(),
+                instrument.to_owned(),
+                pair.midi_key.to_string(),
+                notes::note_name(pair.midi_key),
+                pair.keyboard_key
+                    .clone()
+                    .unwrap_or_else(|| "NONE".to_owned()),
+            ]);
+        }
+    }
+    rows
+}
+
+const TABLE_HEADER: [&str; 5] = ["track", "instrument", "note", "name", "key"];
+
+/// Every note as CSV with a `track,instrument,note,name,key` header, and rests if `mark_rests`
+/// (see [`note_rows`]).
+pub fn csv(tracks: &[MidiKeyTrack], mark_rests: bool) -> String {
+    let field = |value: &str| {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_owned()
+        }
+    };
+    let mut text = String::new();
+    writeln!(text, "{}", TABLE_HEADER.join(",")).ok();
+    for row in note_rows(tracks, mark_rests) {
+        let fields: Vec<_> = row.iter().map(|value| field(value)).collect();
+        writeln!(text, "{}", fields.join(",")).ok();
+    }
+    text
+}
+
+/// Every note as a Markdown table, for pasting into issues and documentation. Rests are marked
+/// as in [`csv`].
+pub fn markdown_table(tracks: &[MidiKeyTrack], mark_rests: bool) -> String {
+    let cell = |value: &str| value.replace('|', "\\|");
+    let mut text = String::new();
+    writeln!(text, "| {} |", TABLE_HEADER.join(" | ")).ok();
+    writeln!(text, "|{}", " --- |".repeat(TABLE_HEADER.len())).ok();
+    for row in note_rows(tracks, mark_rests) {
+        let cells: Vec<_> = row.iter().map(|value| cell(value)).collect();
+        writeln!(text, "| {} |", cells.join(" | ")).ok();
+    }
+    text
+}
+
+/// Measures of the key sequence written on one line when grouping by measure.
+const BARS_PER_LINE: usize = 4;
+
+/// The keys to press for each track, for playing along without the app. Chords of several notes
+/// have their keys bracketed, like `[A S D]`, and unmapped notes show `NONE`.
+///
+/// Without `bars` there is one line per chord with its notes and keys. With the song's timing
+/// in `bars`, the keys are grouped into measures instead, four to a line after the number of
+/// the first, like `  5 | A S D | - | F G H | J |`, with `-` for measures without notes.
+pub fn key_sequence(song_name: &str, tracks: &[MidiKeyTrack], bars: Option<&SongTiming>) -> String {
+    let mut text = String::new();
+    writeln!(text, "Key sequence for {}", song_name).ok();
+    for track in tracks {
+        writeln!(text).ok();
+        match track.instrument() {
+            Some(instrument) => writeln!(text, "{} ({})", track.name, instrument).ok(),
+            None => writeln!(text, "{}", track.name).ok(),
+        };
+        if let Some(timing) = bars {
+            write_bars(&mut text, track, timing);
+            continue;
+        }
+        let lines: Vec<_> = track
+            .chords()
+            .iter()
+            .map(|chord| {
+                let names: Vec<_> = chord
+                    .pair_indices
+                    .iter()
+                    .map(|&index| notes::note_name(track.midi_key_pairs[index].midi_key))
+                    .collect();
+                (names.join(" "), chord.keys(track))
+            })
+            .collect();
+        let width = lines
+            .iter()
+            .map(|(names, _)| text::display_width(names))
+            .max()
+            .unwrap_or(0);
+        for (names, keys) in lines {
+            writeln!(text, "  {}  {}", text::pad_end(&names, width), keys).ok();
+        }
+    }
+    text
+}
+
+/// The chords of `track` measure by measure, [`BARS_PER_LINE`] measures to a line.
+fn write_bars(text: &mut String, track: &MidiKeyTrack, timing: &SongTiming) {
+    let mut measures: Vec<Vec<String>> = Vec::new();
+    for chord in track.chords() {
+        let measure = timing.measure_at(chord.tick);
+        if measures.len() <= measure {
+            measures.resize_with(measure + 1, Vec::new);
+        }
+        measures[measure].push(chord.keys(track));
+    }
+    let number_width = measures.len().to_string().len();
+    for (line, bars) in measures.chunks(BARS_PER_LINE).enumerate() {
+        let number = line * BARS_PER_LINE + 1;
+        write!(text, "  {:>width$} |", number, width = number_width).ok();
+        for keys in bars {
+            if keys.is_empty() {
+                write!(text, " - |").ok();
+            } else {
+                write!(text, " {} |", keys.join(" ")).ok();
+            }
+        }
+        writeln!(text).ok();
+    }
+}
+
+/// `tracks` as a standard MIDI file: a first track with the tempo and time signature changes,
+/// then one track per exported track with its name and notes. Everything is moved `start` ticks
+/// earlier, so a selection from the middle of a song starts right away.
+///
+/// Notes that never ended in the original file are held for one beat.
+pub fn midi_file(
+    tracks: &[MidiKeyTrack],
+    timing: &SongTiming,
+    start: u64,
+) -> std::io::Result<Vec<u8>> {
+    use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+    // Turns `(tick, order, event)` into delta-timed events. At the same tick, lower orders come
+    // first, so a note ends before the same key is struck again.
+    fn to_track(mut events: Vec<(u64, u8, TrackEventKind)>) -> Vec<TrackEvent> {
+        events.sort_by_key(|(tick, order, _)| (*tick, *order));
+        let mut last_tick = 0;
+        let mut track: Vec<TrackEvent> = events
+            .into_iter()
+            .map(|(tick, _, kind)| {
+                let delta = (tick - last_tick).min(u32::MAX as u64) as u32;
+                last_tick = tick;
+                TrackEvent {
+                    delta: delta.into(),
+                    kind,
+                }
+            })
+            .collect();
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        track
+    }
+
+    let tempo_at_start = timing
+        .tempo_changes
+        .iter()
+        .rev()
+        .find(|(tick, _)| *tick <= start)
+        .map_or(timing.tempo, |&(_, tempo)| tempo);
+    let signature_at_start = timing
+        .time_signature_changes
+        .iter()
+        .rev()
+        .find(|(tick, _)| *tick <= start)
+        .map_or(timing.time_signature, |&(_, signature)| signature);
+    let time_signature = |(beats, note_value): (u8, u8)| {
+        TrackEventKind::Meta(MetaMessage::TimeSignature(
+            beats,
+            note_value.max(1).trailing_zeros() as u8,
+            24,
+            8,
+        ))
+    };
+    let mut tempo_track = vec![
+        (
+            0,
+            0,
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo_at_start.into())),
+        ),
+        (0, 0, time_signature(signature_at_start)),
+    ];
+    tempo_track.extend(
+        timing
+            .time_signature_changes
+            .iter()
+            .filter(|(tick, _)| *tick > start)
+            .map(|&(tick, signature)| (tick - start, 0, time_signature(signature))),
+    );
+    tempo_track.extend(
+        timing
+            .tempo_changes
+            .iter()
+            .filter(|(tick, _)| *tick > start)
+            .map(|&(tick, tempo)| {
+                (
+                    tick - start,
+                    0,
+                    TrackEventKind::Meta(MetaMessage::Tempo(tempo.into())),
+                )
+            }),
+    );
+
+    let beat = timing.ticks_per_beat().round() as u64;
+    let mut midi_tracks = vec![to_track(tempo_track)];
+    for track in tracks {
+        let mut events = vec![(
+            0,
+            0,
+            TrackEventKind::Meta(MetaMessage::TrackName(track.name.as_bytes())),
+        )];
+        for pair in &track.midi_key_pairs {
+            let tick = pair.tick.saturating_sub(start);
+            let channel = pair.channel.into();
+            let key = pair.midi_key.into();
+            events.push((
+                tick,
+                2,
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOn {
+                        key,
+                        vel: pair.velocity.max(1).into(),
+                    },
+                },
+            ));
+            events.push((
+                tick + pair.duration.unwrap_or(beat),
+                1,
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel: 0.into() },
+                },
+            ));
+        }
+        midi_tracks.push(to_track(events));
+    }
+
+    let smf = midly::Smf {
+        header: midly::Header::new(midly::Format::Parallel, timing.timing),
+        tracks: midi_tracks,
+    };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    fn track(name: &str) -> MidiKeyTrack {
+        let mut track = MidiKeyTrack::new();
+        track.name = name.to_owned();
+        track.midi_key_pairs.push(MidiKeyPair {
+            delta: 0,
+            tick: 0,
+            duration: Some(480),
+            midi_key: 60,
+            velocity: 100,
+            channel: 0,
+            keyboard_key: Some("ESC".to_owned()),
+            substituted: false,
+        });
+        track
+    }
+
+    #[test]
+    fn file_name_templates_fill_in_their_tokens() {
+        assert_eq!(
+            expand_file_name("{name}-{basekey}-{date}", "Song", "ESC", "2024-05-01"),
+            "Song-ESC-2024-05-01"
+        );
+        // Arrow key labels are made of slashes.
+        assert_eq!(
+            expand_file_name("{basekey} {name}", "Song", "/\\", ""),
+            "__ Song"
+        );
+        assert_eq!(expand_file_name("{other}", "Song", "ESC", ""), "{other}");
+
+        let date = today();
+        assert_eq!(date.len(), 10);
+        assert!(date
+            .chars()
+            .enumerate()
+            .all(|(index, c)| if index == 4 || index == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }));
+    }
+
+    #[test]
+    fn keyboard_legend_puts_notes_under_their_keys() {
+        let rows = vec![
+            vec!["ESC".to_owned(), "Q".to_owned(), "W".to_owned()],
+            vec!["SPACE".to_owned()],
+        ];
+        let mapping = HashMap::from([
+            (60, "ESC".to_owned()),
+            (61, "Q".to_owned()),
+            (73, "Q".to_owned()),
+        ]);
+        assert_eq!(
+            keyboard_legend(&rows, &mapping),
+            "+---------+---------+---------+\n\
+             | ESC     | Q       | W       |\n\
+             | C4      | C#4/C#5 | -       |\n\
+             +---------+---------+---------+\n\
+             | SPACE   |         |         |\n\
+             | -       |         |         |\n\
+             +---------+---------+---------+\n"
+        );
+    }
+
+    #[test]
+    fn ahk_script_sleeps_follow_tempo_changes() {
+        assert_eq!(ahk_key_name("/\\").as_deref(), Some("{Up}"));
+        assert_eq!(ahk_key_name("BCK").as_deref(), Some("{Backspace}"));
+        assert_eq!(ahk_key_name("Q").as_deref(), Some("q"));
+        assert_eq!(ahk_key_name("%").as_deref(), Some("{%}"));
+        assert_eq!(ahk_key_name("ORYX"), None);
+        assert_eq!(ahk_key_name(""), None);
+
+        let mut melody = track("Melody");
+        let note = melody.midi_key_pairs[0].clone();
+        melody.midi_key_pairs.clear();
+        for (tick, key) in [(0, "A"), (0, ";"), (480, "SPACE"), (960, "ORYX")] {
+            melody.midi_key_pairs.push(MidiKeyPair {
+                tick,
+                keyboard_key: Some(key.to_owned()),
+                ..note.clone()
+            });
+        }
+        // 120 BPM for the first beat, then twice as fast.
+        let timing = SongTiming {
+            tempo_changes: vec![(0, 500_000), (480, 250_000)],
+            ..SongTiming::default()
+        };
+        let script = ahk_script("song", &[melody], &timing);
+        assert!(script.starts_with("; Generated by Planck Scribe from song\n; WARNING:"));
+        assert!(
+            script.ends_with(
+                "F8::\n\
+                 Send, a{;}\n\
+                 Sleep, 500\n\
+                 Send, {Space}\n\
+                 Sleep, 250\n\
+                 ; note 60 has no key (ORYX)\n\
+                 return\n"
+            ),
+            "{script}"
+        );
+    }
+
+    #[test]
+    fn row_breakdown_counts_the_keys_of_each_row() {
+        let mut melody = track("Melody");
+        let note = melody.midi_key_pairs[0].clone();
+        melody.midi_key_pairs.clear();
+        for (midi_key, key) in [
+            (60, Some("A")),
+            (62, Some("A")),
+            (64, Some("Q")),
+            (65, None),
+        ] {
+            melody.midi_key_pairs.push(MidiKeyPair {
+                midi_key,
+                keyboard_key: key.map(str::to_owned),
+                ..note.clone()
+            });
+        }
+        let text = row_breakdown("song", &[melody], &crate::default_planck_rows());
+        assert!(text.starts_with(
+            "Row practice breakdown for song\n\n\
+             Row 1 (1 of 12 keys used, 1 presses)\n\
+             \x20 Q      x1     E4\n\n\
+             Row 2 (1 of 12 keys used, 2 presses)\n\
+             \x20 A      x2     C4 D4\n\n\
+             Row 3 (0 of 12 keys used, 0 presses)\n"
+        ));
+        assert!(text.ends_with("\nUnmapped notes: 1\n"));
+    }
+
+    #[test]
+    fn markdown_table_escapes_pipes() {
+        let table = markdown_table(&[track("Piano | Left hand")], false);
+        assert_eq!(
+            table,
+            "| track | instrument | note | name | key |\n| --- | --- | --- | --- | --- |\n\
+             | Piano \\| Left hand |  | 60 | C4 | ESC |\n"
+        );
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_commas() {
+        let csv = csv(&[track("Piano, \"left\"")], false);
+        assert_eq!(
+            csv,
+            "track,instrument,note,name,key\n\"Piano, \"\"left\"\"\",,60,C4,ESC\n"
+        );
+    }
+
+    #[test]
+    fn csv_uses_the_instrument_override() {
+        let mut piano = track("Left hand");
+        piano.program_changes.push(crate::ProgramChange {
+            tick: 0,
+            name: "Flute".to_owned(),
+            channel: 0,
+        });
+        assert!(csv(&[piano.clone()], false).ends_with("Left hand,Flute,60,C4,ESC\n"));
+        piano.instrument = Some("Violin".to_owned());
+        assert!(csv(&[piano], false).ends_with("Left hand,Violin,60,C4,ESC\n"));
+    }
+
+    #[test]
+    fn chord_chart_follows_meter_changes() {
+        // A C major chord in the first 4/4 measure, then A minor and G major in 3/4.
+        let mut chords = MidiKeyTrack::new();
+        for (tick, keys) in [
+            (0, [60, 64, 67]),
+            (1920, [57, 60, 64]),
+            (3360, [55, 59, 62]),
+        ] {
+            for key in keys {
+                chords.midi_key_pairs.push(MidiKeyPair {
+                    tick,
+                    duration: Some(1439),
+                    midi_key: key,
+                    ..track("Chords").midi_key_pairs[0].clone()
+                });
+            }
+        }
+        let timing = SongTiming {
+            time_signature_changes: vec![(0, (4, 4)), (1920, (3, 4))],
+            ..SongTiming::default()
+        };
+        let chart = chord_chart("Song", &[chords], &timing);
+        assert!(chart.contains("Time signature 4/4, 3/4 from measure 2."));
+        // A second 4/4 measure would hold both the A minor and the G major chord.
+        assert!(
+            chart.contains("| Cmaj   | Am     | Gmaj   |\n"),
+            "{}",
+            chart
+        );
+    }
+
+    #[test]
+    fn midi_export_loads_back_with_the_same_notes() {
+        let mut selection = track("Piano");
+        let note = selection.midi_key_pairs.remove(0);
+        selection.midi_key_pairs = vec![
+            MidiKeyPair {
+                tick: 960,
+                duration: Some(240),
+                midi_key: 64,
+                ..note.clone()
+            },
+            MidiKeyPair {
+                tick: 1200,
+                duration: None,
+                ..note
+            },
+        ];
+        let timing = SongTiming {
+            tempo_changes: vec![(0, 400_000), (1200, 600_000)],
+            tempo: 400_000,
+            time_signature_changes: vec![(0, (4, 4)), (960, (3, 4))],
+            ..SongTiming::default()
+        };
+        // The selection starts on the second beat.
+        let bytes = midi_file(&[selection], &timing, 480).unwrap();
+
+        let (tracks, loaded_timing) =
+            crate::load_midi(&bytes, crate::settings::DuplicateNotes::KeepAll).unwrap();
+        let piano = tracks.iter().find(|track| track.name == "Piano").unwrap();
+        let notes: Vec<_> = piano
+            .midi_key_pairs
+            .iter()
+            .map(|pair| (pair.tick, pair.duration, pair.midi_key, pair.velocity))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![(480, Some(240), 64, 100), (720, Some(480), 60, 100)]
+        );
+        assert_eq!(
+            loaded_timing.tempo_changes,
+            vec![(0, 400_000), (720, 600_000)]
+        );
+        assert_eq!(loaded_timing.time_signature, (4, 4));
+        assert_eq!(
+            loaded_timing.time_signature_changes,
+            vec![(0, (4, 4)), (480, (3, 4))]
+        );
+    }
+
+    #[test]
+    fn rests_are_marked_between_notes() {
+        let mut piano = track("Piano");
+        let note = piano.midi_key_pairs[0].clone();
+        piano.midi_key_pairs.extend([
+            // Starts as the first note ends, so there is no rest before it.
+            MidiKeyPair {
+                tick: 480,
+                duration: Some(960),
+                ..note.clone()
+            },
+            MidiKeyPair {
+                tick: 600,
+                duration: Some(120),
+                ..note.clone()
+            },
+            MidiKeyPair { tick: 1920, ..note },
+        ]);
+        let csv = csv(&[piano], true);
+        let keys: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap())
+            .collect();
+        assert_eq!(keys, vec!["ESC", "ESC", "ESC", "rest:480t", "ESC"]);
+        assert!(csv.contains("Piano,,,,rest:480t\n"));
+    }
+
+    #[test]
+    fn key_sequence_lists_each_chord_with_its_keys() {
+        let mut melody = track("Melody");
+        melody.midi_key_pairs.push(MidiKeyPair {
+            tick: 0,
+            midi_key: 64,
+            keyboard_key: Some("Q".to_owned()),
+            ..melody.midi_key_pairs[0].clone()
+        });
+        melody.midi_key_pairs.push(MidiKeyPair {
+            tick: 480,
+            midi_key: 62,
+            keyboard_key: None,
+            ..melody.midi_key_pairs[0].clone()
+        });
+        assert_eq!(
+            key_sequence("song", &[melody], None),
+            "Key sequence for song\n\
+             \n\
+             Melody\n  \
+             C4 E4  [ESC Q]\n  \
+             D4     NONE\n"
+        );
+    }
+
+    #[test]
+    fn key_sequence_can_be_grouped_by_measure() {
+        let mut melody = track("Melody");
+        let note = melody.midi_key_pairs[0].clone();
+        melody.midi_key_pairs.clear();
+        // 4/4 at 480 ticks per beat: measures start every 1920 ticks.
+        for (tick, key) in [(0, "A"), (0, "S"), (960, "D"), (5760, "F"), (9600, "G")] {
+            melody.midi_key_pairs.push(MidiKeyPair {
+                tick,
+                keyboard_key: Some(key.to_owned()),
+                ..note.clone()
+            });
+        }
+        assert_eq!(
+            key_sequence("song", &[melody], Some(&SongTiming::default())),
+            "Key sequence for song\n\
+             \n\
+             Melody\n  \
+             1 | [A S] D | - | - | F |\n  \
+             5 | - | G |\n"
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use crate::MidiKeyTrack;
+use std::collections::HashMap;
+
+/// Transposes tried by the auto-fit: whole octaves, so the melody itself stays the same.
+const OCTAVE_SHIFTS: [i8; 9] = [0, -12, 12, -24, 24, -36, 36, -48, 48];
+
+/// Number of notes in `tracks` that have a key of their own when shifted by `transpose` on top
+/// of each track's own transpose and the whole song's `song_transpose`. Drum and hidden tracks
+/// are left out, as shifting them doesn't help.
+fn playable_notes(
+    tracks: &[&MidiKeyTrack],
+    mapping: &HashMap<u8, String>,
+    song_transpose: i8,
+    transpose: i8,
+) -> usize {
+    tracks
+        .iter()
+        .filter(|track| !track.percussion && !track.hidden)
+        .flat_map(|track| {
+            track.midi_key_pairs.iter().filter(|pair| {
+                track
+                    .sounding_key(pair.midi_key, song_transpose.saturating_add(transpose))
+                    .is_some_and(|key| mapping.contains_key(&key))
+            })
+        })
+        .count()
+}
+
+/// The octave shift, on top of the tracks' current transposes, that lets the most notes of
+/// `tracks` be played, preferring the smallest shift on ties. Shifting every track by the same amount keeps their parts aligned.
+pub fn best_octave_shift(
+    tracks: &[&MidiKeyTrack],
+    mapping: &HashMap<u8, String>,
+    song_transpose: i8,
+) -> i8 {
+    let mut best = (0, 0);
+    for shift in OCTAVE_SHIFTS {
+        let playable = playable_notes(tracks, mapping, song_transpose, shift);
+        if playable > best.1 {
+            best = (shift, playable);
+        }
+    }
+    best.0
+}
+
+/// Percentage of notes across `tracks` that are mapped to a key of their own (not substituted).
+pub fn playable_percent(tracks: &[MidiKeyTrack]) -> f32 {
+    let pairs = || tracks.iter().flat_map(|track| track.midi_key_pairs.iter());
+    let total = pairs().count();
+    if total == 0 {
+        return 100.0;
+    }
+    let playable = pairs()
+        .filter(|pair| pair.keyboard_key.is_some() && !pair.substituted)
+        .count();
+    playable as f32 * 100.0 / total as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chromatic_planck_mapping, default_planck_rows, MidiKeyPair};
+
+    fn track(keys: &[u8]) -> MidiKeyTrack {
+        let mut track = MidiKeyTrack::new();
+        for (i, &key) in keys.iter().enumerate() {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick: i as u64 * 480,
+                duration: Some(480),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        track
+    }
+
+    #[test]
+    fn fits_each_track_into_range() {
+        // The default layout covers MIDI keys 48 to 95.
+        let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+        let melody = track(&[60, 64, 67]);
+        let bass = track(&[24, 28, 31]);
+        assert_eq!(best_octave_shift(&[&melody], &mapping, 0), 0);
+        assert_eq!(best_octave_shift(&[&bass], &mapping, 0), 24);
+        // Two octaves up brings the bass into range without pushing the melody out.
+        assert_eq!(best_octave_shift(&[&melody, &bass], &mapping, 0), 24);
+    }
+
+    #[test]
+    fn fit_skips_drums_and_starts_from_the_track_transpose() {
+        let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+        let melody = track(&[24, 28, 31]);
+        // More drum hits than melody notes, which would keep the song where it is if counted.
+        let drums = MidiKeyTrack {
+            percussion: true,
+            ..track(&[80, 82, 84, 86])
+        };
+        assert_eq!(best_octave_shift(&[&melody, &drums], &mapping, 0), 24);
+        let hidden = MidiKeyTrack {
+            hidden: true,
+            ..track(&[80, 82, 84, 86])
+        };
+        assert_eq!(best_octave_shift(&[&melody, &hidden], &mapping, 0), 24);
+        // Already shifted into range, the melody needs nothing more.
+        let shifted = MidiKeyTrack {
+            transpose: 24,
+            ..track(&[24, 28, 31])
+        };
+        assert_eq!(best_octave_shift(&[&shifted], &mapping, 0), 0);
+    }
+}
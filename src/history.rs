@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+/// Most steps that can be undone; older ones are forgotten.
+const LIMIT: usize = 100;
+/// Edits of the same kind this close together are undone as one step, so dragging a value
+/// doesn't leave a step for every frame.
+const MERGE_WINDOW: Duration = Duration::from_millis(800);
+
+struct Step<T> {
+    label: &'static str,
+    /// The state before the step, for undo, or after it, for redo.
+    state: T,
+}
+
+/// Undo and redo stacks of snapshots of the state edits change.
+pub struct History<T> {
+    undo: Vec<Step<T>>,
+    redo: Vec<Step<T>>,
+    /// Kind and time of the last recorded edit.
+    last_edit: Option<(&'static str, Instant)>,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            last_edit: None,
+        }
+    }
+}
+
+impl<T> History<T> {
+    /// Records an edit described by `label`, e.g. "transposition", that changed the state from
+    /// `before`. Anything undone so far can no longer be redone.
+    pub fn record(&mut self, label: &'static str, before: T, now: Instant) {
+        self.redo.clear();
+        let merge = matches!(
+            self.last_edit,
+            Some((last, at)) if last == label && now.duration_since(at) < MERGE_WINDOW
+        );
+        if !merge || self.undo.is_empty() {
+            self.undo.push(Step {
+                label,
+                state: before,
+            });
+            if self.undo.len() > LIMIT {
+                self.undo.remove(0);
+            }
+        }
+        self.last_edit = Some((label, now));
+    }
+
+    /// The state before the last edit, given the `current` one to redo to.
+    pub fn undo(&mut self, current: T) -> Option<T> {
+        let step = self.undo.pop()?;
+        self.redo.push(Step {
+            label: step.label,
+            state: current,
+        });
+        self.last_edit = None;
+        Some(step.state)
+    }
+
+    /// The state after the last undone edit, given the `current` one to undo to.
+    pub fn redo(&mut self, current: T) -> Option<T> {
+        let step = self.redo.pop()?;
+        self.undo.push(Step {
+            label: step.label,
+            state: current,
+        });
+        self.last_edit = None;
+        Some(step.state)
+    }
+
+    /// What undo would take back, if anything.
+    pub fn undo_label(&self) -> Option<&'static str> {
+        self.undo.last().map(|step| step.label)
+    }
+
+    /// What redo would bring back, if anything.
+    pub fn redo_label(&self) -> Option<&'static str> {
+        self.redo.last().map(|step| step.label)
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_are_undone_and_redone_in_order() {
+        let start = Instant::now();
+        let mut history = History::default();
+        // Dragging a transpose from 0 to 3 is one step.
+        history.record("transposition", 0, start);
+        history.record("transposition", 1, start + Duration::from_millis(100));
+        history.record("transposition", 2, start + Duration::from_millis(200));
+        history.record("base key change", 3, start + Duration::from_millis(300));
+        history.record("base key change", 4, start + Duration::from_secs(5));
+
+        assert_eq!(history.undo_label(), Some("base key change"));
+        assert_eq!(history.undo(5), Some(4));
+        assert_eq!(history.undo(4), Some(3));
+        assert_eq!(history.undo(3), Some(0));
+        assert_eq!(history.undo(0), None);
+        assert_eq!(history.redo_label(), Some("transposition"));
+        assert_eq!(history.redo(0), Some(3));
+
+        // A new edit drops what was left to redo.
+        history.record("layout edit", 3, start + Duration::from_secs(10));
+        assert_eq!(history.redo_label(), None);
+        assert_eq!(history.undo(6), Some(3));
+        assert_eq!(history.undo(3), Some(0));
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{layout, MidiKeyTrack, NoteFilter, PlanckRows};
+
+/// Distance between two keys on the grid, counted in rows plus columns.
+fn key_distance(a: (usize, usize), b: (usize, usize)) -> usize {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+}
+
+/// Indices of the notes of `track` that are reached by a difficult jump: their key is at least
+/// `min_distance` rows plus columns away from every key of the previous onset.
+///
+/// Unmapped notes, keys missing from the layout and notes the filter hides are passed over, so
+/// a jump is measured from the last notes that were actually played.
+pub fn difficult_jumps(
+    track: &MidiKeyTrack,
+    rows: &PlanckRows,
+    filter: &NoteFilter,
+    min_distance: usize,
+) -> Vec<usize> {
+    let mut jumps = Vec::new();
+    // Positions of the previous onset's keys, and of the current one's so far.
+    let mut previous: Vec<(usize, usize)> = Vec::new();
+    let mut current: Vec<(usize, usize)> = Vec::new();
+    let mut current_tick = None;
+    for (index, pair) in track.midi_key_pairs.iter().enumerate() {
+        if filter.hides(pair) {
+            continue;
+        }
+        let Some(position) = pair
+            .keyboard_key
+            .as_deref()
+            .and_then(|label| layout::key_position(rows, label))
+        else {
+            continue;
+        };
+        if current_tick != Some(pair.tick) {
+            previous = std::mem::take(&mut current);
+            current_tick = Some(pair.tick);
+        }
+        let nearest = previous
+            .iter()
+            .map(|&from| key_distance(from, position))
+            .min();
+        if nearest.is_some_and(|distance| distance >= min_distance) {
+            jumps.push(index);
+        }
+        current.push(position);
+    }
+    jumps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_planck_rows, MidiKeyPair};
+
+    #[test]
+    fn flags_notes_far_from_the_previous_onset() {
+        let mut track = MidiKeyTrack::new();
+        // ESC is at (1,0), Q at (0,1), ETR at (2,11) and P at (0,10).
+        for (tick, key) in [
+            (0, "ESC"),
+            (480, "Q"),
+            (960, "ETR"),
+            (960, "P"),
+            (1440, "ESC"),
+        ] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: Some(key.to_owned()),
+                substituted: false,
+            });
+        }
+        let filter = NoteFilter::default();
+        let rows = default_planck_rows();
+        assert_eq!(difficult_jumps(&track, &rows, &filter, 5), vec![2, 3, 4]);
+        // P is nine keys from Q; ESC is eleven keys from P, the nearer key of that chord.
+        assert_eq!(difficult_jumps(&track, &rows, &filter, 10), vec![2, 4]);
+    }
+}
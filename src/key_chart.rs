@@ -0,0 +1,189 @@
+use crate::{MidiKeyTrack, SongTiming};
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// A4 portrait.
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const LINE_HEIGHT: f32 = 5.0;
+const FONT_SIZE: f32 = 10.0;
+/// Characters of 10 pt Courier that fit between the margins.
+const LINE_CHARS: usize = 84;
+/// Lines of the chart on a page, below the title and above the page number.
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2.0 * MARGIN) / LINE_HEIGHT) as usize - 4;
+/// Width of the measure numbers in front of each line.
+const NUMBER_WIDTH: usize = 5;
+
+/// One printed line of a key chart.
+#[derive(PartialEq, Debug)]
+pub enum ChartLine {
+    /// Name (and instrument) of the track the following measures belong to.
+    Track(String),
+    /// The keys of a measure, chords bracketed. `number` is left out where a long measure
+    /// continues on the next line.
+    Measure { number: Option<usize>, keys: String },
+}
+
+/// The key sequence of `tracks` measure by measure, each track under its own header. Measures
+/// without notes are marked `-`, so the numbering can be followed while playing.
+pub fn chart_lines(tracks: &[MidiKeyTrack], timing: &SongTiming) -> Vec<ChartLine> {
+    let mut lines = Vec::new();
+    for track in tracks {
+        lines.push(ChartLine::Track(match track.instrument() {
+            Some(instrument) => format!("{} ({})", track.name, instrument),
+            None => track.name.clone(),
+        }));
+        let mut measures: Vec<Vec<String>> = Vec::new();
+        for chord in track.chords() {
+            let measure = timing.measure_at(chord.tick);
+            if measures.len() <= measure {
+                measures.resize_with(measure + 1, Vec::new);
+            }
+            measures[measure].push(chord.keys(track));
+        }
+        for (index, keys) in measures.into_iter().enumerate() {
+            if keys.is_empty() {
+                lines.push(ChartLine::Measure {
+                    number: Some(index + 1),
+                    keys: "-".to_owned(),
+                });
+                continue;
+            }
+            let mut number = Some(index + 1);
+            let mut line = String::new();
+            for key in keys {
+                if !line.is_empty() && line.len() + 2 + key.len() > LINE_CHARS - NUMBER_WIDTH {
+                    lines.push(ChartLine::Measure {
+                        number: number.take(),
+                        keys: std::mem::take(&mut line),
+                    });
+                }
+                if !line.is_empty() {
+                    line.push_str("  ");
+                }
+                line.push_str(&key);
+            }
+            lines.push(ChartLine::Measure { number, keys: line });
+        }
+    }
+    lines
+}
+
+/// Splits `lines` into pages of at most `per_page` lines. A track header is never left alone at
+/// the bottom of a page.
+fn paginate(lines: &[ChartLine], per_page: usize) -> Vec<&[ChartLine]> {
+    let mut pages = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let mut end = (start + per_page.max(2)).min(lines.len());
+        if end < lines.len() && matches!(lines[end - 1], ChartLine::Track(_)) {
+            end -= 1;
+        }
+        pages.push(&lines[start..end]);
+        start = end;
+    }
+    pages
+}
+
+/// Writes a printable A4 chart of `lines`, titled with the song name and with the page number
+/// at the bottom of each page.
+pub fn write_pdf(path: &Path, song_name: &str, lines: &[ChartLine]) -> Result<(), printpdf::Error> {
+    let title = format!("Key chart for {}", song_name);
+    let (document, first_page, first_layer) =
+        PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Chart");
+    let heading = document.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let regular = document.add_builtin_font(BuiltinFont::Courier)?;
+    let bold = document.add_builtin_font(BuiltinFont::CourierBold)?;
+
+    let pages = paginate(lines, LINES_PER_PAGE);
+    let page_count = pages.len().max(1);
+    for (page_index, page_lines) in pages.into_iter().enumerate() {
+        let layer = if page_index == 0 {
+            document.get_page(first_page).get_layer(first_layer)
+        } else {
+            let (page, layer) = document.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Chart");
+            document.get_page(page).get_layer(layer)
+        };
+        let mut y = PAGE_HEIGHT - MARGIN;
+        layer.use_text(&title, 14.0, Mm(MARGIN), Mm(y), &heading);
+        y -= 2.0 * LINE_HEIGHT;
+        for line in page_lines {
+            match line {
+                ChartLine::Track(name) => {
+                    y -= LINE_HEIGHT / 2.0;
+                    layer.use_text(name, FONT_SIZE + 1.0, Mm(MARGIN), Mm(y), &bold);
+                }
+                ChartLine::Measure { number, keys } => {
+                    let number = number.map_or(String::new(), |number| number.to_string());
+                    let text = format!("{:>width$} {}", number, keys, width = NUMBER_WIDTH - 1);
+                    layer.use_text(text, FONT_SIZE, Mm(MARGIN), Mm(y), &regular);
+                }
+            }
+            y -= LINE_HEIGHT;
+        }
+        layer.use_text(
+            format!("Page {} of {}", page_index + 1, page_count),
+            8.0,
+            Mm(PAGE_WIDTH / 2.0 - 10.0),
+            Mm(MARGIN / 2.0),
+            &regular,
+        );
+    }
+    document.save(&mut BufWriter::new(File::create(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    #[test]
+    fn chart_is_laid_out_by_measure() {
+        let mut track = MidiKeyTrack::new();
+        track.name = "Piano".to_owned();
+        // 4/4 at 480 ticks per beat: 1920 ticks per measure.
+        for (tick, key) in [(0, "A"), (0, "S"), (960, "D"), (3840, "F")] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: Some(key.to_owned()),
+                substituted: false,
+            });
+        }
+        let measure = |number: Option<usize>, keys: &str| ChartLine::Measure {
+            number,
+            keys: keys.to_owned(),
+        };
+        assert_eq!(
+            chart_lines(&[track], &SongTiming::default()),
+            vec![
+                ChartLine::Track("Piano".to_owned()),
+                measure(Some(1), "[A S]  D"),
+                measure(Some(2), "-"),
+                measure(Some(3), "F"),
+            ]
+        );
+    }
+
+    #[test]
+    fn track_headers_stay_with_their_measures() {
+        let measure = || ChartLine::Measure {
+            number: Some(1),
+            keys: "A".to_owned(),
+        };
+        let lines = vec![
+            ChartLine::Track("One".to_owned()),
+            measure(),
+            measure(),
+            ChartLine::Track("Two".to_owned()),
+            measure(),
+        ];
+        let pages: Vec<usize> = paginate(&lines, 4).iter().map(|page| page.len()).collect();
+        assert_eq!(pages, vec![3, 2]);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{default_planck_rows, PlanckRows};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use thiserror::*;
+
+/// A keyboard layout: rows of key labels, left to right and top to bottom.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Layout {
+    pub name: String,
+    pub rows: PlanckRows,
+}
+
+/// Builds rows from string literals.
+fn rows(rows: &[&[&str]]) -> PlanckRows {
+    rows.iter()
+        .map(|row| row.iter().map(|key| key.to_string()).collect())
+        .collect()
+}
+
+/// Layouts that come with the app, to pick from without a layout file.
+///
+/// Layouts are plain grids: split and staggered boards are flattened into rows, with blank
+/// labels for gaps and the halves of wide keys.
+pub fn builtin_layouts() -> Vec<Layout> {
+    vec![
+        Layout {
+            name: "Planck".to_owned(),
+            rows: default_planck_rows(),
+        },
+        Layout {
+            name: "Preonic".to_owned(),
+            rows: rows(&[
+                &["`", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "BCK"],
+                &[
+                    "TAB", "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "DEL",
+                ],
+                &["ESC", "A", "S", "D", "F", "G", "H", "J", "K", "L", ";", "'"],
+                &[
+                    "SHF", "Z", "X", "C", "V", "B", "N", "M", ",", ".", "/", "ETR",
+                ],
+                &[
+                    "", "CTRL", "ALT", "OS", "LOWER", "SPACE", "", "RAISE", "<-", "\\/", "/\\",
+                    "->",
+                ],
+            ]),
+        },
+        Layout {
+            name: "Corne".to_owned(),
+            rows: rows(&[
+                &[
+                    "TAB", "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "BCK",
+                ],
+                &[
+                    "CTRL", "A", "S", "D", "F", "G", "H", "J", "K", "L", ";", "'",
+                ],
+                &[
+                    "SHF", "Z", "X", "C", "V", "B", "N", "M", ",", ".", "/", "ESC",
+                ],
+                &[
+                    "", "", "", "OS", "LOWER", "SPACE", "ETR", "RAISE", "ALT", "", "", "",
+                ],
+            ]),
+        },
+        Layout {
+            name: "ANSI".to_owned(),
+            rows: rows(&[
+                &[
+                    "`", "1", "2", "3", "4", "5", "6", "7", "8", "9", "0", "-", "=", "BCK",
+                ],
+                &[
+                    "TAB", "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "[", "]", "\\",
+                ],
+                &[
+                    "CAPS", "A", "S", "D", "F", "G", "H", "J", "K", "L", ";", "'", "ETR",
+                ],
+                &[
+                    "SHF", "Z", "X", "C", "V", "B", "N", "M", ",", ".", "/", "RSHF",
+                ],
+                &["CTRL", "OS", "ALT", "SPACE", "RALT", "MENU", "RCTRL"],
+            ]),
+        },
+    ]
+}
+
+/// Row and column of the first key with the given label.
+pub fn key_position(rows: &PlanckRows, label: &str) -> Option<(usize, usize)> {
+    rows.iter().enumerate().find_map(|(row_index, row)| {
+        row.iter()
+            .position(|key| key == label)
+            .map(|column| (row_index, column))
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum LayoutError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid layout file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("layout has no keys")]
+    Empty,
+}
+
+impl Layout {
+    pub fn import(path: &Path) -> Result<Layout, LayoutError> {
+        let layout: Layout = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if !layout.rows.iter().flatten().any(|key| !key.is_empty()) {
+            return Err(LayoutError::Empty);
+        }
+        Ok(layout)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_layouts_have_unique_labels() {
+        for layout in builtin_layouts() {
+            let mut labels: Vec<_> = layout
+                .rows
+                .iter()
+                .flatten()
+                .filter(|key| !key.is_empty())
+                .collect();
+            let keys = labels.len();
+            labels.sort();
+            labels.dedup();
+            assert_eq!(labels.len(), keys, "{} repeats a label", layout.name);
+        }
+        let preonic = &builtin_layouts()[1];
+        assert_eq!(preonic.rows.len(), 5);
+        assert!(preonic.rows.iter().all(|row| row.len() == 12));
+    }
+}
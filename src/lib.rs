@@ -0,0 +1,1676 @@
+//! Planck Scribe translates MIDI files into key sequences for a Planck keyboard.
+//!
+//! The app lives in `run`, and [`cli`] converts files from the command line. The conversion
+//! itself is public so other tools and tests can use it without opening a window:
+//!
+//! - [`load_midi`] parses a file into [`MidiKeyTrack`]s of [`MidiKeyPair`]s, with the
+//!   [`SongTiming`] to turn ticks into time. [`musicxml`] reads MusicXML scores into the same,
+//!   and [`load_song`] takes either.
+//! - A layout is [`PlanckRows`] of key labels; [`layout`] has the built-in ones and reads
+//!   layout files.
+//! - [`planck_mapping`] maps MIDI keys onto a layout as [`settings::Settings`] choose, or use
+//!   [`chromatic_planck_mapping`] and [`isomorphic_planck_mapping`] directly. [`mapping`] has
+//!   the other schemes, and [`mapping::scheme_mapping`] lays out any [`mapping::MappingScheme`].
+//! - [`MidiKeyTrack::remap`] assigns the mapped keys to a track's notes.
+//!
+//! ```no_run
+//! use planck_scribe::{load_midi, planck_mapping, settings::Settings};
+//!
+//! let settings = Settings::default();
+//! let file = std::fs::read("song.mid").unwrap();
+//! let (mut tracks, _timing) = load_midi(&file, settings.duplicate_notes).unwrap();
+//! let mapping = planck_mapping(&settings);
+//! for track in &mut tracks {
+//!     track.remap(&mapping, settings.unmapped_notes, settings.mapping.transpose);
+//!     for chord in track.chords() {
+//!         println!("{}", chord.keys(track));
+//!     }
+//! }
+//! ```
+//!
+//! The window and everything only it needs, like MIDI devices, are behind the default `gui`
+//! feature. Build with `default-features = false` for the library and the command line alone.
+
+// Much of the crate only serves the window, and goes unused without it.
+#![cfg_attr(not(feature = "gui"), allow(dead_code))]
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::*,
+    result::Result,
+};
+use thiserror::*;
+
+#[cfg(feature = "gui")]
+mod app;
+mod batch;
+#[cfg(feature = "gui")]
+mod beat_grid;
+mod chords;
+pub mod cli;
+#[cfg(feature = "audio")]
+mod click_track;
+mod config;
+#[cfg(feature = "gui")]
+mod console;
+mod export;
+mod fit;
+#[cfg(feature = "gui")]
+mod history;
+mod jumps;
+#[cfg(feature = "pdf")]
+mod key_chart;
+pub mod layout;
+#[cfg(feature = "gui")]
+mod live_input;
+pub mod mapping;
+#[cfg(feature = "gui")]
+mod midi_output;
+#[cfg(feature = "gui")]
+mod minimap;
+pub mod musicxml;
+mod notes;
+#[cfg(feature = "gui")]
+mod piano_roll;
+mod playback;
+#[cfg(feature = "gui")]
+mod practice;
+mod qmk;
+pub mod settings;
+#[cfg(feature = "synth")]
+mod synth;
+mod text;
+#[cfg(feature = "gui")]
+mod timeline;
+mod translation;
+mod velocity;
+
+#[cfg(feature = "gui")]
+pub use app::run;
+
+use settings::{DuplicateNotes, KeyNames, ReductionSettings, Settings, UnmappedNotes, VoiceOrder};
+
+/// Key labels of a keyboard, row by row from the top.
+pub type PlanckRows = Vec<Vec<String>>;
+const MIDI_C_KEY: u8 = 60;
+/// Tempo assumed when a file has no `SetTempo` event (120 BPM).
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Timing information needed to convert ticks into wall-clock time.
+#[derive(Clone)]
+pub struct SongTiming {
+    pub timing: midly::Timing,
+    /// Microseconds per beat, taken from the first `SetTempo` event, or 120 BPM if there is none.
+    pub tempo: u32,
+    /// Every `SetTempo` event in the file as `(tick, microseconds per beat)`, sorted by tick.
+    pub tempo_changes: Vec<(u64, u32)>,
+    /// Beats per measure and beat note value, taken from the first `TimeSignature` event.
+    pub time_signature: (u8, u8),
+    /// Every `TimeSignature` event in the file as `(tick, time signature)`, sorted by tick.
+    pub time_signature_changes: Vec<(u64, (u8, u8))>,
+}
+
+impl Default for SongTiming {
+    fn default() -> Self {
+        SongTiming {
+            timing: midly::Timing::Metrical(480.into()),
+            tempo: DEFAULT_TEMPO,
+            tempo_changes: Vec::new(),
+            time_signature: (4, 4),
+            time_signature_changes: Vec::new(),
+        }
+    }
+}
+
+impl SongTiming {
+    pub fn ticks_to_ms(&self, ticks: u64) -> f64 {
+        match self.timing {
+            midly::Timing::Metrical(ticks_per_beat) => {
+                let ticks_per_beat = ticks_per_beat.as_int().max(1) as f64;
+                ticks as f64 * self.tempo as f64 / ticks_per_beat / 1000.0
+            }
+            midly::Timing::Timecode(fps, subframe) => {
+                let ticks_per_second = fps.as_f32() as f64 * subframe.max(1) as f64;
+                ticks as f64 * 1000.0 / ticks_per_second
+            }
+        }
+    }
+
+    /// Time in milliseconds from the start of the song to `tick`, following every tempo change.
+    ///
+    /// Returns `None` if the file's time division is zero, so ticks have no defined length.
+    pub fn tick_to_time_ms(&self, tick: u64) -> Option<f64> {
+        self.time_ms_at(tick as f64)
+    }
+
+    /// [`SongTiming::tick_to_time_ms`] for a position between ticks, like the playback's.
+    fn time_ms_at(&self, tick: f64) -> Option<f64> {
+        let ticks_per_beat = match self.timing {
+            midly::Timing::Metrical(ticks_per_beat) if ticks_per_beat.as_int() == 0 => return None,
+            midly::Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int() as f64,
+            // Timecode divisions count real time, so tempo changes don't apply.
+            midly::Timing::Timecode(..) => return Some(tick * self.ticks_to_ms(1)),
+        };
+        let mut ms = 0.0;
+        let mut segment_start = 0;
+        let mut tempo = DEFAULT_TEMPO;
+        for &(change_tick, change_tempo) in &self.tempo_changes {
+            if change_tick as f64 >= tick {
+                break;
+            }
+            ms += (change_tick - segment_start) as f64 * tempo as f64 / ticks_per_beat / 1000.0;
+            segment_start = change_tick;
+            tempo = change_tempo;
+        }
+        ms += (tick - segment_start as f64) * tempo as f64 / ticks_per_beat / 1000.0;
+        Some(ms)
+    }
+
+    /// The tick `ms` milliseconds into the song, following every tempo change: the inverse of
+    /// [`SongTiming::tick_to_time_ms`]. It falls between ticks unless rounded.
+    ///
+    /// Returns `None` if the file's time division is zero, so ticks have no defined length.
+    pub fn time_ms_to_tick(&self, ms: f64) -> Option<f64> {
+        let ticks_per_beat = match self.timing {
+            midly::Timing::Metrical(ticks_per_beat) if ticks_per_beat.as_int() == 0 => return None,
+            midly::Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int() as f64,
+            midly::Timing::Timecode(..) => return Some(self.ms_to_ticks(ms)),
+        };
+        let ms_per_tick = |tempo: u32| (tempo as f64 / ticks_per_beat / 1000.0).max(f64::EPSILON);
+        let mut segment_ms = 0.0;
+        let mut segment_start = 0;
+        let mut tempo = DEFAULT_TEMPO;
+        for &(change_tick, change_tempo) in &self.tempo_changes {
+            let change_ms = segment_ms + (change_tick - segment_start) as f64 * ms_per_tick(tempo);
+            if change_ms >= ms {
+                break;
+            }
+            segment_ms = change_ms;
+            segment_start = change_tick;
+            tempo = change_tempo;
+        }
+        Some(segment_start as f64 + (ms - segment_ms) / ms_per_tick(tempo))
+    }
+
+    /// Beats per minute of [`SongTiming::tempo`].
+    pub fn bpm(&self) -> f64 {
+        60_000_000.0 / self.tempo.max(1) as f64
+    }
+
+    /// True when the file has no `SetTempo` event, so the MIDI default of 120 BPM is assumed.
+    pub fn has_default_tempo(&self) -> bool {
+        self.tempo_changes.is_empty()
+    }
+
+    fn ms_to_ticks(&self, ms: f64) -> f64 {
+        ms / self.ticks_to_ms(1).max(f64::EPSILON)
+    }
+
+    fn ticks_per_measure(&self) -> f64 {
+        self.ticks_per_measure_in(self.time_signature)
+    }
+
+    fn ticks_per_measure_in(&self, (beats, note_value): (u8, u8)) -> f64 {
+        (self.ticks_per_beat() * 4.0 * beats as f64 / note_value.max(1) as f64).max(1.0)
+    }
+
+    /// Measure that `tick` falls in, counted from zero, following every time signature change.
+    /// The first time signature holds from the start of the song, and a change in the middle of
+    /// a measure starts a new one.
+    pub fn measure_at(&self, tick: u64) -> usize {
+        let mut measures = 0;
+        let mut start = 0;
+        let mut signature = self.time_signature;
+        for &(change_tick, change) in &self.time_signature_changes {
+            if change_tick > tick {
+                break;
+            }
+            if change_tick > start {
+                let length = (change_tick - start) as f64 / self.ticks_per_measure_in(signature);
+                // Rounding errors shouldn't make a whole measure look like it ran over.
+                measures += (length - 1e-6).ceil() as usize;
+                start = change_tick;
+            }
+            signature = change;
+        }
+        measures + ((tick - start) as f64 / self.ticks_per_measure_in(signature)) as usize
+    }
+
+    /// Tick that `measure`, counted from zero, starts at: the inverse of
+    /// [`SongTiming::measure_at`], so a measure ends where the next one starts.
+    pub fn measure_start(&self, measure: usize) -> u64 {
+        let mut measures = 0;
+        let mut start = 0;
+        let mut signature = self.time_signature;
+        for &(change_tick, change) in &self.time_signature_changes {
+            if change_tick > start {
+                let length = (change_tick - start) as f64 / self.ticks_per_measure_in(signature);
+                let segment_measures = (length - 1e-6).ceil() as usize;
+                if measure < measures + segment_measures {
+                    break;
+                }
+                measures += segment_measures;
+                start = change_tick;
+            }
+            signature = change;
+        }
+        let offset = (measure - measures) as f64 * self.ticks_per_measure_in(signature);
+        start + offset.round() as u64
+    }
+
+    fn ticks_per_beat(&self) -> f64 {
+        match self.timing {
+            midly::Timing::Metrical(ticks_per_beat) => ticks_per_beat.as_int().max(1) as f64,
+            midly::Timing::Timecode(..) => self.ms_to_ticks(self.tempo as f64 / 1000.0),
+        }
+    }
+}
+
+/// The notes of one MIDI track and the keyboard keys they are mapped to.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct MidiKeyTrack {
+    pub name: String,
+    pub midi_key_pairs: Vec<MidiKeyPair>,
+    /// Instrument changes in the order they occur in the track.
+    pub program_changes: Vec<ProgramChange>,
+    /// Semitones added to every note of this track before mapping.
+    #[serde(default)]
+    pub transpose: i8,
+    /// Set on tracks derived by a polyphony reduction, describing what was kept.
+    #[serde(default)]
+    pub reduction: Option<String>,
+    /// Instrument picked by the user, replacing the one detected from program changes.
+    #[serde(default)]
+    pub instrument: Option<String>,
+    /// Number of SysEx and escape events in the MIDI track. They are skipped, only counted.
+    #[serde(default)]
+    pub sysex_events: usize,
+    /// Left out of exports. Chosen for the current session only, so it is never saved.
+    #[serde(skip)]
+    pub skip_export: bool,
+    /// Left out of the track views, and so also out of exports. Chosen for the current session
+    /// only, like `skip_export`.
+    #[serde(skip)]
+    pub hidden: bool,
+    /// Pitch bend changes in the order they occur in the track.
+    #[serde(default)]
+    pub pitch_bends: Vec<PitchBend>,
+    /// Drum hits rather than pitches, so the notes are shown as drums and never mapped or
+    /// exported. Set on loading for tracks played only on [`PERCUSSION_CHANNEL`].
+    #[serde(default)]
+    pub percussion: bool,
+}
+
+/// The General MIDI percussion channel, channel 10 counted from one.
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+/// What [`MidiKeyTrack::details`] found in a track.
+#[derive(PartialEq, Debug)]
+pub struct TrackDetails {
+    /// MIDI channels (0 to 15) the track's notes and program changes are on, in order.
+    pub channels: Vec<u8>,
+    pub notes: usize,
+    /// Lowest and highest MIDI key of the notes, before transposing.
+    pub range: Option<(u8, u8)>,
+    /// Notes without a key of their own in the current layout, whether they are marked,
+    /// substituted or dropped from exports.
+    pub unmappable: usize,
+}
+
+impl TrackDetails {
+    pub fn unmappable_percent(&self) -> f32 {
+        self.unmappable as f32 * 100.0 / self.notes.max(1) as f32
+    }
+}
+
+/// Bend range assumed for every channel: the General MIDI default of two semitones either way.
+/// Files that change it (RPN 0) are shown with the wrong number of cents.
+const BEND_RANGE_CENTS: f32 = 200.0;
+
+/// A pitch bend change on a channel, bending every note sounding on it.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PitchBend {
+    pub tick: u64,
+    pub channel: u8,
+    /// From -8192 (bent fully down) through 0 (no bend) to 8191 (bent fully up).
+    pub value: i16,
+}
+
+/// An instrument change at a point in time within a track.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ProgramChange {
+    pub tick: u64,
+    pub name: String,
+    /// MIDI channel (0 to 15) the instrument was set on.
+    #[serde(default)]
+    pub channel: u8,
+}
+
+/// Notes of a track that start on the same tick and so are played together. A lone note is a
+/// chord of one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MidiChord {
+    pub tick: u64,
+    /// Indices of the notes in the track, in track order.
+    pub pair_indices: Vec<usize>,
+}
+
+impl MidiChord {
+    /// The keys of the chord's notes, bracketed like `[A S D]` when there is more than one.
+    /// Unmapped notes show `NONE`.
+    pub fn keys(&self, track: &MidiKeyTrack) -> String {
+        let keys: Vec<&str> = self
+            .pair_indices
+            .iter()
+            .map(|&index| {
+                track.midi_key_pairs[index]
+                    .keyboard_key
+                    .as_deref()
+                    .unwrap_or("NONE")
+            })
+            .collect();
+        bracket_keys(&keys)
+    }
+}
+
+/// `keys` joined by spaces, in brackets if there are several, as chords are written.
+fn bracket_keys<S: AsRef<str>>(keys: &[S]) -> String {
+    let keys: Vec<&str> = keys.iter().map(AsRef::as_ref).collect();
+    match keys.as_slice() {
+        [key] => (*key).to_owned(),
+        keys => format!("[{}]", keys.join(" ")),
+    }
+}
+
+impl MidiKeyTrack {
+    pub fn new() -> Self {
+        MidiKeyTrack::default()
+    }
+
+    /// The track's notes grouped by the tick they start on, in order.
+    pub fn chords(&self) -> Vec<MidiChord> {
+        let mut chords: Vec<MidiChord> = Vec::new();
+        for (index, pair) in self.midi_key_pairs.iter().enumerate() {
+            match chords.last_mut() {
+                Some(chord) if chord.tick == pair.tick => chord.pair_indices.push(index),
+                _ => chords.push(MidiChord {
+                    tick: pair.tick,
+                    pair_indices: vec![index],
+                }),
+            }
+        }
+        chords
+    }
+
+    /// Whether the track goes into exports: it is neither hidden, left out of them nor
+    /// percussion.
+    pub fn is_exported(&self) -> bool {
+        !self.skip_export && !self.hidden && !self.percussion
+    }
+
+    /// Whether every note of the track is on the percussion channel.
+    pub fn plays_percussion(&self) -> bool {
+        !self.midi_key_pairs.is_empty()
+            && self
+                .midi_key_pairs
+                .iter()
+                .all(|pair| pair.channel == PERCUSSION_CHANNEL)
+    }
+
+    /// Roughly how many cents `pair` is bent away from equal temperament, from the pitch bend
+    /// in effect on its channel when it starts. `None` for notes that aren't bent.
+    pub fn bend_cents(&self, pair: &MidiKeyPair) -> Option<i32> {
+        let before = self
+            .pitch_bends
+            .partition_point(|bend| bend.tick <= pair.tick);
+        let bend = self.pitch_bends[..before]
+            .iter()
+            .rfind(|bend| bend.channel == pair.channel)?;
+        let cents = (bend.value as f32 / 8192.0 * BEND_RANGE_CENTS).round() as i32;
+        (cents != 0).then_some(cents)
+    }
+
+    /// The instrument the track is played with: the user's choice if there is one, or else
+    /// the first program change.
+    pub fn instrument(&self) -> Option<&str> {
+        self.instrument
+            .as_deref()
+            .or_else(|| self.detected_instrument())
+    }
+
+    fn detected_instrument(&self) -> Option<&str> {
+        self.program_changes
+            .first()
+            .map(|change| change.name.as_str())
+    }
+
+    /// Summary of the track's notes for the track details.
+    pub fn details(&self) -> TrackDetails {
+        let mut channels: Vec<u8> = self
+            .midi_key_pairs
+            .iter()
+            .map(|pair| pair.channel)
+            .chain(self.program_changes.iter().map(|change| change.channel))
+            .collect();
+        channels.sort();
+        channels.dedup();
+        let keys = self.midi_key_pairs.iter().map(|pair| pair.midi_key);
+        TrackDetails {
+            channels,
+            notes: self.midi_key_pairs.len(),
+            range: keys.clone().min().zip(keys.max()),
+            unmappable: self
+                .midi_key_pairs
+                .iter()
+                .filter(|pair| pair.keyboard_key.is_none() || pair.substituted)
+                .count(),
+        }
+    }
+
+    /// A copy of the track keeping at most `reduction.voices` notes per tick, so dense chords
+    /// become playable with fewer fingers. The track itself is left untouched.
+    fn reduced(&self, reduction: &ReductionSettings) -> MidiKeyTrack {
+        let mut pairs = Vec::new();
+        for chord in self.midi_key_pairs.chunk_by(|a, b| a.tick == b.tick) {
+            let mut kept: Vec<usize> = (0..chord.len()).collect();
+            match reduction.keep {
+                VoiceOrder::Highest => kept.sort_by_key(|&i| std::cmp::Reverse(chord[i].midi_key)),
+                VoiceOrder::Lowest => kept.sort_by_key(|&i| chord[i].midi_key),
+            }
+            kept.truncate(reduction.voices.max(1));
+            // Keep the original order within the chord.
+            kept.sort();
+            pairs.extend(kept.into_iter().map(|i| chord[i].clone()));
+        }
+        let description = format!(
+            "{} {} of {}",
+            reduction.voices.max(1),
+            match reduction.keep {
+                VoiceOrder::Highest => "highest",
+                VoiceOrder::Lowest => "lowest",
+            },
+            self.name
+        );
+        MidiKeyTrack {
+            name: format!("{} (reduced)", self.name),
+            midi_key_pairs: pairs,
+            program_changes: self.program_changes.clone(),
+            transpose: self.transpose,
+            reduction: Some(description),
+            pitch_bends: self.pitch_bends.clone(),
+            instrument: self.instrument.clone(),
+            sysex_events: self.sysex_events,
+            skip_export: self.skip_export,
+            hidden: self.hidden,
+            percussion: self.percussion,
+        }
+    }
+
+    /// Removes the notes without a key, fixing up the deltas of the notes after them.
+    pub fn drop_unmapped(&mut self) {
+        let mut last_tick = 0;
+        self.midi_key_pairs.retain_mut(|pair| {
+            if pair.keyboard_key.is_none() {
+                return false;
+            }
+            pair.delta = (pair.tick - last_tick).min(u32::MAX as u64) as u32;
+            last_tick = pair.tick;
+            true
+        });
+    }
+
+    /// Whether a note-on for `key` was already recorded on `tick`.
+    fn has_note_at(&self, tick: u64, key: u8) -> bool {
+        self.midi_key_pairs
+            .iter()
+            .rev()
+            .take_while(|pair| pair.tick == tick)
+            .any(|pair| pair.midi_key == key)
+    }
+
+    /// Semitones the notes of this track are moved by: its own transpose on top of the whole
+    /// song's `song_transpose`.
+    pub fn total_transpose(&self, song_transpose: i8) -> i16 {
+        self.transpose as i16 + song_transpose as i16
+    }
+
+    /// The pitch the note `key` of this track sounds at and is mapped from, or `None` if the
+    /// transposes take it out of the MIDI range. Mapping, playback and the piano roll all go
+    /// through this, so the keys shown are the pitches heard.
+    pub fn sounding_key(&self, key: u8, song_transpose: i8) -> Option<u8> {
+        transpose_key(key, self.total_transpose(song_transpose))
+    }
+
+    /// Looks up the keyboard key of every note, moved by the track's transpose and the whole
+    /// song's `song_transpose`.
+    ///
+    /// Notes outside the mapping are handled as `unmapped` says: substituted notes are marked,
+    /// and notes left without a key are kept, to be marked or dropped when shown. Percussion
+    /// tracks get no keys at all.
+    pub fn remap(
+        &mut self,
+        mapping: &HashMap<u8, String>,
+        unmapped: UnmappedNotes,
+        song_transpose: i8,
+    ) {
+        if self.percussion {
+            for pair in self.midi_key_pairs.iter_mut() {
+                pair.keyboard_key = None;
+                pair.substituted = false;
+            }
+            return;
+        }
+        let semitones = self.total_transpose(song_transpose);
+        for pair in self.midi_key_pairs.iter_mut() {
+            let transposed = pair.midi_key as i16 + semitones;
+            let key = transpose_key(pair.midi_key, semitones);
+            pair.keyboard_key = key.and_then(|key| mapping.get(&key)).cloned();
+            pair.substituted = false;
+            if pair.keyboard_key.is_some() {
+                continue;
+            }
+            let substitute = match unmapped {
+                UnmappedNotes::Mark | UnmappedNotes::Drop => None,
+                UnmappedNotes::Nearest => nearest_mapped_key(mapping, transposed),
+                UnmappedNotes::FoldOctaves => folded_mapped_key(mapping, transposed)
+                    .or_else(|| nearest_mapped_key(mapping, transposed)),
+            };
+            if let Some(substitute) = substitute {
+                pair.keyboard_key = mapping.get(&substitute).cloned();
+                pair.substituted = true;
+            }
+        }
+    }
+}
+
+impl MidiKeyTrack {
+    /// Splits the track into display lines: one per onset tick, plus instrument markers.
+    fn get_midi_keys_lines(&self, settings: &Settings, filter: &NoteFilter) -> Vec<KeyLine> {
+        let display = &settings.display;
+        let mut lines: Vec<KeyLine> = Vec::new();
+        // The first program change names the track, later ones are shown inline.
+        let mut program_changes = self.program_changes.iter().skip(1).peekable();
+        let jumps = if display.highlight_jumps {
+            jumps::difficult_jumps(self, &settings.layout, filter, display.jump_distance)
+        } else {
+            Vec::new()
+        };
+        let mut line_tick = None;
+        for (index, pair) in self.midi_key_pairs.iter().enumerate() {
+            if filter.hides(pair) {
+                continue;
+            }
+            let mut new_line = line_tick != Some(pair.tick);
+            line_tick = Some(pair.tick);
+            while let Some(change) = program_changes.next_if(|change| change.tick <= pair.tick) {
+                lines.push(KeyLine {
+                    segments: vec![(format!("[{}]", change.name), SegmentKind::Plain)],
+                    pair_indices: Vec::new(),
+                });
+                new_line = true;
+            }
+            let keyboard_key = if self.percussion {
+                match notes::drum_name(pair.midi_key) {
+                    Some(drum) => drum.to_owned(),
+                    None => format!("Drum {}", pair.midi_key),
+                }
+            } else {
+                keyboard_key_name(pair, settings)
+            };
+            let mut segments = Vec::new();
+            if display.show_ticks && new_line {
+                segments.push((format!("@{:<7} ", pair.tick), SegmentKind::Plain));
+            }
+            // Labels from custom layouts can be wide (emoji) or carry combining accents, so pad
+            // them by display columns rather than `char`s.
+            let mut pair_text = format!(
+                "{:<4}  ({}) ",
+                pair.midi_key,
+                text::pad_end(&keyboard_key, 4)
+            );
+            if display.show_delta {
+                pair_text += &format!("Δ{:<5} ", pair.delta);
+            }
+            if display.show_frequency {
+                pair_text += &format!(
+                    "{:>7.1}Hz ",
+                    notes::midi_to_frequency(pair.midi_key, settings.reference_pitch)
+                );
+            }
+            if display.show_cents {
+                if let Some(cents) = self.bend_cents(pair) {
+                    pair_text += &format!("≈{:+}¢ ", cents);
+                }
+            }
+            let kind = match pair.keyboard_key {
+                _ if filter.excludes(pair) => SegmentKind::Dimmed,
+                _ if self.percussion => SegmentKind::Plain,
+                None => SegmentKind::Unmapped,
+                Some(_) if display.highlight_scale && !display.scale.contains(pair.midi_key) => {
+                    SegmentKind::OutOfScale
+                }
+                Some(_) => SegmentKind::Mapped,
+            };
+            segments.push((pair_text, kind));
+            if jumps.binary_search(&index).is_ok() {
+                segments.push(("↯ ".to_owned(), SegmentKind::Jump));
+            }
+            if pair
+                .keyboard_key
+                .as_deref()
+                .is_some_and(|key| display.is_modifier_key(key))
+            {
+                segments.push(("⚠ ".to_owned(), SegmentKind::ModifierKey));
+            }
+            match lines.last_mut() {
+                Some(line) if !new_line => {
+                    line.segments.extend(segments);
+                    line.pair_indices.push(index);
+                }
+                _ => lines.push(KeyLine {
+                    segments,
+                    pair_indices: vec![index],
+                }),
+            }
+        }
+        if display.bracket_chords {
+            for line in lines.iter_mut().filter(|line| line.pair_indices.len() > 1) {
+                let keys: Vec<String> = line
+                    .pair_indices
+                    .iter()
+                    .map(|&index| keyboard_key_name(&self.midi_key_pairs[index], settings))
+                    .collect();
+                line.segments
+                    .push((format!(" {}", bracket_keys(&keys)), SegmentKind::Plain));
+            }
+        }
+        if display.show_chord_names {
+            for line in lines.iter_mut().filter(|line| line.pair_indices.len() > 1) {
+                let keys: Vec<u8> = line
+                    .pair_indices
+                    .iter()
+                    .map(|&index| self.midi_key_pairs[index].midi_key)
+                    .collect();
+                if let Some(guess) = chords::name_chord(&keys) {
+                    let doubt = if guess.certain { "" } else { "?" };
+                    line.segments
+                        .push((format!(" {}{}", guess.name, doubt), SegmentKind::Plain));
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// How a note's keyboard key is written: its label or layout coordinates, `~` marking a
+/// substituted note and `NONE` an unmapped one.
+fn keyboard_key_name(pair: &MidiKeyPair, settings: &Settings) -> String {
+    let key_name = pair.keyboard_key.as_deref().map(|label| {
+        match (
+            settings.display.key_names,
+            layout::key_position(&settings.layout, label),
+        ) {
+            (KeyNames::Coordinates, Some((row, column))) => format!("({},{})", row, column),
+            _ => label.to_owned(),
+        }
+    });
+    match key_name {
+        Some(key) if pair.substituted => format!("~{}", key),
+        Some(key) => key,
+        None => "NONE".to_owned(),
+    }
+}
+
+/// Finds the mapped MIDI key closest in pitch to `key`, preferring the lower one on ties.
+fn nearest_mapped_key(mapping: &HashMap<u8, String>, key: i16) -> Option<u8> {
+    (0..=255_i16)
+        .flat_map(|distance| [key - distance, key + distance])
+        .filter_map(|candidate| u8::try_from(candidate).ok())
+        .find(|candidate| mapping.contains_key(candidate))
+}
+
+/// The mapped key closest to `key` that is a whole number of octaves away from it.
+fn folded_mapped_key(mapping: &HashMap<u8, String>, key: i16) -> Option<u8> {
+    (1..=10_i16)
+        .flat_map(|octaves| [key - 12 * octaves, key + 12 * octaves])
+        .filter_map(|candidate| u8::try_from(candidate).ok())
+        .find(|candidate| mapping.contains_key(candidate))
+}
+
+/// What a piece of a key line shows, which decides its color.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum SegmentKind {
+    Plain,
+    Mapped,
+    Unmapped,
+    /// A mapped note outside the highlighted scale.
+    OutOfScale,
+    /// Marks a note reached by a difficult jump.
+    Jump,
+    /// Marks a note mapped to a modifier key, which is probably a mapping mistake.
+    ModifierKey,
+    /// A note caught by a filter that dims instead of hiding.
+    Dimmed,
+}
+
+impl SegmentKind {
+    /// Whether the segment shows a note, rather than a marker or other text.
+    fn is_note(self) -> bool {
+        matches!(
+            self,
+            SegmentKind::Mapped
+                | SegmentKind::Unmapped
+                | SegmentKind::OutOfScale
+                | SegmentKind::Dimmed
+        )
+    }
+}
+
+/// One line of the track view.
+#[derive(PartialEq, Debug)]
+struct KeyLine {
+    segments: Vec<(String, SegmentKind)>,
+    /// Indices into `midi_key_pairs` of the notes shown on this line.
+    pair_indices: Vec<usize>,
+}
+
+/// Decides which notes are left out of the track view. The default leaves every note in.
+#[derive(Default)]
+struct NoteFilter {
+    /// Notes shorter than this many ticks are treated as grace notes and filtered out.
+    min_duration: Option<u64>,
+    /// Draw filtered-out notes faintly instead of hiding them.
+    dim: bool,
+    /// Only notes starting in this tick range are shown; the rest are always hidden.
+    ticks: Option<std::ops::Range<u64>>,
+    /// Always hide notes without a key.
+    drop_unmapped: bool,
+}
+
+impl NoteFilter {
+    /// Whether any filter catches the note, whether it ends up hidden or dimmed.
+    fn excludes(&self, pair: &MidiKeyPair) -> bool {
+        self.outside_range(pair)
+            || matches!(
+                (self.min_duration, pair.duration),
+                (Some(min_duration), Some(duration)) if duration < min_duration
+            )
+    }
+
+    fn hides(&self, pair: &MidiKeyPair) -> bool {
+        self.outside_range(pair)
+            || (self.drop_unmapped && pair.keyboard_key.is_none())
+            || (!self.dim && self.excludes(pair))
+    }
+
+    fn outside_range(&self, pair: &MidiKeyPair) -> bool {
+        self.ticks
+            .as_ref()
+            .is_some_and(|ticks| !ticks.contains(&pair.tick))
+    }
+}
+
+/// A single note and the keyboard key that plays it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MidiKeyPair {
+    /// Raw delta time of the note-on event, as read from the file.
+    pub delta: u32,
+    /// Absolute start tick of the note within its track.
+    pub tick: u64,
+    /// Length of the note in ticks, or `None` if it is never released.
+    pub duration: Option<u64>,
+    pub midi_key: u8,
+    /// How hard the note was struck, from 1 to 127, as recorded in the file.
+    #[serde(default = "default_velocity")]
+    pub velocity: u8,
+    /// MIDI channel (0 to 15) the note was played on.
+    #[serde(default)]
+    pub channel: u8,
+    pub keyboard_key: Option<String>,
+    /// The note had no key of its own and was moved to the nearest mapped note.
+    #[serde(default)]
+    pub substituted: bool,
+}
+
+/// Velocity of notes saved before velocities were kept: the usual "mezzo forte" default.
+fn default_velocity() -> u8 {
+    100
+}
+
+/// The standard Planck layout's base layer.
+pub fn default_planck_rows() -> PlanckRows {
+    [
+        [
+            "TAB", "Q", "W", "E", "R", "T", "Y", "U", "I", "O", "P", "BCK",
+        ],
+        ["ESC", "A", "S", "D", "F", "G", "H", "J", "K", "L", ";", "'"],
+        [
+            "SHF", "Z", "X", "C", "V", "B", "N", "M", ",", ".", "/", "ETR",
+        ],
+        [
+            "", "CTRL", "ALT", "ORYX", "OS", "SHFDOWN", "SPACE", "SHFUP", "<-", "\\/", "/\\", "->",
+        ],
+    ]
+    .map(|row| row.map(|key| key.to_owned()).into_iter().collect())
+    .into_iter()
+    .collect()
+}
+
+/// Maps one semitone per key, reading `rows` left to right and top to bottom, with middle C on
+/// `base_key`.
+///
+/// Blank cells, such as the bottom-left one of the default layout, have no key to press. They
+/// still take up their semitone, so the keys after them keep their notes, but no note is mapped
+/// to them and that semitone stays unmapped.
+pub fn chromatic_planck_mapping(base_key: &str, rows: PlanckRows) -> HashMap<u8, String> {
+    mapping::scheme_mapping(&mapping::Chromatic, base_key, &rows)
+}
+
+/// Maps notes so each key sounds `column_interval` semitones above the key on its left and
+/// `row_interval` semitones above the key in the row above, with middle C on `base_key`.
+///
+/// Unlike the chromatic walk, neighbouring rows are musically related (e.g. a fourth apart),
+/// in the spirit of Wicki-Hayden style layouts. When several keys play the same note, the one
+/// closest to the base key gets it.
+pub fn isomorphic_planck_mapping(
+    base_key: &str,
+    rows: &PlanckRows,
+    row_interval: i8,
+    column_interval: i8,
+) -> HashMap<u8, String> {
+    let scheme = mapping::Isomorphic {
+        row_interval,
+        column_interval,
+    };
+    mapping::scheme_mapping(&scheme, base_key, rows)
+}
+
+/// The key mapping selected in the settings, with the keys assigned by hand on top.
+pub fn planck_mapping(settings: &Settings) -> HashMap<u8, String> {
+    let mut mapping = mapping::scheme_mapping(
+        settings.mapping.scheme().as_ref(),
+        &settings.base_key,
+        &settings.layout,
+    );
+    settings
+        .mapping
+        .custom
+        .apply(&mut mapping, &settings.layout);
+    mapping
+}
+
+/// `key` moved by `semitones`, or `None` if that leaves the MIDI range.
+pub fn transpose_key(key: u8, semitones: i16) -> Option<u8> {
+    u8::try_from(key as i16 + semitones)
+        .ok()
+        .filter(|key| *key <= 127)
+}
+
+fn program_to_string() -> HashMap<u8, String> {
+    let mappings = [
+        (0, "Piano"),
+        (1, "Acoustic Grand Piano or Piano 1"),
+        (2, "Bright Acoustic Piano or Piano 2"),
+        (3, "Electric Grand Piano or Piano 3 (usually modeled after Yamaha CP70)"),
+        (4, "Honky-tonk Piano"),
+        (5, "Electric Piano 1 (usually a Rhodes piano)"),
+        (6, "Electric Piano 2 (usually an FM piano patch)"),
+        (7, "Harpsichord"),
+        (8, "Clavinet"),
+        // Chromatic Percussion
+        (9, "Celesta"),
+        (10, "Glockenspiel"),
+        (11, "Music Box"),
+        (12, "Vibraphone"),
+        (13, "Marimba"),
+        (14, "Xylophone"),
+        (15, "Tubular Bells"),
+        (16, "Dulcimer or Santoor"),
+        // Organ
+        (17, "Drawbar Organ or Organ 1"),
+        (18, "Percussive Organ or Organ 2"),
+        (19, "Rock Organ or Organ 3"),
+        (20, "Church Organ"),
+        (21, "Reed Organ"),
+        (22, "Accordion"),
+        (23, "Harmonica"),
+        (24, "Bandoneon or Tango Accordion"),
+        // Guitar
+        (25, "Acoustic Guitar (nylon)"),
+        (26, "Acoustic Guitar (steel)"),
+        (27, "Electric Guitar (jazz)"),
+        (28, "Electric Guitar (clean, usually resembling a Fender Stratocaster ran through a Roland Jazz Chorus amp)"),
+        (29, "Electric Guitar (muted)"),
+        (30, "Electric Guitar (overdriven)"),
+        (31, "Electric Guitar (distortion)"),
+        (32, "Electric Guitar (harmonics)"),
+        // Bass
+        (33, "Acoustic Bass"),
+        (34, "Electric Bass (finger)"),
+        (35, "Electric Bass (picked)"),
+        (36, "Electric Bass (fretless)"),
+        (37, "Slap Bass 1"),
+        (38, "Slap Bass 2"),
+        (39, "Synth Bass 1"),
+        (40, "Synth Bass 2"),
+        // Strings
+        (41, "Violin"),
+        (42, "Viola"),
+        (43, "Cello"),
+        (44, "Contrabass"),
+        (45, "Tremolo Strings"),
+        (46, "Pizzicato Strings"),
+        (47, "Orchestral Harp"),
+        (48, "Timpani"),
+        // Ensemble
+        (49, "String Ensemble 1"),
+        (50, "String Ensemble 2"),
+        (51, "Synth Strings 1"),
+        (52, "Synth Strings 2"),
+        (53, "Choir Aahs"),
+        (54, "Voice Oohs (or Doos)"),
+        (55, "Synth Voice or Synth Choir"),
+        (56, "Orchestra Hit"),
+        // Brass
+        (57, "Trumpet"),
+        (58, "Trombone"),
+        (59, "Tuba"),
+        (60, "Muted Trumpet"),
+        (61, "French Horn"),
+        (62, "Brass Section"),
+        (63, "Synth Brass 1"),
+        (64, "Synth Brass 2"),
+        // Reed
+        (65, "Soprano Sax"),
+        (66, "Alto Sax"),
+        (67, "Tenor Sax"),
+        (68, "Baritone Sax"),
+        (69, "Oboe"),
+        (70, "English Horn"),
+        (71, "Bassoon"),
+        (72, "Clarinet"),
+        // Pipe
+        (73, "Piccolo"),
+        (74, "Flute"),
+        (75, "Recorder"),
+        (76, "Pan Flute"),
+        (77, "Blown bottle"),
+        (78, "Shakuhachi"),
+        (79, "Whistle"),
+        (80, "Ocarina"),
+        // Synth Lead
+        (81, "Lead 1 (square, often chorused)"),
+        (82, "Lead 2 (sawtooth, often chorused)"),
+        (83, "Lead 3 (triangle, or calliope, usually resembling a woodwind)"),
+        (84, "Lead 4 (sine, or chiff)"),
+        (85, "Lead 5 (charang, a guitar-like lead)"),
+        (86, "Lead 6 (voice)"),
+        (87, "Lead 7 (fifths)"),
+        (88, "Lead 8 (bass and lead or solo lead)"),
+        // Synth Pad
+        (89, "Pad 1 (new age, pad stacked with a bell, often derived from \"Fantasia\" patch from Roland D-50)"),
+        (90, "Pad 2 (warm, a mellower saw pad)"),
+        (91, "Pad 3 (polysynth or poly, a saw-like percussive pad resembling an early 1980s polyphonic synthesizer)"),
+        (92, "Pad 4 (choir, similar to \"synth voice\")"),
+        (93, "Pad 5 (bowed glass or bowed, a sound resembling a glass harmonica)"),
+        (94, "Pad 6 (metallic, often created from a grand piano sample played with the attack removed)"),
+        (95, "Pad 7 (halo, choir-like pad)"),
+        (96, "Pad 8 (sweep, pad with a pronounced \"wah\" filter effect)"),
+        // Synth Effects
+        (97, "FX 1 (rain, a bright pluck with echoing pulses)"),
+        (98, "FX 2 (soundtrack, a bright perfect fifth pad)"),
+        (99, "FX 3 (crystal, a synthesized bell sound)"),
+        (100, "FX 4 (atmosphere, usually a classical guitar-like sound)"),
+        (101, "FX 5 (brightness, a fast-attack stacked pad with choir or bell)"),
+        (102, "FX 6 (goblins, a slow-attack pad with chirping or murmuring sounds)"),
+        (103, "FX 7 (echoes or echo drops, similar to \"rain\")"),
+        (104, "FX 8 (sci-fi or star theme, usually an electric guitar-like pad)"),
+        // Ethnic
+        (105, "Sitar"),
+        (106, "Banjo"),
+        (107, "Shamisen"),
+        (108, "Koto"),
+        (109, "Kalimba"),
+        (110, "Bag pipe"),
+        (111, "Fiddle"),
+        (112, "Shanai"),
+        // Percussive
+        (113, "Tinkle Bell"),
+        (114, "Agogô or cowbell"),
+        (115, "Steel Drums"),
+        (116, "Woodblock"),
+        (117, "Taiko Drum"),
+        (118, "Melodic Tom or 808 Toms"),
+        (119, "Synth Drum"),
+        (120, "Reverse Cymbal"),
+        // Sound Effects
+        (121, "Guitar Fret Noise"),
+        (122, "Breath Noise"),
+        (123, "Seashore"),
+        (124, "Bird Tweet"),
+        (125, "Telephone Ring"),
+        (126, "Helicopter"),
+        (127, "Applause"),
+        (128, "Gunshot"),
+    ];
+    mappings
+        .into_iter()
+        .map(|x| (x.0, x.1.to_string()))
+        .collect()
+}
+
+/// General MIDI instrument family of `program`, numbered 1 to 128 as instruments are named in
+/// this crate: each family spans eight programs, starting with Piano at 1 to 8.
+pub fn program_family(program: u8) -> &'static str {
+    const FAMILIES: [&str; 16] = [
+        "Piano",
+        "Chromatic Percussion",
+        "Organ",
+        "Guitar",
+        "Bass",
+        "Strings",
+        "Ensemble",
+        "Brass",
+        "Reed",
+        "Pipe",
+        "Synth Lead",
+        "Synth Pad",
+        "Synth Effects",
+        "Ethnic",
+        "Percussive",
+        "Sound Effects",
+    ];
+    FAMILIES[(program.saturating_sub(1) as usize / 8).min(FAMILIES.len() - 1)]
+}
+
+/// General MIDI family of an instrument named as in [`program_to_string`].
+fn instrument_family(name: &str) -> Option<&'static str> {
+    program_number(name).map(program_family)
+}
+
+/// The program an instrument named as in [`program_to_string`] was set with.
+fn program_number(name: &str) -> Option<u8> {
+    static PROGRAMS: std::sync::OnceLock<HashMap<String, u8>> = std::sync::OnceLock::new();
+    let programs = PROGRAMS.get_or_init(|| {
+        program_to_string()
+            .into_iter()
+            .map(|(program, name)| (name, program))
+            .collect()
+    });
+    programs.get(name).copied()
+}
+
+/// Parses a standard MIDI file into one track per MIDI track, with notes paired into durations
+/// and instruments named, plus the timing needed to convert ticks into time.
+///
+/// Notes are left unmapped; call [`MidiKeyTrack::remap`] to assign keyboard keys. A track is
+/// named after its `TrackName` event, or else its first instrument, or else its channel prefix
+/// or position. While a channel prefix (`MidiChannel` meta event) is in effect, only program
+/// changes on that channel name the track's instruments.
+///
+/// When a pitch is struck again before it was released, each note-off ends the oldest sounding
+/// note of that pitch (first in, first out). A note that is never released keeps no duration.
+pub fn load_midi(
+    file: &[u8],
+    duplicate_notes: DuplicateNotes,
+) -> Result<(Vec<MidiKeyTrack>, SongTiming), LoadMidiFileError> {
+    let parsed_midi = midly::Smf::parse(file)?;
+    let program_names = program_to_string();
+
+    let mut tracks = Vec::with_capacity(parsed_midi.tracks.len());
+    let mut timing = SongTiming {
+        timing: parsed_midi.header.timing,
+        tempo: DEFAULT_TEMPO,
+        tempo_changes: Vec::new(),
+        time_signature: (4, 4),
+        time_signature_changes: Vec::new(),
+    };
+    let mut found_tempo = false;
+    let mut found_time_signature = false;
+    log::info!("found tracks: {}", parsed_midi.tracks.len());
+    for (track_index, track) in parsed_midi.tracks.into_iter().enumerate() {
+        let mut midi_key_track = MidiKeyTrack::new();
+        // Roughly half of a typical track's events are note-ons.
+        midi_key_track.midi_key_pairs.reserve(track.len() / 2);
+        let mut track_name = None;
+        // Channel that meta events currently apply to, and the first one the track declared.
+        let mut channel_prefix: Option<u8> = None;
+        let mut track_channel: Option<u8> = None;
+        let mut tick: u64 = 0;
+        // Indices of the sounding notes for each (channel, key), oldest first, waiting for
+        // their note-offs.
+        let mut open_notes: HashMap<(u8, u8), VecDeque<usize>> = HashMap::new();
+        for note in track {
+            tick += note.delta.as_int() as u64;
+            match note.kind {
+                midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => {
+                    timing.tempo_changes.push((tick, tempo.as_int()));
+                    if !found_tempo {
+                        timing.tempo = tempo.as_int();
+                        found_tempo = true;
+                    }
+                }
+                midly::TrackEventKind::Meta(midly::MetaMessage::TimeSignature(
+                    numerator,
+                    denominator_power,
+                    ..,
+                )) if numerator > 0 && denominator_power < 8 => {
+                    let signature = (numerator, 1 << denominator_power);
+                    timing.time_signature_changes.push((tick, signature));
+                    if !found_time_signature {
+                        timing.time_signature = signature;
+                        found_time_signature = true;
+                    }
+                }
+                midly::TrackEventKind::Meta(midly::MetaMessage::MidiChannel(channel)) => {
+                    channel_prefix = Some(channel.as_int());
+                    track_channel.get_or_insert(channel.as_int());
+                }
+                midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(name)) => {
+                    let name = String::from_utf8_lossy(name).trim().to_owned();
+                    if track_name.is_none() && !name.is_empty() {
+                        track_name = Some(name);
+                    }
+                }
+                midly::TrackEventKind::Midi { channel, message } => match message {
+                    midly::MidiMessage::NoteOn { key, vel }
+                        if vel > 0
+                            && duplicate_notes == DuplicateNotes::KeepFirst
+                            && midi_key_track.has_note_at(tick, key.as_int()) => {}
+                    midly::MidiMessage::NoteOn { key, vel } if vel > 0 => {
+                        let pair = MidiKeyPair {
+                            delta: note.delta.as_int(),
+                            tick,
+                            duration: None,
+                            midi_key: key.as_int(),
+                            velocity: vel.as_int(),
+                            channel: channel.as_int(),
+                            keyboard_key: None,
+                            substituted: false,
+                        };
+                        open_notes
+                            .entry((channel.as_int(), key.as_int()))
+                            .or_default()
+                            .push_back(midi_key_track.midi_key_pairs.len());
+                        midi_key_track.midi_key_pairs.push(pair);
+                    }
+                    // A note-on with zero velocity is a note-off by convention.
+                    midly::MidiMessage::NoteOn { key, .. }
+                    | midly::MidiMessage::NoteOff { key, .. } => {
+                        let sounding = open_notes.get_mut(&(channel.as_int(), key.as_int()));
+                        if let Some(index) = sounding.and_then(|notes| notes.pop_front()) {
+                            let pair = &mut midi_key_track.midi_key_pairs[index];
+                            pair.duration = Some(tick - pair.tick);
+                        }
+                    }
+                    midly::MidiMessage::ProgramChange { program }
+                        if channel_prefix.is_none_or(|prefix| prefix == channel.as_int()) =>
+                    {
+                        if let Some(name) = program_names.get(&program.as_int()) {
+                            midi_key_track.program_changes.push(ProgramChange {
+                                tick,
+                                name: name.clone(),
+                                channel: channel.as_int(),
+                            });
+                        }
+                    }
+                    midly::MidiMessage::PitchBend { bend } => {
+                        midi_key_track.pitch_bends.push(PitchBend {
+                            tick,
+                            channel: channel.as_int(),
+                            value: bend.as_int(),
+                        });
+                    }
+                    _ => (),
+                },
+                // Device-specific data, possibly large or split over several events; none of it
+                // affects the notes, so it is only counted.
+                midly::TrackEventKind::SysEx(_) | midly::TrackEventKind::Escape(_) => {
+                    midi_key_track.sysex_events += 1;
+                }
+                _ => (),
+            }
+        }
+        if midi_key_track.sysex_events > 0 {
+            log::debug!(
+                "skipped {} SysEx events in track {}",
+                midi_key_track.sysex_events,
+                track_index + 1
+            );
+        }
+        midi_key_track.name = track_name
+            .or_else(|| {
+                let first_program = midi_key_track.program_changes.first();
+                first_program.map(|change| change.name.clone())
+            })
+            .unwrap_or_else(|| match track_channel {
+                Some(channel) => format!("Channel {}", channel + 1),
+                None => format!("Channel {}", track_index + 1),
+            });
+        midi_key_track.percussion = midi_key_track.plays_percussion();
+        tracks.push(midi_key_track)
+    }
+    timing.tempo_changes.sort_by_key(|(tick, _)| *tick);
+    timing.time_signature_changes.sort_by_key(|(tick, _)| *tick);
+    Ok((tracks, timing))
+}
+
+/// Reads a MIDI file or a MusicXML score, telling them apart by their contents.
+pub fn load_song(
+    file: &[u8],
+    duplicate_notes: DuplicateNotes,
+) -> Result<(Vec<MidiKeyTrack>, SongTiming), LoadMidiFileError> {
+    if musicxml::is_musicxml(file) {
+        Ok(musicxml::load_musicxml(file)?)
+    } else {
+        load_midi(file, duplicate_notes)
+    }
+}
+
+/// Channels that notes of `tracks` are played on: those in `order` first, then the rest from
+/// lowest to highest.
+pub fn channel_order(tracks: &[MidiKeyTrack], order: &[u8]) -> Vec<u8> {
+    let mut used = [false; 16];
+    for pair in tracks.iter().flat_map(|track| track.midi_key_pairs.iter()) {
+        used[pair.channel as usize & 15] = true;
+    }
+    let mut channels = Vec::new();
+    for channel in order.iter().copied().chain(0..16) {
+        if used.get(channel as usize) == Some(&true) && !channels.contains(&channel) {
+            channels.push(channel);
+        }
+    }
+    channels
+}
+
+/// Regroups the notes of `tracks` into one track per MIDI channel, in the order given by
+/// [`channel_order`]. Useful for files that keep all parts in one track, e.g. format 0.
+///
+/// SysEx events belong to no channel, so they are all counted with the first new track.
+pub fn split_by_channel(tracks: &[MidiKeyTrack], order: &[u8]) -> Vec<MidiKeyTrack> {
+    let mut sysex_events: usize = tracks.iter().map(|track| track.sysex_events).sum();
+    channel_order(tracks, order)
+        .into_iter()
+        .map(|channel| {
+            let mut pairs: Vec<MidiKeyPair> = tracks
+                .iter()
+                .flat_map(|track| track.midi_key_pairs.iter())
+                .filter(|pair| pair.channel == channel)
+                .cloned()
+                .collect();
+            pairs.sort_by_key(|pair| pair.tick);
+            // Deltas are relative to the previous note of the new track.
+            let mut previous_tick = 0;
+            for pair in pairs.iter_mut() {
+                pair.delta = (pair.tick - previous_tick) as u32;
+                previous_tick = pair.tick;
+            }
+            let mut program_changes: Vec<ProgramChange> = tracks
+                .iter()
+                .flat_map(|track| track.program_changes.iter())
+                .filter(|change| change.channel == channel)
+                .cloned()
+                .collect();
+            program_changes.sort_by_key(|change| change.tick);
+            let mut pitch_bends: Vec<PitchBend> = tracks
+                .iter()
+                .flat_map(|track| track.pitch_bends.iter())
+                .filter(|bend| bend.channel == channel)
+                .cloned()
+                .collect();
+            pitch_bends.sort_by_key(|bend| bend.tick);
+            MidiKeyTrack {
+                name: match program_changes.first() {
+                    _ if channel == PERCUSSION_CHANNEL => {
+                        format!("Percussion (channel {})", channel + 1)
+                    }
+                    Some(change) => format!("{} (channel {})", change.name, channel + 1),
+                    None => format!("Channel {}", channel + 1),
+                },
+                percussion: channel == PERCUSSION_CHANNEL,
+                midi_key_pairs: pairs,
+                program_changes,
+                sysex_events: std::mem::take(&mut sysex_events),
+                pitch_bends,
+                ..MidiKeyTrack::default()
+            }
+        })
+        .collect()
+}
+
+#[derive(Error, Debug)]
+pub enum LoadMidiFileError {
+    #[error("io error: {0}")]
+    IOError(std::io::Error),
+    #[error("midly error: {0}")]
+    MidlyError(midly::Error),
+    #[error("no track exists")]
+    NoTrackError,
+    #[error("MusicXML error: {0}")]
+    MusicXmlError(musicxml::MusicXmlError),
+}
+
+impl From<std::io::Error> for LoadMidiFileError {
+    fn from(value: std::io::Error) -> Self {
+        LoadMidiFileError::IOError(value)
+    }
+}
+
+impl From<midly::Error> for LoadMidiFileError {
+    fn from(value: midly::Error) -> Self {
+        LoadMidiFileError::MidlyError(value)
+    }
+}
+
+impl From<musicxml::MusicXmlError> for LoadMidiFileError {
+    fn from(value: musicxml::MusicXmlError) -> Self {
+        LoadMidiFileError::MusicXmlError(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::{Format, Header, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+    pub(crate) fn note_on(delta: u32, key: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOn {
+                    key: key.into(),
+                    vel: 100.into(),
+                },
+            },
+        }
+    }
+
+    pub(crate) fn note_off(delta: u32, key: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message: MidiMessage::NoteOff {
+                    key: key.into(),
+                    vel: 0.into(),
+                },
+            },
+        }
+    }
+
+    /// A quarter note at 480 ticks per beat, not yet mapped to a key.
+    fn pair(tick: u64, key: u8) -> MidiKeyPair {
+        MidiKeyPair {
+            delta: 0,
+            tick,
+            duration: Some(480),
+            midi_key: key,
+            velocity: 100,
+            channel: 0,
+            keyboard_key: None,
+            substituted: false,
+        }
+    }
+
+    #[test]
+    fn programs_are_bucketed_into_families() {
+        assert_eq!(program_family(0), "Piano");
+        assert_eq!(program_family(8), "Piano");
+        assert_eq!(program_family(9), "Chromatic Percussion");
+        assert_eq!(program_family(41), "Strings");
+        assert_eq!(program_family(128), "Sound Effects");
+        assert_eq!(program_family(u8::MAX), "Sound Effects");
+
+        let mut track = MidiKeyTrack::new();
+        assert_eq!(track.instrument().and_then(instrument_family), None);
+        track.instrument = Some("Violin".to_owned());
+        assert_eq!(
+            track.instrument().and_then(instrument_family),
+            Some("Strings")
+        );
+        assert_eq!(instrument_family("Kazoo"), None);
+    }
+
+    #[test]
+    fn non_ascii_key_labels_line_up() {
+        let settings = Settings {
+            layout: vec![vec!["ESC".to_owned(), "É".to_owned(), "🎹".to_owned()]],
+            ..Default::default()
+        };
+        let mapping = chromatic_planck_mapping("ESC", settings.layout.clone());
+        let mut track = MidiKeyTrack::new();
+        for (i, key) in [60, 61, 62].into_iter().enumerate() {
+            track.midi_key_pairs.push(pair(i as u64 * 480, key));
+        }
+        // The first program change names the track; later ones get a line of their own.
+        for (tick, name) in [(0, "Piano"), (480, "Flûte à bec")] {
+            track.program_changes.push(ProgramChange {
+                tick,
+                name: name.to_owned(),
+                channel: 0,
+            });
+        }
+        track.remap(&mapping, UnmappedNotes::Mark, 0);
+        let filter = NoteFilter::default();
+        let lines = track.get_midi_keys_lines(&settings, &filter);
+        let note_lines: Vec<_> = lines
+            .iter()
+            .filter(|line| !line.pair_indices.is_empty())
+            .map(|line| line.segments[0].0.as_str())
+            .collect();
+        assert_eq!(
+            note_lines,
+            vec!["60    (ESC ) ", "61    (É   ) ", "62    (🎹  ) "]
+        );
+        assert!(note_lines
+            .iter()
+            .all(|line| text::display_width(line) == text::display_width(note_lines[0])));
+        assert!(lines
+            .iter()
+            .any(|line| line.segments[0].0 == "[Flûte à bec]"));
+    }
+
+    #[test]
+    fn isomorphic_mapping_moves_a_fourth_per_row() {
+        let mapping = isomorphic_planck_mapping("ESC", &default_planck_rows(), 5, 1);
+        assert_eq!(mapping[&60], "ESC");
+        assert_eq!(mapping[&61], "A");
+        assert_eq!(mapping[&55], "TAB");
+        // T and G also land on 60 and 65, but ESC and SHF are closer to the base key.
+        assert_eq!(mapping[&65], "SHF");
+        assert_eq!(mapping[&66], "Z");
+    }
+
+    #[test]
+    fn blank_layout_cells_get_no_note() {
+        let rows = default_planck_rows();
+        let chromatic = chromatic_planck_mapping("ESC", rows.clone());
+        assert!(chromatic.values().all(|key| !key.is_empty()));
+        // The blank cell still counts as a semitone between ETR and CTRL.
+        assert_eq!(chromatic[&83], "ETR");
+        assert_eq!(chromatic.get(&84), None);
+        assert_eq!(chromatic[&85], "CTRL");
+
+        let isomorphic = isomorphic_planck_mapping("ESC", &rows, 5, 1);
+        assert!(isomorphic.values().all(|key| !key.is_empty()));
+    }
+
+    #[test]
+    fn chord_lines_end_with_a_chord_name() {
+        let mut settings = Settings::default();
+        settings.display.show_chord_names = true;
+        let mut track = MidiKeyTrack::new();
+        for (tick, key) in [(0, 60), (0, 64), (0, 67), (480, 60), (480, 64), (960, 65)] {
+            track.midi_key_pairs.push(pair(tick, key));
+        }
+        let filter = NoteFilter::default();
+        let names: Vec<_> = track
+            .get_midi_keys_lines(&settings, &filter)
+            .iter()
+            .map(|line| line.segments.last().unwrap().0.clone())
+            .collect();
+        // Single notes are left alone.
+        assert_eq!(names[0], " Cmaj");
+        assert_eq!(names[1], " Cmaj?");
+        assert!(!names[2].starts_with(' '));
+    }
+
+    #[test]
+    fn pitch_bends_are_shown_in_cents() {
+        let bend = |delta: u32, channel: u8, value: i16| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: channel.into(),
+                message: MidiMessage::PitchBend {
+                    bend: midly::PitchBend::from_int(value),
+                },
+            },
+        };
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![vec![
+                note_on(0, 60),
+                note_off(480, 60),
+                // A quarter tone up, then a bend on another channel that leaves it alone.
+                bend(0, 0, 2048),
+                bend(0, 1, -8192),
+                note_on(0, 62),
+                note_off(480, 62),
+                bend(0, 0, 0),
+                note_on(0, 64),
+                note_off(480, 64),
+            ]],
+        };
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        let (tracks, _) = load_midi(&bytes, DuplicateNotes::KeepAll).unwrap();
+        let track = &tracks[0];
+        let cents: Vec<_> = track
+            .midi_key_pairs
+            .iter()
+            .map(|pair| track.bend_cents(pair))
+            .collect();
+        assert_eq!(cents, vec![None, Some(50), None]);
+
+        let mut settings = Settings::default();
+        settings.display.show_cents = true;
+        let filter = NoteFilter::default();
+        let lines = track.get_midi_keys_lines(&settings, &filter);
+        assert!(lines[1].segments[0].0.contains("≈+50¢"));
+        assert!(!lines[0].segments[0].0.contains('¢'));
+    }
+
+    #[test]
+    fn notes_on_modifier_keys_are_flagged() {
+        let mut settings = Settings::default();
+        settings.display.modifier_keys = "SHF, CTRL".to_owned();
+        let mut track = MidiKeyTrack::new();
+        for (tick, key) in [(0, "A"), (480, "CTRL"), (960, "SHFUP")] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                keyboard_key: Some(key.to_owned()),
+                ..pair(tick, 60)
+            });
+        }
+        let filter = NoteFilter::default();
+        let flagged: Vec<_> = track
+            .get_midi_keys_lines(&settings, &filter)
+            .iter()
+            .map(|line| {
+                line.segments
+                    .iter()
+                    .any(|(_, kind)| *kind == SegmentKind::ModifierKey)
+            })
+            .collect();
+        assert_eq!(flagged, vec![false, true, false]);
+    }
+
+    #[test]
+    fn keys_can_be_named_by_their_position() {
+        let mut settings = Settings::default();
+        let mut note = MidiKeyPair {
+            keyboard_key: Some("A".to_owned()),
+            ..pair(0, 62)
+        };
+        assert_eq!(keyboard_key_name(&note, &settings), "A");
+        settings.display.key_names = KeyNames::Coordinates;
+        assert_eq!(keyboard_key_name(&note, &settings), "(1,1)");
+        note.substituted = true;
+        assert_eq!(keyboard_key_name(&note, &settings), "~(1,1)");
+        // Keys that aren't on the layout keep their label.
+        note.keyboard_key = Some("F13".to_owned());
+        assert_eq!(keyboard_key_name(&note, &settings), "~F13");
+        note.keyboard_key = None;
+        assert_eq!(keyboard_key_name(&note, &settings), "NONE");
+    }
+
+    #[test]
+    fn chords_group_the_notes_of_each_tick() {
+        let mut track = MidiKeyTrack::new();
+        for (tick, key, label) in [(0, 60, "A"), (0, 64, "S"), (0, 67, "D"), (480, 62, "")] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                keyboard_key: (!label.is_empty()).then(|| label.to_owned()),
+                ..pair(tick, key)
+            });
+        }
+        let chords = track.chords();
+        assert_eq!(
+            chords,
+            vec![
+                MidiChord {
+                    tick: 0,
+                    pair_indices: vec![0, 1, 2],
+                },
+                MidiChord {
+                    tick: 480,
+                    pair_indices: vec![3],
+                },
+            ]
+        );
+        assert_eq!(chords[0].keys(&track), "[A S D]");
+        assert_eq!(chords[1].keys(&track), "NONE");
+
+        let mut settings = Settings::default();
+        settings.display.bracket_chords = true;
+        let filter = NoteFilter::default();
+        let lines = track.get_midi_keys_lines(&settings, &filter);
+        assert_eq!(lines[0].segments.last().unwrap().0, " [A S D]");
+        assert_eq!(lines[1].segments.len(), 1);
+    }
+
+    #[test]
+    fn track_details_summarise_the_notes() {
+        let mut track = MidiKeyTrack::new();
+        for (key, channel, label, substituted) in [
+            (60, 0, Some("ESC"), false),
+            (48, 2, Some("TAB"), true),
+            (72, 0, None, false),
+            (64, 0, Some("D"), false),
+        ] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                channel,
+                keyboard_key: label.map(str::to_owned),
+                substituted,
+                ..pair(0, key)
+            });
+        }
+        track.program_changes.push(ProgramChange {
+            tick: 0,
+            name: "Violin".to_owned(),
+            channel: 9,
+        });
+        let details = track.details();
+        assert_eq!(
+            details,
+            TrackDetails {
+                channels: vec![0, 2, 9],
+                notes: 4,
+                range: Some((48, 72)),
+                unmappable: 2,
+            }
+        );
+        assert_eq!(details.unmappable_percent(), 50.0);
+        assert_eq!(MidiKeyTrack::new().details().unmappable_percent(), 0.0);
+    }
+}
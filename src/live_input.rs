@@ -0,0 +1,245 @@
+use crate::{MidiKeyPair, MidiKeyTrack, SongTiming};
+use eframe::egui;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use thiserror::*;
+
+const CLIENT_NAME: &str = "Planck Scribe";
+
+#[derive(Error, Debug)]
+pub enum LiveInputError {
+    #[error("MIDI input unavailable: {0}")]
+    Init(#[from] midir::InitError),
+    #[error("could not connect: {0}")]
+    Connect(String),
+    #[error("the MIDI input is no longer there")]
+    NoSuchPort,
+}
+
+/// A note pressed or released on the MIDI input, stamped when it arrived.
+pub struct NoteEvent {
+    pub key: u8,
+    pub velocity: u8,
+    pub pressed: bool,
+    pub received: Instant,
+}
+
+/// Reads a note-on or note-off out of a raw MIDI message.
+fn note_event(message: &[u8], received: Instant) -> Option<NoteEvent> {
+    let (&status, data) = message.split_first()?;
+    let (key, velocity) = match data {
+        [key, velocity, ..] => (*key, *velocity),
+        _ => return None,
+    };
+    let pressed = match status & 0xF0 {
+        0x90 => velocity > 0,
+        0x80 => false,
+        _ => return None,
+    };
+    Some(NoteEvent {
+        key,
+        velocity,
+        pressed,
+        received,
+    })
+}
+
+/// Names of the MIDI inputs that can be connected to.
+pub fn ports() -> Result<Vec<String>, LiveInputError> {
+    let input = MidiInput::new(CLIENT_NAME)?;
+    Ok(input
+        .ports()
+        .iter()
+        .filter_map(|port| input.port_name(port).ok())
+        .collect())
+}
+
+/// A connected MIDI input and the notes currently held on it.
+///
+/// midir calls back on its own input thread; notes are parsed there and sent to the UI over a
+/// lock-free channel, waking it right away instead of waiting for the next frame.
+pub struct LiveInput {
+    pub port_name: String,
+    connection: Option<MidiInputConnection<()>>,
+    events: mpsc::Receiver<NoteEvent>,
+    held: BTreeSet<u8>,
+    /// Time from a note arriving to the frame that shows it, averaged over recent notes.
+    latency: Option<Duration>,
+}
+
+impl LiveInput {
+    pub fn connect(port_index: usize, ctx: egui::Context) -> Result<LiveInput, LiveInputError> {
+        let mut input = MidiInput::new(CLIENT_NAME)?;
+        input.ignore(Ignore::All);
+        let ports = input.ports();
+        let port = ports.get(port_index).ok_or(LiveInputError::NoSuchPort)?;
+        let port_name = input.port_name(port).unwrap_or_default();
+        let (sender, events) = mpsc::channel();
+        let connection = input
+            .connect(
+                port,
+                "live input",
+                move |_, message, _| {
+                    if let Some(event) = note_event(message, Instant::now()) {
+                        if sender.send(event).is_ok() {
+                            ctx.request_repaint();
+                        }
+                    }
+                },
+                (),
+            )
+            .map_err(|err| LiveInputError::Connect(err.to_string()))?;
+        log::info!("connected to MIDI input {port_name}");
+        Ok(LiveInput {
+            port_name,
+            connection: Some(connection),
+            events,
+            held: BTreeSet::new(),
+            latency: None,
+        })
+    }
+
+    /// Takes in the notes that arrived since the last frame and returns them in order.
+    pub fn poll(&mut self) -> Vec<NoteEvent> {
+        let mut events = Vec::new();
+        for event in self.events.try_iter() {
+            let sample = event.received.elapsed();
+            self.latency = Some(match self.latency {
+                Some(latency) => latency.mul_f32(0.8) + sample.mul_f32(0.2),
+                None => sample,
+            });
+            if event.pressed {
+                self.held.insert(event.key);
+            } else {
+                self.held.remove(&event.key);
+            }
+            events.push(event);
+        }
+        events
+    }
+
+    /// MIDI keys held down right now, lowest first.
+    pub fn held(&self) -> impl Iterator<Item = u8> + '_ {
+        self.held.iter().copied()
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+}
+
+impl Drop for LiveInput {
+    /// Closing the connection stops midir's input thread and waits for it to finish.
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.close();
+            log::info!("disconnected from MIDI input {}", self.port_name);
+        }
+    }
+}
+
+/// Writes the notes played on the MIDI input into a track, timed from when recording started
+/// at the song's tempo.
+pub struct Recorder {
+    /// Index of the track being recorded into.
+    pub track: usize,
+    started: Instant,
+    /// Index in the track of each held note, to give it a duration when it is released.
+    held: HashMap<u8, usize>,
+}
+
+impl Recorder {
+    pub fn new(track: usize) -> Recorder {
+        Recorder {
+            track,
+            started: Instant::now(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Adds a pressed note to `track`, or ends the held note a release belongs to. Notes are
+    /// left unmapped.
+    pub fn record(&mut self, event: &NoteEvent, track: &mut MidiKeyTrack, timing: &SongTiming) {
+        let elapsed = event.received.saturating_duration_since(self.started);
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        let tick = timing
+            .time_ms_to_tick(ms)
+            .unwrap_or_else(|| timing.ms_to_ticks(ms))
+            .round() as u64;
+        if event.pressed {
+            let last_tick = track.midi_key_pairs.last().map_or(0, |pair| pair.tick);
+            self.held.insert(event.key, track.midi_key_pairs.len());
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: tick.saturating_sub(last_tick).min(u32::MAX as u64) as u32,
+                tick: tick.max(last_tick),
+                duration: None,
+                midi_key: event.key,
+                velocity: event.velocity,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        } else if let Some(pair) = self
+            .held
+            .remove(&event.key)
+            .and_then(|index| track.midi_key_pairs.get_mut(index))
+        {
+            pair.duration = Some(tick.saturating_sub(pair.tick));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_note_ons_and_offs() {
+        let now = Instant::now();
+        let event = |message: &[u8]| note_event(message, now).map(|e| (e.key, e.pressed));
+        assert_eq!(event(&[0x90, 60, 100]), Some((60, true)));
+        assert_eq!(event(&[0x93, 61, 0]), Some((61, false)));
+        assert_eq!(event(&[0x80, 62, 64]), Some((62, false)));
+        // Control changes and truncated messages are ignored.
+        assert_eq!(event(&[0xB0, 64, 127]), None);
+        assert_eq!(event(&[0x90, 60]), None);
+    }
+
+    #[test]
+    fn recorded_notes_are_timed_at_the_song_tempo() {
+        let mut recorder = Recorder::new(0);
+        let mut track = MidiKeyTrack::new();
+        // 480 ticks per beat at 120 BPM: a beat every 500 ms.
+        let timing = SongTiming::default();
+        let at = |ms: u64| recorder.started + Duration::from_millis(ms);
+        let events = [
+            note_event(&[0x90, 60, 90], at(500)).unwrap(),
+            note_event(&[0x90, 64, 80], at(1000)).unwrap(),
+            note_event(&[0x80, 60, 0], at(1250)).unwrap(),
+        ];
+        for event in &events {
+            recorder.record(event, &mut track, &timing);
+        }
+        let notes: Vec<_> = track
+            .midi_key_pairs
+            .iter()
+            .map(|pair| {
+                (
+                    pair.tick,
+                    pair.delta,
+                    pair.duration,
+                    pair.midi_key,
+                    pair.velocity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            notes,
+            vec![(480, 480, Some(720), 60, 90), (960, 480, None, 64, 80)]
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Ways of laying MIDI notes out over a keyboard grid, starting from middle C on a base key.
+
+use crate::{layout, PlanckRows, MIDI_C_KEY};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+use thiserror::*;
+
+/// A way of giving each cell of a layout a note.
+pub trait MappingScheme {
+    /// Semitones from the base key, at `base`, to the key at `cell`. Both are (row, column)
+    /// positions in `rows`, rows counted from the top.
+    fn semitones(&self, rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32;
+}
+
+/// One semitone per key, reading the whole grid left to right and top to bottom.
+pub struct Chromatic;
+
+impl MappingScheme for Chromatic {
+    fn semitones(&self, rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32 {
+        // Blank cells take up their semitone too, so the keys after them keep their notes.
+        let index = |(row, column): (usize, usize)| {
+            rows[..row].iter().map(Vec::len).sum::<usize>() as i32 + column as i32
+        };
+        index(cell) - index(base)
+    }
+}
+
+/// A fixed interval per column and per row, so a shape plays the same chord anywhere.
+pub struct Isomorphic {
+    /// Semitones from one row to the row below.
+    pub row_interval: i8,
+    /// Semitones from one key to the key on its right.
+    pub column_interval: i8,
+}
+
+impl MappingScheme for Isomorphic {
+    fn semitones(&self, _rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32 {
+        (cell.0 as i32 - base.0 as i32) * self.row_interval as i32
+            + (cell.1 as i32 - base.1 as i32) * self.column_interval as i32
+    }
+}
+
+// The hexagonal layouts below are laid on the grid with each row shifted half a key right of
+// the row below: the key above is the up-right neighbour on the hexagons, the key above and
+// to the left the up-left one.
+
+/// Wicki–Hayden: a fifth up-right and a fourth up-left, so a whole tone to the right. Major
+/// scales fall into two rows.
+pub struct WickiHayden;
+
+impl MappingScheme for WickiHayden {
+    fn semitones(&self, rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32 {
+        Isomorphic {
+            row_interval: -7,
+            column_interval: 2,
+        }
+        .semitones(rows, base, cell)
+    }
+}
+
+/// Harmonic Table: a major third up-right and a minor third up-left, so a semitone to the
+/// right. Triads are three neighbouring keys.
+pub struct HarmonicTable;
+
+impl MappingScheme for HarmonicTable {
+    fn semitones(&self, rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32 {
+        Isomorphic {
+            row_interval: -4,
+            column_interval: 1,
+        }
+        .semitones(rows, base, cell)
+    }
+}
+
+/// Jankó: whole tones along each row, the row above a semitone higher, so every scale has
+/// the same fingering in every key.
+pub struct Janko;
+
+impl MappingScheme for Janko {
+    fn semitones(&self, rows: &PlanckRows, base: (usize, usize), cell: (usize, usize)) -> i32 {
+        Isomorphic {
+            row_interval: -1,
+            column_interval: 2,
+        }
+        .semitones(rows, base, cell)
+    }
+}
+
+/// Maps MIDI keys onto `rows` as `scheme` lays them out, with middle C on `base_key`.
+///
+/// Blank cells have no key to press, so no note is mapped to them. When several keys play the
+/// same note, the one closest to the base key gets it.
+///
+/// If `base_key` is not in `rows`, middle C goes on the first key instead, and a layout with
+/// no keys maps nothing.
+pub fn scheme_mapping(
+    scheme: &dyn MappingScheme,
+    base_key: &str,
+    rows: &PlanckRows,
+) -> HashMap<u8, String> {
+    let Some(base) = layout::key_position(rows, base_key).or_else(|| {
+        let first_key = rows.iter().flatten().find(|key| !key.is_empty())?;
+        log::warn!(
+            "{} is not in the layout, mapping from {}",
+            base_key,
+            first_key
+        );
+        layout::key_position(rows, first_key)
+    }) else {
+        return HashMap::new();
+    };
+
+    // MIDI key -> (distance from the base key, keyboard key)
+    let mut closest_keys: HashMap<u8, (usize, &String)> = HashMap::new();
+    for (row_index, row) in rows.iter().enumerate() {
+        for (column, keyboard_key) in row.iter().enumerate() {
+            if keyboard_key.is_empty() {
+                continue;
+            }
+            let midi_key_i32 =
+                MIDI_C_KEY as i32 + scheme.semitones(rows, base, (row_index, column));
+            let Some(key_u8) = u8::try_from(midi_key_i32).ok().filter(|key| *key <= 127) else {
+                continue;
+            };
+            let distance = row_index.abs_diff(base.0) + column.abs_diff(base.1);
+            let closest = closest_keys
+                .entry(key_u8)
+                .or_insert((distance, keyboard_key));
+            if distance < closest.0 {
+                *closest = (distance, keyboard_key);
+            }
+        }
+    }
+    closest_keys
+        .into_iter()
+        .map(|(midi_key, (_, keyboard_key))| (midi_key, keyboard_key.clone()))
+        .collect()
+}
+
+/// Notes assigned to keys by hand, overriding what the scheme gives them.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default, Debug)]
+#[serde(default)]
+pub struct CustomMapping {
+    /// The notes each overridden key plays, by key label. A key without notes plays nothing.
+    pub keys: BTreeMap<String, Vec<u8>>,
+}
+
+#[derive(Error, Debug)]
+pub enum CustomMappingError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid mapping file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("{0} is not a MIDI note")]
+    InvalidNote(u8),
+}
+
+impl CustomMapping {
+    /// Gives the overridden keys of `rows` their notes in `mapping`, taking the notes away from
+    /// the keys the scheme gave them to. Keys that aren't in `rows` are left out.
+    pub fn apply(&self, mapping: &mut HashMap<u8, String>, rows: &PlanckRows) {
+        for (key, notes) in &self.keys {
+            if layout::key_position(rows, key).is_none() {
+                continue;
+            }
+            mapping.retain(|_, mapped| mapped != key);
+            for &note in notes {
+                mapping.insert(note, key.clone());
+            }
+        }
+    }
+
+    /// Assigns `note` to `key` on top of the notes it has, moving it off any other key. A key
+    /// it was the last note of goes back to what the scheme gives it.
+    pub fn assign(&mut self, key: &str, note: u8) {
+        self.keys.retain(|_, notes| {
+            let assigned = notes.len();
+            notes.retain(|assigned| *assigned != note);
+            assigned == 0 || !notes.is_empty()
+        });
+        let notes = self.keys.entry(key.to_owned()).or_default();
+        notes.push(note);
+        notes.sort();
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), CustomMappingError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn import(path: &Path) -> Result<CustomMapping, CustomMappingError> {
+        let custom: CustomMapping = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if let Some(&note) = custom.keys.values().flatten().find(|note| **note > 127) {
+            return Err(CustomMappingError::InvalidNote(note));
+        }
+        Ok(custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_planck_rows;
+
+    #[test]
+    fn hexagonal_schemes_keep_their_intervals() {
+        let rows = default_planck_rows();
+        let base = (1, 5);
+        for (scheme, up_right, up_left) in [
+            (&WickiHayden as &dyn MappingScheme, 7, 5),
+            (&HarmonicTable, 4, 3),
+            (&Janko, 1, -1),
+        ] {
+            assert_eq!(scheme.semitones(&rows, base, (0, 5)), up_right);
+            assert_eq!(scheme.semitones(&rows, base, (0, 4)), up_left);
+            assert_eq!(scheme.semitones(&rows, base, (1, 6)), up_right - up_left);
+            assert_eq!(scheme.semitones(&rows, base, (2, 5)), -up_right);
+
+            let mapping = scheme_mapping(scheme, "G", &rows);
+            assert_eq!(mapping[&MIDI_C_KEY], "G");
+            // The bottom-left cell is blank.
+            assert!(mapping.values().all(|key| !key.is_empty()));
+        }
+    }
+
+    #[test]
+    fn missing_base_key_falls_back_to_the_first_key() {
+        let rows = default_planck_rows();
+        assert_eq!(
+            scheme_mapping(&Chromatic, "NOT A KEY", &rows),
+            scheme_mapping(&Chromatic, "TAB", &rows)
+        );
+        let blank = vec![vec![String::new(); 3]];
+        assert!(scheme_mapping(&Chromatic, "TAB", &blank).is_empty());
+    }
+
+    #[test]
+    fn custom_keys_override_the_scheme() {
+        let rows = default_planck_rows();
+        let mut mapping = scheme_mapping(&Chromatic, "ESC", &rows);
+        let mut custom = CustomMapping::default();
+        custom.assign("A", 72);
+        custom.assign("A", 48);
+        custom.assign("Q", 50);
+        // Assigning a note again moves it.
+        custom.assign("S", 50);
+        custom.keys.insert("D".to_owned(), Vec::new());
+        custom.keys.insert("NOT A KEY".to_owned(), vec![61]);
+        custom.apply(&mut mapping, &rows);
+
+        assert_eq!(custom.keys["A"], [48, 72]);
+        assert_eq!(mapping[&48], "A");
+        assert_eq!(mapping[&72], "A");
+        // A no longer plays its chromatic note, and D plays nothing.
+        assert_eq!(mapping.get(&61), None);
+        assert!(!mapping.values().any(|key| key == "D"));
+        assert_eq!(mapping[&50], "S");
+        assert!(!custom.keys.contains_key("Q"));
+        assert_eq!(mapping[&49], "Q");
+        assert_eq!(mapping[&60], "ESC");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{program_number, MidiKeyTrack, SongTiming};
+use midir::{MidiOutput, MidiOutputConnection};
+use std::{
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+use thiserror::*;
+
+const CLIENT_NAME: &str = "Planck Scribe";
+
+#[derive(Error, Debug)]
+pub enum MidiOutputError {
+    #[error("MIDI output unavailable: {0}")]
+    Init(#[from] midir::InitError),
+    #[error("could not connect: {0}")]
+    Connect(String),
+    #[error("the MIDI output is no longer there")]
+    NoSuchPort,
+}
+
+/// Names of the MIDI outputs that can be played through, e.g. the system's software synth.
+pub fn ports() -> Result<Vec<String>, MidiOutputError> {
+    let output = MidiOutput::new(CLIENT_NAME)?;
+    Ok(output
+        .ports()
+        .iter()
+        .filter_map(|port| output.port_name(port).ok())
+        .collect())
+}
+
+/// Where playback is heard: a MIDI output or the built-in synth.
+pub trait Sound {
+    /// What the sound is playing through, as shown in the UI.
+    fn name(&self) -> String;
+    fn send(&self, messages: Vec<Vec<u8>>);
+    /// Silences every channel, e.g. on pause or when playback jumps.
+    fn all_notes_off(&self);
+    /// Tunes A4 to `reference_pitch` Hz, where the sound can be tuned.
+    fn tune(&self, _reference_pitch: f64) {}
+}
+
+/// What the UI asks the output thread to send.
+enum Command {
+    Messages(Vec<Vec<u8>>),
+    AllNotesOff,
+}
+
+/// "All notes off" (controller 123) for every channel.
+fn all_notes_off() -> impl Iterator<Item = [u8; 3]> {
+    (0..16).map(|channel| [0xB0 | channel, 123, 0])
+}
+
+/// The program change message for an instrument named as in the tracks, if it is known.
+fn program_message(change: &crate::ProgramChange) -> Option<Vec<u8>> {
+    let program = program_number(&change.name)?;
+    Some(vec![0xC0 | (change.channel & 0x0F), program.min(127)])
+}
+
+/// Program changes setting the instrument each channel has at `tick`, for starting playback
+/// part way through a song.
+pub fn programs_at(tracks: &[MidiKeyTrack], tick: f64) -> Vec<Vec<u8>> {
+    let mut programs = std::collections::BTreeMap::new();
+    let mut changes: Vec<_> = tracks
+        .iter()
+        .filter(|track| !track.hidden)
+        .flat_map(|track| track.program_changes.iter())
+        .filter(|change| change.tick as f64 <= tick)
+        .collect();
+    changes.sort_by_key(|change| change.tick);
+    for change in changes {
+        programs.insert(change.channel & 0x0F, change);
+    }
+    programs.into_values().filter_map(program_message).collect()
+}
+
+/// Program changes, note-ons and note-offs of `tracks` falling in `from..to` ticks, in time
+/// order, with each track's transpose and the whole song's `song_transpose` applied so the
+/// sound matches the mapped keys. Offs come before ons at the same tick, so a repeated note is
+/// struck again, and program changes come before both. Notes that never end are held for a
+/// beat.
+pub fn messages_between(
+    tracks: &[MidiKeyTrack],
+    timing: &SongTiming,
+    from: f64,
+    to: f64,
+    song_transpose: i8,
+) -> Vec<Vec<u8>> {
+    let beat = timing.ticks_per_beat() as u64;
+    let in_range = |tick: u64| (from..to).contains(&(tick as f64));
+    // (tick, order at the tick, message)
+    let mut events: Vec<(u64, u8, Vec<u8>)> = Vec::new();
+    for track in tracks.iter().filter(|track| !track.hidden) {
+        for change in &track.program_changes {
+            if let Some(message) = program_message(change).filter(|_| in_range(change.tick)) {
+                events.push((change.tick, 0, message));
+            }
+        }
+        for pair in &track.midi_key_pairs {
+            let Some(key) = track.sounding_key(pair.midi_key, song_transpose) else {
+                continue;
+            };
+            let channel = pair.channel & 0x0F;
+            if in_range(pair.tick) {
+                events.push((
+                    pair.tick,
+                    2,
+                    vec![0x90 | channel, key, pair.velocity.max(1)],
+                ));
+            }
+            let end = pair.tick + pair.duration.unwrap_or(beat);
+            if in_range(end) {
+                events.push((end, 1, vec![0x80 | channel, key, 0]));
+            }
+        }
+    }
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+    events.into_iter().map(|(_, _, message)| message).collect()
+}
+
+/// A connected MIDI output that playback is heard through, e.g. the system's software synth.
+///
+/// The connection lives on its own thread, which the UI sends messages to over a channel, so a
+/// slow driver never stalls a frame.
+pub struct MidiOut {
+    pub port_name: String,
+    commands: Option<mpsc::Sender<Command>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MidiOut {
+    pub fn connect(port_index: usize) -> Result<MidiOut, MidiOutputError> {
+        let output = MidiOutput::new(CLIENT_NAME)?;
+        let ports = output.ports();
+        let port = ports.get(port_index).ok_or(MidiOutputError::NoSuchPort)?;
+        let port_name = output.port_name(port).unwrap_or_default();
+        let connection = output
+            .connect(port, "playback")
+            .map_err(|err| MidiOutputError::Connect(err.to_string()))?;
+        let (sender, commands) = mpsc::channel();
+        let thread = thread::spawn(move || output_thread(connection, commands));
+        log::info!("connected to MIDI output {port_name}");
+        Ok(MidiOut {
+            port_name,
+            commands: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    fn command(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            // The thread only stops once the sender is dropped, so this can't fail.
+            let _ = commands.send(command);
+        }
+    }
+}
+
+impl Sound for MidiOut {
+    fn name(&self) -> String {
+        self.port_name.clone()
+    }
+
+    fn send(&self, messages: Vec<Vec<u8>>) {
+        if !messages.is_empty() {
+            self.command(Command::Messages(messages));
+        }
+    }
+
+    fn all_notes_off(&self) {
+        self.command(Command::AllNotesOff);
+    }
+}
+
+/// Sends whatever the UI asks for until the `MidiOut` is dropped, then silences the synth.
+fn output_thread(mut connection: MidiOutputConnection, commands: mpsc::Receiver<Command>) {
+    let mut send = |message: &[u8]| {
+        if let Err(err) = connection.send(message) {
+            log::warn!("could not send to MIDI output: {err}");
+        }
+    };
+    for command in commands {
+        match command {
+            Command::Messages(messages) => messages.iter().for_each(|message| send(message)),
+            Command::AllNotesOff => all_notes_off().for_each(|message| send(&message)),
+        }
+    }
+    all_notes_off().for_each(|message| send(&message));
+    connection.close();
+}
+
+impl Drop for MidiOut {
+    /// Closing the channel ends the output thread; waits for it to silence the synth.
+    fn drop(&mut self) {
+        self.commands = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+            log::info!("disconnected from MIDI output {}", self.port_name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MidiKeyPair, ProgramChange};
+
+    #[test]
+    fn notes_are_sent_as_playback_passes_them() {
+        let mut track = MidiKeyTrack::new();
+        track.transpose = 12;
+        for (tick, key, duration) in [(0, 60, Some(480)), (480, 62, None)] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration,
+                midi_key: key,
+                velocity: 100,
+                channel: 1,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        let timing = SongTiming::default();
+        let tracks = [track];
+        assert_eq!(
+            messages_between(&tracks, &timing, 0.0, 480.0, 0),
+            vec![vec![0x91, 72, 100]]
+        );
+        // The first note ends where the second starts.
+        assert_eq!(
+            messages_between(&tracks, &timing, 480.0, 960.0, 0),
+            vec![vec![0x81, 72, 0], vec![0x91, 74, 100]]
+        );
+        // The second note has no end, so it is held for a beat.
+        assert_eq!(
+            messages_between(&tracks, &timing, 960.0, 1000.0, 0),
+            vec![vec![0x81, 74, 0]]
+        );
+    }
+
+    #[test]
+    fn program_changes_are_sent_before_the_notes() {
+        let mut track = MidiKeyTrack::new();
+        track.midi_key_pairs.push(MidiKeyPair {
+            delta: 0,
+            tick: 480,
+            duration: Some(480),
+            midi_key: 60,
+            velocity: 100,
+            channel: 2,
+            keyboard_key: None,
+            substituted: false,
+        });
+        for (tick, name) in [
+            (0, "Church Organ"),
+            (480, "Violin"),
+            (960, "Not an instrument"),
+        ] {
+            track.program_changes.push(ProgramChange {
+                tick,
+                name: name.to_owned(),
+                channel: 2,
+            });
+        }
+        let violin = program_number("Violin").unwrap();
+        let tracks = [track];
+        assert_eq!(
+            messages_between(&tracks, &SongTiming::default(), 480.0, 960.0, 0),
+            vec![vec![0xC2, violin], vec![0x92, 60, 100]]
+        );
+        // Starting part way through sets the instrument of the time.
+        assert_eq!(programs_at(&tracks, 720.0), vec![vec![0xC2, violin]]);
+    }
+}
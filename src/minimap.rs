@@ -0,0 +1,121 @@
+use crate::{KeyLine, MidiKeyTrack};
+use eframe::egui::{self, Rect, Sense};
+use std::ops::Range;
+
+/// Number of rows a track's overview is reduced to, however long the track is.
+const BUCKETS: usize = 256;
+
+/// A track's notes condensed into a fixed number of rows.
+pub struct Overview {
+    /// Lowest and highest MIDI key in each row, or `None` where no notes are.
+    rows: Vec<Option<(u8, u8)>>,
+    /// Number of key lines the rows cover.
+    lines: usize,
+    /// Lowest and highest MIDI key in the whole track.
+    range: (u8, u8),
+}
+
+impl Overview {
+    /// Downsamples the key lines of `track` into at most [`BUCKETS`] rows.
+    pub fn new(track: &MidiKeyTrack, lines: &[KeyLine]) -> Overview {
+        let buckets = lines.len().min(BUCKETS);
+        let mut rows: Vec<Option<(u8, u8)>> = vec![None; buckets];
+        for (line_index, line) in lines.iter().enumerate() {
+            let row = &mut rows[line_index * buckets / lines.len()];
+            for &pair_index in &line.pair_indices {
+                let key = track.midi_key_pairs[pair_index].midi_key;
+                *row = Some(row.map_or((key, key), |(low, high)| (low.min(key), high.max(key))));
+            }
+        }
+        let range = rows
+            .iter()
+            .flatten()
+            .fold((u8::MAX, 0), |(low, high), row| {
+                (low.min(row.0), high.max(row.1))
+            });
+        Overview {
+            rows,
+            lines: lines.len(),
+            range,
+        }
+    }
+}
+
+/// Paints `overview` into `rect` as pitch (left to right) over position in the track (top to
+/// bottom), out of `total_lines` lines in the tallest track, with `visible` lines outlined.
+///
+/// Returns the line that was clicked or dragged to, if any.
+pub fn minimap_ui(
+    ui: &mut egui::Ui,
+    rect: Rect,
+    overview: &Overview,
+    total_lines: usize,
+    visible: Range<f32>,
+) -> Option<f32> {
+    let response = ui.interact(rect, ui.id().with("minimap"), Sense::click_and_drag());
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+
+    let total_lines = total_lines.max(1) as f32;
+    let y_for_line = |line: f32| rect.top() + line / total_lines * rect.height();
+    let (low, high) = overview.range;
+    let span = high.saturating_sub(low).max(1) as f32;
+    let x_for_key = |key: u8| rect.left() + 2.0 + (key - low) as f32 / span * (rect.width() - 4.0);
+    let buckets = overview.rows.len().max(1) as f32;
+    let lines_per_row = overview.lines as f32 / buckets;
+    for (row_index, row) in overview.rows.iter().enumerate() {
+        let Some((row_low, row_high)) = *row else {
+            continue;
+        };
+        let top = y_for_line(row_index as f32 * lines_per_row);
+        let bottom = y_for_line((row_index + 1) as f32 * lines_per_row).max(top + 1.0);
+        let note_rect = Rect::from_min_max(
+            egui::pos2(x_for_key(row_low) - 1.0, top),
+            egui::pos2(x_for_key(row_high) + 1.0, bottom),
+        );
+        painter.rect_filled(note_rect, 0.0, visuals.text_color().gamma_multiply(0.6));
+    }
+
+    let visible_rect = Rect::from_x_y_ranges(
+        rect.x_range(),
+        y_for_line(visible.start)..=y_for_line(visible.end),
+    );
+    painter.rect_stroke(visible_rect, 2.0, visuals.selection.stroke);
+
+    let pointer = response.interact_pointer_pos()?;
+    (response.clicked() || response.dragged())
+        .then(|| (pointer.y - rect.top()) / rect.height() * total_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    #[test]
+    fn long_tracks_are_downsampled() {
+        let mut track = MidiKeyTrack::new();
+        let mut lines = Vec::new();
+        for i in 0..BUCKETS * 4 {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick: i as u64,
+                duration: None,
+                midi_key: 40 + (i % 4) as u8,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+            lines.push(KeyLine {
+                segments: Vec::new(),
+                pair_indices: vec![i],
+            });
+        }
+        let overview = Overview::new(&track, &lines);
+        assert_eq!(overview.rows.len(), BUCKETS);
+        assert!(overview.rows.iter().all(|row| *row == Some((40, 43))));
+        assert_eq!(overview.range, (40, 43));
+    }
+}
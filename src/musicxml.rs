@@ -0,0 +1,483 @@
+//! Reads MusicXML scores, plain (`.musicxml`, `.xml`) or compressed (`.mxl`), into the same
+//! tracks and timing as a MIDI file.
+
+use crate::{program_to_string, MidiKeyPair, MidiKeyTrack, ProgramChange, SongTiming};
+use roxmltree::{Document, Node, ParsingOptions};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+};
+use thiserror::*;
+
+/// Time division of the loaded song, in ticks per quarter note.
+const TICKS_PER_BEAT: u64 = 480;
+/// Velocity of MusicXML's default dynamics, 100% of forte.
+const FORTE_VELOCITY: f64 = 90.0;
+
+#[derive(Error, Debug)]
+pub enum MusicXmlError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid compressed score: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("invalid XML: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("the score is not UTF-8 encoded")]
+    Encoding,
+    #[error("no score in the compressed file")]
+    MissingScore,
+    #[error("not a MusicXML score")]
+    NotAScore,
+    #[error("timewise scores are not supported")]
+    Timewise,
+}
+
+/// Whether `file` looks like a MusicXML score rather than a MIDI file: a zip archive or XML.
+pub fn is_musicxml(file: &[u8]) -> bool {
+    let text = file.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(file);
+    file.starts_with(b"PK\x03\x04") || text.trim_ascii_start().starts_with(b"<")
+}
+
+/// Parses a MusicXML score into one track per part, at 480 ticks per quarter note.
+///
+/// Tied notes become one note, grace notes and unpitched (percussion) notes are left out. The
+/// tempo and time signatures come from every part, and each part's first MIDI instrument gives
+/// its channel and program.
+pub fn load_musicxml(file: &[u8]) -> Result<(Vec<MidiKeyTrack>, SongTiming), MusicXmlError> {
+    let text = if file.starts_with(b"PK\x03\x04") {
+        unzip_score(file)?
+    } else {
+        String::from_utf8(file.to_vec()).map_err(|_| MusicXmlError::Encoding)?
+    };
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let document = Document::parse_with_options(text, options)?;
+    let score = document.root_element();
+    match score.tag_name().name() {
+        "score-partwise" => {}
+        "score-timewise" => return Err(MusicXmlError::Timewise),
+        _ => return Err(MusicXmlError::NotAScore),
+    }
+
+    let mut timing = SongTiming {
+        timing: midly::Timing::Metrical((TICKS_PER_BEAT as u16).into()),
+        ..SongTiming::default()
+    };
+    let part_list = child(score, "part-list");
+    let mut tracks = Vec::new();
+    for part in score.children().filter(|node| node.has_tag_name("part")) {
+        let id = part.attribute("id").unwrap_or_default();
+        let score_part = part_list.and_then(|list| {
+            list.children()
+                .find(|node| node.has_tag_name("score-part") && node.attribute("id") == Some(id))
+        });
+        tracks.push(read_part(part, score_part, &mut timing));
+    }
+
+    timing.tempo_changes.sort_by_key(|(tick, _)| *tick);
+    timing.tempo_changes.dedup();
+    timing.time_signature_changes.sort_by_key(|(tick, _)| *tick);
+    timing.time_signature_changes.dedup();
+    if let Some(&(_, tempo)) = timing.tempo_changes.first() {
+        timing.tempo = tempo;
+    }
+    if let Some(&(_, signature)) = timing.time_signature_changes.first() {
+        timing.time_signature = signature;
+    }
+    Ok((tracks, timing))
+}
+
+/// The score in a compressed MusicXML file: the root file named in `META-INF/container.xml`,
+/// or else the first XML file outside `META-INF`.
+fn unzip_score(file: &[u8]) -> Result<String, MusicXmlError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(file))?;
+    let read = |archive: &mut zip::ZipArchive<_>, name: &str| -> Result<String, MusicXmlError> {
+        let mut text = String::new();
+        archive
+            .by_name(name)?
+            .read_to_string(&mut text)
+            .map_err(|_| MusicXmlError::Encoding)?;
+        Ok(text)
+    };
+    let root_file = read(&mut archive, "META-INF/container.xml")
+        .ok()
+        .and_then(|container| {
+            let document = Document::parse(&container).ok()?;
+            let root_file = document
+                .descendants()
+                .find(|node| node.has_tag_name("rootfile"))?;
+            root_file.attribute("full-path").map(str::to_owned)
+        });
+    let name = match root_file {
+        Some(name) => name,
+        None => archive
+            .file_names()
+            .find(|name| {
+                !name.starts_with("META-INF/")
+                    && (name.ends_with(".xml") || name.ends_with(".musicxml"))
+            })
+            .ok_or(MusicXmlError::MissingScore)?
+            .to_owned(),
+    };
+    read(&mut archive, &name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+/// The text of the child element `name`, parsed.
+fn child_value<T: std::str::FromStr>(node: Node, name: &str) -> Option<T> {
+    child(node, name)?.text()?.trim().parse().ok()
+}
+
+/// Velocity of a dynamics value, in percent of forte.
+fn velocity(dynamics: f64) -> u8 {
+    (FORTE_VELOCITY * dynamics / 100.0)
+        .round()
+        .clamp(1.0, 127.0) as u8
+}
+
+/// MIDI key of a `<pitch>` element, if it is in MIDI's range.
+fn midi_key(pitch: Node) -> Option<u8> {
+    let step = match child(pitch, "step")?.text()?.trim() {
+        "C" => 0,
+        "D" => 2,
+        "E" => 4,
+        "F" => 5,
+        "G" => 7,
+        "A" => 9,
+        "B" => 11,
+        _ => return None,
+    };
+    // Microtones are rounded to the nearest semitone.
+    let alter = child_value::<f64>(pitch, "alter").unwrap_or(0.0).round() as i32;
+    let octave: i32 = child_value(pitch, "octave")?;
+    u8::try_from((octave + 1) * 12 + step + alter)
+        .ok()
+        .filter(|key| *key <= 127)
+}
+
+/// Reads the notes of a `<part>`, adding its tempo and time signature changes to `timing`.
+/// `score_part` is its entry in the part list, with its name and instrument.
+fn read_part(part: Node, score_part: Option<Node>, timing: &mut SongTiming) -> MidiKeyTrack {
+    let mut track = MidiKeyTrack::new();
+    track.name = score_part
+        .and_then(|score_part| child(score_part, "part-name")?.text())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| part.attribute("id").unwrap_or_default())
+        .to_owned();
+    let instrument = score_part.and_then(|score_part| child(score_part, "midi-instrument"));
+    let channel = instrument
+        .and_then(|instrument| child_value::<u8>(instrument, "midi-channel"))
+        .map_or(0, |channel| channel.clamp(1, 16) - 1);
+    if let Some(program) =
+        instrument.and_then(|instrument| child_value::<u8>(instrument, "midi-program"))
+    {
+        if let Some(name) = program_to_string().get(&program.saturating_sub(1)) {
+            track.program_changes.push(ProgramChange {
+                tick: 0,
+                name: name.clone(),
+                channel,
+            });
+        }
+    }
+
+    let mut divisions: u64 = 1;
+    let mut tick: u64 = 0;
+    let mut chord_tick: u64 = 0;
+    let mut dynamics = 100.0;
+    // Index of the note each tie on a key continues.
+    let mut open_ties: HashMap<u8, usize> = HashMap::new();
+    // `None` for durations too long to count in ticks, which are left out.
+    let ticks = |duration: u64, divisions: u64| {
+        duration
+            .checked_mul(TICKS_PER_BEAT)
+            .map(|ticks| ticks / divisions.max(1))
+    };
+    for element in part
+        .children()
+        .filter(|node| node.has_tag_name("measure"))
+        .flat_map(|measure| measure.children())
+        .filter(Node::is_element)
+    {
+        let duration = child_value::<u64>(element, "duration").unwrap_or(0);
+        match element.tag_name().name() {
+            "attributes" => {
+                if let Some(value) = child_value(element, "divisions") {
+                    divisions = value;
+                }
+                if let Some(time) = child(element, "time") {
+                    // Compound meters like 3+2 are added up. Beats or a beat type of zero, or
+                    // too many beats for a MIDI time signature, count as missing.
+                    let beats = child(time, "beats").and_then(|beats| {
+                        let sum = beats
+                            .text()?
+                            .split('+')
+                            .map(|beats| beats.trim().parse::<u16>().ok())
+                            .try_fold(0u16, |sum, beats| sum.checked_add(beats?))?;
+                        u8::try_from(sum).ok().filter(|&beats| beats > 0)
+                    });
+                    let beat_type =
+                        child_value::<u8>(time, "beat-type").filter(|&beat_type| beat_type > 0);
+                    if let (Some(beats), Some(beat_type)) = (beats, beat_type) {
+                        timing
+                            .time_signature_changes
+                            .push((tick, (beats, beat_type)));
+                    }
+                }
+            }
+            "backup" => {
+                if let Some(back) = ticks(duration, divisions) {
+                    tick = tick.saturating_sub(back);
+                }
+            }
+            "forward" => {
+                if let Some(ahead) = ticks(duration, divisions) {
+                    tick = tick.saturating_add(ahead);
+                }
+            }
+            "direction" | "sound" => {
+                let sound = if element.has_tag_name("sound") {
+                    Some(element)
+                } else {
+                    child(element, "sound")
+                };
+                if let Some(sound) = sound {
+                    if let Some(bpm) = sound
+                        .attribute("tempo")
+                        .and_then(|bpm| bpm.parse::<f64>().ok())
+                    {
+                        if bpm > 0.0 {
+                            timing
+                                .tempo_changes
+                                .push((tick, (60_000_000.0 / bpm).round() as u32));
+                        }
+                    }
+                    if let Some(value) = sound
+                        .attribute("dynamics")
+                        .and_then(|value| value.parse().ok())
+                    {
+                        dynamics = value;
+                    }
+                }
+            }
+            "note" => {
+                if child(element, "grace").is_some() || child(element, "cue").is_some() {
+                    continue;
+                }
+                let Some(length) = ticks(duration, divisions) else {
+                    continue;
+                };
+                let start = if child(element, "chord").is_some() {
+                    chord_tick
+                } else {
+                    chord_tick = tick;
+                    tick = tick.saturating_add(length);
+                    chord_tick
+                };
+                let Some(key) = child(element, "pitch").and_then(midi_key) else {
+                    // A rest or an unpitched note.
+                    continue;
+                };
+                let ties = |kind| {
+                    element.children().any(|node| {
+                        node.has_tag_name("tie") && node.attribute("type") == Some(kind)
+                    })
+                };
+                let continued = ties("stop").then(|| open_ties.remove(&key)).flatten();
+                let index = match continued {
+                    Some(index) => {
+                        let pair = &mut track.midi_key_pairs[index];
+                        // A tie from another voice can end before the note it continues starts.
+                        pair.duration =
+                            Some(start.saturating_add(length).saturating_sub(pair.tick));
+                        index
+                    }
+                    None => {
+                        let note_dynamics = element
+                            .attribute("dynamics")
+                            .and_then(|value| value.parse().ok())
+                            .unwrap_or(dynamics);
+                        track.midi_key_pairs.push(MidiKeyPair {
+                            delta: 0,
+                            tick: start,
+                            duration: Some(length),
+                            midi_key: key,
+                            velocity: velocity(note_dynamics),
+                            channel,
+                            keyboard_key: None,
+                            substituted: false,
+                        });
+                        track.midi_key_pairs.len() - 1
+                    }
+                };
+                if ties("start") {
+                    open_ties.insert(key, index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Voices are written one after the other within a measure, so put the notes in time order.
+    track.midi_key_pairs.sort_by_key(|pair| pair.tick);
+    let mut previous_tick = 0;
+    for pair in track.midi_key_pairs.iter_mut() {
+        pair.delta = (pair.tick - previous_tick).min(u32::MAX as u64) as u32;
+        previous_tick = pair.tick;
+    }
+    track.percussion = track.plays_percussion();
+    track
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCORE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 4.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<score-partwise version="4.0">
+  <part-list>
+    <score-part id="P1">
+      <part-name>Flute</part-name>
+      <midi-instrument id="P1-I1">
+        <midi-channel>2</midi-channel>
+        <midi-program>74</midi-program>
+      </midi-instrument>
+    </score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes>
+        <divisions>2</divisions>
+        <time><beats>3</beats><beat-type>4</beat-type></time>
+      </attributes>
+      <direction><sound tempo="90"/></direction>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>2</duration></note>
+      <note><chord/><pitch><step>E</step><octave>4</octave></pitch><duration>2</duration></note>
+      <note><rest/><duration>1</duration></note>
+      <note><grace/><pitch><step>D</step><octave>4</octave></pitch></note>
+      <note><pitch><step>F</step><alter>1</alter><octave>4</octave></pitch><duration>3</duration><tie type="start"/></note>
+    </measure>
+    <measure number="2">
+      <note><pitch><step>F</step><alter>1</alter><octave>4</octave></pitch><duration>2</duration><tie type="stop"/></note>
+      <backup><duration>2</duration></backup>
+      <note dynamics="50"><pitch><step>B</step><alter>-1</alter><octave>3</octave></pitch><duration>2</duration></note>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+
+    #[test]
+    fn score_parts_become_tracks() {
+        assert!(is_musicxml(SCORE.as_bytes()));
+        assert!(!is_musicxml(b"MThd"));
+        let (tracks, timing) = load_musicxml(SCORE.as_bytes()).unwrap();
+        assert_eq!(tracks.len(), 1);
+        let track = &tracks[0];
+        assert_eq!(track.name, "Flute");
+        assert_eq!(track.midi_key_pairs[0].channel, 1);
+        let notes: Vec<_> = track
+            .midi_key_pairs
+            .iter()
+            .map(|pair| (pair.tick, pair.midi_key, pair.duration.unwrap(), pair.delta))
+            .collect();
+        // The tied F# lasts across the bar line, and the B flat of the second voice starts
+        // with the second half of the tie.
+        assert_eq!(
+            notes,
+            [
+                (0, 60, 480, 0),
+                (0, 64, 480, 0),
+                (720, 66, 1200, 720),
+                (1440, 58, 480, 720),
+            ]
+        );
+        assert_eq!(track.midi_key_pairs[3].velocity, 45);
+        assert_eq!(timing.time_signature, (3, 4));
+        assert_eq!(timing.tempo, 666_667);
+
+        let timewise = "<score-timewise/>";
+        assert!(matches!(
+            load_musicxml(timewise.as_bytes()),
+            Err(MusicXmlError::Timewise)
+        ));
+    }
+
+    #[test]
+    fn compressed_scores_are_unzipped() {
+        let mut file = Vec::new();
+        {
+            let mut archive = zip::ZipWriter::new(Cursor::new(&mut file));
+            let options = zip::write::FileOptions::default();
+            archive
+                .start_file("META-INF/container.xml", options)
+                .unwrap();
+            std::io::Write::write_all(
+                &mut archive,
+                br#"<container><rootfiles><rootfile full-path="song.musicxml"/></rootfiles></container>"#,
+            )
+            .unwrap();
+            archive.start_file("song.musicxml", options).unwrap();
+            std::io::Write::write_all(&mut archive, SCORE.as_bytes()).unwrap();
+            archive.finish().unwrap();
+        }
+        assert!(is_musicxml(&file));
+        let (tracks, _) = load_musicxml(&file).unwrap();
+        assert_eq!(tracks[0].midi_key_pairs.len(), 4);
+    }
+
+    #[test]
+    fn malformed_ties_and_durations_are_survived() {
+        let score = r#"<score-partwise version="4.0">
+  <part-list><score-part id="P1"><part-name>Piano</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>1</divisions></attributes>
+      <forward><duration>2</duration></forward>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>1</duration><tie type="start"/></note>
+      <backup><duration>3</duration></backup>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>1</duration><tie type="stop"/></note>
+      <note><pitch><step>D</step><octave>4</octave></pitch><duration>1000000000000000000</duration></note>
+      <forward><duration>1000000000000000000</duration></forward>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>1</duration></note>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+        let (tracks, _) = load_musicxml(score.as_bytes()).unwrap();
+        let notes: Vec<_> = tracks[0]
+            .midi_key_pairs
+            .iter()
+            .map(|pair| (pair.tick, pair.midi_key, pair.duration.unwrap()))
+            .collect();
+        // The tie stop in the second voice comes before the C it continues, and the D and the
+        // forward are too long to count in ticks.
+        assert_eq!(notes, [(480, 64, 480), (960, 60, 0)]);
+    }
+
+    #[test]
+    fn unusable_time_signatures_are_skipped() {
+        let score = r#"<score-partwise version="4.0">
+  <part-list><score-part id="P1"><part-name>Piano</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><time><beats>200+100</beats><beat-type>4</beat-type></time></attributes>
+      <attributes><time><beats>0</beats><beat-type>4</beat-type></time></attributes>
+      <attributes><time><beats>3</beats><beat-type>0</beat-type></time></attributes>
+      <attributes><time><beats>3+2</beats><beat-type>8</beat-type></time></attributes>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>1</duration></note>
+    </measure>
+  </part>
+</score-partwise>
+"#;
+        let (_, timing) = load_musicxml(score.as_bytes()).unwrap();
+        assert_eq!(timing.time_signature_changes, [(0, (5, 8))]);
+        assert_eq!(timing.time_signature, (5, 8));
+    }
+}
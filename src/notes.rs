@@ -0,0 +1,254 @@
+use serde::{Deserialize, Serialize};
+
+/// Standard concert pitch of A4 in Hz.
+pub const DEFAULT_REFERENCE_PITCH: f64 = 440.0;
+
+/// Frequency in Hz of a MIDI key in twelve-tone equal temperament, tuned so that A4 (69)
+/// sounds at `reference_pitch` Hz.
+pub fn midi_to_frequency(key: u8, reference_pitch: f64) -> f64 {
+    reference_pitch * 2f64.powf((key as f64 - 69.0) / 12.0)
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// General MIDI percussion sounds, from key 35 up.
+const DRUM_NAMES: [&str; 47] = [
+    "Acoustic Bass Drum",
+    "Bass Drum 1",
+    "Side Stick",
+    "Acoustic Snare",
+    "Hand Clap",
+    "Electric Snare",
+    "Low Floor Tom",
+    "Closed Hi-Hat",
+    "High Floor Tom",
+    "Pedal Hi-Hat",
+    "Low Tom",
+    "Open Hi-Hat",
+    "Low-Mid Tom",
+    "Hi-Mid Tom",
+    "Crash Cymbal 1",
+    "High Tom",
+    "Ride Cymbal 1",
+    "Chinese Cymbal",
+    "Ride Bell",
+    "Tambourine",
+    "Splash Cymbal",
+    "Cowbell",
+    "Crash Cymbal 2",
+    "Vibraslap",
+    "Ride Cymbal 2",
+    "Hi Bongo",
+    "Low Bongo",
+    "Mute Hi Conga",
+    "Open Hi Conga",
+    "Low Conga",
+    "High Timbale",
+    "Low Timbale",
+    "High Agogo",
+    "Low Agogo",
+    "Cabasa",
+    "Maracas",
+    "Short Whistle",
+    "Long Whistle",
+    "Short Guiro",
+    "Long Guiro",
+    "Claves",
+    "Hi Wood Block",
+    "Low Wood Block",
+    "Mute Cuica",
+    "Open Cuica",
+    "Mute Triangle",
+    "Open Triangle",
+];
+
+/// The General MIDI drum played by `key` on the percussion channel, e.g. "Acoustic Snare" for
+/// 38. Keys outside the standard drum kit have no name.
+pub fn drum_name(key: u8) -> Option<&'static str> {
+    DRUM_NAMES.get((key as usize).checked_sub(35)?).copied()
+}
+
+/// Name of a pitch class (0 for C up to 11 for B), without an octave.
+pub fn pitch_class_name(pitch_class: u8) -> &'static str {
+    NOTE_NAMES[pitch_class as usize % 12]
+}
+
+/// Name of a MIDI key such as `C4` or `F#3`, with `C4` as middle C (60).
+pub fn note_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+/// Named intervals up to an octave, with their size in semitones.
+pub const INTERVALS: [(&str, i8); 13] = [
+    ("Minor second", 1),
+    ("Major second", 2),
+    ("Minor third", 3),
+    ("Major third", 4),
+    ("Perfect fourth", 5),
+    ("Tritone", 6),
+    ("Perfect fifth", 7),
+    ("Minor sixth", 8),
+    ("Major sixth", 9),
+    ("Minor seventh", 10),
+    ("Major seventh", 11),
+    ("Octave", 12),
+    ("Two octaves", 24),
+];
+
+/// Kind of scale, by the steps it takes from its tonic.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum ScaleMode {
+    #[default]
+    Major,
+    /// The natural minor scale.
+    Minor,
+}
+
+/// A key that notes can be checked against, e.g. D major.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Scale {
+    /// Pitch class of the first degree, 0 for C up to 11 for B.
+    pub tonic: u8,
+    pub mode: ScaleMode,
+}
+
+impl Scale {
+    /// Whether `key` is one of the seven degrees of the scale rather than a chromatic note.
+    pub fn contains(&self, key: u8) -> bool {
+        let steps: [u8; 7] = match self.mode {
+            ScaleMode::Major => [0, 2, 4, 5, 7, 9, 11],
+            ScaleMode::Minor => [0, 2, 3, 5, 7, 8, 10],
+        };
+        let degree = (key % 12 + 12 - self.tonic % 12) % 12;
+        steps.contains(&degree)
+    }
+}
+
+/// Which octave number middle C (MIDI key 60) is written with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum MiddleC {
+    /// Scientific pitch notation, used by most software: middle C is `C4`.
+    #[default]
+    C4,
+    /// Used by Yamaha and some DAWs: middle C is `C3`.
+    C3,
+}
+
+impl MiddleC {
+    /// Octave number of middle C.
+    fn octave(self) -> i32 {
+        match self {
+            MiddleC::C4 => 4,
+            MiddleC::C3 => 3,
+        }
+    }
+}
+
+/// Parses a note name such as `C4`, `F#3`, `Bb2` or `C#-1` into a MIDI key, reading octave
+/// numbers by `convention`.
+///
+/// The letter may be either case and be followed by any number of sharps (`#`, `♯`) or flats
+/// (`b`, `♭`). Whitespace around the name and before the octave is ignored. Returns `None` for
+/// anything else, or if the note is outside the MIDI range.
+pub fn parse_note_name(name: &str, convention: MiddleC) -> Option<u8> {
+    let mut chars = name.trim().chars();
+    let pitch_class: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let mut rest = chars.as_str();
+    let mut accidentals = 0;
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('#' | '♯') => accidentals += 1,
+            Some('b' | '♭') => accidentals -= 1,
+            _ => break,
+        }
+        rest = chars.as_str();
+    }
+    let octave = rest.trim_start();
+    // `parse` would accept a leading `+`, which isn't part of a note name.
+    if octave.starts_with('+') {
+        return None;
+    }
+    let octave: i32 = octave.parse().ok()?;
+    let key = (octave - convention.octave() + 5) * 12 + pitch_class + accidentals;
+    u8::try_from(key).ok().filter(|key| *key <= 127)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequencies_follow_the_reference_pitch() {
+        let close = |a: f64, b: f64| (a - b).abs() < 0.01;
+        assert_eq!(midi_to_frequency(69, DEFAULT_REFERENCE_PITCH), 440.0);
+        assert!(close(
+            midi_to_frequency(60, DEFAULT_REFERENCE_PITCH),
+            261.63
+        ));
+        assert_eq!(midi_to_frequency(81, DEFAULT_REFERENCE_PITCH), 880.0);
+        assert_eq!(midi_to_frequency(69, 432.0), 432.0);
+        assert!(close(midi_to_frequency(60, 432.0), 256.87));
+    }
+
+    #[test]
+    fn scales_contain_their_degrees_only() {
+        let c_major = Scale::default();
+        let in_c_major: Vec<_> = (60..72).filter(|&key| c_major.contains(key)).collect();
+        assert_eq!(in_c_major, vec![60, 62, 64, 65, 67, 69, 71]);
+        // E minor: E F# G A B C D.
+        let e_minor = Scale {
+            tonic: 4,
+            mode: ScaleMode::Minor,
+        };
+        let in_e_minor: Vec<_> = (60..72).filter(|&key| e_minor.contains(key)).collect();
+        assert_eq!(in_e_minor, vec![60, 62, 64, 66, 67, 69, 71]);
+        assert!(!e_minor.contains(65));
+    }
+
+    #[test]
+    fn parses_accidentals_and_octaves() {
+        let parse = |name| parse_note_name(name, MiddleC::C4);
+        assert_eq!(parse("C4"), Some(60));
+        assert_eq!(parse(" c4 "), Some(60));
+        assert_eq!(parse("Bb3"), Some(58));
+        assert_eq!(parse("B♭3"), Some(58));
+        assert_eq!(parse("F#3"), Some(54));
+        assert_eq!(parse("C##4"), Some(62));
+        assert_eq!(parse("C#-1"), Some(1));
+        assert_eq!(parse("C-1"), Some(0));
+        assert_eq!(parse("G9"), Some(127));
+        assert_eq!(parse("B# 3"), Some(60));
+    }
+
+    #[test]
+    fn middle_c_convention_shifts_octaves() {
+        assert_eq!(parse_note_name("C3", MiddleC::C3), Some(60));
+        assert_eq!(parse_note_name("C-2", MiddleC::C3), Some(0));
+        assert_eq!(parse_note_name("C-1", MiddleC::C3), Some(12));
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let parse = |name| parse_note_name(name, MiddleC::C4);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("C"), None);
+        assert_eq!(parse("H4"), None);
+        assert_eq!(parse("C+4"), None);
+        assert_eq!(parse("C4x"), None);
+        assert_eq!(parse("Cb-1"), None);
+        assert_eq!(parse("G#9"), None);
+        assert_eq!(parse("C99999999999"), None);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::{notes, velocity::VelocityScale, MidiKeyTrack, NoteFilter, SongTiming};
+use eframe::{
+    egui::{self, Align2, FontId, Rect, Sense},
+    epaint::{ecolor::Hsva, Color32},
+};
+
+/// Width of the note names along the left edge.
+const GUTTER_WIDTH: f32 = 32.0;
+
+/// Lowest and highest transposed key of the notes `filter` shows, or `None` without notes.
+fn pitch_range(
+    tracks: &[&MidiKeyTrack],
+    filter: &NoteFilter,
+    song_transpose: i8,
+) -> Option<(u8, u8)> {
+    let keys = tracks.iter().flat_map(|track| {
+        track
+            .midi_key_pairs
+            .iter()
+            .filter(|pair| !filter.hides(pair))
+            .filter_map(|pair| track.sounding_key(pair.midi_key, song_transpose))
+    });
+    keys.fold(None, |range, key| match range {
+        None => Some((key, key)),
+        Some((low, high)) => Some((low.min(key), high.max(key))),
+    })
+}
+
+fn is_black_key(key: u8) -> bool {
+    matches!(key % 12, 1 | 3 | 6 | 8 | 10)
+}
+
+/// A color per track that stays apart from its neighbours however many tracks there are.
+fn track_color(index: usize) -> Color32 {
+    let hue = (index as f32 * 0.618_034).fract();
+    Hsva::new(hue, 0.55, 0.75, 1.0).into()
+}
+
+/// Draws the notes of all shown tracks on one grid, time to the right and pitch upwards, with
+/// each note's keyboard key on its bar. Tracks are told apart by color.
+///
+/// Ctrl+scroll (or pinching) over the roll zooms it, changing `pixels_per_beat` and
+/// `semitone_height`.
+#[allow(clippy::too_many_arguments)]
+pub fn piano_roll_ui(
+    ui: &mut egui::Ui,
+    tracks: &[MidiKeyTrack],
+    filter: &NoteFilter,
+    timing: &SongTiming,
+    velocities: &VelocityScale,
+    pixels_per_beat: &mut f32,
+    semitone_height: &mut f32,
+    playhead: f64,
+    song_transpose: i8,
+) {
+    let shown: Vec<(usize, &MidiKeyTrack)> = tracks
+        .iter()
+        .enumerate()
+        .filter(|(_, track)| !track.hidden)
+        .collect();
+    let shown_tracks: Vec<&MidiKeyTrack> = shown.iter().map(|(_, track)| *track).collect();
+    let Some((low, high)) = pitch_range(&shown_tracks, filter, song_transpose) else {
+        ui.weak("No notes to show.");
+        return;
+    };
+    // A semitone of room above and below the outer notes.
+    let (low, high) = (low.saturating_sub(1), high.saturating_add(1).min(127));
+    let row_height = *semitone_height;
+    let pixels_per_tick = *pixels_per_beat / timing.ticks_per_beat() as f32;
+    let song_end = shown_tracks
+        .iter()
+        .flat_map(|track| track.midi_key_pairs.last())
+        .map(|pair| pair.tick + pair.duration.unwrap_or(0))
+        .max()
+        .unwrap_or(0);
+
+    let output = egui::ScrollArea::both()
+        .auto_shrink([false, false])
+        .show_viewport(ui, |ui, viewport| {
+            let size = egui::vec2(
+                GUTTER_WIDTH + song_end as f32 * pixels_per_tick + *pixels_per_beat,
+                (high - low + 1) as f32 * row_height,
+            );
+            let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+            let painter = ui.painter_at(rect);
+            let visuals = ui.visuals();
+            let font = FontId::monospace((row_height * 0.8).clamp(6.0, 12.0));
+            let visible_left = rect.left() + viewport.left();
+            let row_top = |key: u8| rect.top() + (high - key) as f32 * row_height;
+            let tick_x = |tick: f64| rect.left() + GUTTER_WIDTH + tick as f32 * pixels_per_tick;
+
+            for key in low..=high {
+                let row = Rect::from_min_size(
+                    egui::pos2(visible_left, row_top(key)),
+                    egui::vec2(viewport.width(), row_height),
+                );
+                if is_black_key(key) {
+                    painter.rect_filled(row, 0.0, visuals.faint_bg_color);
+                }
+                if key % 12 == 0 {
+                    painter.hline(
+                        row.x_range(),
+                        row.bottom(),
+                        visuals.widgets.noninteractive.bg_stroke,
+                    );
+                }
+            }
+
+            // Measure lines, only across the visible part.
+            let measure = timing.ticks_per_measure().max(1.0);
+            let first_measure =
+                ((viewport.left() - GUTTER_WIDTH) / pixels_per_tick).max(0.0) as f64 / measure;
+            let last_measure = (viewport.right() / pixels_per_tick) as f64 / measure;
+            for number in first_measure.floor() as u64..=last_measure.ceil() as u64 {
+                painter.vline(
+                    tick_x(number as f64 * measure),
+                    rect.y_range(),
+                    visuals.widgets.noninteractive.bg_stroke,
+                );
+            }
+
+            // Notes can extend to the right of their start, so look back one viewport width.
+            let first_tick = ((viewport.left() - GUTTER_WIDTH - viewport.width()) / pixels_per_tick)
+                .max(0.0) as u64;
+            let last_tick = (viewport.right() / pixels_per_tick) as u64;
+            for (index, track) in &shown {
+                let color = track_color(*index);
+                let first = track
+                    .midi_key_pairs
+                    .partition_point(|pair| pair.tick < first_tick);
+                for pair in track.midi_key_pairs[first..]
+                    .iter()
+                    .take_while(|pair| pair.tick <= last_tick)
+                    .filter(|pair| !filter.hides(pair))
+                {
+                    let Some(key) = track.sounding_key(pair.midi_key, song_transpose) else {
+                        continue;
+                    };
+                    let width = (pair.duration.unwrap_or(0) as f32 * pixels_per_tick).max(4.0);
+                    let note_rect = Rect::from_min_size(
+                        egui::pos2(tick_x(pair.tick as f64), row_top(key)),
+                        egui::vec2(width, row_height),
+                    )
+                    .shrink2(egui::vec2(0.0, 0.5));
+                    let (fill, label) = match &pair.keyboard_key {
+                        Some(key) => (color, key.as_str()),
+                        None => (Color32::DARK_RED, "NONE"),
+                    };
+                    // Softer notes are drawn fainter.
+                    let velocity = velocities.apply(pair.velocity) as f32 / 127.0;
+                    let fill = fill.gamma_multiply(0.4 + 0.6 * velocity);
+                    let fill = if filter.excludes(pair) {
+                        fill.gamma_multiply(0.3)
+                    } else {
+                        fill
+                    };
+                    painter.rect_filled(note_rect, 2.0, fill);
+                    if pair.substituted {
+                        painter.rect_stroke(
+                            note_rect,
+                            2.0,
+                            egui::Stroke::new(1.0, visuals.warn_fg_color),
+                        );
+                    }
+                    if row_height >= 8.0 && width >= 12.0 {
+                        painter.text(
+                            note_rect.left_center() + egui::vec2(2.0, 0.0),
+                            Align2::LEFT_CENTER,
+                            label,
+                            font.clone(),
+                            visuals.strong_text_color(),
+                        );
+                    }
+                }
+            }
+
+            painter.vline(
+                tick_x(playhead),
+                rect.y_range(),
+                egui::Stroke::new(1.5, visuals.warn_fg_color),
+            );
+
+            // Note names stay at the left edge while scrolling sideways.
+            let gutter = Rect::from_min_size(
+                egui::pos2(visible_left, rect.top()),
+                egui::vec2(GUTTER_WIDTH, rect.height()),
+            );
+            painter.rect_filled(gutter, 0.0, visuals.extreme_bg_color);
+            for key in (low..=high).filter(|key| key % 12 == 0 || row_height >= 12.0) {
+                painter.text(
+                    egui::pos2(gutter.right() - 3.0, row_top(key) + row_height / 2.0),
+                    Align2::RIGHT_CENTER,
+                    notes::note_name(key),
+                    FontId::proportional((row_height * 0.8).clamp(8.0, 10.0)),
+                    visuals.weak_text_color(),
+                );
+            }
+        });
+
+    if ui.rect_contains_pointer(output.inner_rect) {
+        let zoom = ui.input(|i| i.zoom_delta_2d());
+        *pixels_per_beat = (*pixels_per_beat * zoom.x).clamp(5.0, 400.0);
+        *semitone_height = (*semitone_height * zoom.y).clamp(4.0, 32.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    #[test]
+    fn pitch_range_covers_transposed_shown_notes() {
+        let mut track = MidiKeyTrack::new();
+        track.transpose = -12;
+        for (tick, key, duration) in [(0, 60, 480), (480, 72, 480), (960, 50, 10)] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(duration),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        let mut filter = NoteFilter::default();
+        assert_eq!(pitch_range(&[&track], &filter, 0), Some((38, 60)));
+        // The short note is hidden, so it doesn't widen the range.
+        filter.min_duration = Some(240);
+        assert_eq!(pitch_range(&[&track], &filter, 0), Some((48, 60)));
+        assert_eq!(pitch_range(&[], &filter, 0), None);
+    }
+}
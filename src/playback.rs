@@ -0,0 +1,220 @@
+use crate::SongTiming;
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Settings for the "slow to fast" practice loop.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TempoRamp {
+    pub enabled: bool,
+    /// Tempo increase applied each time the loop repeats, in percent of the song tempo.
+    pub step_percent: f32,
+    /// Tempo at which the ramp stops increasing, in percent of the song tempo.
+    pub target_percent: f32,
+}
+
+/// User preferences for playback, kept in the settings.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct PlaybackSettings {
+    /// Playback tempo as a percentage of the song tempo.
+    pub tempo_percent: f32,
+    pub ramp: TempoRamp,
+    /// Show "note 42 / 380" beside the playback time.
+    pub show_note_count: bool,
+    /// Light the sounding and upcoming keys on the keyboard panel and keep the playing line of
+    /// the key list in view.
+    pub follow: bool,
+    /// How far ahead upcoming keys are shown while following, in seconds of playback.
+    pub look_ahead_secs: f32,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        PlaybackSettings {
+            tempo_percent: 100.0,
+            ramp: TempoRamp {
+                enabled: false,
+                step_percent: 5.0,
+                target_percent: 100.0,
+            },
+            show_note_count: false,
+            follow: false,
+            look_ahead_secs: 1.0,
+        }
+    }
+}
+
+/// Wall-clock driven playback position through the loaded song.
+#[derive(Default)]
+pub struct Playback {
+    pub playing: bool,
+    /// Current position in ticks.
+    pub position: f64,
+    pub looping: bool,
+    pub loop_start: u64,
+    pub loop_end: u64,
+    /// Start over from the beginning at the end of the song. A loop region, when set, wins.
+    pub loop_song: bool,
+    /// Number of times the loop region has repeated since playback started.
+    loop_pass: u32,
+    /// Playback has been started, stepped or sought since the last stop.
+    started: bool,
+    last_update: Option<Instant>,
+}
+
+impl Playback {
+    pub fn play(&mut self) {
+        if !self.playing {
+            if self.looping && self.position < self.loop_start as f64 {
+                self.position = self.loop_start as f64;
+            }
+            self.playing = true;
+            self.started = true;
+            self.last_update = Some(Instant::now());
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+        self.last_update = None;
+    }
+
+    /// Stops playback, rewinds and resets the tempo ramp.
+    pub fn stop(&mut self) {
+        self.pause();
+        self.position = if self.looping {
+            self.loop_start as f64
+        } else {
+            0.0
+        };
+        self.loop_pass = 0;
+        self.started = false;
+    }
+
+    /// Jumps to the given tick, keeping the play/pause state.
+    pub fn seek(&mut self, tick: f64) {
+        self.position = tick.max(0.0);
+        self.started = true;
+        if self.playing {
+            self.last_update = Some(Instant::now());
+        }
+    }
+
+    /// Whether playback has moved since it was last stopped, i.e. there is a position to report.
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    fn has_loop_region(&self) -> bool {
+        self.looping && self.loop_end > self.loop_start
+    }
+
+    /// Tempo of the current pass, as a percentage of the song tempo.
+    pub fn current_tempo_percent(&self, settings: &PlaybackSettings) -> f32 {
+        if settings.ramp.enabled && self.has_loop_region() {
+            let ramped =
+                settings.tempo_percent + settings.ramp.step_percent * self.loop_pass as f32;
+            ramped.min(settings.ramp.target_percent.max(settings.tempo_percent))
+        } else {
+            settings.tempo_percent
+        }
+    }
+
+    /// Advances the position by the time elapsed since the last update. Returns the tick
+    /// playback ran to if it went back to the start of the loop or song, or stopped at the end.
+    pub fn update(
+        &mut self,
+        settings: &PlaybackSettings,
+        timing: &SongTiming,
+        song_end: u64,
+    ) -> Option<f64> {
+        if !self.playing {
+            return None;
+        }
+        let now = Instant::now();
+        let elapsed_ms = self
+            .last_update
+            .map(|last| (now - last).as_secs_f64() * 1000.0)
+            .unwrap_or(0.0);
+        self.last_update = Some(now);
+        let scaled_ms = elapsed_ms * self.current_tempo_percent(settings) as f64 / 100.0;
+        self.position = timing
+            .time_ms_at(self.position)
+            .and_then(|ms| timing.time_ms_to_tick(ms + scaled_ms))
+            .unwrap_or_else(|| self.position + timing.ms_to_ticks(scaled_ms));
+
+        if self.has_loop_region() {
+            if self.position >= self.loop_end as f64 {
+                self.position = self.loop_start as f64;
+                self.loop_pass += 1;
+                return Some(self.loop_end as f64);
+            }
+        } else if self.position >= song_end as f64 {
+            if self.loop_song {
+                self.position = 0.0;
+            } else {
+                self.stop();
+            }
+            return Some(song_end as f64);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looping_the_song_starts_over_instead_of_stopping() {
+        let settings = PlaybackSettings::default();
+        let timing = SongTiming::default();
+        let mut playback = Playback::default();
+        playback.play();
+        playback.seek(1000.0);
+        assert_eq!(playback.update(&settings, &timing, 960), Some(960.0));
+        assert!(!playback.playing);
+
+        playback.loop_song = true;
+        playback.play();
+        playback.seek(1000.0);
+        assert_eq!(playback.update(&settings, &timing, 960), Some(960.0));
+        assert!(playback.playing);
+        assert!(playback.position < 960.0);
+    }
+
+    #[test]
+    fn loop_passes_ramp_the_tempo_up_to_the_target() {
+        let settings = PlaybackSettings {
+            tempo_percent: 60.0,
+            ramp: TempoRamp {
+                enabled: true,
+                step_percent: 15.0,
+                target_percent: 100.0,
+            },
+            ..PlaybackSettings::default()
+        };
+        let timing = SongTiming::default();
+        let mut playback = Playback {
+            looping: true,
+            loop_start: 480,
+            loop_end: 960,
+            ..Playback::default()
+        };
+        playback.play();
+        assert_eq!(playback.position, 480.0);
+        let mut tempos = Vec::new();
+        for _ in 0..4 {
+            tempos.push(playback.current_tempo_percent(&settings));
+            playback.seek(1000.0);
+            assert_eq!(playback.update(&settings, &timing, 1920), Some(960.0));
+            assert!(playback.position < 960.0);
+        }
+        assert_eq!(tempos, vec![60.0, 75.0, 90.0, 100.0]);
+
+        // Stopping starts the ramp over.
+        playback.stop();
+        assert_eq!(playback.position, 480.0);
+        assert_eq!(playback.current_tempo_percent(&settings), 60.0);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::MidiKeyTrack;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::Path,
+    time::{Instant, SystemTime},
+};
+use thiserror::*;
+
+/// Most sessions kept in the history file; older ones are dropped.
+pub const HISTORY_CAPACITY: usize = 200;
+
+/// Labels that can't be typed as a single key press in the app (modifiers and layer keys).
+const UNTYPEABLE_KEYS: [&str; 7] = ["SHF", "CTRL", "ALT", "OS", "ORYX", "SHFDOWN", "SHFUP"];
+
+/// A practice run through one track: the player types each note's key in order.
+pub struct Practice {
+    pub track: usize,
+    /// Keys to press for each onset, in order. Notes without a typeable key are left out.
+    chords: Vec<Vec<String>>,
+    position: usize,
+    /// Keys of the current chord that haven't been pressed yet.
+    pending: Vec<String>,
+    correct: u32,
+    wrong: u32,
+    started: Instant,
+}
+
+impl Practice {
+    pub fn start(track_index: usize, track: &MidiKeyTrack) -> Practice {
+        let mut chords: Vec<Vec<String>> = Vec::new();
+        let mut last_tick = None;
+        for pair in &track.midi_key_pairs {
+            let Some(key) = pair
+                .keyboard_key
+                .as_deref()
+                .filter(|key| !key.is_empty() && !UNTYPEABLE_KEYS.contains(key))
+            else {
+                continue;
+            };
+            match chords.last_mut() {
+                Some(chord) if last_tick == Some(pair.tick) => {
+                    if !chord.iter().any(|pending| pending == key) {
+                        chord.push(key.to_owned());
+                    }
+                }
+                _ => chords.push(vec![key.to_owned()]),
+            }
+            last_tick = Some(pair.tick);
+        }
+        let pending = chords.first().cloned().unwrap_or_default();
+        Practice {
+            track: track_index,
+            chords,
+            position: 0,
+            pending,
+            correct: 0,
+            wrong: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Scores a key press against the current chord, moving on once the whole chord is pressed.
+    pub fn press(&mut self, label: &str) {
+        if self.is_finished() {
+            return;
+        }
+        match self
+            .pending
+            .iter()
+            .position(|key| key.eq_ignore_ascii_case(label))
+        {
+            Some(index) => {
+                self.pending.remove(index);
+                self.correct += 1;
+                if self.pending.is_empty() {
+                    self.position += 1;
+                    self.pending = self.chords.get(self.position).cloned().unwrap_or_default();
+                }
+            }
+            None => self.wrong += 1,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.chords.len()
+    }
+
+    /// Keys still to press for the current chord.
+    pub fn expected(&self) -> &[String] {
+        &self.pending
+    }
+
+    /// `(chords done, total chords)`
+    pub fn progress(&self) -> (usize, usize) {
+        (self.position, self.chords.len())
+    }
+
+    /// Share of key presses that were right, from 0 to 1.
+    pub fn accuracy(&self) -> f32 {
+        let presses = self.correct + self.wrong;
+        if presses == 0 {
+            1.0
+        } else {
+            self.correct as f32 / presses as f32
+        }
+    }
+
+    /// Summarises the session for the history.
+    pub fn record(&self, song: &str) -> SessionRecord {
+        let minutes = self.started.elapsed().as_secs_f32() / 60.0;
+        SessionRecord {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            song: song.to_owned(),
+            accuracy: self.accuracy(),
+            notes: self.correct,
+            notes_per_minute: if minutes > 0.0 {
+                self.correct as f32 / minutes
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+/// The Planck key label typed by a key event, if any.
+pub fn typed_key(event: &egui::Event) -> Option<String> {
+    match event {
+        egui::Event::Text(text) => {
+            let mut chars = text.chars();
+            match (chars.next()?, chars.next()) {
+                (' ', None) => Some("SPACE".to_owned()),
+                (c, None) => Some(c.to_uppercase().to_string()),
+                _ => None,
+            }
+        }
+        egui::Event::Key {
+            key, pressed: true, ..
+        } => {
+            let label = match key {
+                egui::Key::Escape => "ESC",
+                egui::Key::Tab => "TAB",
+                egui::Key::Backspace => "BCK",
+                egui::Key::Enter => "ETR",
+                egui::Key::ArrowLeft => "<-",
+                egui::Key::ArrowDown => "\\/",
+                egui::Key::ArrowUp => "/\\",
+                egui::Key::ArrowRight => "->",
+                _ => return None,
+            };
+            Some(label.to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Result of one practice session.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SessionRecord {
+    /// When the session ended, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub song: String,
+    /// Share of key presses that were right, from 0 to 1.
+    pub accuracy: f32,
+    /// Notes pressed correctly.
+    pub notes: u32,
+    /// Playing speed: correct notes per minute.
+    pub notes_per_minute: f32,
+}
+
+/// Past practice sessions, oldest first, as stored in the history file.
+#[derive(Serialize, Deserialize, Default)]
+pub struct PracticeHistory {
+    pub sessions: Vec<SessionRecord>,
+}
+
+#[derive(Error, Debug)]
+pub enum PracticeError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid practice history: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl PracticeHistory {
+    pub fn load(path: &Path) -> Result<PracticeHistory, PracticeError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PracticeError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Adds a session, dropping the oldest ones beyond [`HISTORY_CAPACITY`].
+    pub fn push(&mut self, record: SessionRecord) {
+        self.sessions.push(record);
+        self.prune(HISTORY_CAPACITY);
+    }
+
+    /// Keeps only the `keep` most recent sessions.
+    pub fn prune(&mut self, keep: usize) {
+        let excess = self.sessions.len().saturating_sub(keep);
+        self.sessions.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    fn pair(tick: u64, key: &str) -> MidiKeyPair {
+        MidiKeyPair {
+            delta: 0,
+            tick,
+            duration: Some(480),
+            midi_key: 60,
+            velocity: 100,
+            channel: 0,
+            keyboard_key: Some(key.to_owned()),
+            substituted: false,
+        }
+    }
+
+    #[test]
+    fn scores_presses_chord_by_chord() {
+        let mut track = MidiKeyTrack::new();
+        track.midi_key_pairs = vec![
+            pair(0, "A"),
+            pair(0, "S"),
+            pair(480, "SHF"),
+            pair(960, "ESC"),
+        ];
+        let mut practice = Practice::start(0, &track);
+        assert_eq!(practice.progress(), (0, 2));
+        practice.press("S");
+        practice.press("Q");
+        practice.press("a");
+        // The shift note can't be typed, so ESC comes next.
+        assert_eq!(practice.expected(), ["ESC".to_owned()]);
+        practice.press("ESC");
+        assert!(practice.is_finished());
+        assert_eq!(practice.accuracy(), 0.75);
+    }
+
+    #[test]
+    fn history_is_capped() {
+        let mut history = PracticeHistory::default();
+        let practice = Practice::start(0, &MidiKeyTrack::new());
+        for _ in 0..HISTORY_CAPACITY + 5 {
+            history.push(practice.record("song"));
+        }
+        assert_eq!(history.sessions.len(), HISTORY_CAPACITY);
+        history.prune(10);
+        assert_eq!(history.sessions.len(), 10);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{MidiKeyTrack, PlanckRows, SongTiming};
+use std::fmt::Write;
+
+/// The QMK keycode of a keyboard label without its `KC_` prefix, e.g. `SCLN` for `;`.
+///
+/// Returns `None` for keys QMK has no basic keycode for, such as the layer keys and blank cells.
+pub fn keycode(label: &str) -> Option<String> {
+    let name = match label {
+        "TAB" => "TAB",
+        "BCK" => "BSPC",
+        "ESC" => "ESC",
+        "CAPS" => "CAPS",
+        "SHF" => "LSFT",
+        "RSHF" => "RSFT",
+        "ETR" => "ENT",
+        "CTRL" => "LCTL",
+        "RCTRL" => "RCTL",
+        "ALT" => "LALT",
+        "RALT" => "RALT",
+        "OS" => "LGUI",
+        "MENU" => "APP",
+        "SPACE" => "SPC",
+        "<-" => "LEFT",
+        "\\/" => "DOWN",
+        "/\\" => "UP",
+        "->" => "RGHT",
+        "`" => "GRV",
+        "-" => "MINS",
+        "=" => "EQL",
+        "[" => "LBRC",
+        "]" => "RBRC",
+        "\\" => "BSLS",
+        ";" => "SCLN",
+        "'" => "QUOT",
+        "," => "COMM",
+        "." => "DOT",
+        "/" => "SLSH",
+        _ => {
+            let mut chars = label.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase().into()),
+                _ => None,
+            };
+        }
+    };
+    Some(name.to_owned())
+}
+
+/// The QMK layout macro for a grid of `rows`. Boards that aren't a full grid get the generic
+/// `LAYOUT`, which may need renaming for the keyboard.
+fn layout_macro(rows: &PlanckRows) -> String {
+    let columns = rows.first().map_or(0, Vec::len);
+    if rows.iter().any(|row| row.len() != columns) {
+        "LAYOUT".to_owned()
+    } else if rows.len() == 4 && columns == 12 {
+        "LAYOUT_planck_grid".to_owned()
+    } else {
+        format!("LAYOUT_ortho_{}x{}", rows.len(), columns)
+    }
+}
+
+/// Writes a layer of `rows` with the keycode `key` gives each (row, column) cell, one row per
+/// line.
+fn write_layer(
+    keymap: &mut String,
+    index: usize,
+    rows: &PlanckRows,
+    key: impl Fn((usize, usize), &str) -> String,
+) {
+    writeln!(keymap, "    [{}] = {}(", index, layout_macro(rows)).ok();
+    let lines: Vec<String> = rows
+        .iter()
+        .enumerate()
+        .map(|(row_index, row)| {
+            let keys: Vec<String> = row
+                .iter()
+                .enumerate()
+                .map(|(column, label)| key((row_index, column), label))
+                .collect();
+            format!("        {}", keys.join(", "))
+        })
+        .collect();
+    writeln!(keymap, "{}", lines.join(",\n")).ok();
+    writeln!(keymap, "    ),").ok();
+}
+
+/// Builds a QMK `keymap.c` with two layers: the base layer types `rows` as labelled, and on the
+/// macro layer `base_key` types the song's key sequence with its timing.
+///
+/// The macro layer is held with the first key that has no keycode of its own (e.g. `ORYX` or
+/// `LOWER`), or else the first blank cell. Notes starting on the same tick are pressed
+/// together, and the gap until the next group becomes a delay, following the song's tempo
+/// changes.
+pub fn keymap_c(
+    song_name: &str,
+    tracks: &[MidiKeyTrack],
+    timing: &SongTiming,
+    rows: &PlanckRows,
+    base_key: &str,
+) -> String {
+    let mut pairs: Vec<_> = tracks
+        .iter()
+        .flat_map(|track| track.midi_key_pairs.iter())
+        .collect();
+    pairs.sort_by_key(|pair| pair.tick);
+    let cells = || {
+        rows.iter().enumerate().flat_map(|(row_index, row)| {
+            row.iter()
+                .enumerate()
+                .map(move |(column, label)| ((row_index, column), label.as_str()))
+        })
+    };
+    let layer_key = cells()
+        .find(|(_, label)| !label.is_empty() && keycode(label).is_none())
+        .or_else(|| cells().find(|(_, label)| label.is_empty()));
+
+    let mut keymap = String::new();
+    writeln!(keymap, "// Generated by Planck Scribe from {}", song_name).ok();
+    writeln!(keymap, "//").ok();
+    match layer_key {
+        Some((_, label)) if !label.is_empty() => writeln!(
+            keymap,
+            "// Hold {} and press {} to type the song into the focused window.",
+            label, base_key
+        ),
+        Some(_) => writeln!(
+            keymap,
+            "// Hold the blank key and press {} to type the song into the focused window.",
+            base_key
+        ),
+        None => writeln!(
+            keymap,
+            "// Every key has a keycode, so put MO(1) somewhere to reach the song on layer 1."
+        ),
+    }
+    .ok();
+    writeln!(keymap, "#include QMK_KEYBOARD_H").ok();
+    writeln!(keymap).ok();
+    writeln!(keymap, "enum custom_keycodes {{").ok();
+    writeln!(keymap, "    PLAY_SONG = SAFE_RANGE,").ok();
+    writeln!(keymap, "}};").ok();
+    writeln!(keymap).ok();
+    writeln!(
+        keymap,
+        "const uint16_t PROGMEM keymaps[][MATRIX_ROWS][MATRIX_COLS] = {{"
+    )
+    .ok();
+    write_layer(&mut keymap, 0, rows, |cell, label| {
+        if layer_key.is_some_and(|(layer_cell, _)| layer_cell == cell) {
+            "MO(1)".to_owned()
+        } else {
+            keycode(label).map_or("KC_NO".to_owned(), |code| format!("KC_{}", code))
+        }
+    });
+    write_layer(&mut keymap, 1, rows, |_, label| {
+        if label == base_key {
+            "PLAY_SONG".to_owned()
+        } else {
+            "KC_TRNS".to_owned()
+        }
+    });
+    writeln!(keymap, "}};").ok();
+    writeln!(keymap).ok();
+
+    writeln!(
+        keymap,
+        "bool process_record_user(uint16_t keycode, keyrecord_t *record) {{"
+    )
+    .ok();
+    writeln!(
+        keymap,
+        "    if (keycode == PLAY_SONG && record->event.pressed) {{"
+    )
+    .ok();
+    writeln!(keymap, "        SEND_STRING(\"\"").ok();
+    // Rounding the time of each group rather than each gap keeps the delays from drifting.
+    let time_ms = |tick: u64| {
+        timing
+            .tick_to_time_ms(tick)
+            .unwrap_or_else(|| timing.ticks_to_ms(tick))
+            .round() as u64
+    };
+    let mut index = 0;
+    while index < pairs.len() {
+        let tick = pairs[index].tick;
+        let mut keys = Vec::new();
+        while index < pairs.len() && pairs[index].tick == tick {
+            let pair = pairs[index];
+            match pair.keyboard_key.as_deref().and_then(keycode) {
+                Some(key) if !keys.contains(&key) => keys.push(key),
+                Some(_) => {}
+                None => {
+                    writeln!(
+                        keymap,
+                        "            // note {} has no key ({})",
+                        pair.midi_key,
+                        pair.keyboard_key.as_deref().unwrap_or("NONE")
+                    )
+                    .ok();
+                }
+            }
+            index += 1;
+        }
+        let mut line = String::new();
+        for key in &keys {
+            write!(line, "SS_DOWN(X_{}) ", key).ok();
+        }
+        for key in &keys {
+            write!(line, "SS_UP(X_{}) ", key).ok();
+        }
+        if let Some(next) = pairs.get(index) {
+            let delay_ms = time_ms(next.tick) - time_ms(tick);
+            if delay_ms > 0 {
+                write!(line, "SS_DELAY({})", delay_ms).ok();
+            }
+        }
+        if !line.is_empty() {
+            writeln!(keymap, "            {}", line.trim_end()).ok();
+        }
+    }
+    writeln!(keymap, "        );").ok();
+    writeln!(keymap, "        return false;").ok();
+    writeln!(keymap, "    }}").ok();
+    writeln!(keymap, "    return true;").ok();
+    writeln!(keymap, "}}").ok();
+    keymap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{default_planck_rows, MidiKeyPair};
+
+    #[test]
+    fn keymap_types_the_song_on_the_macro_layer() {
+        assert_eq!(keycode(";").as_deref(), Some("SCLN"));
+        assert_eq!(keycode("q").as_deref(), Some("Q"));
+        assert_eq!(keycode("ORYX"), None);
+        assert_eq!(keycode(""), None);
+
+        let mut track = MidiKeyTrack::new();
+        for (tick, key) in [(0, "A"), (0, "S"), (480, ";"), (960, "ORYX")] {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick,
+                duration: Some(480),
+                midi_key: 60,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: Some(key.to_owned()),
+                substituted: false,
+            });
+        }
+        let rows = default_planck_rows();
+        // 120 BPM for the first beat, then twice as fast.
+        let timing = SongTiming {
+            tempo_changes: vec![(0, 500_000), (480, 250_000)],
+            ..SongTiming::default()
+        };
+        let keymap = keymap_c("Song", &[track], &timing, &rows, "ESC");
+        assert!(keymap.contains("[0] = LAYOUT_planck_grid(\n        KC_TAB, KC_Q,"));
+        assert!(keymap.contains("// Hold ORYX and press ESC"));
+        assert!(keymap.contains("        KC_NO, KC_LCTL, KC_LALT, MO(1), KC_LGUI, KC_NO,"));
+        assert!(keymap.contains("        PLAY_SONG, KC_TRNS,"));
+        assert!(keymap.contains(
+            "            SS_DOWN(X_A) SS_DOWN(X_S) SS_UP(X_A) SS_UP(X_S) SS_DELAY(500)\n\
+             \x20           SS_DOWN(X_SCLN) SS_UP(X_SCLN) SS_DELAY(250)\n\
+             \x20           // note 60 has no key (ORYX)\n        );"
+        ));
+    }
+}
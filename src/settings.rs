@@ -0,0 +1,627 @@
+use crate::{
+    default_planck_rows,
+    mapping::{self, CustomMapping, MappingScheme},
+    notes,
+    playback::PlaybackSettings,
+    PlanckRows,
+};
+#[cfg(feature = "gui")]
+use eframe::{egui, epaint::Color32};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+use thiserror::*;
+
+/// Version of the settings file schema. Bump when fields change meaning.
+pub const SETTINGS_VERSION: u32 = 1;
+
+/// Semitones the whole song can be transposed by.
+pub const TRANSPOSE_RANGE: RangeInclusive<i8> = -24..=24;
+/// Frequencies of A4, in Hz, that the reference pitch can be tuned to.
+pub const REFERENCE_PITCH_RANGE: RangeInclusive<f64> = 380.0..=480.0;
+/// Playback speeds, in percent of the song's tempo.
+pub const TEMPO_PERCENT_RANGE: RangeInclusive<f32> = 25.0..=200.0;
+
+/// How the tracks are laid out on screen.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TrackLayout {
+    /// One column of key lines per track.
+    #[default]
+    List,
+    /// One horizontal lane per track with notes positioned by time.
+    Timeline,
+    /// One aligned row per note combining the fields picked in [`TableFields`].
+    Table,
+    /// One column per beat of the time signature, with the notes of each beat stacked.
+    Beats,
+    /// All tracks on one grid with time to the right and pitch upwards.
+    PianoRoll,
+}
+
+/// Which columns the table layout shows for each note.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TableFields {
+    pub midi_key: bool,
+    pub note_name: bool,
+    pub keyboard_key: bool,
+    pub time: bool,
+    #[serde(default)]
+    pub velocity: bool,
+    /// Pad the MIDI key, note and key columns to the same width in every track, so columns
+    /// line up across tracks too. Numbers are right-aligned.
+    pub align: bool,
+}
+
+impl Default for TableFields {
+    fn default() -> Self {
+        TableFields {
+            midi_key: true,
+            note_name: true,
+            keyboard_key: true,
+            time: true,
+            velocity: false,
+            align: true,
+        }
+    }
+}
+
+/// How the pressed key of each note is written in the track view.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum KeyNames {
+    /// The key's label from the layout, such as `ESC`.
+    #[default]
+    Labels,
+    /// The key's `(row,col)` position in the layout, counted from zero.
+    Coordinates,
+}
+
+/// How the track view looks, including optional extra columns.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DisplaySettings {
+    /// Prefix each line with the absolute tick it starts on.
+    pub show_ticks: bool,
+    /// Show each note's raw delta time (ticks since the previous event in the track).
+    pub show_delta: bool,
+    /// Show each note's frequency in Hz.
+    pub show_frequency: bool,
+    /// Show roughly how many cents pitch bend moves each bent note off equal temperament.
+    pub show_cents: bool,
+    /// Draw a miniature of the keyboard before each line with the pressed keys lit.
+    pub show_key_grid: bool,
+    pub layout: TrackLayout,
+    pub key_names: KeyNames,
+    pub table_fields: TableFields,
+    /// Horizontal zoom of the timeline and piano roll layouts, in pixels per beat.
+    pub timeline_zoom: f32,
+    /// Height of one semitone in the piano roll layout, in pixels.
+    pub piano_roll_row_height: f32,
+    /// Color notes outside `scale` differently, to spot accidentals.
+    pub highlight_scale: bool,
+    pub scale: notes::Scale,
+    /// Mark notes whose key is far from the keys of the previous onset.
+    pub highlight_jumps: bool,
+    /// Rows plus columns between keys from which a move counts as a difficult jump.
+    pub jump_distance: usize,
+    /// Follow each chord with all of its keys in brackets, like `[A S D]`.
+    pub bracket_chords: bool,
+    /// Start each measure of the list layout with a `| 3` line.
+    pub bar_lines: bool,
+    /// Follow each chord with a guess at its name, marked `?` when the notes only partly fit.
+    pub show_chord_names: bool,
+    /// Labels of modifier keys, separated by spaces or commas. Notes mapped to them are flagged,
+    /// since a melody note on a modifier is usually a layout mistake.
+    pub modifier_keys: String,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            show_ticks: false,
+            show_delta: false,
+            show_frequency: false,
+            show_cents: false,
+            show_key_grid: false,
+            layout: TrackLayout::default(),
+            key_names: KeyNames::default(),
+            table_fields: TableFields::default(),
+            timeline_zoom: 40.0,
+            piano_roll_row_height: 10.0,
+            highlight_scale: false,
+            scale: notes::Scale::default(),
+            highlight_jumps: false,
+            jump_distance: 5,
+            bracket_chords: false,
+            bar_lines: false,
+            show_chord_names: false,
+            modifier_keys: "SHF CTRL ALT OS".to_owned(),
+        }
+    }
+}
+
+impl DisplaySettings {
+    pub fn is_modifier_key(&self, label: &str) -> bool {
+        self.modifier_keys
+            .split([' ', ','])
+            .any(|key| !key.is_empty() && key == label)
+    }
+}
+
+/// Note text colors as RGB. `None` uses a color from the current theme.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ColorSettings {
+    pub mapped: Option<[u8; 3]>,
+    pub unmapped: Option<[u8; 3]>,
+    /// Mapped notes outside the highlighted scale.
+    pub out_of_scale: Option<[u8; 3]>,
+}
+
+#[cfg(feature = "gui")]
+impl ColorSettings {
+    /// The mapped and unmapped note colors to draw with.
+    pub fn resolve(&self, visuals: &egui::Visuals) -> (Color32, Color32) {
+        let rgb = |color: [u8; 3]| Color32::from_rgb(color[0], color[1], color[2]);
+        (
+            self.mapped.map_or(visuals.strong_text_color(), rgb),
+            self.unmapped.map_or(visuals.error_fg_color, rgb),
+        )
+    }
+
+    /// The color for notes outside the highlighted scale.
+    pub fn resolve_out_of_scale(&self, visuals: &egui::Visuals) -> Color32 {
+        self.out_of_scale.map_or(visuals.warn_fg_color, |color| {
+            Color32::from_rgb(color[0], color[1], color[2])
+        })
+    }
+}
+
+/// What happens to notes caught by a filter.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum FilteredNotes {
+    /// Leave them out of the display.
+    #[default]
+    Hide,
+    /// Keep them in place, drawn faintly, so the excluded notes stay in context.
+    Dim,
+}
+
+/// Notes left out of the track view. Filtering never changes the loaded notes.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct FilterSettings {
+    /// Hide very short notes, treating them as grace notes and ornaments.
+    pub hide_grace_notes: bool,
+    /// Notes shorter than a `1/grace_note_value` note are hidden, e.g. `32` for a 32nd note.
+    pub grace_note_value: u32,
+    pub filtered_notes: FilteredNotes,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        FilterSettings {
+            hide_grace_notes: false,
+            grace_note_value: 32,
+            filtered_notes: FilteredNotes::default(),
+        }
+    }
+}
+
+/// What to do when a track has several note-ons for the same pitch on the same tick.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum DuplicateNotes {
+    /// Keep every note-on, as written in the file.
+    #[default]
+    KeepAll,
+    /// Keep only the first note-on; the duplicates are dropped while loading.
+    KeepFirst,
+}
+
+/// How MIDI notes are laid out over the keyboard, starting from middle C on the base key.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum MappingKind {
+    /// One semitone per key, reading the whole grid left to right and top to bottom.
+    #[default]
+    Chromatic,
+    /// A fixed interval per column and per row, so a shape plays the same chord anywhere.
+    Isomorphic,
+    /// Isomorphic with the intervals of the Wicki–Hayden layout.
+    WickiHayden,
+    /// Isomorphic with the intervals of the Harmonic Table.
+    HarmonicTable,
+    /// Isomorphic with the intervals of the Jankó keyboard.
+    Janko,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct MappingSettings {
+    pub kind: MappingKind,
+    /// Semitones from one row to the row below, for the isomorphic mapping.
+    pub row_interval: i8,
+    /// Semitones from one key to the key on its right, for the isomorphic mapping.
+    pub column_interval: i8,
+    /// Keys given their notes by hand, on top of the scheme.
+    pub custom: CustomMapping,
+    /// Semitones every note of the song is shifted by, as it is mapped and played, from -24 to 24.
+    pub transpose: i8,
+}
+
+impl MappingSettings {
+    /// The scheme that lays the notes out.
+    pub fn scheme(&self) -> Box<dyn MappingScheme> {
+        match self.kind {
+            MappingKind::Chromatic => Box::new(mapping::Chromatic),
+            MappingKind::Isomorphic => Box::new(mapping::Isomorphic {
+                row_interval: self.row_interval,
+                column_interval: self.column_interval,
+            }),
+            MappingKind::WickiHayden => Box::new(mapping::WickiHayden),
+            MappingKind::HarmonicTable => Box::new(mapping::HarmonicTable),
+            MappingKind::Janko => Box::new(mapping::Janko),
+        }
+    }
+}
+
+impl Default for MappingSettings {
+    fn default() -> Self {
+        MappingSettings {
+            kind: MappingKind::default(),
+            row_interval: 5,
+            column_interval: 1,
+            custom: CustomMapping::default(),
+            transpose: 0,
+        }
+    }
+}
+
+/// Which notes of a chord a polyphony reduction keeps.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum VoiceOrder {
+    #[default]
+    Highest,
+    Lowest,
+}
+
+/// Parameters for reducing a track to fewer simultaneous notes.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ReductionSettings {
+    /// Maximum number of notes kept on each tick.
+    pub voices: usize,
+    pub keep: VoiceOrder,
+}
+
+impl Default for ReductionSettings {
+    fn default() -> Self {
+        ReductionSettings {
+            voices: 2,
+            keep: VoiceOrder::default(),
+        }
+    }
+}
+
+/// Where exports are offered to be saved and what they are called.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ExportSettings {
+    /// Folder the save dialog opens in, or `None` for the system default.
+    pub directory: Option<PathBuf>,
+    /// File name without the extension. `{name}`, `{basekey}` and `{date}` are replaced by the
+    /// song name, the base key and today's date.
+    pub file_name_template: String,
+    /// Make chord onsets stand out in click track exports.
+    pub accent_chords: bool,
+    /// Put a `rest:<ticks>t` row, e.g. `rest:480t`, in table exports wherever a track falls
+    /// silent between notes, so the rhythm can be rebuilt from the table alone.
+    pub mark_rests: bool,
+    /// Files exported at once when exporting a batch, or 0 for one per CPU core.
+    pub batch_threads: usize,
+    /// Write key sequences measure by measure, like `| A S D | F G H |`.
+    pub group_bars: bool,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        ExportSettings {
+            directory: None,
+            file_name_template: "{name}".to_owned(),
+            accent_chords: true,
+            mark_rests: false,
+            batch_threads: 0,
+            group_bars: false,
+        }
+    }
+}
+
+/// The large keyboard shown below the tracks.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct KeyboardPanelSettings {
+    pub show: bool,
+    /// Height of the panel in points, set by dragging the divider above it.
+    pub height: f32,
+    /// Shade each key by how often the song presses it.
+    pub heatmap: bool,
+}
+
+impl Default for KeyboardPanelSettings {
+    fn default() -> Self {
+        KeyboardPanelSettings {
+            show: false,
+            height: 180.0,
+            heatmap: false,
+        }
+    }
+}
+
+/// How the opacity of faded notes follows their velocity.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum FadeCurve {
+    /// Opacity grows evenly with velocity.
+    #[default]
+    Linear,
+    /// Opacity stays low for most velocities and rises steeply for the loudest notes.
+    Exponential,
+}
+
+/// Evening out of note velocities for display and preview.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct VelocitySettings {
+    pub normalize: bool,
+    /// Velocity the softest note is shown with when normalizing.
+    pub min: u8,
+    /// Velocity the loudest note is shown with when normalizing.
+    pub max: u8,
+    /// Draw quieter notes fainter in the list layout.
+    pub fade: bool,
+    pub fade_curve: FadeCurve,
+}
+
+impl Default for VelocitySettings {
+    fn default() -> Self {
+        VelocitySettings {
+            normalize: false,
+            min: 40,
+            max: 100,
+            fade: false,
+            fade_curve: FadeCurve::default(),
+        }
+    }
+}
+
+/// What happens to notes the mapping has no key for.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Default, Debug)]
+pub enum UnmappedNotes {
+    /// Keep them, shown as `NONE` in the unmapped color.
+    #[default]
+    Mark,
+    /// Play them on the key of the nearest mapped note, marked `~`.
+    Nearest,
+    /// Move them by whole octaves onto a mapped key, marked `~`. Notes no octave of which is
+    /// mapped go to the nearest mapped note.
+    FoldOctaves,
+    /// Leave them out of the track views and exports.
+    Drop,
+}
+
+/// Reads [`UnmappedNotes`], or the `substitute_unmapped` flag of older settings files.
+fn unmapped_notes<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<UnmappedNotes, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Strategy(UnmappedNotes),
+        Substitute(bool),
+    }
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::Strategy(strategy) => strategy,
+        Stored::Substitute(true) => UnmappedNotes::Nearest,
+        Stored::Substitute(false) => UnmappedNotes::Mark,
+    })
+}
+
+/// Every user preference, as shared through settings files.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Settings {
+    /// [`SETTINGS_VERSION`] the file was written with, or 0 for files from before it was saved.
+    #[serde(default)]
+    pub version: u32,
+    /// Keyboard key that middle C is mapped to.
+    pub base_key: String,
+    pub layout: PlanckRows,
+    pub mapping: MappingSettings,
+    pub display: DisplaySettings,
+    pub colors: ColorSettings,
+    pub velocity: VelocitySettings,
+    pub filter: FilterSettings,
+    pub duplicate_notes: DuplicateNotes,
+    /// Show one track per MIDI channel instead of the file's own tracks.
+    pub split_by_channel: bool,
+    pub reduction: ReductionSettings,
+    #[serde(alias = "substitute_unmapped", deserialize_with = "unmapped_notes")]
+    pub unmapped_notes: UnmappedNotes,
+    /// How octave numbers in typed note names are read.
+    pub middle_c: notes::MiddleC,
+    /// Apply the best auto-fit octave shift to every track right after loading a file.
+    pub auto_transpose: bool,
+    /// Frequency of A4 in Hz, used for the frequency display and audio.
+    pub reference_pitch: f64,
+    pub playback: PlaybackSettings,
+    pub export: ExportSettings,
+    pub keyboard_panel: KeyboardPanelSettings,
+    /// Save the open file and settings on exit, to be offered back on the next start. Turning it
+    /// off forgets the saved session.
+    pub save_session: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            base_key: "ESC".to_owned(),
+            layout: default_planck_rows(),
+            mapping: MappingSettings::default(),
+            display: DisplaySettings::default(),
+            colors: ColorSettings::default(),
+            velocity: VelocitySettings::default(),
+            filter: FilterSettings::default(),
+            duplicate_notes: DuplicateNotes::default(),
+            split_by_channel: false,
+            reduction: ReductionSettings::default(),
+            unmapped_notes: UnmappedNotes::default(),
+            auto_transpose: false,
+            middle_c: notes::MiddleC::default(),
+            reference_pitch: notes::DEFAULT_REFERENCE_PITCH,
+            playback: PlaybackSettings::default(),
+            export: ExportSettings::default(),
+            keyboard_panel: KeyboardPanelSettings::default(),
+            save_session: true,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid settings file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("base key \"{0}\" is not in the layout")]
+    MissingBaseKey(String),
+    #[error("{name} {value} is not between {min} and {max}")]
+    OutOfRange {
+        name: &'static str,
+        value: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// Fails with [`SettingsError::OutOfRange`] unless `range` holds `value`.
+fn check_range<T: PartialOrd + Into<f64> + Copy>(
+    name: &'static str,
+    value: T,
+    range: RangeInclusive<T>,
+) -> Result<(), SettingsError> {
+    if range.contains(&value) {
+        return Ok(());
+    }
+    Err(SettingsError::OutOfRange {
+        name,
+        value: value.into(),
+        min: (*range.start()).into(),
+        max: (*range.end()).into(),
+    })
+}
+
+impl Settings {
+    pub fn export(&self, path: &Path) -> Result<(), SettingsError> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    pub fn import(path: &Path) -> Result<Settings, SettingsError> {
+        let settings: Settings = serde_json::from_str(&fs::read_to_string(path)?)?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Checks that middle C's key is on the layout, and that the transpose, reference pitch and
+    /// playback speed are within the ranges the app offers.
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !self
+            .layout
+            .iter()
+            .flatten()
+            .any(|key| *key == self.base_key)
+        {
+            return Err(SettingsError::MissingBaseKey(self.base_key.clone()));
+        }
+        check_range("transpose", self.mapping.transpose, TRANSPOSE_RANGE)?;
+        check_range(
+            "reference pitch",
+            self.reference_pitch,
+            REFERENCE_PITCH_RANGE,
+        )?;
+        check_range(
+            "playback speed",
+            self.playback.tempo_percent,
+            TEMPO_PERCENT_RANGE,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exported_settings_import_back() {
+        let directory =
+            std::env::temp_dir().join(format!("planck-scribe-settings-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("settings.json");
+        let settings = Settings {
+            base_key: "A".to_owned(),
+            unmapped_notes: UnmappedNotes::Nearest,
+            reference_pitch: 432.0,
+            ..Settings::default()
+        };
+        settings.export(&path).unwrap();
+
+        let imported = Settings::import(&path).unwrap();
+        assert_eq!(imported.version, SETTINGS_VERSION);
+        assert_eq!(imported.base_key, "A");
+        assert_eq!(imported.unmapped_notes, UnmappedNotes::Nearest);
+        assert_eq!(imported.reference_pitch, 432.0);
+        assert_eq!(imported.layout, settings.layout);
+
+        // Older files only had a flag for substituting unmapped notes, and left out what
+        // came later.
+        fs::write(&path, r#"{"base_key": "Q", "substitute_unmapped": true}"#).unwrap();
+        let imported = Settings::import(&path).unwrap();
+        assert_eq!(imported.version, 0);
+        assert_eq!(imported.unmapped_notes, UnmappedNotes::Nearest);
+        assert_eq!(imported.layout, default_planck_rows());
+
+        fs::write(&path, r#"{"base_key": "NOT A KEY"}"#).unwrap();
+        assert!(matches!(
+            Settings::import(&path),
+            Err(SettingsError::MissingBaseKey(key)) if key == "NOT A KEY"
+        ));
+        fs::write(&path, r#"{"mapping": {"transpose": 30}}"#).unwrap();
+        assert!(matches!(
+            Settings::import(&path),
+            Err(SettingsError::OutOfRange {
+                name: "transpose",
+                ..
+            })
+        ));
+        fs::write(&path, r#"{"reference_pitch": 0.0}"#).unwrap();
+        assert!(matches!(
+            Settings::import(&path),
+            Err(SettingsError::OutOfRange {
+                name: "reference pitch",
+                ..
+            })
+        ));
+        fs::write(&path, r#"{"playback": {"tempo_percent": 1000.0}}"#).unwrap();
+        assert!(matches!(
+            Settings::import(&path),
+            Err(SettingsError::OutOfRange {
+                name: "playback speed",
+                ..
+            })
+        ));
+        fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,384 @@
+//! A small built-in synth that plays MIDI messages on the system's audio output, so playback can
+//! be heard without a MIDI synth installed.
+
+use crate::{midi_output::Sound, notes, PERCUSSION_CHANNEL};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::{cell::Cell, f32::consts::TAU, sync::mpsc};
+use thiserror::*;
+
+/// Most notes sounding at once; the oldest is cut off to make room for another.
+const MAX_VOICES: usize = 48;
+/// Loudness of a single full-velocity note, leaving headroom for chords.
+const VOICE_GAIN: f32 = 0.12;
+
+#[derive(Error, Debug)]
+pub enum SynthError {
+    #[error("no audio output device")]
+    NoDevice,
+    #[error("audio output unavailable: {0}")]
+    Config(#[from] cpal::DefaultStreamConfigError),
+    #[error("could not open the audio output: {0}")]
+    Build(#[from] cpal::BuildStreamError),
+    #[error("could not start the audio output: {0}")]
+    Play(#[from] cpal::PlayStreamError),
+    #[error("unsupported audio sample format {0}")]
+    SampleFormat(cpal::SampleFormat),
+}
+
+/// What the UI asks the audio thread to play.
+enum Command {
+    Messages(Vec<Vec<u8>>),
+    AllNotesOff,
+    /// Frequency of A4 in Hz.
+    Tune(f64),
+}
+
+/// How the notes of an instrument sound: the strength of each harmonic, and an envelope that
+/// rises to full level over `attack` seconds, falls to `sustain` over `decay` seconds while the
+/// note is held, and fades out over `release` seconds once it ends.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Timbre {
+    harmonics: &'static [f32],
+    attack: f32,
+    decay: f32,
+    sustain: f32,
+    release: f32,
+}
+
+/// Pianos, guitars and other struck or plucked instruments fade even while held.
+const STRUCK: Timbre = Timbre {
+    harmonics: &[1.0, 0.5, 0.3, 0.15, 0.08],
+    attack: 0.005,
+    decay: 1.5,
+    sustain: 0.0,
+    release: 0.15,
+};
+const ORGAN: Timbre = Timbre {
+    harmonics: &[1.0, 0.6, 0.0, 0.4, 0.0, 0.2],
+    attack: 0.01,
+    decay: 0.0,
+    sustain: 1.0,
+    release: 0.05,
+};
+/// Bowed and blown instruments, and synth sounds.
+const SUSTAINED: Timbre = Timbre {
+    harmonics: &[1.0, 0.5, 0.33, 0.25, 0.2, 0.16],
+    attack: 0.06,
+    decay: 0.2,
+    sustain: 0.8,
+    release: 0.25,
+};
+/// Flutes and other pipes are close to a pure tone.
+const PIPE: Timbre = Timbre {
+    harmonics: &[1.0, 0.1, 0.05],
+    attack: 0.05,
+    decay: 0.1,
+    sustain: 0.9,
+    release: 0.15,
+};
+/// Drums are a burst of noise.
+const DRUM: Timbre = Timbre {
+    harmonics: &[],
+    attack: 0.001,
+    decay: 0.12,
+    sustain: 0.0,
+    release: 0.05,
+};
+
+/// The timbre of General MIDI `program`, by its family of eight.
+fn timbre(program: u8) -> Timbre {
+    match program / 8 {
+        // Piano, chromatic percussion, guitar, bass, ethnic, percussive.
+        0 | 1 | 3 | 4 | 13 | 14 => STRUCK,
+        2 => ORGAN,
+        9 => PIPE,
+        _ => SUSTAINED,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Attack,
+    Decay,
+    Release,
+}
+
+struct Voice {
+    channel: u8,
+    key: u8,
+    timbre: Timbre,
+    /// Cycles per sample.
+    step: f32,
+    /// Position in the current cycle, from 0 to 1.
+    phase: f32,
+    gain: f32,
+    level: f32,
+    stage: Stage,
+}
+
+impl Voice {
+    fn finished(&self) -> bool {
+        self.level <= 0.0 && self.stage != Stage::Attack
+    }
+}
+
+/// The notes sounding and the instrument of each channel, turned into samples.
+struct Voices {
+    sample_rate: f32,
+    reference_pitch: f64,
+    programs: [u8; 16],
+    voices: Vec<Voice>,
+    /// State of the noise generator for drums.
+    noise: u32,
+}
+
+impl Voices {
+    fn new(sample_rate: f32, reference_pitch: f64) -> Voices {
+        Voices {
+            sample_rate,
+            reference_pitch,
+            programs: [0; 16],
+            voices: Vec::new(),
+            noise: 0x1234_5678,
+        }
+    }
+
+    fn handle(&mut self, message: &[u8]) {
+        let channel = message.first().map_or(0, |status| status & 0x0F);
+        match *message {
+            [status, key, velocity] if status & 0xF0 == 0x90 && velocity > 0 => {
+                self.note_on(channel, key, velocity)
+            }
+            [status, key, _] if status & 0xF0 == 0x90 || status & 0xF0 == 0x80 => {
+                self.note_off(channel, key)
+            }
+            [status, program] if status & 0xF0 == 0xC0 => {
+                self.programs[channel as usize] = program;
+            }
+            // All notes off.
+            [status, 123, _] if status & 0xF0 == 0xB0 => self
+                .voices
+                .iter_mut()
+                .filter(|voice| voice.channel == channel)
+                .for_each(|voice| voice.stage = Stage::Release),
+            _ => {}
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        // A note struck again starts over rather than sounding twice.
+        self.voices
+            .retain(|voice| voice.channel != channel || voice.key != key);
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        let timbre = if channel == PERCUSSION_CHANNEL {
+            DRUM
+        } else {
+            timbre(self.programs[channel as usize])
+        };
+        let frequency = notes::midi_to_frequency(key, self.reference_pitch) as f32;
+        self.voices.push(Voice {
+            channel,
+            key,
+            timbre,
+            step: frequency / self.sample_rate,
+            phase: 0.0,
+            gain: VOICE_GAIN * velocity as f32 / 127.0,
+            level: 0.0,
+            stage: Stage::Attack,
+        });
+    }
+
+    fn note_off(&mut self, channel: u8, key: u8) {
+        for voice in self.voices.iter_mut() {
+            if voice.channel == channel && voice.key == key {
+                voice.stage = Stage::Release;
+            }
+        }
+    }
+
+    /// The next sample of every voice mixed together, from -1 to 1.
+    fn next_sample(&mut self) -> f32 {
+        let sample_rate = self.sample_rate;
+        let mut mixed = 0.0;
+        for voice in self.voices.iter_mut() {
+            let timbre = voice.timbre;
+            let per_second = |seconds: f32| 1.0 / (seconds * sample_rate).max(1.0);
+            match voice.stage {
+                Stage::Attack => {
+                    voice.level += per_second(timbre.attack);
+                    if voice.level >= 1.0 {
+                        voice.level = 1.0;
+                        voice.stage = Stage::Decay;
+                    }
+                }
+                Stage::Decay => {
+                    voice.level = (voice.level - (1.0 - timbre.sustain) * per_second(timbre.decay))
+                        .max(timbre.sustain);
+                }
+                Stage::Release => voice.level -= per_second(timbre.release),
+            }
+            let tone = if timbre.harmonics.is_empty() {
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+            } else {
+                timbre
+                    .harmonics
+                    .iter()
+                    .enumerate()
+                    // Harmonics above half the sample rate would alias.
+                    .take_while(|(index, _)| voice.step * (*index as f32 + 1.0) < 0.5)
+                    .map(|(index, strength)| {
+                        strength * (TAU * voice.phase * (index as f32 + 1.0)).sin()
+                    })
+                    .sum()
+            };
+            voice.phase = (voice.phase + voice.step).fract();
+            mixed += tone * voice.gain * voice.level.max(0.0);
+        }
+        self.voices.retain(|voice| !voice.finished());
+        mixed.tanh()
+    }
+}
+
+/// The built-in synth, playing on the default audio output.
+///
+/// The audio device pulls samples on its own thread, which the UI sends messages to over a
+/// channel, so playback never waits on a frame.
+pub struct Synth {
+    device_name: String,
+    commands: mpsc::Sender<Command>,
+    reference_pitch: Cell<f64>,
+    /// Plays until dropped.
+    _stream: cpal::Stream,
+}
+
+impl Synth {
+    pub fn start(reference_pitch: f64) -> Result<Synth, SynthError> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or(SynthError::NoDevice)?;
+        let device_name = device.name().unwrap_or_else(|_| "audio output".to_owned());
+        let supported = device.default_output_config()?;
+        let config: cpal::StreamConfig = supported.clone().into();
+        let voices = Voices::new(config.sample_rate.0 as f32, reference_pitch);
+        let (sender, commands) = mpsc::channel();
+        let stream = match supported.sample_format() {
+            cpal::SampleFormat::F32 => stream::<f32>(&device, &config, voices, commands),
+            cpal::SampleFormat::I16 => stream::<i16>(&device, &config, voices, commands),
+            cpal::SampleFormat::U16 => stream::<u16>(&device, &config, voices, commands),
+            format => return Err(SynthError::SampleFormat(format)),
+        }?;
+        stream.play()?;
+        log::info!("playing through the built-in synth on {device_name}");
+        Ok(Synth {
+            device_name,
+            commands: sender,
+            reference_pitch: Cell::new(reference_pitch),
+            _stream: stream,
+        })
+    }
+
+    fn command(&self, command: Command) {
+        // The stream, and so the receiver, lives as long as the synth.
+        let _ = self.commands.send(command);
+    }
+}
+
+impl Sound for Synth {
+    fn name(&self) -> String {
+        format!("the built-in synth ({})", self.device_name)
+    }
+
+    fn send(&self, messages: Vec<Vec<u8>>) {
+        if !messages.is_empty() {
+            self.command(Command::Messages(messages));
+        }
+    }
+
+    fn all_notes_off(&self) {
+        self.command(Command::AllNotesOff);
+    }
+
+    fn tune(&self, reference_pitch: f64) {
+        if self.reference_pitch.replace(reference_pitch) != reference_pitch {
+            self.command(Command::Tune(reference_pitch));
+        }
+    }
+}
+
+/// An output stream that plays `voices`, taking commands between buffers.
+fn stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut voices: Voices,
+    commands: mpsc::Receiver<Command>,
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: cpal::SizedSample + cpal::FromSample<f32>,
+{
+    let channels = config.channels.max(1) as usize;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _| {
+            for command in commands.try_iter() {
+                match command {
+                    Command::Messages(messages) => {
+                        messages.iter().for_each(|message| voices.handle(message))
+                    }
+                    Command::AllNotesOff => {
+                        for voice in voices.voices.iter_mut() {
+                            voice.stage = Stage::Release;
+                        }
+                    }
+                    Command::Tune(reference_pitch) => voices.reference_pitch = reference_pitch,
+                }
+            }
+            for frame in data.chunks_mut(channels) {
+                frame.fill(T::from_sample(voices.next_sample()));
+            }
+        },
+        |err| log::warn!("audio output error: {err}"),
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_sound_until_released() {
+        let mut voices = Voices::new(1000.0, 440.0);
+        voices.handle(&[0xC1, 19]);
+        voices.handle(&[0x91, 69, 127]);
+        voices.handle(&[0x99, 36, 100]);
+        assert_eq!(voices.voices[0].timbre, ORGAN);
+        assert_eq!(voices.voices[0].step, 0.44);
+        assert_eq!(voices.voices[1].timbre, DRUM);
+
+        let samples: Vec<f32> = (0..200).map(|_| voices.next_sample()).collect();
+        assert!(samples.iter().any(|sample| sample.abs() > 0.01));
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+        // The drum has died away, and the held organ note keeps sounding.
+        assert_eq!(voices.voices.len(), 1);
+
+        // A note-on with no velocity is a note-off.
+        voices.handle(&[0x91, 69, 0]);
+        (0..100).for_each(|_| {
+            voices.next_sample();
+        });
+        assert!(voices.voices.is_empty());
+    }
+
+    #[test]
+    fn programs_pick_the_instrument_family() {
+        assert_eq!(timbre(0), STRUCK);
+        assert_eq!(timbre(19), ORGAN);
+        assert_eq!(timbre(40), SUSTAINED);
+        assert_eq!(timbre(73), PIPE);
+    }
+}
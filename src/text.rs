@@ -0,0 +1,36 @@
+use unicode_width::UnicodeWidthStr;
+
+/// Number of monospace columns `text` takes up. Unlike its length in bytes or `char`s, this
+/// counts wide characters such as emoji and CJK as two columns and combining accents as none.
+pub fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+/// `text` followed by enough spaces to fill `width` columns, like `format!("{:<width$}")` but
+/// measuring in columns, so labels with wide or combining characters still line up.
+pub fn pad_end(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", text, " ".repeat(padding))
+}
+
+/// Enough spaces to fill `width` columns followed by `text`, for right-aligning numbers.
+pub fn pad_start(text: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(text));
+    format!("{}{}", " ".repeat(padding), text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_by_columns() {
+        assert_eq!(pad_end("ESC", 4), "ESC ");
+        assert_eq!(pad_end("É", 4), "É   ");
+        // A combining accent takes no column of its own.
+        assert_eq!(pad_end("E\u{301}", 4), "E\u{301}   ");
+        assert_eq!(pad_end("🎹", 4), "🎹  ");
+        assert_eq!(pad_end("SHFDOWN", 4), "SHFDOWN");
+        assert_eq!(pad_start("60", 3), " 60");
+    }
+}
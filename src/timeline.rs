@@ -0,0 +1,116 @@
+use crate::{velocity::VelocityScale, MidiKeyTrack, NoteFilter, SongTiming};
+use eframe::{
+    egui::{self, Align2, FontId, Rect, Sense},
+    epaint::Color32,
+};
+
+const LANE_HEIGHT: f32 = 28.0;
+
+/// Draws each track as a horizontal lane with its notes positioned by start tick.
+///
+/// Only notes inside the visible part of the scroll area are painted, so long songs stay cheap.
+pub fn timeline_ui(
+    ui: &mut egui::Ui,
+    tracks: &[MidiKeyTrack],
+    filter: &NoteFilter,
+    timing: &SongTiming,
+    velocities: &VelocityScale,
+    pixels_per_beat: f32,
+    playhead: f64,
+) {
+    let tracks: Vec<&MidiKeyTrack> = tracks.iter().filter(|track| !track.hidden).collect();
+    let pixels_per_tick = pixels_per_beat / timing.ticks_per_beat() as f32;
+    let song_end = tracks
+        .iter()
+        .flat_map(|track| track.midi_key_pairs.last())
+        .map(|pair| pair.tick + pair.duration.unwrap_or(0))
+        .max()
+        .unwrap_or(0);
+
+    egui::ScrollArea::both()
+        .auto_shrink([false, false])
+        .show_viewport(ui, |ui, viewport| {
+            let size = egui::vec2(
+                song_end as f32 * pixels_per_tick + pixels_per_beat,
+                tracks.len() as f32 * LANE_HEIGHT,
+            );
+            let (rect, _) = ui.allocate_exact_size(size, Sense::hover());
+            let painter = ui.painter_at(rect);
+            let visuals = ui.visuals();
+            let font = FontId::monospace(11.0);
+
+            // Notes can extend to the right of their start, so look back one viewport width.
+            let first_tick =
+                ((viewport.left() - viewport.width()) / pixels_per_tick).max(0.0) as u64;
+            let last_tick = (viewport.right() / pixels_per_tick) as u64;
+            let visible_left = rect.left() + viewport.left();
+
+            for (lane, track) in tracks.iter().enumerate() {
+                let top = rect.top() + lane as f32 * LANE_HEIGHT;
+                if lane % 2 == 1 {
+                    painter.rect_filled(
+                        Rect::from_min_size(
+                            egui::pos2(visible_left, top),
+                            egui::vec2(viewport.width(), LANE_HEIGHT),
+                        ),
+                        0.0,
+                        visuals.faint_bg_color,
+                    );
+                }
+
+                let first = track
+                    .midi_key_pairs
+                    .partition_point(|pair| pair.tick < first_tick);
+                for pair in track.midi_key_pairs[first..]
+                    .iter()
+                    .take_while(|pair| pair.tick <= last_tick)
+                    .filter(|pair| !filter.hides(pair))
+                {
+                    let left = rect.left() + pair.tick as f32 * pixels_per_tick;
+                    let width = (pair.duration.unwrap_or(0) as f32 * pixels_per_tick).max(4.0);
+                    let note_rect = Rect::from_min_size(
+                        egui::pos2(left, top + 4.0),
+                        egui::vec2(width, LANE_HEIGHT - 8.0),
+                    );
+                    let (fill, label) = match &pair.keyboard_key {
+                        Some(key) if pair.substituted => {
+                            (Color32::from_rgb(160, 110, 30), key.as_str())
+                        }
+                        Some(key) => (visuals.selection.bg_fill, key.as_str()),
+                        None => (Color32::DARK_RED, "NONE"),
+                    };
+                    // Softer notes are drawn fainter.
+                    let velocity = velocities.apply(pair.velocity) as f32 / 127.0;
+                    let fill = fill.gamma_multiply(0.4 + 0.6 * velocity);
+                    let fill = if filter.excludes(pair) {
+                        fill.gamma_multiply(0.3)
+                    } else {
+                        fill
+                    };
+                    painter.rect_filled(note_rect, 2.0, fill);
+                    painter.text(
+                        note_rect.left_center() + egui::vec2(2.0, 0.0),
+                        Align2::LEFT_CENTER,
+                        label,
+                        font.clone(),
+                        visuals.strong_text_color(),
+                    );
+                }
+
+                painter.text(
+                    egui::pos2(visible_left + 4.0, top + 1.0),
+                    Align2::LEFT_TOP,
+                    format!("{} ({})", track.name, track.midi_key_pairs.len()),
+                    FontId::proportional(10.0),
+                    visuals.weak_text_color(),
+                );
+            }
+
+            let playhead_x = rect.left() + playhead as f32 * pixels_per_tick;
+            painter.vline(
+                playhead_x,
+                rect.y_range(),
+                egui::Stroke::new(1.5, visuals.warn_fg_color),
+            );
+        });
+}
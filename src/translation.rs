@@ -0,0 +1,149 @@
+use crate::{config::Annotation, layout, settings::MappingSettings, MidiKeyTrack, PlanckRows};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use thiserror::*;
+
+/// Version of the translation file schema. Bump when fields change meaning.
+pub const TRANSLATION_VERSION: u32 = 1;
+
+/// A song's mapped key sequence as written to JSON, including the mapping it was made with
+/// and each track's transpose, so that importing it reproduces the same translation.
+#[derive(Serialize, Deserialize)]
+pub struct Translation {
+    pub version: u32,
+    /// Name of the MIDI file the translation was made from.
+    pub source: Option<String>,
+    pub base_key: String,
+    pub layout: PlanckRows,
+    /// Older files predate this field and were always chromatic.
+    #[serde(default)]
+    pub mapping: MappingSettings,
+    pub tracks: Vec<MidiKeyTrack>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Error, Debug)]
+pub enum TranslationError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid translation file: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported translation version {0}")]
+    UnsupportedVersion(u32),
+    #[error("base key \"{0}\" is not in the layout")]
+    MissingBaseKey(String),
+}
+
+impl Translation {
+    pub fn to_json(&self) -> Result<String, TranslationError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Translation, TranslationError> {
+        let translation: Translation = serde_json::from_str(json)?;
+        if translation.version > TRANSLATION_VERSION {
+            return Err(TranslationError::UnsupportedVersion(translation.version));
+        }
+        if layout::key_position(&translation.layout, &translation.base_key).is_none() {
+            return Err(TranslationError::MissingBaseKey(translation.base_key));
+        }
+        Ok(translation)
+    }
+
+    pub fn export(&self, path: &Path) -> Result<(), TranslationError> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn import(path: &Path) -> Result<Translation, TranslationError> {
+        Translation::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chromatic_planck_mapping, default_planck_rows,
+        settings::{Settings, UnmappedNotes},
+        MidiKeyPair, NoteFilter,
+    };
+
+    fn track(name: &str, keys: &[u8], transpose: i8) -> MidiKeyTrack {
+        let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+        let mut track = MidiKeyTrack::new();
+        track.name = name.to_owned();
+        track.transpose = transpose;
+        for (i, &key) in keys.iter().enumerate() {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 240,
+                tick: 240 * (i as u64 + 1),
+                duration: Some(120),
+                midi_key: key,
+                velocity: 100,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        track.remap(&mapping, UnmappedNotes::Mark, 0);
+        track
+    }
+
+    #[test]
+    fn export_then_import_reproduces_translation() {
+        let translation = Translation {
+            version: TRANSLATION_VERSION,
+            source: Some("song.mid".to_owned()),
+            base_key: "ESC".to_owned(),
+            layout: default_planck_rows(),
+            mapping: MappingSettings::default(),
+            tracks: vec![
+                track("Melody", &[60, 62, 64], 0),
+                track("Bass", &[36, 40, 43], 12),
+            ],
+            annotations: vec![Annotation {
+                track: 1,
+                note: 2,
+                text: "watch this jump".to_owned(),
+            }],
+        };
+        let imported = Translation::from_json(&translation.to_json().unwrap()).unwrap();
+
+        let settings = Settings::default();
+        let filter = NoteFilter::default();
+        assert_eq!(imported.tracks.len(), translation.tracks.len());
+        for (original, imported) in translation.tracks.iter().zip(imported.tracks.iter()) {
+            assert_eq!(original.name, imported.name);
+            assert_eq!(original.transpose, imported.transpose);
+            assert_eq!(
+                original.get_midi_keys_lines(&settings, &filter),
+                imported.get_midi_keys_lines(&settings, &filter)
+            );
+        }
+        assert_eq!(imported.annotations, translation.annotations);
+        assert_eq!(imported.tracks[1].transpose, 12);
+        assert_eq!(
+            imported.tracks[1].midi_key_pairs[0].keyboard_key.as_deref(),
+            Some("TAB")
+        );
+    }
+
+    #[test]
+    fn translations_without_their_base_key_are_rejected() {
+        let translation = Translation {
+            version: TRANSLATION_VERSION,
+            source: None,
+            base_key: "NOT A KEY".to_owned(),
+            layout: default_planck_rows(),
+            mapping: MappingSettings::default(),
+            tracks: Vec::new(),
+            annotations: Vec::new(),
+        };
+        assert!(matches!(
+            Translation::from_json(&translation.to_json().unwrap()),
+            Err(TranslationError::MissingBaseKey(_))
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    settings::{FadeCurve, VelocitySettings},
+    MidiKeyTrack,
+};
+
+/// Maps recorded note velocities into the range chosen in the velocity settings, for display
+/// and preview. The notes themselves keep their recorded velocity.
+#[derive(Clone, Copy)]
+pub struct VelocityScale {
+    /// Softest and loudest velocity in the song.
+    from: (u8, u8),
+    /// Range to spread them over, or `None` to keep velocities as recorded.
+    to: Option<(u8, u8)>,
+}
+
+impl VelocityScale {
+    pub fn new(tracks: &[MidiKeyTrack], settings: &VelocitySettings) -> VelocityScale {
+        let from = tracks
+            .iter()
+            .flat_map(|track| track.midi_key_pairs.iter())
+            .fold((u8::MAX, 0), |(low, high), pair| {
+                (low.min(pair.velocity), high.max(pair.velocity))
+            });
+        let (min, max) = (settings.min.min(127), settings.max.min(127));
+        VelocityScale {
+            from,
+            to: settings.normalize.then_some((min.min(max), max.max(min))),
+        }
+    }
+
+    /// The velocity to show or play for a note recorded with `velocity`. Scaling is linear, so
+    /// louder notes stay louder by the same proportion.
+    pub fn apply(&self, velocity: u8) -> u8 {
+        let Some((min, max)) = self.to else {
+            return velocity;
+        };
+        let (low, high) = self.from;
+        if high <= low {
+            return ((min as u16 + max as u16) / 2) as u8;
+        }
+        let fraction = (velocity.clamp(low, high) - low) as f32 / (high - low) as f32;
+        (min as f32 + fraction * (max - min) as f32).round() as u8
+    }
+}
+
+/// Opacity of the quietest notes when fading, so no note disappears.
+const MIN_OPACITY: f32 = 0.2;
+
+/// How opaque a note of `velocity` is drawn when quieter notes are faded, from
+/// [`MIN_OPACITY`] for silent notes to 1 for the loudest.
+pub fn fade_opacity(velocity: u8, curve: FadeCurve) -> f32 {
+    let loudness = velocity.min(127) as f32 / 127.0;
+    let loudness = match curve {
+        FadeCurve::Linear => loudness,
+        FadeCurve::Exponential => (4.0 * loudness).exp_m1() / 4.0f32.exp_m1(),
+    };
+    MIN_OPACITY + (1.0 - MIN_OPACITY) * loudness
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MidiKeyPair;
+
+    fn track(velocities: &[u8]) -> MidiKeyTrack {
+        let mut track = MidiKeyTrack::new();
+        for (i, &velocity) in velocities.iter().enumerate() {
+            track.midi_key_pairs.push(MidiKeyPair {
+                delta: 0,
+                tick: i as u64 * 480,
+                duration: Some(480),
+                midi_key: 60,
+                velocity,
+                channel: 0,
+                keyboard_key: None,
+                substituted: false,
+            });
+        }
+        track
+    }
+
+    #[test]
+    fn spreads_velocities_over_the_target_range() {
+        let settings = VelocitySettings {
+            normalize: true,
+            min: 40,
+            max: 100,
+            ..VelocitySettings::default()
+        };
+        let scale = VelocityScale::new(&[track(&[10, 20, 30])], &settings);
+        assert_eq!(scale.apply(10), 40);
+        assert_eq!(scale.apply(20), 70);
+        assert_eq!(scale.apply(30), 100);
+
+        let scale = VelocityScale::new(&[track(&[64, 64])], &settings);
+        assert_eq!(scale.apply(64), 70);
+
+        let off = VelocitySettings {
+            normalize: false,
+            ..settings
+        };
+        assert_eq!(VelocityScale::new(&[track(&[10, 30])], &off).apply(10), 10);
+    }
+
+    #[test]
+    fn quieter_notes_fade() {
+        for curve in [FadeCurve::Linear, FadeCurve::Exponential] {
+            assert_eq!(fade_opacity(0, curve), MIN_OPACITY);
+            assert_eq!(fade_opacity(127, curve), 1.0);
+            assert!(fade_opacity(40, curve) < fade_opacity(90, curve));
+        }
+        // The exponential curve keeps middling notes fainter.
+        assert!(fade_opacity(64, FadeCurve::Exponential) < fade_opacity(64, FadeCurve::Linear));
+    }
+}
@@ -0,0 +1,357 @@
+use midly::{Format, Header, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use planck_scribe::{
+    channel_order, chromatic_planck_mapping, default_planck_rows, layout, load_midi,
+    planck_mapping,
+    settings::{DuplicateNotes, Settings, UnmappedNotes},
+    split_by_channel, MidiKeyTrack, ProgramChange,
+};
+use std::time::{Duration, Instant};
+
+fn load(file: &[u8]) -> (Vec<MidiKeyTrack>, planck_scribe::SongTiming) {
+    load_midi(file, DuplicateNotes::KeepAll).expect("fixture should parse")
+}
+
+/// `(tick, duration, key)` of every note in the track.
+fn notes(track: &MidiKeyTrack) -> Vec<(u64, Option<u64>, u8)> {
+    track
+        .midi_key_pairs
+        .iter()
+        .map(|pair| (pair.tick, pair.duration, pair.midi_key))
+        .collect()
+}
+
+#[test]
+fn format_0_pairs_notes_into_durations() {
+    let (tracks, timing) = load(include_bytes!("fixtures/format0.mid"));
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].name, "Channel 1");
+    assert_eq!(
+        notes(&tracks[0]),
+        vec![
+            (0, Some(480), 60),
+            (480, Some(480), 64),
+            (960, Some(480), 67),
+            (960, Some(480), 72),
+        ]
+    );
+    let deltas: Vec<_> = tracks[0].midi_key_pairs.iter().map(|p| p.delta).collect();
+    assert_eq!(deltas, vec![0, 0, 0, 0]);
+    assert_eq!(timing.tempo, 500_000);
+}
+
+#[test]
+fn format_1_keeps_every_track() {
+    let (tracks, timing) = load(include_bytes!("fixtures/format1.mid"));
+    let names: Vec<_> = tracks.iter().map(|track| track.name.as_str()).collect();
+    assert_eq!(names, vec!["Channel 1", "Channel 2", "Channel 3"]);
+    assert!(tracks[0].midi_key_pairs.is_empty());
+    assert_eq!(
+        notes(&tracks[1]),
+        vec![(0, Some(240), 60), (240, Some(240), 62)]
+    );
+    assert_eq!(notes(&tracks[2]), vec![(0, Some(480), 48)]);
+    // Tempo and time signature come from the conductor track but apply to the whole song.
+    assert_eq!(timing.tempo, 600_000);
+    assert_eq!(timing.time_signature, (3, 4));
+}
+
+#[test]
+fn program_changes_name_the_track_and_are_kept_in_order() {
+    let (tracks, _) = load(include_bytes!("fixtures/program_changes.mid"));
+    assert_eq!(tracks[0].name, "Acoustic Grand Piano or Piano 1");
+    assert_eq!(
+        tracks[0].program_changes,
+        vec![
+            ProgramChange {
+                tick: 0,
+                name: "Acoustic Grand Piano or Piano 1".to_owned(),
+                channel: 0,
+            },
+            ProgramChange {
+                tick: 960,
+                name: "Flute".to_owned(),
+                channel: 0,
+            },
+        ]
+    );
+    assert_eq!(tracks[0].midi_key_pairs.len(), 4);
+}
+
+#[test]
+fn track_names_take_precedence_over_instruments() {
+    let (tracks, _) = load(include_bytes!("fixtures/track_names.mid"));
+    assert_eq!(tracks[0].name, "Melody");
+    assert_eq!(tracks[0].program_changes[0].name, "Flute");
+    assert_eq!(tracks[1].name, "Channel 2");
+}
+
+#[test]
+fn channel_prefixes_attribute_instruments_and_names() {
+    let (tracks, _) = load(include_bytes!("fixtures/channel_prefix.mid"));
+    // The flute is set up on channel 1, not on the track's channel 4.
+    assert_eq!(tracks[0].name, "Violin");
+    assert_eq!(tracks[0].program_changes.len(), 1);
+    assert_eq!(tracks[1].name, "Channel 10");
+}
+
+#[test]
+fn tempo_changes_are_integrated_over_time() {
+    let (tracks, timing) = load(include_bytes!("fixtures/tempo.mid"));
+    assert_eq!(
+        timing.tempo_changes,
+        vec![(0, 500_000), (480, 250_000), (960, 1_000_000)]
+    );
+    assert_eq!(timing.tick_to_time_ms(480), Some(500.0));
+    assert_eq!(timing.tick_to_time_ms(960), Some(750.0));
+    let end = tracks[0].midi_key_pairs.last().unwrap();
+    assert_eq!(
+        timing.tick_to_time_ms(end.tick + end.duration.unwrap()),
+        Some(1750.0)
+    );
+    for (ms, tick) in [
+        (0.0, 0.0),
+        (500.0, 480.0),
+        (625.0, 720.0),
+        (750.0, 960.0),
+        (1750.0, 1440.0),
+    ] {
+        assert_eq!(timing.time_ms_to_tick(ms).map(f64::round), Some(tick));
+    }
+}
+
+#[test]
+fn files_without_a_tempo_play_at_120_bpm() {
+    let (tracks, timing) = load(include_bytes!("fixtures/no_tempo.mid"));
+    assert!(timing.has_default_tempo());
+    assert_eq!(timing.bpm(), 120.0);
+    let end = tracks[0].midi_key_pairs.last().unwrap();
+    assert_eq!(
+        timing.tick_to_time_ms(end.tick + end.duration.unwrap()),
+        Some(1000.0)
+    );
+    assert_eq!(timing.ticks_to_ms(480), 500.0);
+}
+
+#[test]
+fn loaded_notes_map_onto_the_default_layout() {
+    let (mut tracks, _) = load(include_bytes!("fixtures/format0.mid"));
+    let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+    tracks[0].remap(&mapping, UnmappedNotes::Mark, 0);
+    let keys: Vec<_> = tracks[0]
+        .midi_key_pairs
+        .iter()
+        .map(|pair| pair.keyboard_key.as_deref())
+        .collect();
+    assert_eq!(keys, vec![Some("ESC"), Some("F"), Some("J"), Some("SHF")]);
+}
+
+#[test]
+fn settings_map_onto_a_builtin_layout() {
+    let (mut tracks, _) = load(include_bytes!("fixtures/format0.mid"));
+    let preonic = layout::builtin_layouts()
+        .into_iter()
+        .find(|layout| layout.name == "Preonic")
+        .unwrap();
+    let settings = Settings {
+        layout: preonic.rows,
+        base_key: "A".to_owned(),
+        ..Settings::default()
+    };
+    tracks[0].remap(
+        &planck_mapping(&settings),
+        UnmappedNotes::Mark,
+        settings.mapping.transpose,
+    );
+    let chords: Vec<_> = tracks[0]
+        .chords()
+        .iter()
+        .map(|chord| chord.keys(&tracks[0]))
+        .collect();
+    assert_eq!(chords, vec!["A", "G", "[K Z]"]);
+}
+
+#[test]
+fn hundreds_of_tracks_load_quickly() {
+    let note = |delta: u32, key: u8, vel: u8| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        },
+    };
+    let track: Vec<_> = (0..500)
+        .flat_map(|i| {
+            [
+                note(0, 40 + (i % 48) as u8, 100),
+                note(120, 40 + (i % 48) as u8, 0),
+            ]
+        })
+        .collect();
+    let smf = Smf {
+        header: Header::new(Format::Parallel, Timing::Metrical(480.into())),
+        tracks: vec![track; 200],
+    };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).unwrap();
+
+    let start = Instant::now();
+    let (mut tracks, _) = load(&bytes);
+    let mapping = chromatic_planck_mapping("ESC", default_planck_rows());
+    for track in tracks.iter_mut() {
+        track.remap(&mapping, UnmappedNotes::Nearest, 0);
+    }
+    let elapsed = start.elapsed();
+
+    assert_eq!(tracks.len(), 200);
+    assert!(tracks.iter().all(|track| track.midi_key_pairs.len() == 500));
+    // Generous enough for unoptimised builds on slow machines; a pathological load path
+    // (e.g. quadratic note pairing) takes far longer.
+    assert!(
+        elapsed < Duration::from_secs(5),
+        "loading took {:?}",
+        elapsed
+    );
+}
+
+#[test]
+fn overlapping_notes_of_the_same_pitch_pair_first_in_first_out() {
+    let note = |delta: u32, key: u8, vel: u8| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        },
+    };
+    let track = vec![
+        note(0, 60, 100),
+        // Struck again before the first one is released.
+        note(240, 60, 100),
+        note(240, 60, 0),
+        note(240, 60, 0),
+        // A stray note-off with nothing sounding is ignored.
+        note(0, 60, 0),
+        note(0, 62, 100),
+        note(0, 62, 100),
+        note(480, 62, 0),
+    ];
+    let smf = Smf {
+        header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+        tracks: vec![track],
+    };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).unwrap();
+
+    let (tracks, _) = load(&bytes);
+    assert_eq!(
+        notes(&tracks[0]),
+        vec![
+            (0, Some(480), 60),
+            (240, Some(480), 60),
+            (720, Some(480), 62),
+            // Never released.
+            (720, None, 62),
+        ]
+    );
+}
+
+#[test]
+fn sysex_events_are_counted_and_skipped() {
+    let note = |delta: u32, key: u8, vel: u8| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: vel.into(),
+            },
+        },
+    };
+    fn sysex(data: &[u8]) -> TrackEvent<'_> {
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::SysEx(data),
+        }
+    }
+    // A large dump, and a message split over an unterminated SysEx and an escape event.
+    let dump = vec![0x10; 100_000];
+    let track = vec![
+        sysex(&[0x7E, 0x7F, 0x09, 0x01, 0xF7]),
+        sysex(&dump),
+        note(0, 60, 100),
+        sysex(&[0x43, 0x10]),
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Escape(&[0x4C, 0xF7]),
+        },
+        note(480, 60, 0),
+    ];
+    let smf = Smf {
+        header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+        tracks: vec![track],
+    };
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes).unwrap();
+
+    let (tracks, _) = load(&bytes);
+    assert_eq!(tracks[0].sysex_events, 4);
+    assert_eq!(notes(&tracks[0]), vec![(0, Some(480), 60)]);
+    let split = split_by_channel(&tracks, &[]);
+    assert_eq!(split[0].sysex_events, 4);
+}
+
+#[test]
+fn splitting_by_channel_follows_the_chosen_order() {
+    let note = |delta: u32, channel: u8, message: MidiMessage| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: channel.into(),
+            message,
+        },
+    };
+    let on = |key: u8| MidiMessage::NoteOn {
+        key: key.into(),
+        vel: 100.into(),
+    };
+    let off = |key: u8| MidiMessage::NoteOff {
+        key: key.into(),
+        vel: 0.into(),
+    };
+    let track = vec![
+        note(0, 9, MidiMessage::ProgramChange { program: 0.into() }),
+        note(0, 0, on(60)),
+        note(0, 9, on(36)),
+        note(480, 0, off(60)),
+        note(0, 9, off(36)),
+        note(0, 0, on(62)),
+        note(480, 0, off(62)),
+    ];
+    let smf = Smf {
+        header: Header::new(Format::SingleTrack, Timing::Metrical(480.into())),
+        tracks: vec![track],
+    };
+    let mut bytes = Vec::new();
+    smf.write(&mut bytes).unwrap();
+    let (tracks, _) = load(&bytes);
+
+    let split = split_by_channel(&tracks, &[]);
+    assert_eq!(split.len(), 2);
+    assert_eq!(split[0].name, "Channel 1");
+    assert_eq!(
+        notes(&split[0]),
+        vec![(0, Some(480), 60), (480, Some(480), 62)]
+    );
+    assert_eq!(split[0].midi_key_pairs[1].delta, 480);
+
+    let split = split_by_channel(&tracks, &[9]);
+    // Channel 10 is drums, whatever its program.
+    assert_eq!(split[0].name, "Percussion (channel 10)");
+    assert!(split[0].percussion && !split[1].percussion);
+    assert_eq!(notes(&split[0]), vec![(0, Some(480), 36)]);
+    assert_eq!(channel_order(&tracks, &[9, 4]), vec![9, 0]);
+}